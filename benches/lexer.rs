@@ -0,0 +1,26 @@
+// Lexing throughput on multi-megabyte inputs. Run with `cargo bench`; the
+// time per megabyte should stay flat as the input grows if the scanner is
+// linear.
+
+use std::hint::black_box;
+use std::time::Instant;
+use toy_lang::lexer::Scanner;
+
+const SNIPPET: &str = "let café = \"naïve 😀 string\";\nfn add ( a , b ) { return a + b ; }\nif x >= 10 && y != 0 { z += 1 ; }\n";
+
+fn source_of_size(bytes: usize) -> String {
+    SNIPPET.repeat(bytes / SNIPPET.len() + 1)
+}
+
+fn main() {
+    for megabytes in [1, 2, 4, 8] {
+        let input = source_of_size(megabytes * 1024 * 1024);
+        let started = Instant::now();
+        let token_count = Scanner::new(black_box(&input)).count();
+        let elapsed = started.elapsed();
+        println!(
+            "{megabytes:>2} MiB: {token_count:>9} tokens in {elapsed:>10.2?} ({:.2?}/MiB)",
+            elapsed / megabytes as u32
+        );
+    }
+}
@@ -0,0 +1,77 @@
+// The bytecode VM against the tree-walking interpreter on the same programs.
+// Run with `cargo bench --bench vm`; compiling is timed with the VM since a
+// program is compiled every time it is run.
+
+use std::hint::black_box;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use toy_lang::compiler::compile;
+use toy_lang::interpreter::Interpreter;
+use toy_lang::parser::parse;
+use toy_lang::resolve::resolve;
+use toy_lang::vm::Vm;
+
+const PROGRAMS: [(&str, &str); 4] = [
+    (
+        "fib",
+        "fn fib ( n ) { if n < 2 { return n } return fib ( n - 1 ) + fib ( n - 2 ) }
+         print ( fib ( 25 ) )",
+    ),
+    (
+        "closures",
+        "fn counter ( ) { let count = 0 return fn ( ) { count += 1 return count } }
+         let next = counter ( )
+         fn times ( n ) { if n > 0 { next ( ) times ( n - 1 ) } }
+         for c in \"abcdefghijklmnopqrstuvwxyz\" { times ( 2000 ) }
+         print ( next ( ) )",
+    ),
+    (
+        "objects",
+        "fn Point ( x , y ) { this . x = x this . y = y this . sum = fn ( ) { return this . x + this . y } }
+         let total = obj { value : 0 }
+         fn run ( n ) { if n > 0 { total . value += new Point ( n , 1 ) . sum ( ) run ( n - 1 ) } }
+         for c in \"abcdefghijklmnopqrst\" { run ( 2000 ) }
+         print ( total . value )",
+    ),
+    (
+        "strings",
+        "let text = \"\"
+         fn grow ( n ) { if n > 0 { text += `${ n % 10 }` grow ( n - 1 ) } }
+         for c in \"abcdefghij\" { grow ( 3000 ) }
+         let count = 0
+         for c in text { if c == \"7\" { count += 1 } }
+         print ( count )",
+    ),
+];
+
+// The fastest of a few runs, to keep noise out of the comparison
+fn fastest(mut run: impl FnMut()) -> Duration {
+    (0..5)
+        .map(|_| {
+            let started = Instant::now();
+            run();
+            started.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    for (name, source) in PROGRAMS {
+        let program = parse(source).unwrap();
+        let interpreted = fastest(|| {
+            Interpreter::new(Box::new(std::io::sink()))
+                .run(black_box(&program))
+                .unwrap();
+        });
+        let compiled = fastest(|| {
+            let (resolution, _) = resolve(black_box(&program));
+            let script = Rc::new(compile(&program, &resolution));
+            Vm::new(Box::new(std::io::sink())).run(script).unwrap();
+        });
+        println!(
+            "{name:>8}: interpreter {interpreted:>10.2?}, vm {compiled:>10.2?} ({:.2}x)",
+            interpreted.as_secs_f64() / compiled.as_secs_f64()
+        );
+    }
+}
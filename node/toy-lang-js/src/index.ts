@@ -22,6 +22,8 @@ io.on('connection', (socket) => {
       const tokenizer = new Tokenizer(code);
       const tokens = tokenizer.tokenize();
       socket.emit('tokens', tokens);
+      socket.emit('diagnostics', tokenizer.diagnostics());
+      socket.emit('diagnosticsHtml', tokenizer.render_diagnostics(true));
       tokenizer.free();
     } catch (error) {
       console.error('Tokenization error:', error);
@@ -0,0 +1,658 @@
+use crate::parser::*;
+use crate::resolve::{DeclId, DeclarationKind, NodeId, Resolution};
+use crate::runtime::compound_operator;
+use crate::token::*;
+use std::fmt;
+use std::rc::Rc;
+
+// Variables live in numbered slots of the scope that declares them and are
+// found by how many scopes out that is, worked out here from the resolver's
+// bindings instead of by name at run time. Operands that index a table, such
+// as `Constant` or `Closure`, refer to the tables of the enclosing `Chunk`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Instruction {
+    Constant(usize),
+    Pop,
+    Dup,
+    // Duplicates the top two values, for compound assignment to `a[b]`
+    Dup2,
+    Swap,
+    // Writes leave the value on the stack
+    Get { depth: usize, slot: usize },
+    Set { depth: usize, slot: usize },
+    // Builtins such as `print`, named by a string constant
+    GetGlobal(usize),
+    SetGlobal(usize),
+    // Fails with the message in a string constant, for code the resolver
+    // could not make sense of
+    Error(usize),
+    This,
+    // Enters a new scope laid out as `scopes[index]`
+    PushScope(usize),
+    PopScope,
+    Closure(usize),
+    // Pops `count` key and value pairs into a new object
+    Object(usize),
+    GetMember(usize),
+    SetMember(usize),
+    GetIndex,
+    SetIndex,
+    // Pops `count` values and pushes them joined into one string
+    Template(usize),
+    Negate,
+    Not,
+    Binary(BinaryOperator),
+    Jump(usize),
+    JumpIfFalse(usize),
+    // Jump without popping the condition, for `&&`, `||`, `&&=` and `||=`
+    JumpIfFalsePeek(usize),
+    JumpIfTruePeek(usize),
+    Call(usize),
+    // Like `Call`, with the object the function was read from below it as
+    // `this`
+    CallMethod(usize),
+    New(usize),
+    Return,
+    // Pops a value and starts iterating over its keys or characters
+    Iterate,
+    // Pushes the next item, or ends the iteration and jumps
+    Next(usize),
+}
+
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Instruction>,
+    // Where each instruction came from, for runtime errors
+    pub spans: Vec<TokenSpan>,
+    pub constants: Vec<LiteralToken>,
+    pub functions: Vec<Rc<Prototype>>,
+    // The slot names of each scope a `PushScope` enters
+    pub scopes: Vec<Rc<[String]>>,
+}
+
+// A compiled function, or the top level of a program when `name` is None and
+// `parameters` is 0
+#[derive(Debug)]
+pub struct Prototype {
+    pub name: Option<String>,
+    pub parameters: usize,
+    // The slot names of the scope a call runs in: the parameters, then the
+    // names declared directly in the body
+    pub locals: Rc<[String]>,
+    pub chunk: Chunk,
+}
+
+pub fn compile(program: &Program, resolution: &Resolution) -> Prototype {
+    let mut compiler = Compiler {
+        resolution,
+        scopes: Vec::new(),
+        chunk: Chunk::default(),
+        functions: 0,
+    };
+    let locals = compiler.declarations(&program.statements);
+    let names = compiler.names(&locals);
+    compiler.scopes.push(locals);
+    let end = program
+        .statements
+        .last()
+        .map_or(&program.span, |statement| &statement.span);
+    for statement in &program.statements {
+        compiler.statement(statement);
+    }
+    compiler.constant(LiteralToken::Undefined, end);
+    compiler.emit(Instruction::Return, end);
+    Prototype {
+        name: None,
+        parameters: 0,
+        locals: names,
+        chunk: compiler.chunk,
+    }
+}
+
+struct Compiler<'a> {
+    resolution: &'a Resolution,
+    // The declarations of every scope around the code being compiled,
+    // innermost last, in slot order
+    scopes: Vec<Vec<DeclId>>,
+    chunk: Chunk,
+    // How many functions the code being compiled is nested in
+    functions: usize,
+}
+
+impl Compiler<'_> {
+    fn emit(&mut self, instruction: Instruction, span: &TokenSpan) -> usize {
+        self.chunk.code.push(instruction);
+        self.chunk.spans.push(span.clone());
+        self.chunk.code.len() - 1
+    }
+
+    // Points the jump at `at` to the next instruction
+    fn patch(&mut self, at: usize) {
+        let target = self.chunk.code.len();
+        match &mut self.chunk.code[at] {
+            Instruction::Jump(to)
+            | Instruction::JumpIfFalse(to)
+            | Instruction::JumpIfFalsePeek(to)
+            | Instruction::JumpIfTruePeek(to)
+            | Instruction::Next(to) => *to = target,
+            other => unreachable!("{other:?} is not a jump"),
+        }
+    }
+
+    fn add_constant(&mut self, literal: LiteralToken) -> usize {
+        match self.chunk.constants.iter().position(|c| *c == literal) {
+            Some(index) => index,
+            None => {
+                self.chunk.constants.push(literal);
+                self.chunk.constants.len() - 1
+            }
+        }
+    }
+
+    fn constant(&mut self, literal: LiteralToken, span: &TokenSpan) {
+        let index = self.add_constant(literal);
+        self.emit(Instruction::Constant(index), span);
+    }
+
+    fn name(&mut self, name: &str) -> usize {
+        self.add_constant(LiteralToken::String(name.to_string()))
+    }
+
+    fn fail(&mut self, message: String, span: &TokenSpan) {
+        let index = self.add_constant(LiteralToken::String(message));
+        self.emit(Instruction::Error(index), span);
+    }
+
+    // The names `statements` declare in the scope they run in
+    fn declarations(&self, statements: &[Stmt]) -> Vec<DeclId> {
+        statements
+            .iter()
+            .filter_map(|statement| match &statement.kind {
+                StmtKind::Let { name, .. } | StmtKind::Object { name, .. } => Some(name),
+                StmtKind::Function(function) => function.name.as_ref(),
+                _ => None,
+            })
+            .filter_map(|name| self.resolution.declared(NodeId::of(&name.span)))
+            .collect()
+    }
+
+    fn names(&self, declarations: &[DeclId]) -> Rc<[String]> {
+        declarations
+            .iter()
+            .map(|id| self.resolution.declaration(*id).name.clone())
+            .collect()
+    }
+
+    // Where a declaration lives, counting scopes outwards from the current one
+    fn slot(&self, declaration: DeclId) -> Option<(usize, usize)> {
+        self.scopes
+            .iter()
+            .rev()
+            .enumerate()
+            .find_map(|(depth, scope)| {
+                let slot = scope.iter().position(|id| *id == declaration)?;
+                Some((depth, slot))
+            })
+    }
+
+    // Runs `body` in a scope of its own holding `declarations`, or in the
+    // current one when it declares nothing
+    fn scoped(
+        &mut self,
+        declarations: Vec<DeclId>,
+        span: &TokenSpan,
+        body: impl FnOnce(&mut Self),
+    ) {
+        if declarations.is_empty() {
+            return body(self);
+        }
+        self.chunk.scopes.push(self.names(&declarations));
+        let index = self.chunk.scopes.len() - 1;
+        self.emit(Instruction::PushScope(index), span);
+        self.scopes.push(declarations);
+        body(self);
+        self.scopes.pop();
+        self.emit(Instruction::PopScope, span);
+    }
+
+    fn block(&mut self, block: &Block) {
+        let declarations = self.declarations(&block.statements);
+        self.scoped(declarations, &block.span, |compiler| {
+            for statement in &block.statements {
+                compiler.statement(statement);
+            }
+        });
+    }
+
+    // Stores the value on top of the stack in the variable `name` declares
+    fn define(&mut self, name: &Identifier) {
+        match self.resolution.declared(NodeId::of(&name.span)) {
+            Some(declaration) => self.store(declaration, &name.name, &name.span),
+            None => self.fail(format!("undefined variable `{}`", name.name), &name.span),
+        }
+        self.emit(Instruction::Pop, &name.span);
+    }
+
+    fn store(&mut self, declaration: DeclId, name: &str, span: &TokenSpan) {
+        if self.resolution.declaration(declaration).kind == DeclarationKind::Builtin {
+            let index = self.name(name);
+            self.emit(Instruction::SetGlobal(index), span);
+            return;
+        }
+        match self.slot(declaration) {
+            Some((depth, slot)) => {
+                self.emit(Instruction::Set { depth, slot }, span);
+            }
+            None => self.fail(format!("assignment to undeclared variable `{name}`"), span),
+        }
+    }
+
+    fn statement(&mut self, statement: &Stmt) {
+        let span = &statement.span;
+        match &statement.kind {
+            StmtKind::Let { name, value, .. } => {
+                match value {
+                    Some(value) => self.expression(value),
+                    None => self.constant(LiteralToken::Undefined, span),
+                }
+                self.define(name);
+            }
+            StmtKind::Function(function) => {
+                self.function(function);
+                if let Some(name) = &function.name {
+                    self.define(name);
+                } else {
+                    self.emit(Instruction::Pop, span);
+                }
+            }
+            StmtKind::Object { name, fields, .. } => {
+                self.object(fields, span);
+                self.define(name);
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                let otherwise = self.emit(Instruction::JumpIfFalse(0), span);
+                self.block(then_branch);
+                match else_branch {
+                    Some(else_branch) => {
+                        let end = self.emit(Instruction::Jump(0), span);
+                        self.patch(otherwise);
+                        self.statement(else_branch);
+                        self.patch(end);
+                    }
+                    None => self.patch(otherwise),
+                }
+            }
+            StmtKind::For {
+                binding,
+                iterable,
+                body,
+            } => {
+                self.expression(iterable);
+                self.emit(Instruction::Iterate, &iterable.span);
+                let start = self.chunk.code.len();
+                let next = self.emit(Instruction::Next(0), span);
+                // Each pass gets a fresh scope, so closures made in the body
+                // keep the item they saw
+                let mut declarations = Vec::new();
+                declarations.extend(self.resolution.declared(NodeId::of(&binding.span)));
+                declarations.extend(self.declarations(&body.statements));
+                self.scoped(declarations, &body.span, |compiler| {
+                    compiler.define(binding);
+                    for statement in &body.statements {
+                        compiler.statement(statement);
+                    }
+                });
+                self.emit(Instruction::Jump(start), span);
+                self.patch(next);
+            }
+            StmtKind::Return(value) => {
+                if self.functions == 0 {
+                    return self.fail("`return` outside of a function".to_string(), span);
+                }
+                match value {
+                    Some(value) => self.expression(value),
+                    None => self.constant(LiteralToken::Undefined, span),
+                }
+                self.emit(Instruction::Return, span);
+            }
+            StmtKind::Block(block) => self.block(block),
+            StmtKind::Expression(expr) => {
+                self.expression(expr);
+                self.emit(Instruction::Pop, span);
+            }
+        }
+    }
+
+    // Compiles `function` into its own chunk and leaves a closure over the
+    // current scope on the stack
+    fn function(&mut self, function: &Function) {
+        let mut locals: Vec<DeclId> = function
+            .parameters
+            .iter()
+            .filter_map(|parameter| self.resolution.declared(NodeId::of(&parameter.name.span)))
+            .collect();
+        let parameters = locals.len();
+        locals.extend(self.declarations(&function.body.statements));
+        let names = self.names(&locals);
+
+        let outer = std::mem::take(&mut self.chunk);
+        self.scopes.push(locals);
+        self.functions += 1;
+        for statement in &function.body.statements {
+            self.statement(statement);
+        }
+        self.constant(LiteralToken::Undefined, &function.body.span);
+        self.emit(Instruction::Return, &function.body.span);
+        self.functions -= 1;
+        self.scopes.pop();
+        let chunk = std::mem::replace(&mut self.chunk, outer);
+
+        self.chunk.functions.push(Rc::new(Prototype {
+            name: function.name.as_ref().map(|name| name.name.clone()),
+            parameters,
+            locals: names,
+            chunk,
+        }));
+        let index = self.chunk.functions.len() - 1;
+        self.emit(Instruction::Closure(index), &function.span);
+    }
+
+    fn object(&mut self, fields: &[ObjectField], span: &TokenSpan) {
+        for field in fields {
+            self.constant(
+                LiteralToken::String(field.key.name.clone()),
+                &field.key.span,
+            );
+            self.expression(&field.value);
+        }
+        self.emit(Instruction::Object(fields.len()), span);
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        let span = &expr.span;
+        match &expr.kind {
+            ExprKind::Literal(literal) => self.constant(literal.clone(), span),
+            ExprKind::Identifier(name) => self.variable(name, span),
+            ExprKind::ObjectReference(ObjectReferenceToken::This) => {
+                self.emit(Instruction::This, span);
+            }
+            ExprKind::ObjectReference(_) => {
+                self.fail("`super` is not supported yet".to_string(), span)
+            }
+            ExprKind::New { callee, arguments } => {
+                self.expression(callee);
+                self.arguments(arguments);
+                self.emit(Instruction::New(arguments.len()), span);
+            }
+            ExprKind::Object(fields) => self.object(fields, span),
+            ExprKind::Template {
+                strings,
+                expressions,
+            } => {
+                self.constant(LiteralToken::String(strings[0].clone()), span);
+                for (expression, text) in expressions.iter().zip(&strings[1..]) {
+                    self.expression(expression);
+                    self.constant(LiteralToken::String(text.clone()), span);
+                }
+                self.emit(Instruction::Template(expressions.len() * 2 + 1), span);
+            }
+            ExprKind::Function(function) => self.function(function),
+            ExprKind::Unary {
+                operator, operand, ..
+            } => {
+                self.expression(operand);
+                let instruction = match operator {
+                    UnaryOperator::Negate => Instruction::Negate,
+                    UnaryOperator::Not | UnaryOperator::LogicalNot => Instruction::Not,
+                };
+                self.emit(instruction, span);
+            }
+            ExprKind::Binary {
+                operator,
+                left,
+                right,
+                ..
+            } => {
+                self.expression(left);
+                let jump = match operator {
+                    BinaryOperator::Logical(LogicalToken::And) => Instruction::JumpIfFalsePeek(0),
+                    BinaryOperator::Logical(LogicalToken::Or) => Instruction::JumpIfTruePeek(0),
+                    operator => {
+                        self.expression(right);
+                        self.emit(Instruction::Binary(operator.clone()), span);
+                        return;
+                    }
+                };
+                let end = self.emit(jump, span);
+                self.emit(Instruction::Pop, span);
+                self.expression(right);
+                self.patch(end);
+            }
+            ExprKind::Assign {
+                operator,
+                target,
+                value,
+                ..
+            } => self.assign(operator, target, value),
+            ExprKind::Call { callee, arguments } => match &callee.kind {
+                ExprKind::Member { object, property } => {
+                    self.expression(object);
+                    self.emit(Instruction::Dup, &callee.span);
+                    let index = self.name(&property.name);
+                    self.emit(Instruction::GetMember(index), &callee.span);
+                    self.arguments(arguments);
+                    self.emit(Instruction::CallMethod(arguments.len()), span);
+                }
+                _ => {
+                    self.expression(callee);
+                    self.arguments(arguments);
+                    self.emit(Instruction::Call(arguments.len()), span);
+                }
+            },
+            ExprKind::Member { object, property } => {
+                self.expression(object);
+                let index = self.name(&property.name);
+                self.emit(Instruction::GetMember(index), span);
+            }
+            ExprKind::Index { object, index } => {
+                self.expression(object);
+                self.expression(index);
+                self.emit(Instruction::GetIndex, span);
+            }
+        }
+    }
+
+    fn arguments(&mut self, arguments: &[Expr]) {
+        for argument in arguments {
+            self.expression(argument);
+        }
+    }
+
+    fn variable(&mut self, name: &str, span: &TokenSpan) {
+        let Some(declaration) = self.resolution.lookup(NodeId::of(span)) else {
+            return self.fail(format!("undefined variable `{name}`"), span);
+        };
+        if self.resolution.declaration(declaration).kind == DeclarationKind::Builtin {
+            let index = self.name(name);
+            self.emit(Instruction::GetGlobal(index), span);
+            return;
+        }
+        match self.slot(declaration) {
+            Some((depth, slot)) => {
+                self.emit(Instruction::Get { depth, slot }, span);
+            }
+            None => self.fail(format!("undefined variable `{name}`"), span),
+        }
+    }
+
+    fn assign(&mut self, operator: &AssignmentToken, target: &Expr, value: &Expr) {
+        let span = &target.span;
+        // The object and key of a member or index target are evaluated once,
+        // up front, and stay on the stack below the value
+        let place = match &target.kind {
+            ExprKind::Identifier(name) => Place::Variable(name),
+            ExprKind::Member { object, property } => {
+                self.expression(object);
+                Place::Member(self.name(&property.name))
+            }
+            ExprKind::Index { object, index } => {
+                self.expression(object);
+                self.expression(index);
+                Place::Index
+            }
+            _ => return self.fail("invalid assignment target".to_string(), span),
+        };
+
+        let jump = match operator {
+            AssignmentToken::Assign => {
+                self.expression(value);
+                return self.write(&place, span);
+            }
+            AssignmentToken::AndAssign => Instruction::JumpIfFalsePeek(0),
+            AssignmentToken::OrAssign => Instruction::JumpIfTruePeek(0),
+            compound => {
+                self.read(&place, span);
+                self.expression(value);
+                self.emit(Instruction::Binary(compound_operator(compound)), span);
+                return self.write(&place, span);
+            }
+        };
+        // `&&=` and `||=` keep the current value when it already decides the
+        // result, dropping the object and key it was read from
+        self.read(&place, span);
+        let short = self.emit(jump, span);
+        self.emit(Instruction::Pop, span);
+        self.expression(value);
+        self.write(&place, span);
+        let end = self.emit(Instruction::Jump(0), span);
+        self.patch(short);
+        let keys = match place {
+            Place::Variable(_) => 0,
+            Place::Member(_) => 1,
+            Place::Index => 2,
+        };
+        for _ in 0..keys {
+            self.emit(Instruction::Swap, span);
+            self.emit(Instruction::Pop, span);
+        }
+        self.patch(end);
+    }
+
+    // Pushes the current value of `place`, keeping its object and key
+    fn read(&mut self, place: &Place, span: &TokenSpan) {
+        match place {
+            Place::Variable(name) => self.variable(name, span),
+            Place::Member(index) => {
+                self.emit(Instruction::Dup, span);
+                self.emit(Instruction::GetMember(*index), span);
+            }
+            Place::Index => {
+                self.emit(Instruction::Dup2, span);
+                self.emit(Instruction::GetIndex, span);
+            }
+        }
+    }
+
+    fn write(&mut self, place: &Place, span: &TokenSpan) {
+        match place {
+            Place::Variable(name) => match self.resolution.lookup(NodeId::of(span)) {
+                Some(declaration) => self.store(declaration, name, span),
+                None => self.fail(format!("assignment to undeclared variable `{name}`"), span),
+            },
+            Place::Member(index) => {
+                self.emit(Instruction::SetMember(*index), span);
+            }
+            Place::Index => {
+                self.emit(Instruction::SetIndex, span);
+            }
+        }
+    }
+}
+
+// What an assignment stores into
+enum Place<'a> {
+    Variable(&'a str),
+    // The name constant of the member
+    Member(usize),
+    Index,
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::Get { depth, slot } => write!(f, "Get {depth} {slot}"),
+            Instruction::Set { depth, slot } => write!(f, "Set {depth} {slot}"),
+            Instruction::Binary(operator) => write!(f, "Binary {operator:?}"),
+            other => {
+                // Tuple variants print as `Name operand`
+                let debug = format!("{other:?}");
+                match debug.split_once('(') {
+                    Some((name, operand)) => write!(f, "{name} {}", operand.trim_end_matches(')')),
+                    None => write!(f, "{debug}"),
+                }
+            }
+        }
+    }
+}
+
+// One instruction per line with its offset, source line and, for operands
+// that index a table, what they refer to. Nested functions follow the code
+// that creates them.
+pub fn disassemble(prototype: &Prototype) -> String {
+    let mut output = String::new();
+    disassemble_into(prototype, "script", &mut output);
+    output
+}
+
+fn disassemble_into(prototype: &Prototype, title: &str, output: &mut String) {
+    use std::fmt::Write;
+    let chunk = &prototype.chunk;
+    let _ = writeln!(output, "== {title} ==");
+    if !prototype.locals.is_empty() {
+        let _ = writeln!(output, "locals: {}", prototype.locals.join(", "));
+    }
+    for (offset, instruction) in chunk.code.iter().enumerate() {
+        let line = chunk.spans[offset].line + 1;
+        let detail = match instruction {
+            Instruction::Constant(index) => Some(literal(&chunk.constants[*index])),
+            Instruction::GetGlobal(index)
+            | Instruction::SetGlobal(index)
+            | Instruction::GetMember(index)
+            | Instruction::SetMember(index)
+            | Instruction::Error(index) => Some(literal(&chunk.constants[*index])),
+            Instruction::PushScope(index) => Some(chunk.scopes[*index].join(", ")),
+            Instruction::Closure(index) => Some(match &chunk.functions[*index].name {
+                Some(name) => format!("fn {name}"),
+                None => "fn".to_string(),
+            }),
+            _ => None,
+        };
+        let _ = match detail {
+            Some(detail) => writeln!(output, "{offset:04} {line:>4}  {instruction} ({detail})"),
+            None => writeln!(output, "{offset:04} {line:>4}  {instruction}"),
+        };
+    }
+    for function in &chunk.functions {
+        output.push('\n');
+        let title = match &function.name {
+            Some(name) => format!("fn {name}"),
+            None => "fn".to_string(),
+        };
+        disassemble_into(function, &title, output);
+    }
+}
+
+fn literal(literal: &LiteralToken) -> String {
+    match literal {
+        LiteralToken::Number(number) => number.to_string(),
+        LiteralToken::String(value) => format!("{value:?}"),
+        LiteralToken::Boolean(value) => value.to_string(),
+        LiteralToken::Null => "null".to_string(),
+        LiteralToken::Undefined => "undefined".to_string(),
+    }
+}
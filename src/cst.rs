@@ -0,0 +1,568 @@
+use crate::diagnostic::Diagnostic;
+use crate::lexer::Scanner;
+use crate::parser::{
+    Block, Expr, ExprKind, Function, Identifier, ObjectField, Parser, Stmt, StmtKind, TypeExpr,
+};
+use crate::token::{Token, TokenSpan, TokenType};
+use std::fmt::Write;
+use std::ops::Range;
+use std::rc::Rc;
+
+// A lossless syntax tree in two layers. Green nodes are immutable and know
+// only their kind, their children and their width in bytes, never where they
+// are. Red nodes are created on demand while walking down from the root and
+// add a parent pointer and an absolute offset. Every byte of the input,
+// whitespace and comments included, belongs to exactly one token, so the text
+// of the root is always the input. `parse` builds a new tree each time; no
+// green nodes are carried over from a previous parse.
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SyntaxKind {
+    Program,
+    // Tokens of top-level statements that failed to parse
+    Error,
+    Let,
+    // Named function declarations and anonymous `fn (..) {..}` expressions
+    Function,
+    // `obj Name { .. }`
+    Object,
+    // `export` and the declaration it marks
+    Export,
+    If,
+    For,
+    Return,
+    Block,
+    ExpressionStatement,
+    // An identifier being declared: variable, function, parameter, field or
+    // property name
+    Name,
+    // An identifier used as an expression
+    NameRef,
+    Literal,
+    ObjectReference,
+    New,
+    ObjectLiteral,
+    ObjectField,
+    Template,
+    Unary,
+    Binary,
+    Assign,
+    Call,
+    Member,
+    Index,
+    // A type annotation, with the tokens of any nested types directly inside
+    Type,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct GreenToken {
+    pub token_type: TokenType,
+    pub text: String,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum GreenElement {
+    Node(Rc<GreenNode>),
+    Token(Rc<GreenToken>),
+}
+
+impl GreenElement {
+    pub fn width(&self) -> usize {
+        match self {
+            GreenElement::Node(node) => node.width,
+            GreenElement::Token(token) => token.text.len(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct GreenNode {
+    pub kind: SyntaxKind,
+    pub width: usize,
+    pub children: Vec<GreenElement>,
+}
+
+impl GreenNode {
+    pub fn new(kind: SyntaxKind, children: Vec<GreenElement>) -> Self {
+        let width = children.iter().map(GreenElement::width).sum();
+        Self {
+            kind,
+            width,
+            children,
+        }
+    }
+
+    fn write_text(&self, text: &mut String) {
+        for child in &self.children {
+            match child {
+                GreenElement::Node(node) => node.write_text(text),
+                GreenElement::Token(token) => text.push_str(&token.text),
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct NodeData {
+    green: Rc<GreenNode>,
+    parent: Option<SyntaxNode>,
+    offset: usize,
+}
+
+// A green node at a position in the tree. Cloning is cheap, and two values
+// are equal when they are the same node of the same tree.
+#[derive(Debug, Clone)]
+pub struct SyntaxNode(Rc<NodeData>);
+
+impl PartialEq for SyntaxNode {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0.green, &other.0.green) && self.0.offset == other.0.offset
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxToken {
+    green: Rc<GreenToken>,
+    parent: SyntaxNode,
+    offset: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+impl SyntaxNode {
+    pub fn new_root(green: Rc<GreenNode>) -> Self {
+        SyntaxNode(Rc::new(NodeData {
+            green,
+            parent: None,
+            offset: 0,
+        }))
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.0.green.kind
+    }
+
+    pub fn green(&self) -> &Rc<GreenNode> {
+        &self.0.green
+    }
+
+    pub fn parent(&self) -> Option<SyntaxNode> {
+        self.0.parent.clone()
+    }
+
+    // This node, its parent, and so on up to the root
+    pub fn ancestors(&self) -> impl Iterator<Item = SyntaxNode> {
+        std::iter::successors(Some(self.clone()), SyntaxNode::parent)
+    }
+
+    // Byte range of the node in the input
+    pub fn range(&self) -> Range<usize> {
+        self.0.offset..self.0.offset + self.0.green.width
+    }
+
+    pub fn text(&self) -> String {
+        let mut text = String::with_capacity(self.0.green.width);
+        self.0.green.write_text(&mut text);
+        text
+    }
+
+    pub fn children(&self) -> Vec<SyntaxElement> {
+        let mut offset = self.0.offset;
+        let mut children = Vec::with_capacity(self.0.green.children.len());
+        for child in &self.0.green.children {
+            children.push(match child {
+                GreenElement::Node(green) => SyntaxElement::Node(SyntaxNode(Rc::new(NodeData {
+                    green: green.clone(),
+                    parent: Some(self.clone()),
+                    offset,
+                }))),
+                GreenElement::Token(green) => SyntaxElement::Token(SyntaxToken {
+                    green: green.clone(),
+                    parent: self.clone(),
+                    offset,
+                }),
+            });
+            offset += child.width();
+        }
+        children
+    }
+
+    pub fn child_nodes(&self) -> impl Iterator<Item = SyntaxNode> {
+        self.children().into_iter().filter_map(|child| match child {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None,
+        })
+    }
+
+    // Every token under this node, in source order
+    pub fn tokens(&self) -> Vec<SyntaxToken> {
+        let mut tokens = Vec::new();
+        for child in self.children() {
+            match child {
+                SyntaxElement::Node(node) => tokens.extend(node.tokens()),
+                SyntaxElement::Token(token) => tokens.push(token),
+            }
+        }
+        tokens
+    }
+
+    // The token containing the byte at `offset`
+    pub fn token_at_offset(&self, offset: usize) -> Option<SyntaxToken> {
+        for child in self.children() {
+            match child {
+                SyntaxElement::Node(node) if node.range().contains(&offset) => {
+                    return node.token_at_offset(offset);
+                }
+                SyntaxElement::Token(token) if token.range().contains(&offset) => {
+                    return Some(token);
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    // An indented outline of the tree, one node or token per line
+    pub fn debug_tree(&self) -> String {
+        let mut out = String::new();
+        self.write_tree(&mut out, 0);
+        out
+    }
+
+    fn write_tree(&self, out: &mut String, depth: usize) {
+        let range = self.range();
+        let _ = writeln!(
+            out,
+            "{:indent$}{:?}@{}..{}",
+            "",
+            self.kind(),
+            range.start,
+            range.end,
+            indent = depth * 2
+        );
+        for child in self.children() {
+            match child {
+                SyntaxElement::Node(node) => node.write_tree(out, depth + 1),
+                SyntaxElement::Token(token) => {
+                    let range = token.range();
+                    let _ = writeln!(
+                        out,
+                        "{:indent$}{:?}@{}..{} {:?}",
+                        "",
+                        token.token_type(),
+                        range.start,
+                        range.end,
+                        token.text(),
+                        indent = (depth + 1) * 2
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl SyntaxToken {
+    pub fn token_type(&self) -> &TokenType {
+        &self.green.token_type
+    }
+
+    pub fn text(&self) -> &str {
+        &self.green.text
+    }
+
+    pub fn parent(&self) -> SyntaxNode {
+        self.parent.clone()
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.offset..self.offset + self.green.text.len()
+    }
+
+    // Whitespace and comments, which the grammar skips
+    pub fn is_trivia(&self) -> bool {
+        is_trivia(&self.green.token_type)
+    }
+}
+
+fn is_trivia(token_type: &TokenType) -> bool {
+    matches!(token_type, TokenType::WhiteSpace(_) | TokenType::Comment(_))
+}
+
+// Parses `input` into a syntax tree, along with everything the parser
+// reports. A tree is built even when parsing fails; statements that failed
+// become `Error` nodes.
+pub fn parse(input: &str) -> (SyntaxNode, Vec<Diagnostic>) {
+    let mut parser = Parser::new(Scanner::new(input));
+    let (program, _) = parser.parse_program_partial();
+    let mut builder = TreeBuilder {
+        input,
+        tokens: Scanner::new(input).collect(),
+        position: 0,
+        stack: vec![Vec::new()],
+    };
+    for statement in &program.statements {
+        let start = statement.export.as_ref().unwrap_or(&statement.span).start;
+        builder.error_until(start);
+        builder.statement(statement);
+    }
+    builder.error_until(input.len());
+    let children = builder.stack.pop().unwrap_or_default();
+    let root = GreenNode::new(SyntaxKind::Program, children);
+    (
+        SyntaxNode::new_root(Rc::new(root)),
+        parser.diagnostics().to_vec(),
+    )
+}
+
+// Builds green nodes by walking the AST alongside the full token stream.
+// Each AST node claims the tokens inside its span that no child claims, and
+// trivia between children goes to the innermost node around it.
+struct TreeBuilder<'a> {
+    input: &'a str,
+    tokens: Vec<Token>,
+    // Index of the next token not yet placed in the tree
+    position: usize,
+    // Children collected so far for each node being built, innermost last
+    stack: Vec<Vec<GreenElement>>,
+}
+
+impl TreeBuilder<'_> {
+    fn push(&mut self, element: GreenElement) {
+        self.stack
+            .last_mut()
+            .expect("the root is on the stack until the end")
+            .push(element);
+    }
+
+    fn token(&mut self) {
+        let token = &self.tokens[self.position];
+        let span = &token.token_span;
+        let green = GreenToken {
+            token_type: token.token_type.clone(),
+            text: self.input[span.start..span.end].to_string(),
+        };
+        self.position += 1;
+        self.push(GreenElement::Token(Rc::new(green)));
+    }
+
+    // Places every token starting before `offset` in the current node
+    fn tokens_until(&mut self, offset: usize) {
+        while self
+            .tokens
+            .get(self.position)
+            .is_some_and(|token| token.token_span.start < offset)
+        {
+            self.token();
+        }
+    }
+
+    // Like `tokens_until`, but wraps any significant tokens, such as those
+    // of a statement that failed to parse, in an `Error` node
+    fn error_until(&mut self, offset: usize) {
+        let end = self.tokens[self.position..]
+            .iter()
+            .position(|token| token.token_span.start >= offset)
+            .map_or(self.tokens.len(), |count| self.position + count);
+        let significant = |token: &&Token| !is_trivia(&token.token_type);
+        let first = self.tokens[self.position..end]
+            .iter()
+            .position(|token| significant(&token));
+        let last = self.tokens[self.position..end]
+            .iter()
+            .rposition(|token| significant(&token));
+        if let (Some(first), Some(last)) = (first, last) {
+            let (first, last) = (self.position + first, self.position + last);
+            self.tokens_until(self.tokens[first].token_span.start);
+            self.enter(self.tokens[first].token_span.start);
+            self.leave(SyntaxKind::Error, self.tokens[last].token_span.end);
+        }
+        self.tokens_until(offset);
+    }
+
+    fn enter(&mut self, start: usize) {
+        self.tokens_until(start);
+        self.stack.push(Vec::new());
+    }
+
+    fn leave(&mut self, kind: SyntaxKind, end: usize) {
+        self.tokens_until(end);
+        let children = self.stack.pop().expect("every node left was entered");
+        self.push(GreenElement::Node(Rc::new(GreenNode::new(kind, children))));
+    }
+
+    fn node(&mut self, kind: SyntaxKind, span: &TokenSpan, children: impl FnOnce(&mut Self)) {
+        self.enter(span.start);
+        children(self);
+        self.leave(kind, span.end);
+    }
+
+    fn name(&mut self, identifier: &Identifier) {
+        self.node(SyntaxKind::Name, &identifier.span, |_| {});
+    }
+
+    fn annotation(&mut self, annotation: Option<&TypeExpr>) {
+        if let Some(annotation) = annotation {
+            self.node(SyntaxKind::Type, &annotation.span, |_| {});
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.node(SyntaxKind::Block, &block.span, |builder| {
+            for statement in &block.statements {
+                builder.statement(statement);
+            }
+        });
+    }
+
+    fn function(&mut self, function: &Function) {
+        self.node(SyntaxKind::Function, &function.span, |builder| {
+            if let Some(name) = &function.name {
+                builder.name(name);
+            }
+            for parameter in &function.parameters {
+                builder.name(&parameter.name);
+                builder.annotation(parameter.annotation.as_ref());
+            }
+            builder.annotation(function.return_type.as_deref());
+            builder.block(&function.body);
+        });
+    }
+
+    fn fields(&mut self, fields: &[ObjectField]) {
+        for field in fields {
+            let span = field.key.span.to(&field.value.span);
+            self.node(SyntaxKind::ObjectField, &span, |builder| {
+                builder.name(&field.key);
+                builder.expression(&field.value);
+            });
+        }
+    }
+
+    fn statement(&mut self, statement: &Stmt) {
+        match &statement.export {
+            Some(export) => {
+                let span = export.to(&statement.span);
+                self.node(SyntaxKind::Export, &span, |builder| {
+                    builder.statement_node(statement)
+                });
+            }
+            None => self.statement_node(statement),
+        }
+    }
+
+    fn statement_node(&mut self, statement: &Stmt) {
+        let span = &statement.span;
+        match &statement.kind {
+            StmtKind::Let {
+                name,
+                annotation,
+                value,
+            } => self.node(SyntaxKind::Let, span, |builder| {
+                builder.name(name);
+                builder.annotation(annotation.as_ref());
+                if let Some(value) = value {
+                    builder.expression(value);
+                }
+            }),
+            StmtKind::Function(function) => self.function(function),
+            StmtKind::Object { name, fields, .. } => {
+                self.node(SyntaxKind::Object, span, |builder| {
+                    builder.name(name);
+                    builder.fields(fields);
+                })
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => self.node(SyntaxKind::If, span, |builder| {
+                builder.expression(condition);
+                builder.block(then_branch);
+                if let Some(else_branch) = else_branch {
+                    builder.statement(else_branch);
+                }
+            }),
+            StmtKind::For {
+                binding,
+                iterable,
+                body,
+            } => self.node(SyntaxKind::For, span, |builder| {
+                builder.name(binding);
+                builder.expression(iterable);
+                builder.block(body);
+            }),
+            StmtKind::Return(value) => self.node(SyntaxKind::Return, span, |builder| {
+                if let Some(value) = value {
+                    builder.expression(value);
+                }
+            }),
+            StmtKind::Block(block) => self.block(block),
+            StmtKind::Expression(expr) => {
+                self.node(SyntaxKind::ExpressionStatement, span, |builder| {
+                    builder.expression(expr);
+                })
+            }
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        let span = &expr.span;
+        match &expr.kind {
+            ExprKind::Literal(_) => self.node(SyntaxKind::Literal, span, |_| {}),
+            ExprKind::Identifier(_) => self.node(SyntaxKind::NameRef, span, |_| {}),
+            ExprKind::ObjectReference(_) => self.node(SyntaxKind::ObjectReference, span, |_| {}),
+            ExprKind::New { callee, arguments } => self.node(SyntaxKind::New, span, |builder| {
+                builder.expression(callee);
+                for argument in arguments {
+                    builder.expression(argument);
+                }
+            }),
+            ExprKind::Object(fields) => self.node(SyntaxKind::ObjectLiteral, span, |builder| {
+                builder.fields(fields);
+            }),
+            ExprKind::Function(function) => self.function(function),
+            ExprKind::Template { expressions, .. } => {
+                self.node(SyntaxKind::Template, span, |builder| {
+                    for expression in expressions {
+                        builder.expression(expression);
+                    }
+                })
+            }
+            ExprKind::Unary { operand, .. } => self.node(SyntaxKind::Unary, span, |builder| {
+                builder.expression(operand);
+            }),
+            ExprKind::Binary { left, right, .. } => {
+                self.node(SyntaxKind::Binary, span, |builder| {
+                    builder.expression(left);
+                    builder.expression(right);
+                })
+            }
+            ExprKind::Assign { target, value, .. } => {
+                self.node(SyntaxKind::Assign, span, |builder| {
+                    builder.expression(target);
+                    builder.expression(value);
+                })
+            }
+            ExprKind::Call { callee, arguments } => self.node(SyntaxKind::Call, span, |builder| {
+                builder.expression(callee);
+                for argument in arguments {
+                    builder.expression(argument);
+                }
+            }),
+            ExprKind::Member { object, property } => {
+                self.node(SyntaxKind::Member, span, |builder| {
+                    builder.expression(object);
+                    builder.name(property);
+                })
+            }
+            ExprKind::Index { object, index } => self.node(SyntaxKind::Index, span, |builder| {
+                builder.expression(object);
+                builder.expression(index);
+            }),
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use crate::diagnostic::Diagnostic;
+use crate::token::{DelimiterToken, Token, TokenSpan, TokenType};
+
+// The closer for each opening delimiter
+const PAIRS: [(DelimiterToken, DelimiterToken); 3] = [
+    (
+        DelimiterToken::OpenParenthesis,
+        DelimiterToken::CloseParenthesis,
+    ),
+    (DelimiterToken::OpenBracket, DelimiterToken::CloseBracket),
+    (DelimiterToken::OpenBrace, DelimiterToken::CloseBrace),
+];
+
+fn spelling(delimiter: &DelimiterToken) -> &'static str {
+    match delimiter {
+        DelimiterToken::OpenParenthesis => "(",
+        DelimiterToken::CloseParenthesis => ")",
+        DelimiterToken::OpenBracket => "[",
+        DelimiterToken::CloseBracket => "]",
+        DelimiterToken::OpenBrace => "{",
+        DelimiterToken::CloseBrace => "}",
+        DelimiterToken::SingleQuote => "'",
+        DelimiterToken::DoubleQuote => "\"",
+        DelimiterToken::BackTick => "`",
+        DelimiterToken::EOF => "end of input",
+    }
+}
+
+// Tracks `(`, `[` and `{` as tokens stream past and reports closers that do
+// not match. Only the nesting is checked, so the problems found here are
+// independent of the grammar and the parser can use `depth` to resynchronize
+// after an error.
+#[derive(Debug, Default)]
+pub struct DelimiterChecker {
+    // Every delimiter still open, innermost last
+    open: Vec<(DelimiterToken, TokenSpan)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl DelimiterChecker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn depth(&self) -> usize {
+        self.open.len()
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
+    }
+
+    pub fn feed(&mut self, token: &Token) {
+        let TokenType::Delimiter(delimiter) = &token.token_type else {
+            return;
+        };
+        if PAIRS.iter().any(|(open, _)| open == delimiter) {
+            self.open
+                .push((delimiter.clone(), token.token_span.clone()));
+        } else if let Some((opener, _)) = PAIRS.iter().find(|(_, close)| close == delimiter) {
+            self.close(opener, delimiter, &token.token_span);
+        }
+    }
+
+    // A closer pops back to the innermost delimiter it matches, reporting any
+    // left unclosed in between. One that matches nothing open is taken as a
+    // typo for the innermost closer, so `[1, 2}` is reported once rather than
+    // again as an unclosed `[`; with nothing open it is reported and ignored.
+    fn close(&mut self, opener: &DelimiterToken, closer: &DelimiterToken, span: &TokenSpan) {
+        let position = self.open.iter().rposition(|(open, _)| open == opener);
+        match (position, self.open.last()) {
+            (Some(position), _) if position + 1 == self.open.len() => {}
+            (_, Some((innermost, innermost_span))) => {
+                let expected = PAIRS
+                    .iter()
+                    .find(|(open, _)| open == innermost)
+                    .map(|(_, close)| spelling(close))
+                    .unwrap_or_default();
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        "E0008",
+                        format!("mismatched closing delimiter `{}`", spelling(closer)),
+                        span.clone(),
+                    )
+                    .with_label(
+                        innermost_span.clone(),
+                        format!("unclosed `{}` opened here", spelling(innermost)),
+                    )
+                    .with_note(format!(
+                        "expected `{expected}` to close the `{}`",
+                        spelling(innermost)
+                    )),
+                );
+            }
+            (_, None) => {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        "E0009",
+                        format!("unexpected closing delimiter `{}`", spelling(closer)),
+                        span.clone(),
+                    )
+                    .with_note(format!("no `{}` is open here", spelling(opener))),
+                );
+            }
+        }
+        match position {
+            Some(position) => self.open.truncate(position),
+            None => {
+                self.open.pop();
+            }
+        }
+    }
+
+    // Reports every delimiter still open when the input ends at `end`
+    pub fn finish(&mut self, end: &TokenSpan) {
+        for (open, span) in std::mem::take(&mut self.open).into_iter().rev() {
+            let close = PAIRS
+                .iter()
+                .find(|(candidate, _)| *candidate == open)
+                .map(|(_, close)| spelling(close))
+                .unwrap_or_default();
+            self.diagnostics.push(
+                Diagnostic::error(
+                    "E0010",
+                    format!("unclosed delimiter `{}`", spelling(&open)),
+                    span,
+                )
+                .with_label(end.clone(), "input ends here")
+                .with_note(format!("add a `{close}` to close it")),
+            );
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use crate::diagnostic::Diagnostic;
+use crate::lexer::Scanner;
+use crate::parser::{Parser, Program};
+use crate::token::TokenSpan;
+
+// The `type`s that mark a section as toy-lang. A section without a `type` is
+// toy-lang too.
+pub const TOY_TYPES: [&str; 2] = ["text/toy", "application/toy"];
+
+// One `<script>` block of a document, or the whole of a plain toy-lang file
+#[derive(Debug, PartialEq, Clone)]
+pub struct Section {
+    // In the order written, with names lowercased and quotes removed
+    pub attributes: Vec<(String, String)>,
+    // The text between the tags, exactly as written
+    pub source: String,
+    // Where `source` starts in the document, in bytes and UTF-16 code units,
+    // and the zero-based line it starts on
+    pub offset: usize,
+    pub utf16_offset: usize,
+    pub line: usize,
+    // From `<script` through `</script>`, or all of a plain file
+    pub span: TokenSpan,
+    // A toy-lang section's source once parsed, with spans that count from
+    // the start of the document. None for other languages and for sections
+    // with syntax errors.
+    pub program: Option<Program>,
+}
+
+impl Section {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| attribute == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // The MIME type of the section's language, from `type`
+    pub fn media_type(&self) -> Option<&str> {
+        self.attribute("type")
+    }
+
+    // The framework or runtime the section targets, such as `nodejs`
+    pub fn tech(&self) -> Option<&str> {
+        self.attribute("tech")
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.attribute("name")
+    }
+
+    pub fn is_toy(&self) -> bool {
+        self.media_type()
+            .is_none_or(|media_type| TOY_TYPES.contains(&media_type))
+    }
+}
+
+// A `.toy` file split into its sections, in the order they appear
+#[derive(Debug, PartialEq, Clone)]
+pub struct Document {
+    pub text: String,
+    pub sections: Vec<Section>,
+}
+
+impl Document {
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections
+            .iter()
+            .find(|section| section.name() == Some(name))
+    }
+
+    pub fn toy_sections(&self) -> impl Iterator<Item = &Section> {
+        self.sections.iter().filter(|section| section.is_toy())
+    }
+}
+
+// Splits `text` into its `<script>` sections and parses the toy-lang ones.
+// Only whitespace and `<!-- -->` comments may come between sections. Text
+// that does not start with a `<script>` tag is a plain toy-lang file, which
+// becomes a single section without attributes.
+pub fn parse_document(text: &str) -> (Document, Vec<Diagnostic>) {
+    let mut splitter = Splitter {
+        text,
+        position: 0,
+        sections: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+    splitter.skip_trivia();
+    if splitter.script_tag_ahead() {
+        splitter.split();
+    } else {
+        splitter.sections.push(Section {
+            attributes: Vec::new(),
+            source: text.to_string(),
+            offset: 0,
+            utf16_offset: 0,
+            line: 0,
+            span: span(text, 0, text.len()),
+            program: None,
+        });
+    }
+    let Splitter {
+        mut sections,
+        mut diagnostics,
+        ..
+    } = splitter;
+    for section in sections.iter_mut().filter(|section| section.is_toy()) {
+        let end = section.offset + section.source.len();
+        let scanner = Scanner::resume(
+            &text[..end],
+            section.offset,
+            section.utf16_offset,
+            section.line,
+        );
+        let mut parser = Parser::new(scanner);
+        section.program = parser.parse_program().ok();
+        diagnostics.extend_from_slice(parser.diagnostics());
+    }
+    let document = Document {
+        text: text.to_string(),
+        sections,
+    };
+    (document, diagnostics)
+}
+
+// The span of `text[start..end]`, with the line and UTF-16 offsets worked
+// out from the start of `text`
+fn span(text: &str, start: usize, end: usize) -> TokenSpan {
+    let utf16_start = text[..start].encode_utf16().count();
+    TokenSpan {
+        start,
+        end,
+        line: text[..start].matches('\n').count(),
+        utf16_start,
+        utf16_end: utf16_start + text[start..end].encode_utf16().count(),
+    }
+}
+
+struct Splitter<'a> {
+    text: &'a str,
+    position: usize,
+    sections: Vec<Section>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Splitter<'_> {
+    fn rest(&self) -> &str {
+        &self.text[self.position..]
+    }
+
+    fn starts_with_ignore_case(&self, prefix: &str) -> bool {
+        self.rest()
+            .get(..prefix.len())
+            .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+    }
+
+    fn script_tag_ahead(&self) -> bool {
+        self.starts_with_ignore_case("<script")
+            && self.rest()["<script".len()..]
+                .chars()
+                .next()
+                .is_none_or(|c| c.is_whitespace() || c == '>')
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    // Whitespace and `<!-- -->` comments
+    fn skip_trivia(&mut self) {
+        loop {
+            self.skip_whitespace();
+            if !self.rest().starts_with("<!--") {
+                return;
+            }
+            match self.rest().find("-->") {
+                Some(end) => self.position += end + "-->".len(),
+                None => {
+                    let start = self.position;
+                    self.position = self.text.len();
+                    self.error("E0601", "unterminated comment", start, start + "<!--".len());
+                }
+            }
+        }
+    }
+
+    fn error(&mut self, code: &'static str, message: impl Into<String>, start: usize, end: usize) {
+        self.diagnostics.push(Diagnostic::error(
+            code,
+            message,
+            span(self.text, start, end),
+        ));
+    }
+
+    fn split(&mut self) {
+        loop {
+            self.skip_trivia();
+            if self.position == self.text.len() {
+                return;
+            }
+            if self.script_tag_ahead() {
+                if !self.section() {
+                    return;
+                }
+                continue;
+            }
+            // Report the stray text up to the next tag and carry on from there
+            let start = self.position;
+            let first = self.rest().chars().next().map_or(0, char::len_utf8);
+            let next_tag = self.rest()[first..]
+                .find('<')
+                .map_or(self.text.len(), |offset| start + first + offset);
+            let end = start + self.text[start..next_tag].trim_end().len();
+            self.error("E0600", "expected a `<script>` section", start, end);
+            self.position = next_tag;
+        }
+    }
+
+    // Reads one `<script ..>..</script>` block. Returns false if the rest of
+    // the document could not be split.
+    fn section(&mut self) -> bool {
+        let tag_start = self.position;
+        self.position += "<script".len();
+        let Some(attributes) = self.attributes(tag_start) else {
+            return false;
+        };
+        let offset = self.position;
+        let (end, close_end) = match self.closing_tag() {
+            Some(found) => found,
+            None => {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        "E0601",
+                        "`<script>` section is never closed",
+                        span(self.text, tag_start, offset),
+                    )
+                    .with_note("end the section with `</script>`"),
+                );
+                (self.text.len(), self.text.len())
+            }
+        };
+        let start = span(self.text, offset, offset);
+        let section = Section {
+            attributes,
+            source: self.text[offset..end].to_string(),
+            offset,
+            utf16_offset: start.utf16_start,
+            line: start.line,
+            span: span(self.text, tag_start, close_end),
+            program: None,
+        };
+        if let Some(name) = section.name() {
+            if let Some(previous) = self
+                .sections
+                .iter()
+                .find(|other| other.name() == Some(name))
+            {
+                let diagnostic = Diagnostic::error(
+                    "E0603",
+                    format!("there is already a section named `{name}`"),
+                    span(self.text, tag_start, offset),
+                )
+                .with_label(previous.span.clone(), "first defined here");
+                self.diagnostics.push(diagnostic);
+            }
+        }
+        self.sections.push(section);
+        self.position = close_end;
+        true
+    }
+
+    // The attributes of the tag that started at `tag_start`, through its `>`
+    fn attributes(&mut self, tag_start: usize) -> Option<Vec<(String, String)>> {
+        let mut attributes: Vec<(String, String)> = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let Some(next) = self.rest().chars().next() else {
+                self.error(
+                    "E0601",
+                    "unterminated `<script>` tag",
+                    tag_start,
+                    tag_start + "<script".len(),
+                );
+                return None;
+            };
+            if next == '>' {
+                self.position += 1;
+                return Some(attributes);
+            }
+            let name_start = self.position;
+            let name_length = self
+                .rest()
+                .find(|c: char| c.is_whitespace() || matches!(c, '=' | '>' | '"' | '\'' | '/'))
+                .unwrap_or(self.rest().len());
+            if name_length == 0 {
+                self.error(
+                    "E0602",
+                    format!("unexpected `{next}` in `<script>` tag"),
+                    name_start,
+                    name_start + next.len_utf8(),
+                );
+                self.position += next.len_utf8();
+                continue;
+            }
+            self.position += name_length;
+            let name = self.text[name_start..self.position].to_ascii_lowercase();
+            let name_end = self.position;
+            self.skip_whitespace();
+            let value = if self.rest().starts_with('=') {
+                self.position += 1;
+                self.skip_whitespace();
+                self.attribute_value()?
+            } else {
+                String::new()
+            };
+            if attributes.iter().any(|(other, _)| *other == name) {
+                self.error(
+                    "E0602",
+                    format!("duplicate attribute `{name}`"),
+                    name_start,
+                    name_end,
+                );
+                continue;
+            }
+            attributes.push((name, value));
+        }
+    }
+
+    fn attribute_value(&mut self) -> Option<String> {
+        let start = self.position;
+        match self.rest().chars().next() {
+            Some(quote @ ('"' | '\'')) => match self.rest()[1..].find(quote) {
+                Some(length) => {
+                    self.position += length + 2;
+                    Some(self.text[start + 1..start + 1 + length].to_string())
+                }
+                None => {
+                    self.error("E0602", "unterminated attribute value", start, start + 1);
+                    None
+                }
+            },
+            _ => {
+                let length = self
+                    .rest()
+                    .find(|c: char| c.is_whitespace() || c == '>')
+                    .unwrap_or(self.rest().len());
+                self.position += length;
+                Some(self.text[start..self.position].to_string())
+            }
+        }
+    }
+
+    // Where the section's content ends and where its `</script>`, matched as
+    // HTML does regardless of case and with space before the `>`, ends
+    fn closing_tag(&self) -> Option<(usize, usize)> {
+        let lowercase = self.rest().to_ascii_lowercase();
+        let mut from = 0;
+        while let Some(found) = lowercase[from..].find("</script") {
+            let start = from + found;
+            let after = &lowercase[start + "</script".len()..];
+            let trimmed = after.trim_start();
+            if trimmed.starts_with('>') {
+                let end = start + "</script".len() + (after.len() - trimmed.len()) + 1;
+                return Some((self.position + start, self.position + end));
+            }
+            from = start + "</script".len();
+        }
+        None
+    }
+}
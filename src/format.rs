@@ -0,0 +1,296 @@
+use crate::cst::{self, SyntaxElement, SyntaxKind, SyntaxNode, SyntaxToken};
+use crate::diagnostic::Diagnostic;
+use crate::token::*;
+
+const INDENT: &str = "    ";
+
+// Reformats `source` in the standard style: four space indentation, single
+// spaces around infix operators, opening braces on the line of their `fn`,
+// `obj`, `if` or `for`, and a `;` after every simple statement. Comments are
+// kept where they were, along with single blank lines between statements.
+// Formatting the output again gives the same text. Code that does not parse
+// is left alone and its errors returned instead.
+pub fn format(source: &str) -> Result<String, Vec<Diagnostic>> {
+    let (root, diagnostics) = cst::parse(source);
+    if diagnostics.iter().any(Diagnostic::is_error) {
+        return Err(diagnostics);
+    }
+    let mut printer = Printer::default();
+    printer.node(&root);
+    Ok(printer.finish())
+}
+
+fn is_comment(element: &SyntaxElement) -> bool {
+    let SyntaxElement::Token(token) = element else {
+        return false;
+    };
+    matches!(token.token_type(), TokenType::Comment(_))
+}
+
+fn is_whitespace(element: &SyntaxElement) -> bool {
+    let SyntaxElement::Token(token) = element else {
+        return false;
+    };
+    matches!(token.token_type(), TokenType::WhiteSpace(_))
+}
+
+fn is_field(element: &SyntaxElement) -> bool {
+    matches!(element, SyntaxElement::Node(node) if node.kind() == SyntaxKind::ObjectField)
+}
+
+fn is_token(element: &SyntaxElement, token_type: &TokenType) -> bool {
+    matches!(element, SyntaxElement::Token(token) if token.token_type() == token_type)
+}
+
+const OPEN_BRACE: TokenType = TokenType::Delimiter(DelimiterToken::OpenBrace);
+const CLOSE_BRACE: TokenType = TokenType::Delimiter(DelimiterToken::CloseBrace);
+const COMMA: TokenType = TokenType::Punctuation(PunctuatorToken::Comma);
+const SEMICOLON: TokenType = TokenType::Punctuation(PunctuatorToken::Semicolon);
+const NEW_LINE: TokenType = TokenType::WhiteSpace(WhiteSpaceToken::NewLine);
+
+#[derive(Default)]
+struct Printer {
+    out: String,
+    indent: usize,
+    // Line breaks in the input since the last token that was not whitespace
+    newlines: usize,
+    // The next token goes on a new line, after a blank one if `blank_line`
+    line_break: bool,
+    blank_line: bool,
+    // No space before the next token, as after `(` or a prefix `-`
+    glue: bool,
+    // `1 .e5` must keep its space, or it would scan as a number
+    after_number: bool,
+    // Nothing but comments since an opening brace, so no blank line yet
+    after_open: bool,
+}
+
+impl Printer {
+    fn finish(mut self) -> String {
+        self.out.truncate(self.out.trim_end().len());
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.out
+    }
+
+    fn break_line(&mut self, blank: bool) {
+        self.line_break = true;
+        self.blank_line |= blank;
+    }
+
+    fn write(&mut self, text: &str, space: bool) {
+        if std::mem::take(&mut self.line_break) && !self.out.is_empty() {
+            self.out.push('\n');
+            if self.blank_line && !self.after_open {
+                self.out.push('\n');
+            }
+        }
+        self.blank_line = false;
+        if self.out.is_empty() || self.out.ends_with('\n') {
+            self.out.push_str(&INDENT.repeat(self.indent));
+        } else if space && !self.glue {
+            self.out.push(' ');
+        }
+        self.glue = false;
+        self.after_open = text == "{";
+        self.out.push_str(text);
+    }
+
+    fn node(&mut self, node: &SyntaxNode) {
+        match node.kind() {
+            SyntaxKind::Program => self.statements(node),
+            SyntaxKind::Block => self.block(node),
+            SyntaxKind::Object => self.object(node, true),
+            SyntaxKind::ObjectLiteral => {
+                // Kept on one line unless written across lines
+                let children = node.children();
+                let multiline = children.iter().any(is_comment)
+                    || children
+                        .iter()
+                        .skip_while(|child| !is_token(child, &OPEN_BRACE))
+                        .skip(1)
+                        .take_while(|child| is_whitespace(child))
+                        .any(|child| is_token(child, &NEW_LINE));
+                self.object(node, multiline);
+            }
+            SyntaxKind::Let | SyntaxKind::Return | SyntaxKind::ExpressionStatement => {
+                self.children(node);
+                if !node
+                    .children()
+                    .iter()
+                    .any(|child| is_token(child, &SEMICOLON))
+                {
+                    self.write(";", false);
+                }
+            }
+            _ => self.children(node),
+        }
+    }
+
+    fn children(&mut self, node: &SyntaxNode) {
+        for child in node.children() {
+            self.element(&child, node.kind());
+        }
+    }
+
+    fn element(&mut self, element: &SyntaxElement, parent: SyntaxKind) {
+        match element {
+            SyntaxElement::Node(node) => self.node(node),
+            SyntaxElement::Token(token) => self.token(token, parent),
+        }
+    }
+
+    // The statements of the program, one per line
+    fn statements(&mut self, node: &SyntaxNode) {
+        for child in node.children() {
+            if let SyntaxElement::Node(statement) = &child {
+                self.break_line(self.newlines >= 2);
+                self.node(statement);
+            } else {
+                self.element(&child, node.kind());
+            }
+        }
+    }
+
+    fn block(&mut self, node: &SyntaxNode) {
+        let children = node.children();
+        let empty = !children
+            .iter()
+            .any(|child| matches!(child, SyntaxElement::Node(_)) || is_comment(child));
+        for child in &children {
+            match child {
+                SyntaxElement::Token(token) if token.token_type() == &OPEN_BRACE => {
+                    self.token(token, SyntaxKind::Block);
+                    if empty {
+                        self.glue = true;
+                    } else {
+                        self.indent += 1;
+                    }
+                }
+                SyntaxElement::Token(token) if token.token_type() == &CLOSE_BRACE => {
+                    if !empty {
+                        self.indent -= 1;
+                        self.break_line(false);
+                    }
+                    self.token(token, SyntaxKind::Block);
+                }
+                SyntaxElement::Node(statement) => {
+                    self.break_line(self.newlines >= 2);
+                    self.node(statement);
+                }
+                _ => self.element(child, SyntaxKind::Block),
+            }
+        }
+    }
+
+    // `obj { .. }` fields, either each on its own line ending in a comma or
+    // all on one line between spaces
+    fn object(&mut self, node: &SyntaxNode, multiline: bool) {
+        let children = node.children();
+        let fields = children.iter().filter(|child| is_field(child)).count();
+        let empty = fields == 0 && !children.iter().any(is_comment);
+        let multiline = multiline && !empty;
+        let mut seen = 0;
+        for child in &children {
+            match child {
+                SyntaxElement::Token(token) if token.token_type() == &OPEN_BRACE => {
+                    self.token(token, node.kind());
+                    if empty {
+                        self.glue = true;
+                    } else if multiline {
+                        self.indent += 1;
+                    }
+                }
+                SyntaxElement::Token(token) if token.token_type() == &CLOSE_BRACE => {
+                    if multiline {
+                        self.indent -= 1;
+                        self.break_line(false);
+                    }
+                    self.token(token, node.kind());
+                }
+                // Multi-line fields always end in a comma, written below, and
+                // a single line never has a trailing one
+                SyntaxElement::Token(token) if token.token_type() == &COMMA => {
+                    if !multiline && seen < fields {
+                        self.token(token, node.kind());
+                    }
+                }
+                SyntaxElement::Node(field) if field.kind() == SyntaxKind::ObjectField => {
+                    if multiline {
+                        self.break_line(self.newlines >= 2);
+                    }
+                    self.node(field);
+                    seen += 1;
+                    if multiline {
+                        self.write(",", false);
+                    }
+                }
+                _ => self.element(child, node.kind()),
+            }
+        }
+    }
+
+    fn token(&mut self, token: &SyntaxToken, parent: SyntaxKind) {
+        match token.token_type() {
+            TokenType::WhiteSpace(WhiteSpaceToken::NewLine) => self.newlines += 1,
+            TokenType::WhiteSpace(_) => {}
+            TokenType::Comment(comment) => {
+                // A comment after code on the same line stays there
+                if self.newlines > 0 {
+                    self.break_line(self.newlines >= 2);
+                }
+                let after_open = self.after_open;
+                match comment {
+                    CommentToken::Block(_) => self.write(token.text(), true),
+                    CommentToken::Line(_) | CommentToken::Doc(_) => {
+                        self.write(token.text().trim_end(), true);
+                        self.break_line(false);
+                    }
+                }
+                self.after_open = after_open;
+                self.newlines = 0;
+            }
+            token_type => {
+                self.newlines = 0;
+                let space = match token_type {
+                    TokenType::Punctuation(PunctuatorToken::Dot) => self.after_number,
+                    TokenType::Punctuation(_)
+                    | TokenType::Delimiter(
+                        DelimiterToken::CloseParenthesis | DelimiterToken::CloseBracket,
+                    )
+                    | TokenType::Template(TemplateToken::Middle(_) | TemplateToken::End(_)) => {
+                        false
+                    }
+                    TokenType::Delimiter(DelimiterToken::OpenParenthesis) => !matches!(
+                        parent,
+                        SyntaxKind::Call
+                            | SyntaxKind::New
+                            | SyntaxKind::Function
+                            | SyntaxKind::Type
+                    ),
+                    TokenType::Delimiter(DelimiterToken::OpenBracket) => {
+                        parent != SyntaxKind::Index
+                    }
+                    _ => true,
+                };
+                self.write(token.text(), space);
+                self.after_number =
+                    matches!(token_type, TokenType::Literal(LiteralToken::Number(_)));
+                self.glue = match token_type {
+                    TokenType::Delimiter(
+                        DelimiterToken::OpenParenthesis | DelimiterToken::OpenBracket,
+                    )
+                    | TokenType::Punctuation(PunctuatorToken::Dot)
+                    | TokenType::Template(TemplateToken::Start(_) | TemplateToken::Middle(_)) => {
+                        true
+                    }
+                    // Prefix `-` and `!`, but not the keyword `not`
+                    TokenType::Arithmetic(ArithmeticToken::Subtract)
+                    | TokenType::Comparison(ComparisonToken::Not) => parent == SyntaxKind::Unary,
+                    _ => false,
+                };
+            }
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{ConfusableIdentifiers, Scanner};
+use crate::token::{IdentifierToken, Token, TokenSpan, TokenType};
+
+// A token along with what is needed to tell whether an edit can change it
+#[derive(Debug, Clone)]
+struct LexedToken {
+    token: Token,
+    // See `Scanner::examined_end`
+    examined_end: usize,
+    // Whether scanning can restart at the start of this token
+    restartable: bool,
+    // Problems reported while scanning this token. Confusable identifier
+    // warnings depend on every identifier before the token, so they are
+    // worked out afresh in `diagnostics` instead.
+    diagnostics: Vec<Diagnostic>,
+    // An identifier's confusable skeleton, kept so that working out those
+    // warnings again only compares skeletons rather than recomputing them
+    skeleton: Option<String>,
+}
+
+// The tokens an edit replaced: `removed` tokens from index `start` gave way
+// to `inserted` new ones. Tokens after them are unchanged apart from their
+// positions.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TokenEdit {
+    pub start: usize,
+    pub removed: usize,
+    pub inserted: usize,
+}
+
+// Keeps the tokens of a document up to date as it is edited, re-scanning only
+// from the first token an edit can affect until the scanner falls back into
+// step with the old tokens. The result is always what scanning the whole new
+// document would give.
+#[derive(Debug)]
+pub struct IncrementalLexer {
+    source: String,
+    tokens: Vec<LexedToken>,
+    // The furthest any scan has looked past the end of its token, which
+    // bounds how far back before an edit a token can be affected by it
+    max_lookahead: usize,
+}
+
+fn shift(offset: usize, delta: isize) -> usize {
+    offset
+        .checked_add_signed(delta)
+        .expect("tokens after an edit stay within the new source")
+}
+
+fn shift_span(span: &mut TokenSpan, bytes: isize, utf16: isize, lines: isize) {
+    span.start = shift(span.start, bytes);
+    span.end = shift(span.end, bytes);
+    span.utf16_start = shift(span.utf16_start, utf16);
+    span.utf16_end = shift(span.utf16_end, utf16);
+    span.line = shift(span.line, lines);
+}
+
+fn utf16_len(text: &str) -> isize {
+    text.encode_utf16().count() as isize
+}
+
+fn line_count(text: &str) -> isize {
+    text.matches('\n').count() as isize
+}
+
+impl IncrementalLexer {
+    pub fn new(source: &str) -> Self {
+        let mut lexer = Self {
+            source: source.to_string(),
+            tokens: Vec::new(),
+            max_lookahead: 0,
+        };
+        lexer.tokens = lexer.scan(Scanner::new(source), |_, _| false);
+        lexer
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn tokens(&self) -> impl Iterator<Item = &Token> {
+        self.tokens.iter().map(|lexed| &lexed.token)
+    }
+
+    pub fn token_count(&self) -> usize {
+        self.tokens.len()
+    }
+
+    // The same diagnostics, in the same order, as scanning the whole source.
+    // Confusable identifier warnings are the one part not kept per token: an
+    // edit can change which of two look-alikes comes first anywhere later in
+    // the file, so they are matched up again here over every identifier,
+    // though only by comparing the skeletons recorded when each was scanned.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let mut confusables = ConfusableIdentifiers::default();
+        let mut diagnostics = Vec::new();
+        for lexed in &self.tokens {
+            diagnostics.extend(lexed.diagnostics.iter().cloned());
+            if let (TokenType::Identifier(IdentifierToken { value }), Some(skeleton)) =
+                (&lexed.token.token_type, &lexed.skeleton)
+            {
+                diagnostics.extend(confusables.check_skeleton(
+                    value,
+                    skeleton.clone(),
+                    lexed.token.token_span.clone(),
+                ));
+            }
+        }
+        diagnostics
+    }
+
+    // Scans tokens until the end of the input, or until `resync` accepts the
+    // boundary the scanner has reached at the given offset
+    fn scan(
+        &mut self,
+        mut scanner: Scanner,
+        mut resync: impl FnMut(usize, &Scanner) -> bool,
+    ) -> Vec<LexedToken> {
+        let mut tokens = Vec::new();
+        while !scanner.end_of_input() {
+            let offset = tokens
+                .last()
+                .map(|lexed: &LexedToken| lexed.token.token_span.end);
+            if offset.is_some_and(|offset| resync(offset, &scanner)) {
+                break;
+            }
+            let restartable = scanner.at_top_level();
+            let token = scanner.next_token();
+            let examined_end = scanner.examined_end();
+            self.max_lookahead = self
+                .max_lookahead
+                .max(examined_end.saturating_sub(token.token_span.end));
+            let diagnostics = scanner
+                .take_diagnostics()
+                .into_iter()
+                .filter(|diagnostic| diagnostic.code != "W0002")
+                .collect();
+            let skeleton = match &token.token_type {
+                TokenType::Identifier(IdentifierToken { value }) => {
+                    Some(ConfusableIdentifiers::skeleton(value))
+                }
+                _ => None,
+            };
+            tokens.push(LexedToken {
+                token,
+                examined_end,
+                restartable,
+                diagnostics,
+                skeleton,
+            });
+        }
+        tokens
+    }
+
+    // The earliest token whose scan looked at `start` or beyond, moved back to
+    // a token scanning can restart at
+    fn first_affected(&self, start: usize) -> usize {
+        let mut index = self
+            .tokens
+            .partition_point(|lexed| lexed.token.token_span.end + self.max_lookahead <= start);
+        while index < self.tokens.len() && self.tokens[index].examined_end <= start {
+            index += 1;
+        }
+        // Appending still re-scans the last token, since the scanner state at
+        // the very end is not recorded
+        if index == self.tokens.len() {
+            index = index.saturating_sub(1);
+        }
+        while index > 0 && !self.tokens[index].restartable {
+            index -= 1;
+        }
+        index
+    }
+
+    // Replaces the bytes `start..end` of the source with `text` and updates
+    // the tokens to match
+    pub fn apply_edit(
+        &mut self,
+        start: usize,
+        end: usize,
+        text: &str,
+    ) -> Result<TokenEdit, String> {
+        if start > end
+            || end > self.source.len()
+            || !self.source.is_char_boundary(start)
+            || !self.source.is_char_boundary(end)
+        {
+            return Err(format!(
+                "invalid edit range {start}..{end} in a source of {} bytes",
+                self.source.len()
+            ));
+        }
+        let removed_text = &self.source[start..end];
+        let bytes = text.len() as isize - removed_text.len() as isize;
+        let utf16 = utf16_len(text) - utf16_len(removed_text);
+        let lines = line_count(text) - line_count(removed_text);
+        let mut source = String::with_capacity(shift(self.source.len(), bytes));
+        source.push_str(&self.source[..start]);
+        source.push_str(text);
+        source.push_str(&self.source[end..]);
+
+        let first = self.first_affected(start);
+        let scanner = match self.tokens.get(first) {
+            Some(lexed) => {
+                let line = first
+                    .checked_sub(1)
+                    .map_or(0, |previous| self.tokens[previous].token.token_span.line);
+                let span = &lexed.token.token_span;
+                Scanner::resume(&source, span.start, span.utf16_start, line)
+            }
+            None => Scanner::new(&source),
+        };
+
+        // Old tokens wholly after the edit can be reused once the scanner
+        // reaches the start of one, outside any template interpolation
+        let old = std::mem::take(&mut self.tokens);
+        let mut candidate = old.partition_point(|lexed| lexed.token.token_span.start < end);
+        let inserted = self.scan(scanner, |offset, scanner| {
+            while candidate < old.len()
+                && shift(old[candidate].token.token_span.start, bytes) < offset
+            {
+                candidate += 1;
+            }
+            candidate < old.len()
+                && shift(old[candidate].token.token_span.start, bytes) == offset
+                && old[candidate].restartable
+                && scanner.at_top_level()
+        });
+        let reuse_from = if inserted
+            .last()
+            .is_some_and(|lexed| lexed.token.token_span.end == source.len())
+            || candidate >= old.len()
+        {
+            old.len()
+        } else {
+            candidate
+        };
+
+        let edit = TokenEdit {
+            start: first,
+            removed: reuse_from - first,
+            inserted: inserted.len(),
+        };
+        let mut tokens = old;
+        for lexed in &mut tokens[reuse_from..] {
+            shift_span(&mut lexed.token.token_span, bytes, utf16, lines);
+            lexed.examined_end = shift(lexed.examined_end, bytes);
+            for diagnostic in &mut lexed.diagnostics {
+                shift_span(&mut diagnostic.span, bytes, utf16, lines);
+                for label in &mut diagnostic.labels {
+                    shift_span(&mut label.span, bytes, utf16, lines);
+                }
+            }
+        }
+        tokens.splice(first..reuse_from, inserted);
+        self.tokens = tokens;
+        self.source = source;
+        Ok(edit)
+    }
+}
@@ -0,0 +1,465 @@
+use crate::parser::*;
+use crate::runtime::{
+    self, binary, builtins, compound_operator, error, get_index, get_member, iterate, set_index,
+    FunctionValue, Object, RuntimeError, MAX_FRAMES,
+};
+use crate::token::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::rc::Rc;
+
+// A function the program defines, with the scope it was created in
+pub struct Closure {
+    function: Box<Function>,
+    environment: Rc<RefCell<Environment>>,
+}
+
+// Written like the function itself rather than its environment, which may
+// well contain the closure again
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+impl fmt::Display for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.function.name {
+            Some(name) => write!(f, "<fn {}>", name.name),
+            None => write!(f, "<fn>"),
+        }
+    }
+}
+
+pub type Value = runtime::Value<Closure>;
+
+// One lexical scope; lookups walk outwards through `parent`
+#[derive(Debug, Default)]
+pub struct Environment {
+    values: HashMap<String, Value>,
+    parent: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Environment {
+    pub fn new(parent: Option<Rc<RefCell<Environment>>>) -> Rc<RefCell<Self>> {
+        Rc::new(RefCell::new(Environment {
+            values: HashMap::new(),
+            parent,
+        }))
+    }
+
+    pub fn define(&mut self, name: &str, value: Value) {
+        self.values.insert(name.to_string(), value);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        match self.values.get(name) {
+            Some(value) => Some(value.clone()),
+            None => self.parent.as_ref()?.borrow().get(name),
+        }
+    }
+
+    // Returns false when no enclosing scope declares `name`
+    pub fn assign(&mut self, name: &str, value: Value) -> bool {
+        match self.values.get_mut(name) {
+            Some(slot) => {
+                *slot = value;
+                true
+            }
+            None => match &self.parent {
+                Some(parent) => parent.borrow_mut().assign(name, value),
+                None => false,
+            },
+        }
+    }
+}
+
+// How a statement finished: normally, or by unwinding to the enclosing call
+enum Completion {
+    Normal,
+    Return(Value),
+}
+
+// Each call the interpreter makes nests several Rust calls, so a program
+// calling `MAX_FRAMES` deep needs far more stack than a thread starts with
+const STACK_SIZE: usize = 512 << 20;
+
+// Runs `run` on a thread with stack enough for the interpreter to reach the
+// call limit, and returns what it does
+pub fn with_stack<T: Send>(run: impl FnOnce() -> T + Send) -> T {
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, run)
+            .expect("the interpreter thread starts")
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+pub struct Interpreter {
+    globals: Rc<RefCell<Environment>>,
+    environment: Rc<RefCell<Environment>>,
+    output: Box<dyn Write>,
+    // How many calls are running
+    depth: usize,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new(Box::new(std::io::stdout()))
+    }
+}
+
+impl Interpreter {
+    // `print` writes to `output`, which lets tests capture what a program prints
+    pub fn new(output: Box<dyn Write>) -> Self {
+        let globals = Environment::new(None);
+        for (name, value) in builtins() {
+            globals.borrow_mut().define(name, value);
+        }
+        Self {
+            environment: globals.clone(),
+            globals,
+            output,
+            depth: 0,
+        }
+    }
+
+    pub fn globals(&self) -> Rc<RefCell<Environment>> {
+        self.globals.clone()
+    }
+
+    pub fn run(&mut self, program: &Program) -> Result<(), RuntimeError> {
+        for stmt in &program.statements {
+            if let Completion::Return(_) = self.execute(stmt)? {
+                return error("`return` outside of a function", &stmt.span);
+            }
+        }
+        Ok(())
+    }
+
+    // Runs `statements` in `environment`, restoring the current scope afterwards
+    // even if a statement fails
+    fn execute_in(
+        &mut self,
+        statements: &[Stmt],
+        environment: Rc<RefCell<Environment>>,
+    ) -> Result<Completion, RuntimeError> {
+        let previous = std::mem::replace(&mut self.environment, environment);
+        let mut result = Ok(Completion::Normal);
+        for stmt in statements {
+            result = self.execute(stmt);
+            if !matches!(result, Ok(Completion::Normal)) {
+                break;
+            }
+        }
+        self.environment = previous;
+        result
+    }
+
+    fn execute_block(&mut self, block: &Block) -> Result<Completion, RuntimeError> {
+        let scope = Environment::new(Some(self.environment.clone()));
+        self.execute_in(&block.statements, scope)
+    }
+
+    fn define(&mut self, name: &str, value: Value) {
+        self.environment.borrow_mut().define(name, value);
+    }
+
+    fn execute(&mut self, stmt: &Stmt) -> Result<Completion, RuntimeError> {
+        match &stmt.kind {
+            StmtKind::Let { name, value, .. } => {
+                let value = match value {
+                    Some(value) => self.evaluate(value)?,
+                    None => Value::Undefined,
+                };
+                self.define(&name.name, value);
+            }
+            StmtKind::Function(function) => {
+                let closure = self.closure(function);
+                if let Some(name) = &function.name {
+                    self.define(&name.name, closure);
+                }
+            }
+            StmtKind::Object { name, fields, .. } => {
+                let object = self.object(fields)?;
+                self.define(&name.name, object);
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                if self.evaluate(condition)?.is_truthy() {
+                    return self.execute_block(then_branch);
+                } else if let Some(else_branch) = else_branch {
+                    return self.execute(else_branch);
+                }
+            }
+            StmtKind::For {
+                binding,
+                iterable,
+                body,
+            } => {
+                let items = iterate(self.evaluate(iterable)?, &iterable.span)?;
+                for item in items {
+                    let scope = Environment::new(Some(self.environment.clone()));
+                    scope.borrow_mut().define(&binding.name, item);
+                    if let Completion::Return(value) = self.execute_in(&body.statements, scope)? {
+                        return Ok(Completion::Return(value));
+                    }
+                }
+            }
+            StmtKind::Return(value) => {
+                let value = match value {
+                    Some(value) => self.evaluate(value)?,
+                    None => Value::Undefined,
+                };
+                return Ok(Completion::Return(value));
+            }
+            StmtKind::Block(block) => return self.execute_block(block),
+            StmtKind::Expression(expr) => {
+                self.evaluate(expr)?;
+            }
+        }
+        Ok(Completion::Normal)
+    }
+
+    fn closure(&self, function: &Function) -> Value {
+        Value::Function(Rc::new(FunctionValue::Defined(Closure {
+            function: Box::new(function.clone()),
+            environment: self.environment.clone(),
+        })))
+    }
+
+    fn object(&mut self, fields: &[ObjectField]) -> Result<Value, RuntimeError> {
+        let mut object = Object::default();
+        for field in fields {
+            let value = self.evaluate(&field.value)?;
+            object.set(&field.key.name, value);
+        }
+        Ok(Value::Object(Rc::new(RefCell::new(object))))
+    }
+
+    pub fn evaluate(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+        match &expr.kind {
+            ExprKind::Literal(literal) => Ok(literal.clone().into()),
+            ExprKind::Identifier(name) => match self.environment.borrow().get(name) {
+                Some(value) => Ok(value),
+                None => error(format!("undefined variable `{name}`"), &expr.span),
+            },
+            ExprKind::ObjectReference(ObjectReferenceToken::This) => Ok(self
+                .environment
+                .borrow()
+                .get("this")
+                .unwrap_or(Value::Undefined)),
+            ExprKind::ObjectReference(_) => error("`super` is not supported yet", &expr.span),
+            ExprKind::New { callee, arguments } => {
+                let constructor = self.evaluate(callee)?;
+                let arguments = self.arguments(arguments)?;
+                let instance = Value::Object(Rc::new(RefCell::new(Object::default())));
+                match self.call(constructor, arguments, Some(instance.clone()), &expr.span)? {
+                    // A constructor may return its own object in place of `this`
+                    object @ Value::Object(_) => Ok(object),
+                    _ => Ok(instance),
+                }
+            }
+            ExprKind::Object(fields) => self.object(fields),
+            ExprKind::Template {
+                strings,
+                expressions,
+            } => {
+                let mut result = strings[0].clone();
+                for (expression, text) in expressions.iter().zip(&strings[1..]) {
+                    let value = self.evaluate(expression)?;
+                    result.push_str(&value.to_string());
+                    result.push_str(text);
+                }
+                Ok(Value::String(result))
+            }
+            ExprKind::Function(function) => Ok(self.closure(function)),
+            ExprKind::Unary {
+                operator, operand, ..
+            } => {
+                let value = self.evaluate(operand)?;
+                match operator {
+                    UnaryOperator::Negate => match value {
+                        Value::Number(number) => Ok(Value::Number(-number)),
+                        other => {
+                            error(format!("cannot negate a {}", other.type_name()), &expr.span)
+                        }
+                    },
+                    UnaryOperator::Not | UnaryOperator::LogicalNot => {
+                        Ok(Value::Boolean(!value.is_truthy()))
+                    }
+                }
+            }
+            ExprKind::Binary {
+                operator,
+                left,
+                right,
+                ..
+            } => {
+                let left = self.evaluate(left)?;
+                // `&&` and `||` only evaluate their right operand when needed
+                match operator {
+                    BinaryOperator::Logical(LogicalToken::And) if !left.is_truthy() => {
+                        return Ok(left)
+                    }
+                    BinaryOperator::Logical(LogicalToken::Or) if left.is_truthy() => {
+                        return Ok(left)
+                    }
+                    _ => {}
+                }
+                let right = self.evaluate(right)?;
+                binary(operator, left, right, &expr.span)
+            }
+            ExprKind::Assign {
+                operator,
+                target,
+                value,
+                ..
+            } => self.assign(operator, target, value),
+            ExprKind::Call { callee, arguments } => {
+                // Calling through a member access binds `this` to the object
+                let (function, this) = match &callee.kind {
+                    ExprKind::Member { object, property } => {
+                        let object = self.evaluate(object)?;
+                        let function = get_member(&object, &property.name, &callee.span)?;
+                        (function, Some(object))
+                    }
+                    _ => (self.evaluate(callee)?, None),
+                };
+                let arguments = self.arguments(arguments)?;
+                self.call(function, arguments, this, &expr.span)
+            }
+            ExprKind::Member { object, property } => {
+                let object = self.evaluate(object)?;
+                get_member(&object, &property.name, &expr.span)
+            }
+            ExprKind::Index { object, index } => {
+                let object = self.evaluate(object)?;
+                let index = self.evaluate(index)?;
+                get_index(&object, &index, &expr.span)
+            }
+        }
+    }
+
+    fn arguments(&mut self, arguments: &[Expr]) -> Result<Vec<Value>, RuntimeError> {
+        arguments.iter().map(|arg| self.evaluate(arg)).collect()
+    }
+
+    pub fn call(
+        &mut self,
+        function: Value,
+        arguments: Vec<Value>,
+        this: Option<Value>,
+        span: &TokenSpan,
+    ) -> Result<Value, RuntimeError> {
+        let Value::Function(function) = function else {
+            return error(format!("cannot call a {}", function.type_name()), span);
+        };
+        match function.as_ref() {
+            FunctionValue::Native { call, .. } => call(self.output.as_mut(), arguments),
+            FunctionValue::Defined(Closure {
+                function,
+                environment,
+            }) => {
+                if self.depth + 1 >= MAX_FRAMES {
+                    return error("stack overflow", span);
+                }
+                let scope = Environment::new(Some(environment.clone()));
+                {
+                    let mut scope = scope.borrow_mut();
+                    if let Some(this) = this {
+                        scope.define("this", this);
+                    }
+                    // Missing arguments are `undefined`, extra ones are ignored
+                    let mut arguments = arguments.into_iter();
+                    for parameter in &function.parameters {
+                        scope.define(
+                            &parameter.name.name,
+                            arguments.next().unwrap_or(Value::Undefined),
+                        );
+                    }
+                }
+                self.depth += 1;
+                let completion = self.execute_in(&function.body.statements, scope);
+                self.depth -= 1;
+                match completion? {
+                    Completion::Return(value) => Ok(value),
+                    Completion::Normal => Ok(Value::Undefined),
+                }
+            }
+        }
+    }
+
+    fn assign(
+        &mut self,
+        operator: &AssignmentToken,
+        target: &Expr,
+        value: &Expr,
+    ) -> Result<Value, RuntimeError> {
+        // Resolve the object and key up front so they are evaluated only once
+        let place = match &target.kind {
+            ExprKind::Member { object, property } => {
+                Some((self.evaluate(object)?, Value::String(property.name.clone())))
+            }
+            ExprKind::Index { object, index } => {
+                Some((self.evaluate(object)?, self.evaluate(index)?))
+            }
+            _ => None,
+        };
+        let read = |interpreter: &mut Self| match &place {
+            Some((object, key)) => match (object, key) {
+                (Value::Object(object), Value::String(key)) => Ok(object.borrow().get(key)),
+                _ => error("invalid assignment target", &target.span),
+            },
+            None => interpreter.evaluate(target),
+        };
+
+        let new_value = match operator {
+            AssignmentToken::Assign => self.evaluate(value)?,
+            AssignmentToken::AndAssign => {
+                let current = read(self)?;
+                if !current.is_truthy() {
+                    return Ok(current);
+                }
+                self.evaluate(value)?
+            }
+            AssignmentToken::OrAssign => {
+                let current = read(self)?;
+                if current.is_truthy() {
+                    return Ok(current);
+                }
+                self.evaluate(value)?
+            }
+            compound => {
+                let current = read(self)?;
+                let right = self.evaluate(value)?;
+                binary(&compound_operator(compound), current, right, &target.span)?
+            }
+        };
+
+        match (&target.kind, place) {
+            (ExprKind::Identifier(name), _) => {
+                if !self
+                    .environment
+                    .borrow_mut()
+                    .assign(name, new_value.clone())
+                {
+                    return error(
+                        format!("assignment to undeclared variable `{name}`"),
+                        &target.span,
+                    );
+                }
+            }
+            (_, Some((object, key))) => set_index(&object, &key, new_value.clone(), &target.span)?,
+            _ => return error("invalid assignment target", &target.span),
+        }
+        Ok(new_value)
+    }
+}
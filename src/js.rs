@@ -0,0 +1,803 @@
+use crate::parser::*;
+use crate::resolve::{DeclarationKind, NodeId, Resolution};
+use crate::source_map::SourceFile;
+use crate::token::*;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+// An ES2020 module generated from a program, with the Source Map v3 that
+// maps it back to the toy-lang source
+#[derive(Debug, PartialEq, Clone)]
+pub struct JsModule {
+    // The module's file name: the source's with a `.js` extension. The code
+    // ends by pointing at `<file>.map` for its source map.
+    pub file: String,
+    pub code: String,
+    pub source_map: String,
+}
+
+// Names toy-lang allows that JavaScript reserves, or that the generated code
+// relies on being unshadowed. These, and names that start with `$`, are
+// written with a `$` in front, which keeps every other `$` name free for the
+// helpers below.
+const RESERVED: [&str; 41] = [
+    "arguments",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "console",
+    "const",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "enum",
+    "eval",
+    "export",
+    "extends",
+    "false",
+    "finally",
+    "implements",
+    "import",
+    "Infinity",
+    "instanceof",
+    "interface",
+    "NaN",
+    "Object",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "static",
+    "switch",
+    "throw",
+    "true",
+    "try",
+    "typeof",
+    "TypeError",
+    "var",
+    "void",
+    "while",
+    "with",
+];
+
+// Functions the generated code calls for toy-lang semantics JavaScript has no
+// operator for. Only the ones a module uses are written, at its end, where
+// hoisting still makes them visible everywhere.
+const HELPERS: [(&str, &str); 1] = [(
+    "$iterate",
+    "// `for .. in` visits the keys of an object or the characters of a string\n\
+     function $iterate(value) {\n  \
+       if (typeof value === \"string\") return value;\n  \
+       if (typeof value === \"object\" && value !== null) return Object.keys(value);\n  \
+       throw new TypeError(`cannot iterate over a ${value === null ? \"null\" : typeof value}`);\n\
+     }\n",
+)];
+
+// Binding strength of JavaScript expressions; an operand weaker than its
+// position allows is parenthesized
+const ASSIGNMENT: u8 = 2;
+const FUNCTION: u8 = 3;
+const UNARY: u8 = 15;
+const CALL: u8 = 18;
+const MEMBER: u8 = 19;
+const PRIMARY: u8 = 20;
+
+pub fn generate(program: &Program, resolution: &Resolution, source: &SourceFile) -> JsModule {
+    let path = Path::new(&source.name);
+    let stem = path
+        .file_stem()
+        .map_or("module".into(), |stem| stem.to_string_lossy());
+    let file = format!("{stem}.js");
+    let source_name = path.file_name().map_or(source.name.clone(), |name| {
+        name.to_string_lossy().into_owned()
+    });
+
+    let mut generator = Generator {
+        resolution,
+        source,
+        code: String::new(),
+        line: 0,
+        column: 0,
+        indent: 0,
+        mappings: Vec::new(),
+        helpers: BTreeSet::new(),
+    };
+    generator.write(&format!("// Generated by toy-lang from {source_name}\n"));
+    for statement in &program.statements {
+        generator.statement(statement);
+    }
+    // What follows has no toy-lang source
+    generator.map(None);
+    for (name, helper) in HELPERS {
+        if generator.helpers.contains(name) {
+            generator.write("\n");
+            generator.write(helper);
+        }
+    }
+    let exports = exports(program);
+    if !exports.is_empty() {
+        generator.write(&format!("\nexport {{ {} }};\n", exports.join(", ")));
+    }
+    generator.write(&format!("//# sourceMappingURL={file}.map\n"));
+
+    let source_map = format!(
+        "{{\"version\":3,\"file\":{},\"sources\":[{}],\"sourcesContent\":[{}],\"names\":[],\"mappings\":{}}}",
+        quote(&file),
+        quote(&source_name),
+        quote(&source.text),
+        quote(&mappings(&generator.mappings)),
+    );
+    JsModule {
+        file,
+        code: generator.code,
+        source_map,
+    }
+}
+
+// The names of the declarations marked `export`, each once, as `export`
+// specifiers
+fn exports(program: &Program) -> Vec<String> {
+    let mut names: Vec<&str> = Vec::new();
+    for statement in &program.statements {
+        if statement.export.is_none() {
+            continue;
+        }
+        let name = match &statement.kind {
+            StmtKind::Let { name, .. } | StmtKind::Object { name, .. } => name,
+            StmtKind::Function(Function {
+                name: Some(name), ..
+            }) => name,
+            _ => continue,
+        };
+        if !names.contains(&name.name.as_str()) {
+            names.push(&name.name);
+        }
+    }
+    names
+        .into_iter()
+        .map(|name| match identifier(name) {
+            escaped if escaped == name => escaped,
+            escaped => format!("{escaped} as {name}"),
+        })
+        .collect()
+}
+
+fn identifier(name: &str) -> String {
+    if name.starts_with('$') || RESERVED.contains(&name) {
+        format!("${name}")
+    } else {
+        name.to_string()
+    }
+}
+
+// A double-quoted string that is both a JavaScript and a JSON literal
+fn quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            // Line terminators in JavaScript strings before ES2019
+            '\u{2028}' | '\u{2029}' => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Text inside a template literal
+fn template_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('`', "\\`")
+        .replace("${", "\\${")
+        .replace('\r', "\\r")
+}
+
+fn number(number: &NumberToken) -> String {
+    match number {
+        NumberToken::SignedInteger(value) => value.to_string(),
+        NumberToken::Float(value) if value.is_nan() => "NaN".to_string(),
+        NumberToken::Float(value) if *value == f64::INFINITY => "Infinity".to_string(),
+        NumberToken::Float(value) if *value == f64::NEG_INFINITY => "-Infinity".to_string(),
+        NumberToken::Float(value) => format!("{value:?}"),
+        NumberToken::BigInt(value) => format!("{value}n"),
+    }
+}
+
+// A position in the generated code and the source position it came from,
+// if any. Both are zero-based, with columns in UTF-16 code units.
+struct Mapping {
+    line: usize,
+    column: usize,
+    source: Option<(usize, usize)>,
+}
+
+// Source Map v3 `mappings`: per generated line, comma-separated segments of
+// base64 VLQ deltas for the generated column, source index, source line and
+// source column. A segment of only a column marks code with no source.
+fn mappings(mappings: &[Mapping]) -> String {
+    let mut encoded = String::new();
+    let mut line = 0;
+    let mut line_start = true;
+    // Generated columns are relative within a line, everything else across
+    // the whole map
+    let mut previous = [0i64; 4];
+    for mapping in mappings {
+        while line < mapping.line {
+            encoded.push(';');
+            line += 1;
+            line_start = true;
+            previous[0] = 0;
+        }
+        if !line_start {
+            encoded.push(',');
+        }
+        line_start = false;
+        let segment = match mapping.source {
+            Some((source_line, source_column)) => {
+                vec![mapping.column, 0, source_line, source_column]
+            }
+            None => vec![mapping.column],
+        };
+        for (field, value) in segment.into_iter().map(|value| value as i64).enumerate() {
+            vlq(value - previous[field], &mut encoded);
+            previous[field] = value;
+        }
+    }
+    encoded
+}
+
+fn vlq(value: i64, output: &mut String) {
+    const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    // The sign goes in the lowest bit, then five bits per digit with the
+    // sixth set while more digits follow
+    let mut rest = if value < 0 {
+        ((-value as u64) << 1) | 1
+    } else {
+        (value as u64) << 1
+    };
+    loop {
+        let mut digit = (rest & 0b11111) as usize;
+        rest >>= 5;
+        if rest > 0 {
+            digit |= 0b100000;
+        }
+        output.push(BASE64[digit] as char);
+        if rest == 0 {
+            break;
+        }
+    }
+}
+
+struct Generator<'a> {
+    resolution: &'a Resolution,
+    source: &'a SourceFile,
+    code: String,
+    // Zero-based line and UTF-16 column the next character of `code` goes at
+    line: usize,
+    column: usize,
+    indent: usize,
+    // In the order they were written
+    mappings: Vec<Mapping>,
+    helpers: BTreeSet<&'static str>,
+}
+
+impl Generator<'_> {
+    fn write(&mut self, text: &str) {
+        for c in text.chars() {
+            if c == '\n' {
+                self.line += 1;
+                self.column = 0;
+            } else {
+                self.column += c.len_utf16();
+            }
+        }
+        self.code.push_str(text);
+    }
+
+    fn start_line(&mut self) {
+        self.write(&"  ".repeat(self.indent));
+    }
+
+    // Maps the current position in the output to the start of `span`
+    fn mark(&mut self, span: &TokenSpan) {
+        let source = self.source.utf16_position(span.start);
+        self.map(Some(source));
+    }
+
+    fn map(&mut self, source: Option<(usize, usize)>) {
+        let last = self.mappings.last();
+        if last.is_some_and(|last| last.line == self.line && last.column == self.column) {
+            return;
+        }
+        self.mappings.push(Mapping {
+            line: self.line,
+            column: self.column,
+            source,
+        });
+    }
+
+    fn helper(&mut self, name: &'static str) {
+        self.helpers.insert(name);
+        self.write(name);
+    }
+
+    fn doc(&mut self, doc: &Option<String>) {
+        let Some(doc) = doc else {
+            return;
+        };
+        self.start_line();
+        self.write("/**\n");
+        for line in doc.lines() {
+            self.start_line();
+            let line = line.replace("*/", "*\\/");
+            self.write(format!(" * {line}").trim_end());
+            self.write("\n");
+        }
+        self.start_line();
+        self.write(" */\n");
+    }
+
+    fn statement(&mut self, statement: &Stmt) {
+        match &statement.kind {
+            StmtKind::Function(Function { doc, .. }) | StmtKind::Object { doc, .. } => {
+                self.doc(doc)
+            }
+            _ => {}
+        }
+        self.start_line();
+        self.statement_body(statement);
+        self.write("\n");
+    }
+
+    // A statement without its indentation or line break, so `else if` can
+    // continue the line of the `else`
+    fn statement_body(&mut self, statement: &Stmt) {
+        self.mark(&statement.span);
+        match &statement.kind {
+            StmtKind::Let { name, value, .. } => {
+                self.write("let ");
+                self.name(name);
+                if let Some(value) = value {
+                    self.write(" = ");
+                    self.expression(value, ASSIGNMENT);
+                }
+                self.write(";");
+            }
+            StmtKind::Function(function) => {
+                if function.name.is_some() {
+                    self.function(function);
+                } else {
+                    self.write("(");
+                    self.function(function);
+                    self.write(");");
+                }
+            }
+            StmtKind::Object { name, fields, .. } => {
+                self.write("let ");
+                self.name(name);
+                self.write(" = ");
+                self.object(fields);
+                self.write(";");
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.write("if (");
+                self.expression(condition, 0);
+                self.write(") ");
+                self.block(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.write(" else ");
+                    self.statement_body(else_branch);
+                }
+            }
+            StmtKind::For {
+                binding,
+                iterable,
+                body,
+            } => {
+                self.write("for (let ");
+                self.name(binding);
+                self.write(" of ");
+                self.helper("$iterate");
+                self.write("(");
+                self.expression(iterable, 0);
+                self.write(")) ");
+                self.block(body);
+            }
+            StmtKind::Return(value) => {
+                self.write("return");
+                if let Some(value) = value {
+                    self.write(" ");
+                    self.expression(value, 0);
+                }
+                self.write(";");
+            }
+            StmtKind::Block(block) => self.block(block),
+            StmtKind::Expression(expr) => {
+                // A statement starting with `{` or `function` would be read as
+                // a block or a declaration
+                if matches!(
+                    leftmost(expr).kind,
+                    ExprKind::Object(_) | ExprKind::Function(_)
+                ) {
+                    self.write("(");
+                    self.expression(expr, 0);
+                    self.write(");");
+                } else {
+                    self.expression(expr, 0);
+                    self.write(";");
+                }
+            }
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        if block.statements.is_empty() {
+            return self.write("{}");
+        }
+        self.write("{\n");
+        self.indent += 1;
+        for statement in &block.statements {
+            self.statement(statement);
+        }
+        self.indent -= 1;
+        self.start_line();
+        self.write("}");
+    }
+
+    fn name(&mut self, name: &Identifier) {
+        self.mark(&name.span);
+        self.write(&identifier(&name.name));
+    }
+
+    fn function(&mut self, function: &Function) {
+        self.mark(&function.span);
+        self.write("function");
+        if let Some(name) = &function.name {
+            self.write(" ");
+            self.name(name);
+        }
+        self.parameters_and_body(function);
+    }
+
+    fn parameters_and_body(&mut self, function: &Function) {
+        self.write("(");
+        for (index, parameter) in function.parameters.iter().enumerate() {
+            if index > 0 {
+                self.write(", ");
+            }
+            self.name(&parameter.name);
+        }
+        self.write(") ");
+        self.block(&function.body);
+    }
+
+    // Objects holding functions or other objects get a line per field
+    fn object(&mut self, fields: &[ObjectField]) {
+        if fields.is_empty() {
+            return self.write("{}");
+        }
+        let multiline = fields.iter().any(|field| {
+            matches!(&field.value.kind, ExprKind::Function(_))
+                || matches!(&field.value.kind, ExprKind::Object(fields) if !fields.is_empty())
+        });
+        if !multiline {
+            self.write("{ ");
+            for (index, field) in fields.iter().enumerate() {
+                if index > 0 {
+                    self.write(", ");
+                }
+                self.field(field);
+            }
+            return self.write(" }");
+        }
+        self.write("{\n");
+        self.indent += 1;
+        for field in fields {
+            self.start_line();
+            self.field(field);
+            self.write(",\n");
+        }
+        self.indent -= 1;
+        self.start_line();
+        self.write("}");
+    }
+
+    fn field(&mut self, field: &ObjectField) {
+        self.mark(&field.key.span);
+        // Reserved words are fine as property names
+        self.write(&field.key.name);
+        match &field.value.kind {
+            // As a method, where `super` is allowed, unlike in a `function`
+            ExprKind::Function(function) if function.name.is_none() => {
+                self.mark(&function.span);
+                self.parameters_and_body(function);
+            }
+            _ => {
+                self.write(": ");
+                self.expression(&field.value, ASSIGNMENT);
+            }
+        }
+    }
+
+    fn arguments(&mut self, arguments: &[Expr]) {
+        self.write("(");
+        for (index, argument) in arguments.iter().enumerate() {
+            if index > 0 {
+                self.write(", ");
+            }
+            self.expression(argument, ASSIGNMENT);
+        }
+        self.write(")");
+    }
+
+    // Writes `expr`, in parentheses if it binds less tightly than `minimum`
+    fn expression(&mut self, expr: &Expr, minimum: u8) {
+        let parenthesize = precedence(expr) < minimum;
+        if parenthesize {
+            self.write("(");
+        }
+        self.mark(&expr.span);
+        match &expr.kind {
+            ExprKind::Literal(literal) => self.write(&match literal {
+                LiteralToken::Number(value) => number(value),
+                LiteralToken::String(value) => quote(value),
+                LiteralToken::Boolean(value) => value.to_string(),
+                LiteralToken::Null => "null".to_string(),
+                LiteralToken::Undefined => "undefined".to_string(),
+            }),
+            ExprKind::Identifier(name) => {
+                let builtin = self
+                    .resolution
+                    .lookup(NodeId::of(&expr.span))
+                    .map(|id| self.resolution.declaration(id))
+                    .is_some_and(|declaration| declaration.kind == DeclarationKind::Builtin);
+                match name.as_str() {
+                    "print" if builtin => self.write("console.log"),
+                    name => self.write(&identifier(name)),
+                }
+            }
+            ExprKind::ObjectReference(reference) => self.write(match reference {
+                ObjectReferenceToken::This => "this",
+                ObjectReferenceToken::Super => "super",
+                ObjectReferenceToken::New => "new.target",
+            }),
+            ExprKind::New { callee, arguments } => {
+                self.write("new ");
+                self.expression(callee, MEMBER);
+                self.arguments(arguments);
+            }
+            ExprKind::Object(fields) => self.object(fields),
+            ExprKind::Template {
+                strings,
+                expressions,
+            } => {
+                self.write("`");
+                self.write(&template_text(&strings[0]));
+                for (expression, text) in expressions.iter().zip(&strings[1..]) {
+                    self.write("${");
+                    self.expression(expression, 0);
+                    self.write("}");
+                    self.write(&template_text(text));
+                }
+                self.write("`");
+            }
+            ExprKind::Function(function) => self.function(function),
+            ExprKind::Unary {
+                operator, operand, ..
+            } => match operator {
+                UnaryOperator::Negate => {
+                    self.write("-");
+                    // `- -x` must not become `--x`
+                    let nested = matches!(
+                        operand.kind,
+                        ExprKind::Unary {
+                            operator: UnaryOperator::Negate,
+                            ..
+                        }
+                    );
+                    self.expression(operand, if nested { PRIMARY } else { UNARY });
+                }
+                UnaryOperator::Not | UnaryOperator::LogicalNot => {
+                    self.write("!");
+                    self.expression(operand, UNARY);
+                }
+            },
+            ExprKind::Binary {
+                operator,
+                left,
+                right,
+                ..
+            } => self.binary(operator, left, right),
+            ExprKind::Assign {
+                operator,
+                target,
+                value,
+                ..
+            } => match operator {
+                AssignmentToken::AndAssign => self.logical_assign("&&", target, value),
+                AssignmentToken::OrAssign => self.logical_assign("||", target, value),
+                operator => {
+                    self.expression(target, MEMBER);
+                    self.write(match operator {
+                        AssignmentToken::PlusAssign => " += ",
+                        AssignmentToken::MinusAssign => " -= ",
+                        AssignmentToken::MultiplyAssign => " *= ",
+                        AssignmentToken::DivideAssign => " /= ",
+                        AssignmentToken::BitwiseAndAssign => " &= ",
+                        AssignmentToken::BitwiseOrAssign => " |= ",
+                        _ => " = ",
+                    });
+                    self.expression(value, ASSIGNMENT);
+                }
+            },
+            ExprKind::Call { callee, arguments } => {
+                self.expression(callee, CALL);
+                self.arguments(arguments);
+            }
+            ExprKind::Member { object, property } => {
+                // `1.x` would read as a number
+                let number = matches!(object.kind, ExprKind::Literal(LiteralToken::Number(_)));
+                self.expression(object, if number { PRIMARY + 1 } else { CALL });
+                self.write(".");
+                self.mark(&property.span);
+                self.write(&property.name);
+            }
+            ExprKind::Index { object, index } => {
+                self.expression(object, CALL);
+                self.write("[");
+                self.expression(index, 0);
+                self.write("]");
+            }
+        }
+        if parenthesize {
+            self.write(")");
+        }
+    }
+
+    fn binary(&mut self, operator: &BinaryOperator, left: &Expr, right: &Expr) {
+        let (symbol, strength) = match operator {
+            BinaryOperator::Logical(LogicalToken::XOr | LogicalToken::XAnd) => {
+                let symbol = match operator {
+                    BinaryOperator::Logical(LogicalToken::XOr) => " !== ",
+                    _ => " === ",
+                };
+                self.write("(!");
+                self.expression(left, UNARY);
+                self.write(symbol);
+                self.write("!");
+                self.expression(right, UNARY);
+                return self.write(")");
+            }
+            operator => binary_operator(operator),
+        };
+        self.expression(left, strength);
+        self.write(symbol);
+        self.expression(right, strength + 1);
+    }
+
+    // `&&=` and `||=` are ES2021, so they become `a && (a = b)`. The object
+    // and key of a target are evaluated once, through a function, unless
+    // reading them twice is harmless.
+    fn logical_assign(&mut self, operator: &str, target: &Expr, value: &Expr) {
+        let (object, key) = match &target.kind {
+            ExprKind::Member { object, .. } if !pure(object) => (Some(object), None),
+            ExprKind::Index { object, index } if !pure(object) || !pure(index) => {
+                (Some(object), Some(index))
+            }
+            _ => {
+                self.write("(");
+                self.expression(target, MEMBER);
+                self.write(&format!(" {operator} ("));
+                self.expression(target, MEMBER);
+                self.write(" = ");
+                self.expression(value, ASSIGNMENT);
+                return self.write("))");
+            }
+        };
+        let place = match &target.kind {
+            ExprKind::Member { property, .. } => format!("$object.{}", property.name),
+            _ => "$object[$key]".to_string(),
+        };
+        let parameters = if key.is_some() {
+            "$object, $key"
+        } else {
+            "$object"
+        };
+        self.write(&format!(
+            "(({parameters}) => {place} {operator} ({place} = "
+        ));
+        self.expression(value, ASSIGNMENT);
+        self.write("))(");
+        if let Some(object) = object {
+            self.expression(object, ASSIGNMENT);
+        }
+        if let Some(key) = key {
+            self.write(", ");
+            self.expression(key, ASSIGNMENT);
+        }
+        self.write(")");
+    }
+}
+
+fn binary_operator(operator: &BinaryOperator) -> (&'static str, u8) {
+    use ArithmeticToken as A;
+    match operator {
+        BinaryOperator::Logical(LogicalToken::Or) => (" || ", 4),
+        BinaryOperator::Logical(_) => (" && ", 5),
+        BinaryOperator::Arithmetic(A::BitwiseOr) => (" | ", 6),
+        BinaryOperator::Arithmetic(A::BitwiseAnd) => (" & ", 8),
+        BinaryOperator::Comparison(ComparisonToken::Equal) => (" === ", 9),
+        BinaryOperator::Comparison(ComparisonToken::NotEqual) => (" !== ", 9),
+        BinaryOperator::Comparison(ComparisonToken::GreaterThan) => (" > ", 10),
+        BinaryOperator::Comparison(ComparisonToken::GreaterThanOrEqual) => (" >= ", 10),
+        BinaryOperator::Comparison(ComparisonToken::LessThan) => (" < ", 10),
+        BinaryOperator::Comparison(_) => (" <= ", 10),
+        BinaryOperator::Arithmetic(A::Add) => (" + ", 12),
+        BinaryOperator::Arithmetic(A::Subtract) => (" - ", 12),
+        BinaryOperator::Arithmetic(A::Multiply) => (" * ", 13),
+        BinaryOperator::Arithmetic(A::Divide) => (" / ", 13),
+        BinaryOperator::Arithmetic(_) => (" % ", 13),
+    }
+}
+
+fn precedence(expr: &Expr) -> u8 {
+    match &expr.kind {
+        ExprKind::Assign { operator, .. } => match operator {
+            // Written in parentheses of their own
+            AssignmentToken::AndAssign | AssignmentToken::OrAssign => PRIMARY,
+            _ => ASSIGNMENT,
+        },
+        ExprKind::Function(_) => FUNCTION,
+        ExprKind::Unary { .. } => UNARY,
+        ExprKind::Binary { operator, .. } => match operator {
+            BinaryOperator::Logical(LogicalToken::XOr | LogicalToken::XAnd) => PRIMARY,
+            operator => binary_operator(operator).1,
+        },
+        ExprKind::Call { .. } | ExprKind::New { .. } => CALL,
+        ExprKind::Member { .. } | ExprKind::Index { .. } => MEMBER,
+        _ => PRIMARY,
+    }
+}
+
+// The expression a statement's text would start with, which is `expr` itself
+// when the text starts with a parenthesis or an operator
+fn leftmost(expr: &Expr) -> &Expr {
+    let (operand, minimum) = match &expr.kind {
+        ExprKind::Binary { operator, left, .. } if precedence(expr) < PRIMARY => {
+            (left, binary_operator(operator).1)
+        }
+        ExprKind::Assign { target, .. } if precedence(expr) < PRIMARY => (target, MEMBER),
+        ExprKind::Call { callee, .. } => (callee, CALL),
+        ExprKind::Member { object, .. } | ExprKind::Index { object, .. } => (object, CALL),
+        _ => return expr,
+    };
+    if precedence(operand) < minimum {
+        expr
+    } else {
+        leftmost(operand)
+    }
+}
+
+// Whether evaluating `expr` twice gives the same value with no side effects
+fn pure(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Literal(_) | ExprKind::Identifier(_) | ExprKind::ObjectReference(_) => true,
+        ExprKind::Member { object, .. } => pure(object),
+        _ => false,
+    }
+}
@@ -1,4 +1,54 @@
+use crate::diagnostic::Diagnostic;
 use crate::token::*;
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+use std::cell::Cell;
+use std::collections::HashMap;
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::confusable_detection::skeleton;
+use unicode_security::MixedScript;
+
+// Every operator the scanner recognises, longest spellings first so the first
+// match is always the maximal munch.
+const OPERATORS: [(&str, TokenType); 25] = [
+    ("&&=", TokenType::Assignment(AssignmentToken::AndAssign)),
+    ("||=", TokenType::Assignment(AssignmentToken::OrAssign)),
+    ("==", TokenType::Comparison(ComparisonToken::Equal)),
+    ("!=", TokenType::Comparison(ComparisonToken::NotEqual)),
+    (
+        ">=",
+        TokenType::Comparison(ComparisonToken::GreaterThanOrEqual),
+    ),
+    (
+        "<=",
+        TokenType::Comparison(ComparisonToken::LessThanOrEqual),
+    ),
+    ("&&", TokenType::Logical(LogicalToken::And)),
+    ("||", TokenType::Logical(LogicalToken::Or)),
+    ("+=", TokenType::Assignment(AssignmentToken::PlusAssign)),
+    ("-=", TokenType::Assignment(AssignmentToken::MinusAssign)),
+    ("*=", TokenType::Assignment(AssignmentToken::MultiplyAssign)),
+    ("/=", TokenType::Assignment(AssignmentToken::DivideAssign)),
+    (
+        "&=",
+        TokenType::Assignment(AssignmentToken::BitwiseAndAssign),
+    ),
+    (
+        "|=",
+        TokenType::Assignment(AssignmentToken::BitwiseOrAssign),
+    ),
+    ("+", TokenType::Arithmetic(ArithmeticToken::Add)),
+    ("-", TokenType::Arithmetic(ArithmeticToken::Subtract)),
+    ("*", TokenType::Arithmetic(ArithmeticToken::Multiply)),
+    ("/", TokenType::Arithmetic(ArithmeticToken::Divide)),
+    ("%", TokenType::Arithmetic(ArithmeticToken::Modulo)),
+    ("&", TokenType::Arithmetic(ArithmeticToken::BitwiseAnd)),
+    ("|", TokenType::Arithmetic(ArithmeticToken::BitwiseOr)),
+    ("=", TokenType::Assignment(AssignmentToken::Assign)),
+    ("!", TokenType::Comparison(ComparisonToken::Not)),
+    (">", TokenType::Comparison(ComparisonToken::GreaterThan)),
+    ("<", TokenType::Comparison(ComparisonToken::LessThan)),
+];
 
 impl Iterator for Scanner {
     type Item = Token;
@@ -15,18 +65,107 @@ impl Iterator for Scanner {
 #[derive(Debug, Default)]
 pub struct Scanner {
     input: String,
+    // Byte offsets into `input`; always on a char boundary
     cursor_start: usize,
     cursor_end: usize,
+    // The same positions counted in UTF-16 code units, for JS editors
+    utf16_start: usize,
+    utf16_end: usize,
     current_line: usize,
+    diagnostics: Vec<Diagnostic>,
+    confusables: ConfusableIdentifiers,
+    // Exclusive end of the input examined while scanning the current token,
+    // which lookahead can carry past the token's own end
+    examined_end: Cell<usize>,
+    // One entry per template interpolation being scanned, counting the `{`
+    // opened inside it, so the `}` that ends the interpolation is told apart
+    // from one closing an object or block
+    template_braces: Vec<usize>,
+}
+
+// The first identifier seen with each confusable skeleton (UTS #39), so
+// `pаypal` with a Cyrillic `а` is flagged next to `paypal`
+#[derive(Debug, Default)]
+pub struct ConfusableIdentifiers {
+    skeletons: HashMap<String, (String, TokenSpan)>,
 }
 
-impl<'a> From<&'a str> for NumberToken {
-    fn from(value: &'a str) -> Self {
-        let contains_delimiter = value.contains(".") || value.contains(",");
-        if !contains_delimiter {
-            return NumberToken::SignedInteger(value.parse::<i64>().unwrap());
+impl ConfusableIdentifiers {
+    // What identifiers are compared by: two are confusable when their
+    // skeletons are equal
+    pub fn skeleton(value: &str) -> String {
+        skeleton(value).collect()
+    }
+
+    // Records the identifier `value` at `span`, warning when it looks like a
+    // different identifier recorded earlier
+    pub fn check(&mut self, value: &str, span: TokenSpan) -> Option<Diagnostic> {
+        self.check_skeleton(value, Self::skeleton(value), span)
+    }
+
+    // `check` for an identifier whose skeleton is already known
+    pub fn check_skeleton(
+        &mut self,
+        value: &str,
+        skeleton: String,
+        span: TokenSpan,
+    ) -> Option<Diagnostic> {
+        match self.skeletons.get(&skeleton) {
+            // Plain ASCII pairs such as `l` and `I` are left alone
+            Some((other, other_span))
+                if other != value && !(value.is_ascii() && other.is_ascii()) =>
+            {
+                Some(
+                    Diagnostic::warning(
+                        "W0002",
+                        format!("identifier `{value}` is confusable with `{other}`"),
+                        span,
+                    )
+                    .with_label(other_span.clone(), format!("`{other}` is used here")),
+                )
+            }
+            Some(_) => None,
+            None => {
+                self.skeletons.insert(skeleton, (value.to_string(), span));
+                None
+            }
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a str> for NumberToken {
+    type Error = Diagnostic;
+
+    // Parses a whole string with the scanner's number grammar, so `1_000`,
+    // `0xFF` and `1e3` are accepted while `inf`, `NaN` and `1,5` are not.
+    // Integers that do not fit in an i64 still fall back to a float, matching
+    // the overflow behaviour of the arithmetic operators.
+    fn try_from(value: &'a str) -> Result<Self, Self::Error> {
+        let mut scanner = Scanner::new(value);
+        let token = scanner.next_token();
+        if let Some(diagnostic) = scanner
+            .take_diagnostics()
+            .into_iter()
+            .find(|diagnostic| diagnostic.code != "E0004")
+        {
+            return Err(diagnostic);
+        }
+        match token.token_type {
+            TokenType::Literal(LiteralToken::Number(number)) if scanner.end_of_input() => {
+                Ok(number)
+            }
+            _ => Err(Diagnostic::error(
+                "E0005",
+                format!("invalid number literal `{value}`"),
+                TokenSpan {
+                    start: 0,
+                    end: value.len(),
+                    line: 0,
+                    utf16_start: 0,
+                    utf16_end: value.encode_utf16().count(),
+                },
+            )),
         }
-        NumberToken::Float(value.parse::<f64>().unwrap())
     }
 }
 
@@ -36,24 +175,78 @@ impl Scanner {
             input: input.to_string(),
             cursor_start: 0,
             cursor_end: 0,
+            utf16_start: 0,
+            utf16_end: 0,
             current_line: 0,
+            diagnostics: Vec::new(),
+            confusables: ConfusableIdentifiers::default(),
+            examined_end: Cell::new(0),
+            template_braces: Vec::new(),
         }
     }
+
+    // A scanner that starts at the token boundary `offset` (with the matching
+    // UTF-16 offset and zero-based line) rather than at the beginning. The
+    // boundary must lie outside any template interpolation.
+    pub fn resume(input: &str, offset: usize, utf16_offset: usize, line: usize) -> Self {
+        Self {
+            cursor_start: offset,
+            cursor_end: offset,
+            utf16_start: utf16_offset,
+            utf16_end: utf16_offset,
+            current_line: line,
+            ..Self::new(input)
+        }
+    }
+
+    // Exclusive end of the input the last token's scan looked at, counting
+    // lookahead past the token; one past the end of the input if it looked
+    // for more. Editing anything before this offset may change the token.
+    pub fn examined_end(&self) -> usize {
+        self.examined_end.get()
+    }
+
+    // Whether the next token starts outside any template interpolation, so
+    // scanning could restart there without knowing what came before
+    pub fn at_top_level(&self) -> bool {
+        self.template_braces.is_empty()
+    }
+
+    // Problems found so far. Scanning always continues past them: the
+    // offending text is still returned as a token so nothing is lost.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
+    }
     fn inc(&mut self) {
-        self.cursor_end = self.cursor_end + 1;
+        if let Some(c) = self.input[self.cursor_end..].chars().next() {
+            self.cursor_end += c.len_utf8();
+            self.utf16_end += c.len_utf16();
+        }
     }
     fn new_line(&mut self) -> TokenType {
         self.current_line += 1;
         TokenType::WhiteSpace(WhiteSpaceToken::NewLine)
     }
-    fn dec(&mut self) {
-        self.cursor_end = self.cursor_end - 1;
-    }
-    fn current_char(&mut self) -> char {
-        self.input.chars().nth(self.cursor_end).unwrap()
+    fn current_char(&self) -> char {
+        self.peek_nth(0)
+            .expect("current_char is only called before the end of input")
     }
     fn peek(&self) -> Option<char> {
-        self.input.chars().nth(self.cursor_end + 1)
+        self.peek_nth(1)
+    }
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        let next = self.input[self.cursor_end..].char_indices().nth(n);
+        let examined = next.map_or(self.input.len() + 1, |(offset, c)| {
+            self.cursor_end + offset + c.len_utf8()
+        });
+        if examined > self.examined_end.get() {
+            self.examined_end.set(examined);
+        }
+        next.map(|(_, c)| c)
     }
 
     fn eof_token(&mut self) -> Token {
@@ -67,136 +260,685 @@ impl Scanner {
                 start: self.cursor_start,
                 end: self.cursor_end,
                 line: self.current_line,
+                utf16_start: self.utf16_start,
+                utf16_end: self.utf16_end,
             },
         )
     }
+    // Span from the start of the token being scanned through the character
+    // under the cursor
+    fn pending_span(&self) -> TokenSpan {
+        let (len_utf8, len_utf16) = self
+            .peek_nth(0)
+            .map_or((0, 0), |c| (c.len_utf8(), c.len_utf16()));
+        TokenSpan {
+            start: self.cursor_start,
+            end: self.cursor_end + len_utf8,
+            line: self.current_line,
+            utf16_start: self.utf16_start,
+            utf16_end: self.utf16_end + len_utf16,
+        }
+    }
+
+    // Span of the input between two byte offsets within the token being
+    // scanned
+    fn span_between(&self, start: usize, end: usize) -> TokenSpan {
+        let utf16 = |offset: usize| {
+            self.utf16_start + self.input[self.cursor_start..offset].encode_utf16().count()
+        };
+        TokenSpan {
+            start,
+            end,
+            line: self.current_line,
+            utf16_start: utf16(start),
+            utf16_end: utf16(end),
+        }
+    }
+
     pub fn end_of_input(&self) -> bool {
         self.cursor_end >= self.input.len()
     }
 
+    // Span from `start` through the character under the cursor
+    fn span_from(&self, start: usize) -> TokenSpan {
+        let end = self.cursor_end + self.peek_nth(0).map_or(0, char::len_utf8);
+        self.span_between(start, end)
+    }
+
+    // Consumes up to `max` hex digits after the cursor
+    fn scan_hex_digits(&mut self, max: usize) -> String {
+        let mut digits = String::new();
+        while digits.len() < max {
+            match self.peek().filter(char::is_ascii_hexdigit) {
+                Some(digit) => {
+                    self.inc();
+                    digits.push(digit);
+                }
+                None => break,
+            }
+        }
+        digits
+    }
+
+    // Consumes the escape sequence after the `\` under the cursor and returns
+    // the character it stands for. Invalid escapes are reported with the span
+    // of the whole sequence; `None` means there is no sensible character to
+    // keep in its place.
+    fn scan_escape(&mut self) -> Option<char> {
+        let start = self.cursor_end;
+        let next_char = self.peek()?;
+        self.inc();
+        match next_char {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            'r' => Some('\r'),
+            '0' => Some('\0'),
+            '\\' | '"' | '\'' | '`' | '$' => Some(next_char),
+            'x' => {
+                let digits = self.scan_hex_digits(2);
+                if digits.len() == 2 {
+                    let byte = u8::from_str_radix(&digits, 16).expect("two hex digits fit a byte");
+                    return Some(char::from(byte));
+                }
+                let span = self.span_from(start);
+                self.diagnostics.push(
+                    Diagnostic::error("E0007", "invalid `\\x` escape", span)
+                        .with_note("`\\x` takes exactly two hex digits, e.g. `\\x41`"),
+                );
+                None
+            }
+            'u' => self.scan_unicode_escape(start),
+            other => {
+                if other == '\n' {
+                    self.current_line += 1;
+                }
+                let span = self.span_from(start);
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        "E0006",
+                        format!("unknown escape sequence `\\{}`", other.escape_debug()),
+                        span,
+                    )
+                    .with_note(
+                        "valid escapes are `\\n`, `\\t`, `\\r`, `\\0`, `\\\\`, `\\\"`, `\\'`, \
+                         `` \\` ``, `\\$`, `\\x41` and `\\u{1F600}`",
+                    )
+                    .with_note("write a raw string such as `r\"C:\\path\"` to keep backslashes"),
+                );
+                Some(other)
+            }
+        }
+    }
+
+    // `\u{1F600}`: one to six hex digits naming a Unicode scalar value. The
+    // cursor is on the `u`.
+    fn scan_unicode_escape(&mut self, start: usize) -> Option<char> {
+        let syntax_note = "write unicode escapes as `\\u{...}` with 1 to 6 hex digits";
+        if self.peek() != Some('{') {
+            let span = self.span_from(start);
+            self.diagnostics.push(
+                Diagnostic::error("E0007", "invalid unicode escape", span).with_note(syntax_note),
+            );
+            return None;
+        }
+        self.inc();
+        let digits = self.scan_hex_digits(usize::MAX);
+        if self.peek() != Some('}') || digits.is_empty() || digits.len() > 6 {
+            if self.peek() == Some('}') {
+                self.inc();
+            }
+            let span = self.span_from(start);
+            self.diagnostics.push(
+                Diagnostic::error("E0007", "invalid unicode escape", span).with_note(syntax_note),
+            );
+            return None;
+        }
+        self.inc();
+        let value = u32::from_str_radix(&digits, 16).expect("at most six hex digits fit a u32");
+        if let Some(c) = char::from_u32(value) {
+            return Some(c);
+        }
+        let span = self.span_from(start);
+        let diagnostic = if (0xD800..=0xDFFF).contains(&value) {
+            Diagnostic::error(
+                "E0007",
+                format!("unicode escape `\\u{{{digits}}}` is a surrogate"),
+                span,
+            )
+            .with_note("surrogate code points cannot appear in strings on their own")
+        } else {
+            Diagnostic::error(
+                "E0007",
+                format!("unicode escape `\\u{{{digits}}}` is out of range"),
+                span,
+            )
+            .with_note("the largest code point is `\\u{10FFFF}`")
+        };
+        self.diagnostics.push(diagnostic);
+        None
+    }
+
+    fn unterminated_string(&mut self, closing: &str) {
+        let span = self.pending_span();
+        let opening = TokenSpan {
+            end: span.start + 1,
+            utf16_end: span.utf16_start + 1,
+            ..span.clone()
+        };
+        self.diagnostics.push(
+            Diagnostic::error("E0001", "unterminated string literal", span)
+                .with_label(opening, "string starts here")
+                .with_note(format!("add a closing {closing} to end the string")),
+        );
+    }
+
     fn tokenize_string_literal(&mut self, delimiter: char) -> TokenType {
         let mut string_value = String::new();
-        let mut is_escaped = false;
+        let mut terminated = false;
 
         while let Some(next_char) = self.peek() {
             self.inc(); // Consume the current character
 
-            if is_escaped {
-                // Handle escaped characters
-                let escaped_char = match next_char {
-                    'n' => '\n',
-                    't' => '\t',
-                    'r' => '\r',
-                    '\\' => '\\',
-                    '"' => '"',
-                    '\'' => '\'',
-                    '`' => '`',
-                    // For any other character after \, just use the character itself
-                    _ => next_char,
-                };
-                string_value.push(escaped_char);
-                is_escaped = false;
-            } else if next_char == '\\' {
-                is_escaped = true;
+            if next_char == '\\' {
+                string_value.extend(self.scan_escape());
             } else if next_char == delimiter {
+                terminated = true;
                 break;
             } else {
+                if next_char == '\n' {
+                    self.current_line += 1;
+                }
                 string_value.push(next_char);
             }
         }
 
+        if !terminated {
+            self.unterminated_string(&delimiter.to_string());
+        }
+
         TokenType::Literal(LiteralToken::String(string_value))
     }
 
+    // Raw strings keep every character as written: `r"C:\dir"` is the text
+    // `C:\dir`. Any number of `#` may follow the `r` and must then follow the
+    // closing quote too, so `r#"say "hi""#` can contain the quote itself. The
+    // cursor is on the `r`.
+    fn tokenize_raw_string(&mut self) -> TokenType {
+        let mut hashes = 0;
+        while self.peek() == Some('#') {
+            self.inc();
+            hashes += 1;
+        }
+        self.inc();
+        let delimiter = self.current_char();
+        let closing = format!("{delimiter}{}", "#".repeat(hashes));
 
-    pub fn next_token(&mut self) -> Token {
-        //First, make sure it's not the end of input
-        let token = if self.end_of_input() {
-            self.eof_token()
-        } else {
-            // If the next character is alphanumeric, it's a word token
-            let mut word = String::new();
-            while !self.end_of_input() {
-                let current_char = self.current_char();
-                word.push(current_char);
-                if current_char.is_alphanumeric() {
-                    if let Some(peeked) = self.peek() {
-                        if !peeked.is_whitespace() {
-                            self.inc();
-                        } else {
-                            break;
-                        }
-                    } else {
+        let mut string_value = String::new();
+        let mut terminated = false;
+        while let Some(next_char) = self.peek() {
+            self.inc();
+            if next_char == delimiter
+                && (1..=hashes).all(|offset| self.peek_nth(offset) == Some('#'))
+            {
+                for _ in 0..hashes {
+                    self.inc();
+                }
+                terminated = true;
+                break;
+            }
+            if next_char == '\n' {
+                self.current_line += 1;
+            }
+            string_value.push(next_char);
+        }
+
+        if !terminated {
+            self.unterminated_string(&closing);
+        }
+
+        TokenType::Literal(LiteralToken::String(string_value))
+    }
+
+    // `r` followed by optional `#`s and a quote
+    fn at_raw_string(&self) -> bool {
+        if self.current_char() != 'r' {
+            return false;
+        }
+        let hashes = self.input[self.cursor_end + 1..]
+            .chars()
+            .take_while(|c| *c == '#')
+            .count();
+        matches!(self.peek_nth(hashes + 1), Some('"' | '\''))
+    }
+
+    // Scans template text from the cursor, which is on the opening backtick or
+    // on the `}` ending an interpolation, up to the next `${` or the closing
+    // backtick. Template text may span lines.
+    fn tokenize_template(&mut self) -> TokenType {
+        let is_start = self.current_char() == '`';
+        let mut text = String::new();
+        let mut interpolation = false;
+        let mut terminated = false;
+
+        while let Some(next_char) = self.peek() {
+            self.inc();
+            if next_char == '\n' {
+                self.current_line += 1;
+            }
+
+            if next_char == '\\' {
+                text.extend(self.scan_escape());
+            } else if next_char == '$' && self.peek() == Some('{') {
+                self.inc();
+                self.template_braces.push(0);
+                interpolation = true;
+                break;
+            } else if next_char == '`' {
+                terminated = true;
+                break;
+            } else {
+                text.push(next_char);
+            }
+        }
+
+        if !interpolation && !terminated {
+            self.unterminated_string("`");
+        }
+
+        match (is_start, interpolation) {
+            (true, true) => TokenType::Template(TemplateToken::Start(text)),
+            (true, false) => TokenType::Literal(LiteralToken::String(text)),
+            (false, true) => TokenType::Template(TemplateToken::Middle(text)),
+            (false, false) => TokenType::Template(TemplateToken::End(text)),
+        }
+    }
+
+    // `//` line comments, `///` doc comments and `/* */` block comments, which
+    // nest. The token keeps the text between the delimiters.
+    fn tokenize_comment(&mut self) -> TokenType {
+        self.inc(); // Consume the first `/`
+        if self.current_char() == '/' {
+            // `////` and longer rules are plain comments, not docs
+            let is_doc = self.peek() == Some('/') && self.peek_nth(2) != Some('/');
+            if is_doc {
+                self.inc();
+            }
+            let mut text = String::new();
+            while let Some(next_char) = self.peek().filter(|c| *c != '\n') {
+                self.inc();
+                text.push(next_char);
+            }
+            return TokenType::Comment(if is_doc {
+                CommentToken::Doc(text)
+            } else {
+                CommentToken::Line(text)
+            });
+        }
+
+        let mut text = String::new();
+        let mut depth = 1;
+        loop {
+            match (self.peek(), self.peek_nth(2)) {
+                (Some('*'), Some('/')) => {
+                    self.inc();
+                    self.inc();
+                    depth -= 1;
+                    if depth == 0 {
                         break;
                     }
-                } else {
+                    text.push_str("*/");
+                }
+                (Some('/'), Some('*')) => {
+                    self.inc();
+                    self.inc();
+                    depth += 1;
+                    text.push_str("/*");
+                }
+                (Some(next_char), _) => {
+                    self.inc();
+                    if next_char == '\n' {
+                        self.current_line += 1;
+                    }
+                    text.push(next_char);
+                }
+                (None, _) => {
+                    let span = self.pending_span();
+                    let opening = TokenSpan {
+                        end: span.start + 2,
+                        utf16_end: span.utf16_start + 2,
+                        ..span.clone()
+                    };
+                    self.diagnostics.push(
+                        Diagnostic::error("E0003", "unterminated block comment", span)
+                            .with_label(opening, "comment starts here")
+                            .with_note("block comments nest, so every `/*` needs its own `*/`"),
+                    );
                     break;
                 }
             }
-            let token_type = match word.as_str() {
-                // Declarations
-                "let" => TokenType::Declaration(DeclarationToken::Let),
-                "fn" => TokenType::Declaration(DeclarationToken::Function),
-                "obj" => TokenType::Declaration(DeclarationToken::Object),
-                // Literals
-                "true" => TokenType::Literal(LiteralToken::Boolean(true)),
-                "false" => TokenType::Literal(LiteralToken::Boolean(false)),
-                "null" => TokenType::Literal(LiteralToken::Null),
-                "undefined" => TokenType::Literal(LiteralToken::Undefined),
-                // Object References
-                "this" => TokenType::ObjectReference(ObjectReferenceToken::This),
-                "super" => TokenType::ObjectReference(ObjectReferenceToken::Super),
-                "new" => TokenType::ObjectReference(ObjectReferenceToken::New),
-                // Control Flow
-                "if" => TokenType::ControlFlow(ControlFlowToken::If),
-                "for" => TokenType::ControlFlow(ControlFlowToken::For),
-                "else" => TokenType::ControlFlow(ControlFlowToken::Else),
-                "in" => TokenType::ControlFlow(ControlFlowToken::In),
-                "has" => TokenType::ControlFlow(ControlFlowToken::Has),
-                "return" => TokenType::ControlFlow(ControlFlowToken::Return),
-                "\"" => self.tokenize_string_literal('"'),
-                "'" => self.tokenize_string_literal('\''),
-                "`" => self.tokenize_string_literal('`'),
-                " " => TokenType::WhiteSpace(WhiteSpaceToken::Space),
-                "\t" => TokenType::WhiteSpace(WhiteSpaceToken::Tab),
-                "\n" => self.new_line(),
-                "(" => TokenType::Delimiter(DelimiterToken::OpenParenthesis),
-                ")" => TokenType::Delimiter(DelimiterToken::CloseParenthesis),
-                "[" => TokenType::Delimiter(DelimiterToken::OpenBrace),
-                "]" => TokenType::Delimiter(DelimiterToken::CloseBrace),
-                "{" => TokenType::Delimiter(DelimiterToken::OpenBracket),
-                "}" => TokenType::Delimiter(DelimiterToken::CloseBracket),
-                "," => TokenType::Punctuation(PunctuatorToken::Comma),
-                "." => TokenType::Punctuation(PunctuatorToken::Dot),
-                ":" => TokenType::Punctuation(PunctuatorToken::Colon),
-                ";" => TokenType::Punctuation(PunctuatorToken::Semicolon),
-                "+" => TokenType::Arithmetic(ArithmeticToken::Add),
-                "-" => TokenType::Arithmetic(ArithmeticToken::Subtract),
-                "=" => TokenType::Assignment(AssignmentToken::Assign),
-                "/" => TokenType::Arithmetic(ArithmeticToken::Divide),
-                "&" => TokenType::Arithmetic(ArithmeticToken::And),
-                "|" => TokenType::Arithmetic(ArithmeticToken::Or),
-                "*" => TokenType::Arithmetic(ArithmeticToken::Multiply),
-                "!" => TokenType::Comparison(ComparisonToken::Not),
-                ">" => TokenType::Comparison(ComparisonToken::GreaterThan),
-                "<" => TokenType::Comparison(ComparisonToken::LessThan),
-                // Default - Identifier
-                _ => {
-                    let token = if let Ok(num) = word.parse::<i64>() {
-                        TokenType::Literal(LiteralToken::Number(NumberToken::SignedInteger(num)))
-                    } else if let Ok(num) = word.parse::<f64>() {
-                        TokenType::Literal(LiteralToken::Number(NumberToken::Float(num)))
-                    } else {
-                        TokenType::Identifier(IdentifierToken { value: word })
-                    };
-                    token
+        }
+        TokenType::Comment(CommentToken::Block(text))
+    }
+
+    // Consumes digits of `radix` and `_` separators after the cursor
+    fn scan_digits(&mut self, radix: u32) {
+        while self.peek().is_some_and(|c| c == '_' || c.is_digit(radix)) {
+            self.inc();
+        }
+    }
+
+    // Decimal literals with an optional fraction and exponent (`1_000`,
+    // `1.5`, `2e-3`), `0x`, `0o` and `0b` integers, and an `n` suffix on any
+    // integer for a BigInt. Malformed literals still produce a number token
+    // so parsing can continue.
+    fn tokenize_number(&mut self) -> TokenType {
+        let (radix, base_name) = match (self.current_char(), self.peek()) {
+            ('0', Some('x' | 'X')) => (16, "hexadecimal"),
+            ('0', Some('o' | 'O')) => (8, "octal"),
+            ('0', Some('b' | 'B')) => (2, "binary"),
+            _ => (10, "decimal"),
+        };
+        let mut is_float = false;
+        if radix == 10 {
+            self.scan_digits(10);
+            // A `.` only starts a fraction when a digit follows, so `1.max`
+            // stays a member access
+            if self.peek() == Some('.') && self.peek_nth(2).is_some_and(|c| c.is_ascii_digit()) {
+                is_float = true;
+                self.inc();
+                self.scan_digits(10);
+            }
+            let exponent_digits = match (self.peek_nth(2), self.peek_nth(3)) {
+                (Some('+' | '-'), Some(c)) | (Some(c), _) => c.is_ascii_digit(),
+                _ => false,
+            };
+            if matches!(self.peek(), Some('e' | 'E')) && exponent_digits {
+                is_float = true;
+                self.inc();
+                if matches!(self.peek(), Some('+' | '-')) {
+                    self.inc();
                 }
+                self.scan_digits(10);
+            }
+        } else {
+            self.inc(); // Consume the radix prefix
+            self.scan_digits(radix);
+        }
+        let prefix_len = if radix == 10 { 0 } else { 2 };
+        let literal_end = self.cursor_end + 1;
+        let literal = self.input[self.cursor_start..literal_end].to_string();
+
+        let is_big = self.peek() == Some('n');
+        if is_big {
+            self.inc();
+        }
+        // Letters and digits glued to the literal belong to it, so `0b102`
+        // and `12px` are reported rather than split into several tokens
+        let suffix_start = self.cursor_end + 1;
+        while self.peek().is_some_and(|c| c == '_' || c.is_alphanumeric()) {
+            self.inc();
+        }
+        let suffix_end = self.cursor_end + self.current_char().len_utf8();
+
+        // A `_` must sit between two digits
+        for (offset, _) in literal.match_indices('_') {
+            let is_digit = |c: Option<char>| c.is_some_and(|c| c.is_digit(radix));
+            let before = literal[..offset].chars().next_back();
+            let after = literal[offset + 1..].chars().next();
+            if offset <= prefix_len || !is_digit(before) || !is_digit(after) {
+                let start = self.cursor_start + offset;
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        "E0005",
+                        "numeric separator must sit between two digits",
+                        self.span_between(start, start + 1),
+                    )
+                    .with_note("remove the `_`"),
+                );
+            }
+        }
+        if suffix_start < suffix_end {
+            let suffix = &self.input[suffix_start..suffix_end];
+            let first = suffix.chars().next().unwrap_or_default();
+            let diagnostic = if first.is_ascii_digit() && !is_big {
+                Diagnostic::error(
+                    "E0005",
+                    format!("invalid digit `{first}` in {base_name} literal"),
+                    self.span_between(suffix_start, suffix_start + 1),
+                )
+            } else {
+                Diagnostic::error(
+                    "E0005",
+                    format!("invalid suffix `{suffix}` on number literal"),
+                    self.span_between(suffix_start, suffix_end),
+                )
             };
-            let token = self.capture_token(token_type);
+            self.diagnostics.push(diagnostic);
+        }
+
+        let digits: String = literal[prefix_len..]
+            .chars()
+            .filter(|c| *c != '_')
+            .collect();
+        let literal_span = self.span_between(self.cursor_start, literal_end);
+        let number = if digits.is_empty() {
+            // `0o8` already reported its invalid digit
+            if suffix_start == suffix_end {
+                self.diagnostics.push(Diagnostic::error(
+                    "E0005",
+                    format!("missing digits after `{literal}`"),
+                    literal_span,
+                ));
+            }
+            NumberToken::SignedInteger(0)
+        } else if is_float {
+            if is_big {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        "E0005",
+                        "a BigInt literal cannot have a fraction or exponent",
+                        self.span_between(self.cursor_start, suffix_start),
+                    )
+                    .with_note("remove the `n` suffix"),
+                );
+            }
+            NumberToken::Float(digits.parse().unwrap_or(f64::NAN))
+        } else {
+            let big = BigInt::parse_bytes(digits.as_bytes(), radix)
+                .expect("scanned digits are valid for their radix");
+            if is_big {
+                NumberToken::BigInt(big)
+            } else if let Some(integer) = big.to_i64() {
+                NumberToken::SignedInteger(integer)
+            } else {
+                self.diagnostics.push(
+                    Diagnostic::error("E0004", "integer literal is too large", literal_span)
+                        .with_note(format!(
+                            "integers are 64-bit, ranging from {} to {}",
+                            i64::MIN,
+                            i64::MAX
+                        ))
+                        .with_note(format!(
+                            "add an `n` suffix for an arbitrary-precision integer: `{literal}n`"
+                        )),
+                );
+                NumberToken::Float(big.to_f64().unwrap_or(f64::INFINITY))
+            }
+        };
+        TokenType::Literal(LiteralToken::Number(number))
+    }
+
+    // UAX #31 identifiers, plus `_` and `$` anywhere for JS interop
+    fn is_identifier_start(c: char) -> bool {
+        c == '_' || c == '$' || unicode_ident::is_xid_start(c)
+    }
+
+    fn is_identifier_continue(c: char) -> bool {
+        c == '$' || unicode_ident::is_xid_continue(c)
+    }
+
+    // Normalizes the identifier just scanned to NFC, so names that look the
+    // same compare equal, and warns about names that mix scripts or could be
+    // mistaken for a different identifier seen earlier
+    fn identifier(&mut self, word: &str) -> TokenType {
+        let value: String = word.nfc().collect();
+        if !value.is_ascii() {
+            let span = self.pending_span();
+            if !value.as_str().is_single_script() {
+                self.diagnostics.push(
+                    Diagnostic::warning(
+                        "W0001",
+                        format!("identifier `{value}` mixes characters from different scripts"),
+                        span.clone(),
+                    )
+                    .with_note("mixed-script names can look like a different identifier"),
+                );
+            }
+        }
+        let span = self.pending_span();
+        if let Some(diagnostic) = self.confusables.check(&value, span) {
+            self.diagnostics.push(diagnostic);
+        }
+        TokenType::Identifier(IdentifierToken { value })
+    }
+
+    fn is_operator_start(c: char) -> bool {
+        matches!(
+            c,
+            '+' | '-' | '*' | '/' | '%' | '&' | '|' | '=' | '!' | '>' | '<'
+        )
+    }
+
+    // Maximal munch: the longest operator in OPERATORS that matches the input
+    // at the cursor wins, so `&&=` is never split into `&&` and `=`.
+    fn tokenize_operator(&mut self) -> TokenType {
+        let (text, token_type) = OPERATORS
+            .iter()
+            .find(|(text, _)| {
+                text.chars()
+                    .enumerate()
+                    .all(|(offset, c)| self.peek_nth(offset) == Some(c))
+            })
+            .expect("operator start characters always match a single-character operator");
+        // Leave the cursor on the last character of the operator
+        for _ in 1..text.len() {
             self.inc();
-            token
+        }
+        token_type.clone()
+    }
+
+    // Keywords share the identifier character class and are told apart once
+    // the whole word is scanned
+    fn keyword(word: &str) -> Option<TokenType> {
+        Some(match word {
+            // Declarations
+            "let" => TokenType::Declaration(DeclarationToken::Let),
+            "fn" => TokenType::Declaration(DeclarationToken::Function),
+            "obj" => TokenType::Declaration(DeclarationToken::Object),
+            "export" => TokenType::Declaration(DeclarationToken::Export),
+            // Literals
+            "true" => TokenType::Literal(LiteralToken::Boolean(true)),
+            "false" => TokenType::Literal(LiteralToken::Boolean(false)),
+            "null" => TokenType::Literal(LiteralToken::Null),
+            "undefined" => TokenType::Literal(LiteralToken::Undefined),
+            // Object References
+            "this" => TokenType::ObjectReference(ObjectReferenceToken::This),
+            "super" => TokenType::ObjectReference(ObjectReferenceToken::Super),
+            "new" => TokenType::ObjectReference(ObjectReferenceToken::New),
+            // Control Flow
+            "if" => TokenType::ControlFlow(ControlFlowToken::If),
+            "for" => TokenType::ControlFlow(ControlFlowToken::For),
+            "else" => TokenType::ControlFlow(ControlFlowToken::Else),
+            "in" => TokenType::ControlFlow(ControlFlowToken::In),
+            "has" => TokenType::ControlFlow(ControlFlowToken::Has),
+            "return" => TokenType::ControlFlow(ControlFlowToken::Return),
+            // Word operators
+            "and" => TokenType::Logical(LogicalToken::And),
+            "or" => TokenType::Logical(LogicalToken::Or),
+            "not" => TokenType::Logical(LogicalToken::Not),
+            "xor" => TokenType::Logical(LogicalToken::XOr),
+            "xand" => TokenType::Logical(LogicalToken::XAnd),
+            _ => return None,
+        })
+    }
+
+    // An identifier or keyword runs for as long as identifier characters
+    // follow, so any punctuation, operator or whitespace ends it
+    fn tokenize_word(&mut self) -> TokenType {
+        let mut word = self.current_char().to_string();
+        while let Some(next_char) = self.peek().filter(|c| Self::is_identifier_continue(*c)) {
+            self.inc();
+            word.push(next_char);
+        }
+        Self::keyword(&word).unwrap_or_else(|| self.identifier(&word))
+    }
+
+    // Dispatches on the class of the character under the cursor. Each
+    // tokenizer leaves the cursor on the last character of its token, never
+    // looking past it for whitespace, so `let x=1;` scans the same as
+    // `let x = 1 ;`.
+    pub fn next_token(&mut self) -> Token {
+        self.examined_end.set(self.cursor_start);
+        //First, make sure it's not the end of input
+        if self.end_of_input() {
+            return self.eof_token();
+        }
+        let token_type = match self.current_char() {
+            '/' if matches!(self.peek(), Some('/' | '*')) => self.tokenize_comment(),
+            '`' => self.tokenize_template(),
+            '}' if self.template_braces.last() == Some(&0) => {
+                self.template_braces.pop();
+                self.tokenize_template()
+            }
+            '"' => self.tokenize_string_literal('"'),
+            '\'' => self.tokenize_string_literal('\''),
+            'r' if self.at_raw_string() => self.tokenize_raw_string(),
+            c if c.is_ascii_digit() => self.tokenize_number(),
+            c if Self::is_operator_start(c) => self.tokenize_operator(),
+            c if Self::is_identifier_start(c) => self.tokenize_word(),
+            ' ' => TokenType::WhiteSpace(WhiteSpaceToken::Space),
+            '\t' => TokenType::WhiteSpace(WhiteSpaceToken::Tab),
+            '\n' => self.new_line(),
+            // A CRLF line ending is a single newline token
+            '\r' if self.peek() == Some('\n') => {
+                self.inc();
+                self.new_line()
+            }
+            '(' => TokenType::Delimiter(DelimiterToken::OpenParenthesis),
+            ')' => TokenType::Delimiter(DelimiterToken::CloseParenthesis),
+            '[' => TokenType::Delimiter(DelimiterToken::OpenBracket),
+            ']' => TokenType::Delimiter(DelimiterToken::CloseBracket),
+            '{' => {
+                if let Some(depth) = self.template_braces.last_mut() {
+                    *depth += 1;
+                }
+                TokenType::Delimiter(DelimiterToken::OpenBrace)
+            }
+            '}' => {
+                if let Some(depth) = self.template_braces.last_mut() {
+                    *depth -= 1;
+                }
+                TokenType::Delimiter(DelimiterToken::CloseBrace)
+            }
+            ',' => TokenType::Punctuation(PunctuatorToken::Comma),
+            '.' => TokenType::Punctuation(PunctuatorToken::Dot),
+            ':' => TokenType::Punctuation(PunctuatorToken::Colon),
+            ';' => TokenType::Punctuation(PunctuatorToken::Semicolon),
+            other => {
+                // A lone character no rule accepts; keep it as a token so the
+                // rest of the input still scans
+                self.diagnostics.push(Diagnostic::error(
+                    "E0002",
+                    format!("unexpected character `{other}`"),
+                    self.pending_span(),
+                ));
+                TokenType::Unknown(other)
+            }
         };
+        self.inc();
+        let token = self.capture_token(token_type);
         self.cursor_start = self.cursor_end;
+        self.utf16_start = self.utf16_end;
         token
     }
 }
@@ -1,10 +1,34 @@
 // toy-lang/src/lib.rs
 
-mod lexer;
-mod token;
-
-use lexer::Scanner;
-use token::Token;
+pub mod compiler;
+pub mod cst;
+pub mod delimiter;
+pub mod diagnostic;
+pub mod document;
+pub mod format;
+pub mod incremental;
+pub mod interpreter;
+pub mod js;
+pub mod lexer;
+pub mod link;
+pub mod lint;
+pub mod parser;
+pub mod render;
+pub mod resolve;
+pub mod runtime;
+pub mod source_map;
+mod test;
+pub mod token;
+pub mod types;
+pub mod vm;
+pub mod wasm;
+
+use delimiter::DelimiterChecker;
+use diagnostic::Diagnostic;
+use incremental::IncrementalLexer;
+use render::{render_all, RenderStyle};
+use source_map::SourceFile;
+use token::{Token, TokenSpan};
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
@@ -21,7 +45,8 @@ macro_rules! console_log {
 
 #[wasm_bindgen]
 pub struct Tokenizer {
-    scanner: Scanner,
+    lexer: IncrementalLexer,
+    delimiters: DelimiterChecker,
     tokens: Vec<JsValue>,
 }
 
@@ -31,7 +56,8 @@ impl Tokenizer {
     pub fn new(input: &str) -> Self {
         console_log!("Creating new tokenizer with input: {}", input);
         Self {
-            scanner: Scanner::new(input),
+            lexer: IncrementalLexer::new(input),
+            delimiters: DelimiterChecker::new(),
             tokens: Vec::new(),
         }
     }
@@ -39,13 +65,110 @@ impl Tokenizer {
     #[wasm_bindgen]
     pub fn tokenize(&mut self) -> Vec<JsValue> {
         // Convert tokens to JsValue for JavaScript
-        let mut tokens = Vec::new();
-        for token in &mut self.scanner {
-            let token_js = token_to_js_value(&token);
-            tokens.push(token_js);
+        self.tokens = self.lexer.tokens().map(token_to_js_value).collect();
+        self.check_delimiters();
+        self.tokens.clone()
+    }
+
+    // Replaces the UTF-16 range `start..end` of the source with `text`,
+    // re-scanning only the tokens the edit can affect. Returns the index of
+    // the first changed token, how many old tokens were deleted there and the
+    // tokens inserted in their place; later tokens only move.
+    //
+    // Only scanning is incremental. Delimiter balance and confusable
+    // identifiers still take a pass over every token after each edit, since
+    // an edit anywhere can change them to the end of the file; those passes
+    // only look at token kinds and cached skeletons, but they do grow with
+    // the document.
+    #[wasm_bindgen]
+    pub fn apply_edit(&mut self, start: usize, end: usize, text: &str) -> Result<JsValue, JsValue> {
+        let source = self.lexer.source();
+        let (Some(byte_start), Some(byte_end)) =
+            (utf16_to_byte(source, start), utf16_to_byte(source, end))
+        else {
+            return Err(JsValue::from(format!(
+                "invalid edit range {start}..{end}: not on a character boundary"
+            )));
+        };
+        let synced = self.tokens.len() == self.lexer.token_count();
+        let edit = self
+            .lexer
+            .apply_edit(byte_start, byte_end, text)
+            .map_err(JsValue::from)?;
+
+        let inserted: Vec<JsValue> = self
+            .lexer
+            .tokens()
+            .skip(edit.start)
+            .take(edit.inserted)
+            .map(token_to_js_value)
+            .collect();
+        if synced {
+            let removed = edit.start..edit.start + edit.removed;
+            self.tokens.splice(removed, inserted.iter().cloned());
+        } else {
+            self.tokens = self.lexer.tokens().map(token_to_js_value).collect();
+        }
+        self.check_delimiters();
+
+        let obj = js_sys::Object::new();
+        js_sys::Reflect::set(&obj, &"start".into(), &JsValue::from(edit.start as u32)).unwrap();
+        js_sys::Reflect::set(&obj, &"deleted".into(), &JsValue::from(edit.removed as u32)).unwrap();
+        let tokens = js_sys::Array::new();
+        for token in &inserted {
+            tokens.push(token);
         }
-        self.tokens = tokens.clone();
-        tokens
+        js_sys::Reflect::set(&obj, &"tokens".into(), &tokens).unwrap();
+        Ok(obj.into())
+    }
+
+    // Delimiter balance depends on every token, so it is checked afresh over
+    // the whole token list after each change
+    fn check_delimiters(&mut self) {
+        self.delimiters = DelimiterChecker::new();
+        for token in self.lexer.tokens() {
+            self.delimiters.feed(token);
+        }
+        let source = self.lexer.source();
+        let end = source.len();
+        let utf16_end = source.encode_utf16().count();
+        self.delimiters.finish(&TokenSpan {
+            start: end,
+            end,
+            line: source.matches('\n').count(),
+            utf16_start: utf16_end,
+            utf16_end,
+        });
+    }
+
+    // Problems in the current source. Tokenizing never stops at the first
+    // problem, so these accompany a complete token list.
+    #[wasm_bindgen]
+    pub fn diagnostics(&self) -> Vec<JsValue> {
+        self.all_diagnostics()
+            .iter()
+            .map(diagnostic_to_js_value)
+            .collect()
+    }
+
+    // The same diagnostics rendered with source snippets, as plain text or
+    // as HTML for the visualizer
+    #[wasm_bindgen]
+    pub fn render_diagnostics(&self, html: bool) -> String {
+        let style = if html {
+            RenderStyle::Html
+        } else {
+            RenderStyle::Plain
+        };
+        let file = SourceFile::new("input.toy", self.lexer.source());
+        render_all(&self.all_diagnostics(), &file, style)
+    }
+
+    // Lexical problems followed by unbalanced delimiters
+    fn all_diagnostics(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = self.lexer.diagnostics();
+        diagnostics.extend(self.delimiters.diagnostics().iter().cloned());
+        diagnostics
     }
 
     #[wasm_bindgen]
@@ -54,35 +177,158 @@ impl Tokenizer {
     }
 }
 
-// Convert Token to a JavaScript-friendly format
-fn token_to_js_value(token: &Token) -> JsValue {
-    use token::TokenType;
+// The byte offset of a UTF-16 offset into `source`, if it falls between
+// characters
+fn utf16_to_byte(source: &str, utf16_offset: usize) -> Option<usize> {
+    let mut utf16 = 0;
+    for (offset, c) in source.char_indices() {
+        if utf16 >= utf16_offset {
+            return (utf16 == utf16_offset).then_some(offset);
+        }
+        utf16 += c.len_utf16();
+    }
+    (utf16 == utf16_offset).then_some(source.len())
+}
 
-    // Create a JS object for the token
+// The formatted source, or the errors that stopped it being formatted
+#[wasm_bindgen]
+pub fn format_source(input: &str) -> Result<String, JsValue> {
+    format::format(input).map_err(|diagnostics| {
+        diagnostics
+            .iter()
+            .map(diagnostic_to_js_value)
+            .collect::<js_sys::Array>()
+            .into()
+    })
+}
+
+// `{ code, sourceMap }` for the ES module generated from `input`, a file
+// named `name`, or the errors that stopped it compiling
+#[wasm_bindgen]
+pub fn compile_to_js(input: &str, name: &str) -> Result<JsValue, JsValue> {
+    let mut parser = parser::Parser::new(lexer::Scanner::new(input));
+    let program = parser.parse_program().ok();
+    let mut diagnostics = parser.diagnostics().to_vec();
+    let resolution = program.as_ref().map(|program| {
+        let (resolution, resolve_diagnostics) = resolve::resolve(program);
+        diagnostics.extend(resolve_diagnostics);
+        diagnostics.extend(types::check(program, &resolution).1);
+        resolution
+    });
+    let errors: Vec<&Diagnostic> = diagnostics.iter().filter(|d| d.is_error()).collect();
+    let (Some(program), Some(resolution), true) = (program, resolution, errors.is_empty()) else {
+        return Err(errors
+            .into_iter()
+            .map(diagnostic_to_js_value)
+            .collect::<js_sys::Array>()
+            .into());
+    };
+    let module = js::generate(&program, &resolution, &SourceFile::new(name, input));
     let obj = js_sys::Object::new();
+    js_sys::Reflect::set(&obj, &"code".into(), &JsValue::from(module.code)).unwrap();
+    js_sys::Reflect::set(&obj, &"sourceMap".into(), &JsValue::from(module.source_map)).unwrap();
+    Ok(obj.into())
+}
 
-    // Set token span properties
+fn span_to_js_value(token_span: &TokenSpan) -> JsValue {
     let span = js_sys::Object::new();
     js_sys::Reflect::set(
         &span,
         &"start".into(),
-        &JsValue::from(token.token_span.start as u32),
+        &JsValue::from(token_span.start as u32),
     )
     .unwrap();
+    js_sys::Reflect::set(&span, &"end".into(), &JsValue::from(token_span.end as u32)).unwrap();
     js_sys::Reflect::set(
         &span,
-        &"end".into(),
-        &JsValue::from(token.token_span.end as u32),
+        &"line".into(),
+        &JsValue::from(token_span.line as u32),
     )
     .unwrap();
     js_sys::Reflect::set(
         &span,
-        &"line".into(),
-        &JsValue::from(token.token_span.line as u32),
+        &"utf16Start".into(),
+        &JsValue::from(token_span.utf16_start as u32),
     )
     .unwrap();
+    js_sys::Reflect::set(
+        &span,
+        &"utf16End".into(),
+        &JsValue::from(token_span.utf16_end as u32),
+    )
+    .unwrap();
+    span.into()
+}
+
+// Convert a Diagnostic to a JavaScript-friendly format
+fn diagnostic_to_js_value(diagnostic: &Diagnostic) -> JsValue {
+    let obj = js_sys::Object::new();
+    js_sys::Reflect::set(
+        &obj,
+        &"severity".into(),
+        &JsValue::from(diagnostic.severity.to_string()),
+    )
+    .unwrap();
+    js_sys::Reflect::set(&obj, &"code".into(), &JsValue::from(diagnostic.code)).unwrap();
+    js_sys::Reflect::set(&obj, &"message".into(), &JsValue::from(&diagnostic.message)).unwrap();
+    js_sys::Reflect::set(&obj, &"span".into(), &span_to_js_value(&diagnostic.span)).unwrap();
+
+    let labels = js_sys::Array::new();
+    for label in &diagnostic.labels {
+        let label_obj = js_sys::Object::new();
+        js_sys::Reflect::set(&label_obj, &"span".into(), &span_to_js_value(&label.span)).unwrap();
+        js_sys::Reflect::set(
+            &label_obj,
+            &"message".into(),
+            &JsValue::from(&label.message),
+        )
+        .unwrap();
+        labels.push(&label_obj);
+    }
+    js_sys::Reflect::set(&obj, &"labels".into(), &labels).unwrap();
+
+    let notes = js_sys::Array::new();
+    for note in &diagnostic.notes {
+        notes.push(&JsValue::from(note));
+    }
+    js_sys::Reflect::set(&obj, &"notes".into(), &notes).unwrap();
+
+    let suggestions = js_sys::Array::new();
+    for suggestion in &diagnostic.suggestions {
+        let suggestion_obj = js_sys::Object::new();
+        js_sys::Reflect::set(
+            &suggestion_obj,
+            &"message".into(),
+            &JsValue::from(&suggestion.message),
+        )
+        .unwrap();
+        js_sys::Reflect::set(
+            &suggestion_obj,
+            &"span".into(),
+            &span_to_js_value(&suggestion.span),
+        )
+        .unwrap();
+        js_sys::Reflect::set(
+            &suggestion_obj,
+            &"replacement".into(),
+            &JsValue::from(&suggestion.replacement),
+        )
+        .unwrap();
+        suggestions.push(&suggestion_obj);
+    }
+    js_sys::Reflect::set(&obj, &"suggestions".into(), &suggestions).unwrap();
+
+    obj.into()
+}
+
+// Convert Token to a JavaScript-friendly format
+fn token_to_js_value(token: &Token) -> JsValue {
+    use token::TokenType;
+
+    // Create a JS object for the token
+    let obj = js_sys::Object::new();
 
-    js_sys::Reflect::set(&obj, &"span".into(), &span).unwrap();
+    js_sys::Reflect::set(&obj, &"span".into(), &span_to_js_value(&token.token_span)).unwrap();
 
     // Set the token type and value
     match &token.token_type {
@@ -110,6 +356,14 @@ fn token_to_js_value(token: &Token) -> JsValue {
                             js_sys::Reflect::set(&obj, &"numberType".into(), &"float".into())
                                 .unwrap();
                         }
+                        // Sent as a decimal string, since a JS number would
+                        // lose precision
+                        token::NumberToken::BigInt(n) => {
+                            js_sys::Reflect::set(&obj, &"value".into(), &n.to_string().into())
+                                .unwrap();
+                            js_sys::Reflect::set(&obj, &"numberType".into(), &"bigint".into())
+                                .unwrap();
+                        }
                     }
                 }
                 token::LiteralToken::String(s) => {
@@ -130,9 +384,33 @@ fn token_to_js_value(token: &Token) -> JsValue {
                 }
             }
         }
+        TokenType::Template(template) => {
+            js_sys::Reflect::set(&obj, &"type".into(), &"template".into()).unwrap();
+
+            let (part_str, text) = match template {
+                token::TemplateToken::Start(text) => ("start", text),
+                token::TemplateToken::Middle(text) => ("middle", text),
+                token::TemplateToken::End(text) => ("end", text),
+            };
+
+            js_sys::Reflect::set(&obj, &"templatePart".into(), &JsValue::from(part_str)).unwrap();
+            js_sys::Reflect::set(&obj, &"value".into(), &JsValue::from(text)).unwrap();
+        }
         TokenType::WhiteSpace(_) => {
             js_sys::Reflect::set(&obj, &"type".into(), &"whitespace".into()).unwrap();
         }
+        TokenType::Comment(comment) => {
+            js_sys::Reflect::set(&obj, &"type".into(), &"comment".into()).unwrap();
+
+            let (comment_str, text) = match comment {
+                token::CommentToken::Line(text) => ("line", text),
+                token::CommentToken::Block(text) => ("block", text),
+                token::CommentToken::Doc(text) => ("doc", text),
+            };
+
+            js_sys::Reflect::set(&obj, &"commentType".into(), &JsValue::from(comment_str)).unwrap();
+            js_sys::Reflect::set(&obj, &"value".into(), &JsValue::from(text)).unwrap();
+        }
         TokenType::Delimiter(delim) => {
             js_sys::Reflect::set(&obj, &"type".into(), &"delimiter".into()).unwrap();
 
@@ -174,8 +452,6 @@ fn token_to_js_value(token: &Token) -> JsValue {
                 token::ArithmeticToken::Divide => "divide",
                 token::ArithmeticToken::BitwiseAnd => "bitwiseAnd",
                 token::ArithmeticToken::BitwiseOr => "bitwiseOr",
-                token::ArithmeticToken::Or => "or",
-                token::ArithmeticToken::And => "and",
                 token::ArithmeticToken::Modulo => "modulo",
             };
 
@@ -236,6 +512,7 @@ fn token_to_js_value(token: &Token) -> JsValue {
                 token::DeclarationToken::Let => "let",
                 token::DeclarationToken::Function => "function",
                 token::DeclarationToken::Object => "object",
+                token::DeclarationToken::Export => "export",
             };
 
             js_sys::Reflect::set(&obj, &"declarationType".into(), &JsValue::from(decl_str))
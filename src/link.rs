@@ -0,0 +1,408 @@
+use crate::diagnostic::Diagnostic;
+use crate::document::{Document, Section};
+use crate::lexer::Scanner;
+use crate::parser::{Program, StmtKind};
+use crate::resolve::{resolve_section, Resolution};
+use crate::token::*;
+
+// Sections of a document read from each other as `section.member`. The
+// Readme describes what each kind of section exports.
+
+// A name one section makes available to the others: a toy-lang declaration
+// marked `export`, or a name another language's `export` declares
+#[derive(Debug, PartialEq, Clone)]
+pub struct Export {
+    pub name: String,
+    pub span: TokenSpan,
+}
+
+// Section `from` reading `member` of section `to`, both indices into
+// `Document::sections`. The span covers the whole `to.member`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Reference {
+    pub from: usize,
+    pub to: usize,
+    pub member: String,
+    pub span: TokenSpan,
+}
+
+// How the sections of a document use each other. Everything is indexed like
+// `Document::sections`.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Links {
+    // None for sections whose exports could not be worked out because they
+    // have syntax errors
+    pub exports: Vec<Option<Vec<Export>>>,
+    // Each toy-lang section's names, for those that parsed
+    pub resolutions: Vec<Option<Resolution>>,
+    pub references: Vec<Reference>,
+    // The sections with each one after all those it reads from, or None
+    // when some of them read from each other in a cycle
+    pub order: Option<Vec<usize>>,
+}
+
+// Works out what every section of `document` exports and reads from the
+// others. Toy-lang sections are resolved with the other sections in scope;
+// in other languages, `section.member` is found among the tokens. Reports
+// reads of names a section does not export and sections that depend on each
+// other in a cycle.
+pub fn link(document: &Document) -> (Links, Vec<Diagnostic>) {
+    let mut diagnostics = Vec::new();
+    let exports: Vec<_> = document
+        .sections
+        .iter()
+        .map(|section| match &section.program {
+            Some(program) => Some(toy_exports(program)),
+            None if section.is_toy() => None,
+            None => Some(foreign_exports(&tokens(document, section))),
+        })
+        .collect();
+    let mut resolutions = Vec::new();
+    let mut references = Vec::new();
+    for (index, section) in document.sections.iter().enumerate() {
+        let others: Vec<(usize, &str)> = document
+            .sections
+            .iter()
+            .enumerate()
+            .filter(|(other, _)| *other != index)
+            .filter_map(|(other, section)| Some((other, section.name()?)))
+            .collect();
+        let section_index = |name: &str| {
+            others
+                .iter()
+                .find(|(_, other)| *other == name)
+                .map(|(other, _)| *other)
+        };
+        let Some(program) = &section.program else {
+            resolutions.push(None);
+            if !section.is_toy() {
+                let tokens = tokens(document, section);
+                for (to, member) in foreign_references(&tokens, |name| section_index(name)) {
+                    let exported = exports[to].as_ref().is_none_or(|exports| {
+                        exports.iter().any(|export| export.name == member.value)
+                    });
+                    if !exported {
+                        diagnostics.push(missing_export(document, to, &member));
+                    }
+                    references.push(Reference {
+                        from: index,
+                        to,
+                        member: member.value,
+                        span: member.span,
+                    });
+                }
+            }
+            continue;
+        };
+        let scope: Vec<_> = others
+            .iter()
+            .map(|(other, name)| (*name, exports[*other].as_deref()))
+            .collect();
+        let (resolution, resolve_diagnostics) = resolve_section(program, &scope);
+        diagnostics.extend(resolve_diagnostics);
+        for import in &resolution.imports {
+            let name = &resolution.declaration(import.section).name;
+            if let Some(to) = section_index(name) {
+                references.push(Reference {
+                    from: index,
+                    to,
+                    member: import.member.name.clone(),
+                    span: import.span.clone(),
+                });
+            }
+        }
+        resolutions.push(Some(resolution));
+    }
+    let order = order(document, &references, &mut diagnostics);
+    diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+    let links = Links {
+        exports,
+        resolutions,
+        references,
+        order,
+    };
+    (links, diagnostics)
+}
+
+fn missing_export(document: &Document, section: usize, member: &Member) -> Diagnostic {
+    let name = document.sections[section].name().unwrap_or_default();
+    Diagnostic::error(
+        "E0303",
+        format!("section `{name}` does not export `{}`", member.value),
+        member.name_span.clone(),
+    )
+}
+
+// The declarations marked `export`, as the JavaScript module exports
+fn toy_exports(program: &Program) -> Vec<Export> {
+    program
+        .statements
+        .iter()
+        .filter(|statement| statement.export.is_some())
+        .filter_map(|statement| match &statement.kind {
+            StmtKind::Let { name, .. } | StmtKind::Object { name, .. } => Some(name),
+            StmtKind::Function(function) => function.name.as_ref(),
+            _ => None,
+        })
+        .map(|name| Export {
+            name: name.name.clone(),
+            span: name.span.clone(),
+        })
+        .collect()
+}
+
+// The tokens of a section in another language, as far as the toy-lang
+// scanner can make them out, leaving out whitespace and comments. Its
+// diagnostics are of no interest here.
+fn tokens(document: &Document, section: &Section) -> Vec<Token> {
+    let end = section.offset + section.source.len();
+    let mut scanner = Scanner::resume(
+        &document.text[..end],
+        section.offset,
+        section.utf16_offset,
+        section.line,
+    );
+    let mut tokens = Vec::new();
+    loop {
+        let token = scanner.next_token();
+        match token.token_type {
+            TokenType::Delimiter(DelimiterToken::EOF) => return tokens,
+            TokenType::WhiteSpace(_) | TokenType::Comment(_) => {}
+            _ => tokens.push(token),
+        }
+    }
+}
+
+fn identifier(token: Option<&Token>) -> Option<&str> {
+    match &token?.token_type {
+        TokenType::Identifier(identifier) => Some(&identifier.value),
+        _ => None,
+    }
+}
+
+// Words that may come between `export` and the name it declares
+const EXPORT_MODIFIERS: [&str; 9] = [
+    "abstract",
+    "async",
+    "class",
+    "const",
+    "enum",
+    "function",
+    "interface",
+    "type",
+    "var",
+];
+
+// The names declared by JavaScript-style `export` statements:
+// `export const a`, `export function b`, `export class C` and
+// `export { d, e as f }`. Default exports have no name to read.
+fn foreign_exports(tokens: &[Token]) -> Vec<Export> {
+    let mut exports = Vec::new();
+    let export = |token: &Token| Export {
+        name: identifier(Some(token)).unwrap_or_default().to_string(),
+        span: token.token_span.clone(),
+    };
+    for (index, _) in tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| token.token_type == TokenType::Declaration(DeclarationToken::Export))
+    {
+        let mut next = index + 1;
+        if tokens.get(next).map(|token| &token.token_type)
+            == Some(&TokenType::Delimiter(DelimiterToken::OpenBrace))
+        {
+            next += 1;
+            while identifier(tokens.get(next)).is_some() {
+                let mut exported = &tokens[next];
+                if identifier(tokens.get(next + 1)) == Some("as")
+                    && identifier(tokens.get(next + 2)).is_some()
+                {
+                    next += 2;
+                    exported = &tokens[next];
+                }
+                exports.push(export(exported));
+                next += 1;
+                if tokens.get(next).map(|token| &token.token_type)
+                    != Some(&TokenType::Punctuation(PunctuatorToken::Comma))
+                {
+                    break;
+                }
+                next += 1;
+            }
+            continue;
+        }
+        while let Some(token) = tokens.get(next) {
+            let modifier = match &token.token_type {
+                TokenType::Identifier(identifier) => {
+                    EXPORT_MODIFIERS.contains(&identifier.value.as_str())
+                }
+                TokenType::Declaration(DeclarationToken::Let)
+                | TokenType::Arithmetic(ArithmeticToken::Multiply) => true,
+                _ => false,
+            };
+            if !modifier {
+                break;
+            }
+            next += 1;
+        }
+        match identifier(tokens.get(next)) {
+            Some("default") | None => {}
+            Some(_) => exports.push(export(&tokens[next])),
+        }
+    }
+    exports
+}
+
+// A member read from another section, found among the tokens
+struct Member {
+    value: String,
+    // Of the member's name, and of the whole `section.member`
+    name_span: TokenSpan,
+    span: TokenSpan,
+}
+
+// Every `name.member` whose `name` is another section, by `section_index`,
+// and is not itself a member of something else
+fn foreign_references(
+    tokens: &[Token],
+    section_index: impl Fn(&str) -> Option<usize>,
+) -> Vec<(usize, Member)> {
+    let dot = TokenType::Punctuation(PunctuatorToken::Dot);
+    let mut references = Vec::new();
+    for (index, token) in tokens.iter().enumerate() {
+        let Some(to) = identifier(Some(token)).and_then(&section_index) else {
+            continue;
+        };
+        let after_dot = index > 0 && tokens[index - 1].token_type == dot;
+        let followed_by_dot = tokens.get(index + 1).map(|token| &token.token_type) == Some(&dot);
+        match identifier(tokens.get(index + 2)) {
+            Some(member) if followed_by_dot && !after_dot => references.push((
+                to,
+                Member {
+                    value: member.to_string(),
+                    name_span: tokens[index + 2].token_span.clone(),
+                    span: token.token_span.to(&tokens[index + 2].token_span),
+                },
+            )),
+            _ => {}
+        }
+    }
+    references
+}
+
+// The sections in an order where each comes after those it reads from.
+// Reports the cycles that make that impossible.
+fn order(
+    document: &Document,
+    references: &[Reference],
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<Vec<usize>> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum State {
+        New,
+        Visiting,
+        Done,
+    }
+
+    struct Walk<'a> {
+        document: &'a Document,
+        references: &'a [Reference],
+        states: Vec<State>,
+        // The references followed to reach the section being visited
+        path: Vec<&'a Reference>,
+        order: Vec<usize>,
+        cycles: Vec<Diagnostic>,
+    }
+
+    impl<'a> Walk<'a> {
+        fn visit(&mut self, section: usize) {
+            self.states[section] = State::Visiting;
+            let mut targets: Vec<usize> = Vec::new();
+            for reference in self.references.iter().filter(|r| r.from == section) {
+                // The first read of each section stands for the rest
+                if targets.contains(&reference.to) {
+                    continue;
+                }
+                targets.push(reference.to);
+                match self.states[reference.to] {
+                    State::New => {
+                        self.path.push(reference);
+                        self.visit(reference.to);
+                        self.path.pop();
+                    }
+                    State::Visiting => self.cycle(reference),
+                    State::Done => {}
+                }
+            }
+            self.states[section] = State::Done;
+            self.order.push(section);
+        }
+
+        // Reports the cycle that `closing` completes back to the section
+        // being visited
+        fn cycle(&mut self, closing: &'a Reference) {
+            let start = self
+                .path
+                .iter()
+                .position(|reference| reference.from == closing.to)
+                .unwrap_or(self.path.len());
+            let cycle: Vec<&Reference> = self.path[start..]
+                .iter()
+                .copied()
+                .chain([closing])
+                .collect();
+            let name = |section: usize| {
+                self.document.sections[section]
+                    .name()
+                    .unwrap_or_default()
+                    .to_string()
+            };
+            let names: Vec<String> = cycle
+                .iter()
+                .map(|reference| format!("`{}`", name(reference.from)))
+                .chain([format!("`{}`", name(closing.to))])
+                .collect();
+            let mut diagnostic = Diagnostic::error(
+                "E0604",
+                format!(
+                    "sections read from each other in a cycle: {}",
+                    names.join(" -> ")
+                ),
+                cycle[0].span.clone(),
+            );
+            for reference in &cycle[1..] {
+                diagnostic = diagnostic.with_label(
+                    reference.span.clone(),
+                    format!(
+                        "`{}` reads from `{}` here",
+                        name(reference.from),
+                        name(reference.to)
+                    ),
+                );
+            }
+            self.cycles.push(
+                diagnostic
+                    .with_note("a section cannot read from a section that reads back from it"),
+            );
+        }
+    }
+
+    let mut walk = Walk {
+        document,
+        references,
+        states: vec![State::New; document.sections.len()],
+        path: Vec::new(),
+        order: Vec::new(),
+        cycles: Vec::new(),
+    };
+    for section in 0..document.sections.len() {
+        if walk.states[section] == State::New {
+            walk.visit(section);
+        }
+    }
+    if walk.cycles.is_empty() {
+        return Some(walk.order);
+    }
+    diagnostics.extend(walk.cycles);
+    None
+}
@@ -0,0 +1,596 @@
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::parser::*;
+use crate::token::*;
+use std::collections::HashMap;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl Level {
+    fn parse(level: &str) -> Option<Self> {
+        match level {
+            "allow" => Some(Level::Allow),
+            "warn" => Some(Level::Warn),
+            "deny" => Some(Level::Deny),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Rule {
+    pub code: &'static str,
+    // What the rule is called in `toy-lang.toml`
+    pub name: &'static str,
+    pub default: Level,
+    pub summary: &'static str,
+}
+
+pub const UNUSED_LET: Rule = Rule {
+    code: "L0001",
+    name: "unused-let",
+    default: Level::Warn,
+    summary: "a `let` binding that is never read",
+};
+pub const SHADOWING: Rule = Rule {
+    code: "L0002",
+    name: "shadowing",
+    default: Level::Warn,
+    summary: "a declaration reusing a name that is already in scope",
+};
+pub const UNREACHABLE_CODE: Rule = Rule {
+    code: "L0003",
+    name: "unreachable-code",
+    default: Level::Warn,
+    summary: "statements after a `return`",
+};
+pub const NULL_COMPARISON: Rule = Rule {
+    code: "L0004",
+    name: "null-comparison",
+    default: Level::Warn,
+    summary: "`==` or `!=` against only one of `null` and `undefined`",
+};
+pub const ASSIGNMENT_IN_CONDITION: Rule = Rule {
+    code: "L0005",
+    name: "assignment-in-condition",
+    default: Level::Deny,
+    summary: "`=` where `==` was probably meant in an `if` condition",
+};
+pub const XAND: Rule = Rule {
+    code: "L0006",
+    name: "xand",
+    default: Level::Warn,
+    summary: "the easily misread `xand` operator",
+};
+
+pub const RULES: [&Rule; 6] = [
+    &UNUSED_LET,
+    &SHADOWING,
+    &UNREACHABLE_CODE,
+    &NULL_COMPARISON,
+    &ASSIGNMENT_IN_CONDITION,
+    &XAND,
+];
+
+// Which rules are on, and how loudly, keyed by rule code. Rules not
+// mentioned keep their default level.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct LintConfig {
+    levels: HashMap<&'static str, Level>,
+}
+
+impl LintConfig {
+    pub fn level(&self, rule: &Rule) -> Level {
+        self.levels.get(rule.code).copied().unwrap_or(rule.default)
+    }
+
+    // Sets the level of the rule with the given name or code
+    pub fn set(&mut self, rule: &str, level: Level) -> Result<(), String> {
+        let rule = RULES
+            .iter()
+            .find(|candidate| candidate.name == rule || candidate.code == rule)
+            .ok_or_else(|| format!("unknown lint `{rule}`"))?;
+        self.levels.insert(rule.code, level);
+        Ok(())
+    }
+
+    // Reads the `[lints]` table of a `toy-lang.toml`, where each key names a
+    // rule and each value is "allow", "warn" or "deny":
+    //
+    //   [lints]
+    //   unused-let = "allow"
+    //   shadowing = "deny"
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        let table: toml::Table = text.parse().map_err(|error| format!("{error}"))?;
+        let mut config = LintConfig::default();
+        let Some(lints) = table.get("lints") else {
+            return Ok(config);
+        };
+        let lints = lints
+            .as_table()
+            .ok_or("`lints` must be a table of rule levels")?;
+        for (rule, level) in lints {
+            let level = level.as_str().and_then(Level::parse).ok_or_else(|| {
+                format!("the level of `{rule}` must be \"allow\", \"warn\" or \"deny\"")
+            })?;
+            config.set(rule, level)?;
+        }
+        Ok(config)
+    }
+}
+
+// Runs every enabled rule over `program`, which was parsed from `source`
+pub fn lint(program: &Program, source: &str, config: &LintConfig) -> Vec<Diagnostic> {
+    let mut linter = Linter {
+        source,
+        config,
+        scopes: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+    linter.scope(|linter| linter.statements(&program.statements));
+    linter
+        .diagnostics
+        .sort_by_key(|diagnostic| diagnostic.span.start);
+    linter.diagnostics
+}
+
+// Applies the first suggestion of each diagnostic, skipping any that
+// overlap an edit already made
+pub fn apply_suggestions(source: &str, diagnostics: &[Diagnostic]) -> String {
+    let mut suggestions: Vec<_> = diagnostics
+        .iter()
+        .filter_map(|diagnostic| diagnostic.suggestions.first())
+        .collect();
+    suggestions.sort_by_key(|suggestion| (suggestion.span.start, suggestion.span.end));
+    let mut fixed = String::with_capacity(source.len());
+    let mut copied = 0;
+    for suggestion in suggestions {
+        if suggestion.span.start < copied {
+            continue;
+        }
+        fixed.push_str(&source[copied..suggestion.span.start]);
+        fixed.push_str(&suggestion.replacement);
+        copied = suggestion.span.end;
+    }
+    fixed.push_str(&source[copied..]);
+    fixed
+}
+
+#[derive(PartialEq)]
+enum BindingKind {
+    Let,
+    // Functions, objects, parameters, loop variables and exported variables,
+    // which other sections may read
+    Other,
+}
+
+struct Binding {
+    name: String,
+    span: TokenSpan,
+    kind: BindingKind,
+    used: bool,
+}
+
+#[derive(Default)]
+struct Scope<'a> {
+    bindings: Vec<Binding>,
+    // Function bodies are checked once the rest of the scope they are
+    // defined in has been, since they may read names declared after them
+    functions: Vec<&'a Function>,
+}
+
+struct Linter<'a> {
+    source: &'a str,
+    config: &'a LintConfig,
+    scopes: Vec<Scope<'a>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+// `value == null`, `undefined != value` and so on, as the compared value,
+// the literal and the operator
+fn null_comparison(expr: &Expr) -> Option<(&Expr, &LiteralToken, &ComparisonToken)> {
+    let ExprKind::Binary {
+        operator:
+            BinaryOperator::Comparison(
+                comparison @ (ComparisonToken::Equal | ComparisonToken::NotEqual),
+            ),
+        left,
+        right,
+        ..
+    } = &expr.kind
+    else {
+        return None;
+    };
+    for (checked, other) in [(right, left), (left, right)] {
+        if let ExprKind::Literal(literal @ (LiteralToken::Null | LiteralToken::Undefined)) =
+            &checked.kind
+        {
+            return Some((other, literal, comparison));
+        }
+    }
+    None
+}
+
+// A variable or a chain of fields read from one, such as `a.b.c`, which can
+// be written out twice without changing what the code does
+fn is_path(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Identifier(_) | ExprKind::ObjectReference(_) => true,
+        ExprKind::Member { object, .. } => is_path(object),
+        _ => false,
+    }
+}
+
+impl<'a> Linter<'a> {
+    // `x == null || x == undefined` and `x != null && x != undefined`
+    // already cover both
+    fn checks_null_and_undefined(&self, expr: &Expr) -> bool {
+        let ExprKind::Binary {
+            operator,
+            left,
+            right,
+            ..
+        } = &expr.kind
+        else {
+            return false;
+        };
+        let (
+            Some((left_value, left_literal, left_comparison)),
+            Some((right_value, right_literal, right_comparison)),
+        ) = (null_comparison(left), null_comparison(right))
+        else {
+            return false;
+        };
+        let joined = match operator {
+            BinaryOperator::Logical(LogicalToken::Or) => ComparisonToken::Equal,
+            BinaryOperator::Logical(LogicalToken::And) => ComparisonToken::NotEqual,
+            _ => return false,
+        };
+        *left_comparison == joined
+            && left_comparison == right_comparison
+            && left_literal != right_literal
+            && self.text(&left_value.span) == self.text(&right_value.span)
+    }
+
+    // A diagnostic for `rule` at its configured severity, or None when the
+    // rule is off
+    fn diagnostic(&self, rule: &Rule, message: String, span: TokenSpan) -> Option<Diagnostic> {
+        let severity = match self.config.level(rule) {
+            Level::Allow => return None,
+            Level::Warn => Severity::Warning,
+            Level::Deny => Severity::Error,
+        };
+        Some(
+            Diagnostic::new(severity, rule.code, message, span)
+                .with_note(format!("lint `{}`: {}", rule.name, rule.summary)),
+        )
+    }
+
+    fn text(&self, span: &TokenSpan) -> &'a str {
+        &self.source[span.start..span.end]
+    }
+
+    fn scope(&mut self, body: impl FnOnce(&mut Self)) {
+        self.scopes.push(Scope::default());
+        body(self);
+        while let Some(function) = self
+            .scopes
+            .last_mut()
+            .and_then(|scope| scope.functions.pop())
+        {
+            self.function_body(function);
+        }
+        let scope = self.scopes.pop().expect("pushed above");
+        for binding in scope.bindings {
+            if binding.kind == BindingKind::Let && !binding.used && !binding.name.starts_with('_') {
+                let replacement = format!("_{}", binding.name);
+                if let Some(diagnostic) = self.diagnostic(
+                    &UNUSED_LET,
+                    format!("unused variable `{}`", binding.name),
+                    binding.span.clone(),
+                ) {
+                    self.diagnostics.push(diagnostic.with_suggestion(
+                        "if this is intentional, prefix it with an underscore",
+                        binding.span,
+                        replacement,
+                    ));
+                }
+            }
+        }
+    }
+
+    fn declare(&mut self, name: &Identifier, kind: BindingKind) {
+        let previous = self
+            .scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.bindings.iter().rev())
+            .find(|binding| binding.name == name.name && binding.span.start < name.span.start)
+            .map(|binding| binding.span.clone());
+        if let Some(previous) = previous.filter(|_| !name.name.starts_with('_')) {
+            if let Some(diagnostic) = self.diagnostic(
+                &SHADOWING,
+                format!("`{}` shadows an earlier declaration", name.name),
+                name.span.clone(),
+            ) {
+                self.diagnostics
+                    .push(diagnostic.with_label(previous, "previously declared here"));
+            }
+        }
+        self.scopes
+            .last_mut()
+            .expect("declarations happen inside a scope")
+            .bindings
+            .push(Binding {
+                name: name.name.clone(),
+                span: name.span.clone(),
+                kind,
+                used: false,
+            });
+    }
+
+    fn read(&mut self, name: &str) {
+        if let Some(binding) = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .flat_map(|scope| scope.bindings.iter_mut().rev())
+            .find(|binding| binding.name == name)
+        {
+            binding.used = true;
+        }
+    }
+
+    fn statements(&mut self, statements: &'a [Stmt]) {
+        if let Some(position) = statements.iter().position(Stmt::always_returns) {
+            if let (Some(first), Some(last)) = (statements.get(position + 1), statements.last()) {
+                let returning = &statements[position].span;
+                let removed = TokenSpan {
+                    start: returning.end,
+                    end: last.span.end,
+                    line: returning.line,
+                    utf16_start: returning.utf16_end,
+                    utf16_end: last.span.utf16_end,
+                };
+                if let Some(diagnostic) = self.diagnostic(
+                    &UNREACHABLE_CODE,
+                    "unreachable code".to_string(),
+                    first.span.to(&last.span),
+                ) {
+                    self.diagnostics.push(
+                        diagnostic
+                            .with_label(returning.clone(), "any code after this is unreachable")
+                            .with_suggestion("remove the unreachable code", removed, ""),
+                    );
+                }
+            }
+        }
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn block(&mut self, block: &'a Block) {
+        self.scope(|linter| linter.statements(&block.statements));
+    }
+
+    fn defer_function(&mut self, function: &'a Function) {
+        self.scopes
+            .last_mut()
+            .expect("functions are defined inside a scope")
+            .functions
+            .push(function);
+    }
+
+    // Parameters and body statements share one scope, as when called
+    fn function_body(&mut self, function: &'a Function) {
+        self.scope(|linter| {
+            for parameter in &function.parameters {
+                linter.declare(&parameter.name, BindingKind::Other);
+            }
+            linter.statements(&function.body.statements);
+        });
+    }
+
+    fn statement(&mut self, statement: &'a Stmt) {
+        match &statement.kind {
+            StmtKind::Let { name, value, .. } => {
+                // The value is evaluated before the name exists
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+                let kind = match statement.export {
+                    Some(_) => BindingKind::Other,
+                    None => BindingKind::Let,
+                };
+                self.declare(name, kind);
+            }
+            StmtKind::Function(function) => {
+                if let Some(name) = &function.name {
+                    self.declare(name, BindingKind::Other);
+                }
+                self.defer_function(function);
+            }
+            StmtKind::Object { name, fields, .. } => {
+                self.fields(fields);
+                self.declare(name, BindingKind::Other);
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.condition(condition);
+                self.expression(condition);
+                self.block(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            StmtKind::For {
+                binding,
+                iterable,
+                body,
+            } => {
+                self.expression(iterable);
+                self.scope(|linter| {
+                    linter.declare(binding, BindingKind::Other);
+                    linter.statements(&body.statements);
+                });
+            }
+            StmtKind::Return(value) => {
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+            StmtKind::Block(block) => self.block(block),
+            StmtKind::Expression(expr) => self.expression(expr),
+        }
+    }
+
+    fn condition(&mut self, condition: &Expr) {
+        if let ExprKind::Assign {
+            operator: AssignmentToken::Assign,
+            operator_span,
+            ..
+        } = &condition.kind
+        {
+            if let Some(diagnostic) = self.diagnostic(
+                &ASSIGNMENT_IN_CONDITION,
+                "assignment in `if` condition".to_string(),
+                operator_span.clone(),
+            ) {
+                self.diagnostics.push(diagnostic.with_suggestion(
+                    "to compare the values instead",
+                    operator_span.clone(),
+                    "==",
+                ));
+            }
+        }
+    }
+
+    fn fields(&mut self, fields: &'a [ObjectField]) {
+        for field in fields {
+            self.expression(&field.value);
+        }
+    }
+
+    fn expression(&mut self, expr: &'a Expr) {
+        match &expr.kind {
+            ExprKind::Literal(_) | ExprKind::ObjectReference(_) => {}
+            ExprKind::Identifier(name) => self.read(name),
+            ExprKind::New { callee, arguments } | ExprKind::Call { callee, arguments } => {
+                self.expression(callee);
+                for argument in arguments {
+                    self.expression(argument);
+                }
+            }
+            ExprKind::Object(fields) => self.fields(fields),
+            ExprKind::Function(function) => self.defer_function(function),
+            ExprKind::Template { expressions, .. } => {
+                for expression in expressions {
+                    self.expression(expression);
+                }
+            }
+            ExprKind::Unary { operand, .. } => self.expression(operand),
+            ExprKind::Binary {
+                operator,
+                left,
+                right,
+                ..
+            } => {
+                if self.checks_null_and_undefined(expr) {
+                    for side in [left, right] {
+                        if let Some((other, _, _)) = null_comparison(side) {
+                            self.expression(other);
+                        }
+                    }
+                } else {
+                    self.binary(expr, operator, left, right);
+                    self.expression(left);
+                    self.expression(right);
+                }
+            }
+            ExprKind::Assign {
+                operator,
+                target,
+                value,
+                ..
+            } => {
+                // Plain assignment to a variable does not read it
+                match (&target.kind, operator) {
+                    (ExprKind::Identifier(_), AssignmentToken::Assign) => {}
+                    _ => self.expression(target),
+                }
+                self.expression(value);
+            }
+            ExprKind::Member { object, .. } => self.expression(object),
+            ExprKind::Index { object, index } => {
+                self.expression(object);
+                self.expression(index);
+            }
+        }
+    }
+
+    fn binary(&mut self, expr: &Expr, operator: &BinaryOperator, left: &Expr, right: &Expr) {
+        match operator {
+            BinaryOperator::Comparison(_) => {
+                let Some((other, literal, comparison)) = null_comparison(expr) else {
+                    return;
+                };
+                let (matched, missed) = match literal {
+                    LiteralToken::Null => ("null", "undefined"),
+                    _ => ("undefined", "null"),
+                };
+                let Some(diagnostic) = self.diagnostic(
+                    &NULL_COMPARISON,
+                    format!("comparison with `{matched}` does not match `{missed}`"),
+                    expr.span.clone(),
+                ) else {
+                    return;
+                };
+                let diagnostic = diagnostic
+                    .with_note("missing fields and arguments are `undefined`, not `null`");
+                // Anything else, such as a call, would run twice in the fix
+                if !is_path(other) {
+                    self.diagnostics.push(diagnostic);
+                    return;
+                }
+                let value = self.text(&other.span);
+                let replacement = if *comparison == ComparisonToken::Equal {
+                    format!("({value} == null || {value} == undefined)")
+                } else {
+                    format!("({value} != null && {value} != undefined)")
+                };
+                self.diagnostics.push(diagnostic.with_suggestion(
+                    "to match both, compare with each",
+                    expr.span.clone(),
+                    replacement,
+                ));
+            }
+            BinaryOperator::Logical(LogicalToken::XAnd) => {
+                let replacement = format!(
+                    "!({} xor {})",
+                    self.text(&left.span),
+                    self.text(&right.span)
+                );
+                if let Some(diagnostic) = self.diagnostic(
+                    &XAND,
+                    "`xand` is true when both sides are truthy or both are falsy".to_string(),
+                    expr.span.clone(),
+                ) {
+                    self.diagnostics.push(diagnostic.with_suggestion(
+                        "write it as a negated `xor`",
+                        expr.span.clone(),
+                        replacement,
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -1,23 +1,350 @@
-#![feature(let_chains)]
+use std::io::IsTerminal;
+use std::path::Path;
+use std::process::ExitCode;
+use std::rc::Rc;
+use toy_lang::compiler::{compile, disassemble};
+use toy_lang::diagnostic::Diagnostic;
+use toy_lang::document::parse_document;
+use toy_lang::format::format;
+use toy_lang::interpreter::{with_stack, Interpreter};
+use toy_lang::js::generate;
+use toy_lang::lexer::Scanner;
+use toy_lang::link::link;
+use toy_lang::lint::{apply_suggestions, lint, LintConfig};
+use toy_lang::parser::{Parser, Program};
+use toy_lang::render::{render, render_all, RenderStyle};
+use toy_lang::resolve::{resolve, Resolution};
+use toy_lang::source_map::SourceFile;
+use toy_lang::token::TokenType;
+use toy_lang::types::{check, Types};
+use toy_lang::vm::Vm;
+use toy_lang::wasm;
 
-use crate::lexer::Scanner;
-use crate::token::TokenType;
+fn stderr_style() -> RenderStyle {
+    if std::io::stderr().is_terminal() {
+        RenderStyle::Ansi
+    } else {
+        RenderStyle::Plain
+    }
+}
+
+// Reads, parses and resolves `path`, reporting any diagnostics. Returns None
+// if the file could not be read or has errors. Type annotations are optional,
+// so types are only checked with `typed`, for targets that need them;
+// otherwise the types are left empty.
+fn load(path: &str, typed: bool) -> Option<(SourceFile, Program, Resolution, Types)> {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("error: could not read {path}: {error}");
+            return None;
+        }
+    };
+    let mut parser = Parser::new(Scanner::new(&source));
+    // Syntax errors are among the parser's diagnostics
+    let program = parser.parse_program().ok();
+    let mut diagnostics = parser.diagnostics().to_vec();
+    // Names and types are only checked once the program parses
+    let checked = program.as_ref().map(|program| {
+        let (resolution, resolve_diagnostics) = resolve(program);
+        diagnostics.extend(resolve_diagnostics);
+        if !typed {
+            return (resolution, Types::default());
+        }
+        let (types, type_diagnostics) = check(program, &resolution);
+        diagnostics.extend(type_diagnostics);
+        (resolution, types)
+    });
+    let file = SourceFile::new(path, &source);
+    if !diagnostics.is_empty() {
+        eprintln!("{}", render_all(&diagnostics, &file, stderr_style()));
+    }
+    if diagnostics.iter().any(Diagnostic::is_error) {
+        return None;
+    }
+    let (resolution, types) = checked?;
+    Some((file, program?, resolution, types))
+}
 
-mod lexer;
-mod test;
-mod token;
+// Runs the program with the tree-walking interpreter, or with `--vm`
+// compiles it to bytecode and runs that instead. `--dump-bytecode` prints
+// the bytecode without running it.
+fn run(args: &[String]) -> ExitCode {
+    let flags = ["--vm", "--dump-bytecode"];
+    let vm = args.iter().any(|arg| arg == "--vm");
+    let dump = args.iter().any(|arg| arg == "--dump-bytecode");
+    let paths: Vec<&String> = args
+        .iter()
+        .filter(|arg| !flags.contains(&arg.as_str()))
+        .collect();
+    let [path] = paths.as_slice() else {
+        eprintln!("usage: toy-lang run [--vm] [--dump-bytecode] <file>");
+        return ExitCode::FAILURE;
+    };
+    let Some((file, program, resolution, _)) = load(path, false) else {
+        return ExitCode::FAILURE;
+    };
+    let result = if vm || dump {
+        let script = compile(&program, &resolution);
+        if dump {
+            print!("{}", disassemble(&script));
+            return ExitCode::SUCCESS;
+        }
+        Vm::default().run(Rc::new(script))
+    } else {
+        with_stack(|| Interpreter::default().run(&program))
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{}", render(&error.into(), &file, stderr_style()));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// Compiles each file for `--target`, writing the output next to it
+fn build(args: &[String]) -> ExitCode {
+    let usage = "usage: toy-lang build [--target js|wasm] <file>...";
+    let (target, paths) = match args {
+        [flag, target, paths @ ..] if flag == "--target" => (target.as_str(), paths),
+        [flag, ..] if flag == "--target" => {
+            eprintln!("{usage}");
+            return ExitCode::FAILURE;
+        }
+        paths => ("js", paths),
+    };
+    if target != "js" && target != "wasm" {
+        eprintln!("error: unknown target `{target}`, expected `js` or `wasm`");
+        return ExitCode::FAILURE;
+    }
+    if paths.is_empty() {
+        eprintln!("{usage}");
+        return ExitCode::FAILURE;
+    }
+    let mut status = ExitCode::SUCCESS;
+    for path in paths {
+        let Some((file, program, resolution, types)) = load(path, target == "wasm") else {
+            status = ExitCode::FAILURE;
+            continue;
+        };
+        let outputs = if target == "wasm" {
+            match wasm::compile(&program, &resolution, &types) {
+                Ok(module) => vec![(Path::new(path).with_extension("wasm"), module)],
+                Err(diagnostics) => {
+                    eprintln!("{}", render_all(&diagnostics, &file, stderr_style()));
+                    status = ExitCode::FAILURE;
+                    continue;
+                }
+            }
+        } else {
+            let module = generate(&program, &resolution, &file);
+            let map = format!("{}.map", module.file);
+            vec![
+                (
+                    Path::new(path).with_file_name(&module.file),
+                    module.code.into_bytes(),
+                ),
+                (
+                    Path::new(path).with_file_name(map),
+                    module.source_map.into_bytes(),
+                ),
+            ]
+        };
+        for (destination, contents) in outputs {
+            if let Err(error) = std::fs::write(&destination, contents) {
+                eprintln!("error: could not write {}: {error}", destination.display());
+                status = ExitCode::FAILURE;
+            }
+        }
+    }
+    status
+}
+
+// Checks each document: its sections, the names and types in its toy-lang
+// sections, and what the sections read from each other. Plain toy-lang files
+// are documents with a single section.
+fn check_documents(args: &[String]) -> ExitCode {
+    if args.is_empty() {
+        eprintln!("usage: toy-lang check <file>...");
+        return ExitCode::FAILURE;
+    }
+    let mut status = ExitCode::SUCCESS;
+    for path in args {
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(error) => {
+                eprintln!("error: could not read {path}: {error}");
+                status = ExitCode::FAILURE;
+                continue;
+            }
+        };
+        let (document, mut diagnostics) = parse_document(&source);
+        let (links, link_diagnostics) = link(&document);
+        diagnostics.extend(link_diagnostics);
+        for (section, resolution) in document.sections.iter().zip(&links.resolutions) {
+            if let (Some(program), Some(resolution)) = (&section.program, resolution) {
+                diagnostics.extend(check(program, resolution).1);
+            }
+        }
+        diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+        if !diagnostics.is_empty() {
+            let file = SourceFile::new(path, &source);
+            eprintln!("{}", render_all(&diagnostics, &file, stderr_style()));
+        }
+        if diagnostics.iter().any(Diagnostic::is_error) {
+            status = ExitCode::FAILURE;
+        }
+    }
+    status
+}
+
+// Rewrites each file in the standard style, or with `--check` only lists
+// the files that are not formatted and fails if there are any
+fn fmt(args: &[String]) -> ExitCode {
+    let check = args.iter().any(|arg| arg == "--check");
+    let paths: Vec<&String> = args.iter().filter(|arg| *arg != "--check").collect();
+    if paths.is_empty() {
+        eprintln!("usage: toy-lang fmt [--check] <file>...");
+        return ExitCode::FAILURE;
+    }
+    let mut status = ExitCode::SUCCESS;
+    for path in paths {
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(error) => {
+                eprintln!("error: could not read {path}: {error}");
+                status = ExitCode::FAILURE;
+                continue;
+            }
+        };
+        let formatted = match format(&source) {
+            Ok(formatted) => formatted,
+            Err(diagnostics) => {
+                let file = SourceFile::new(path, &source);
+                eprintln!("{}", render_all(&diagnostics, &file, stderr_style()));
+                status = ExitCode::FAILURE;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{path} is not formatted");
+            status = ExitCode::FAILURE;
+        } else if let Err(error) = std::fs::write(path, formatted) {
+            eprintln!("error: could not write {path}: {error}");
+            status = ExitCode::FAILURE;
+        }
+    }
+    status
+}
+
+// The `toy-lang.toml` in the directory of `path` or the nearest one above it
+fn lint_config(path: &str) -> Result<LintConfig, String> {
+    let start = std::fs::canonicalize(path).map_err(|error| format!("{path}: {error}"))?;
+    for directory in start.ancestors().skip(1) {
+        let config = directory.join("toy-lang.toml");
+        if let Ok(text) = std::fs::read_to_string(&config) {
+            return LintConfig::from_toml(&text)
+                .map_err(|error| format!("{}: {error}", config.display()));
+        }
+    }
+    Ok(LintConfig::default())
+}
+
+// Reports lint findings in each file, or with `--fix` applies their
+// suggested fixes first and reports what is left. Fails if any finding is
+// an error.
+fn lint_files(args: &[String]) -> ExitCode {
+    let fix = args.iter().any(|arg| arg == "--fix");
+    let paths: Vec<&String> = args.iter().filter(|arg| *arg != "--fix").collect();
+    if paths.is_empty() {
+        eprintln!("usage: toy-lang lint [--fix] <file>...");
+        return ExitCode::FAILURE;
+    }
+    let mut status = ExitCode::SUCCESS;
+    for path in paths {
+        let config = match lint_config(path) {
+            Ok(config) => config,
+            Err(error) => {
+                eprintln!("error: {error}");
+                status = ExitCode::FAILURE;
+                continue;
+            }
+        };
+        let Ok(mut source) = std::fs::read_to_string(path) else {
+            eprintln!("error: could not read {path}");
+            status = ExitCode::FAILURE;
+            continue;
+        };
+        let mut parser = Parser::new(Scanner::new(&source));
+        let Ok(mut program) = parser.parse_program() else {
+            let file = SourceFile::new(path, &source);
+            eprintln!(
+                "{}",
+                render_all(parser.diagnostics(), &file, stderr_style())
+            );
+            status = ExitCode::FAILURE;
+            continue;
+        };
+        let mut diagnostics = lint(&program, &source, &config);
+        if fix
+            && diagnostics
+                .iter()
+                .any(|diagnostic| !diagnostic.suggestions.is_empty())
+        {
+            source = apply_suggestions(&source, &diagnostics);
+            if let Err(error) = std::fs::write(path, &source) {
+                eprintln!("error: could not write {path}: {error}");
+                status = ExitCode::FAILURE;
+                continue;
+            }
+            match Parser::new(Scanner::new(&source)).parse_program() {
+                Ok(fixed) => program = fixed,
+                Err(error) => {
+                    eprintln!("error: {path} no longer parses after fixing: {error}");
+                    status = ExitCode::FAILURE;
+                    continue;
+                }
+            }
+            diagnostics = lint(&program, &source, &config);
+        }
+        if !diagnostics.is_empty() {
+            let file = SourceFile::new(path, &source);
+            eprintln!("{}", render_all(&diagnostics, &file, stderr_style()));
+        }
+        if diagnostics.iter().any(Diagnostic::is_error) {
+            status = ExitCode::FAILURE;
+        }
+    }
+    status
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.as_slice() {
+        [command, rest @ ..] if command == "run" => return run(rest),
+        [command, rest @ ..] if command == "build" => return build(rest),
+        [command, rest @ ..] if command == "check" => return check_documents(rest),
+        [command, rest @ ..] if command == "fmt" => return fmt(rest),
+        [command, rest @ ..] if command == "lint" => return lint_files(rest),
+        _ => {}
+    }
 
-fn main() {
     let input = r#"
 let x = "Hello World!";
 'other string';
 `third string`
 "string with 'inside string' and \"inside string\""
+let y=x+1;print(y)
 "#;
     let scanner = Scanner::new(input);
     for token in scanner {
         if !matches!(token.token_type, TokenType::WhiteSpace(_)) {
-        println!("{token:?}");
+            println!("{token:?}");
         }
     }
+    ExitCode::SUCCESS
 }
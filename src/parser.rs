@@ -0,0 +1,1059 @@
+use crate::delimiter::DelimiterChecker;
+use crate::diagnostic::Diagnostic;
+use crate::lexer::Scanner;
+use crate::token::*;
+use std::fmt;
+
+const OPEN_CURLY: TokenType = TokenType::Delimiter(DelimiterToken::OpenBrace);
+const CLOSE_CURLY: TokenType = TokenType::Delimiter(DelimiterToken::CloseBrace);
+const OPEN_SQUARE: TokenType = TokenType::Delimiter(DelimiterToken::OpenBracket);
+const CLOSE_SQUARE: TokenType = TokenType::Delimiter(DelimiterToken::CloseBracket);
+const OPEN_PAREN: TokenType = TokenType::Delimiter(DelimiterToken::OpenParenthesis);
+const CLOSE_PAREN: TokenType = TokenType::Delimiter(DelimiterToken::CloseParenthesis);
+const COMMA: TokenType = TokenType::Punctuation(PunctuatorToken::Comma);
+const COLON: TokenType = TokenType::Punctuation(PunctuatorToken::Colon);
+const SEMICOLON: TokenType = TokenType::Punctuation(PunctuatorToken::Semicolon);
+const DOT: TokenType = TokenType::Punctuation(PunctuatorToken::Dot);
+const PIPE: TokenType = TokenType::Arithmetic(ArithmeticToken::BitwiseOr);
+const EOF: TokenType = TokenType::Delimiter(DelimiterToken::EOF);
+const EXPORT: TokenType = TokenType::Declaration(DeclarationToken::Export);
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Program {
+    pub statements: Vec<Stmt>,
+    pub span: TokenSpan,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Identifier {
+    pub name: String,
+    pub span: TokenSpan,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Block {
+    pub statements: Vec<Stmt>,
+    pub span: TokenSpan,
+}
+
+// A type written after the `:` of a `let`, a parameter or a function's
+// parameter list
+#[derive(Debug, PartialEq, Clone)]
+pub struct TypeExpr {
+    pub kind: TypeExprKind,
+    pub span: TokenSpan,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum TypeExprKind {
+    // `int`, `float`, `string`, `bool`, `any` or the name of an `obj`
+    Named(String),
+    Null,
+    Undefined,
+    // `{ x: int, y: string }`
+    Object(Vec<FieldType>),
+    // `fn(int, string): bool`, returning `undefined` when no result type is
+    // given
+    Function {
+        parameters: Vec<TypeExpr>,
+        result: Option<Box<TypeExpr>>,
+    },
+    // `string | null`
+    Union(Vec<TypeExpr>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct FieldType {
+    pub key: Identifier,
+    pub annotation: TypeExpr,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Parameter {
+    pub name: Identifier,
+    pub annotation: Option<TypeExpr>,
+}
+
+// Shared by `fn name(..) {..}` declarations and anonymous `fn (..) {..}`
+// expressions
+#[derive(Debug, PartialEq, Clone)]
+pub struct Function {
+    // Text of the `///` comments directly above a declaration
+    pub doc: Option<String>,
+    pub name: Option<Identifier>,
+    pub parameters: Vec<Parameter>,
+    pub return_type: Option<Box<TypeExpr>>,
+    pub body: Block,
+    pub span: TokenSpan,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ObjectField {
+    pub key: Identifier,
+    pub value: Expr,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: TokenSpan,
+    // The `export` in front of a top-level declaration, outside `span`
+    pub export: Option<TokenSpan>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum StmtKind {
+    Let {
+        name: Identifier,
+        annotation: Option<TypeExpr>,
+        value: Option<Expr>,
+    },
+    Function(Function),
+    // `obj Name { .. }`, shorthand for binding an object literal to `Name`
+    Object {
+        doc: Option<String>,
+        name: Identifier,
+        fields: Vec<ObjectField>,
+    },
+    If {
+        condition: Expr,
+        then_branch: Block,
+        // Either another `If` (for `else if`) or a `Block`
+        else_branch: Option<Box<Stmt>>,
+    },
+    For {
+        binding: Identifier,
+        iterable: Expr,
+        body: Block,
+    },
+    Return(Option<Expr>),
+    Block(Block),
+    Expression(Expr),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: TokenSpan,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum UnaryOperator {
+    // `-`
+    Negate,
+    // `!`
+    Not,
+    // `not`, the keyword spelling of `!`
+    LogicalNot,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum BinaryOperator {
+    Arithmetic(ArithmeticToken),
+    Comparison(ComparisonToken),
+    Logical(LogicalToken),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ExprKind {
+    Literal(LiteralToken),
+    Identifier(String),
+    // `this` and `super`
+    ObjectReference(ObjectReferenceToken),
+    New {
+        callee: Box<Expr>,
+        arguments: Vec<Expr>,
+    },
+    Object(Vec<ObjectField>),
+    Function(Function),
+    // `` `a${x}b` ``: the text pieces around each interpolated expression, so
+    // there is always one more string than expression
+    Template {
+        strings: Vec<String>,
+        expressions: Vec<Expr>,
+    },
+    Unary {
+        operator: UnaryOperator,
+        operator_span: TokenSpan,
+        operand: Box<Expr>,
+    },
+    Binary {
+        operator: BinaryOperator,
+        operator_span: TokenSpan,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Assign {
+        operator: AssignmentToken,
+        operator_span: TokenSpan,
+        target: Box<Expr>,
+        value: Box<Expr>,
+    },
+    Call {
+        callee: Box<Expr>,
+        arguments: Vec<Expr>,
+    },
+    Member {
+        object: Box<Expr>,
+        property: Identifier,
+    },
+    Index {
+        object: Box<Expr>,
+        index: Box<Expr>,
+    },
+}
+
+impl Stmt {
+    // Whether running the statement always ends in a `return`
+    pub fn always_returns(&self) -> bool {
+        match &self.kind {
+            StmtKind::Return(_) => true,
+            StmtKind::Block(block) => block.always_returns(),
+            StmtKind::If {
+                then_branch,
+                else_branch: Some(else_branch),
+                ..
+            } => then_branch.always_returns() && else_branch.always_returns(),
+            _ => false,
+        }
+    }
+}
+
+impl Block {
+    pub fn always_returns(&self) -> bool {
+        self.statements.iter().any(Stmt::always_returns)
+    }
+}
+
+impl Expr {
+    fn new(kind: ExprKind, span: TokenSpan) -> Self {
+        Expr { kind, span }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseError {
+    pub message: String,
+    pub span: TokenSpan,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {}, offset {}",
+            self.message,
+            self.span.line + 1,
+            self.span.start
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<ParseError> for Diagnostic {
+    fn from(error: ParseError) -> Self {
+        Diagnostic::error("E0100", error.message, error.span)
+    }
+}
+
+pub fn parse(input: &str) -> Result<Program, ParseError> {
+    Parser::new(Scanner::new(input)).parse_program()
+}
+
+pub struct Parser {
+    scanner: Scanner,
+    current: Token,
+    // `///` comments between the previous token and `current`
+    current_docs: Vec<String>,
+    previous_span: TokenSpan,
+    // Fed every token as it is consumed
+    delimiters: DelimiterChecker,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Parser {
+    pub fn new(mut scanner: Scanner) -> Self {
+        let (current, current_docs) = Self::next_significant(&mut scanner);
+        let previous_span = current.token_span.clone();
+        let diagnostics = scanner.take_diagnostics();
+        Self {
+            scanner,
+            current,
+            current_docs,
+            previous_span,
+            delimiters: DelimiterChecker::new(),
+            diagnostics,
+        }
+    }
+
+    // Parses to the end of the input even after an error, skipping the rest
+    // of each statement that fails so later problems are reported too. Every
+    // error is recorded in `diagnostics`; the first is also returned.
+    pub fn parse_program(&mut self) -> Result<Program, ParseError> {
+        match self.parse_program_partial() {
+            (_, Some(error)) => Err(error),
+            (program, None) => Ok(program),
+        }
+    }
+
+    // Like `parse_program`, but keeps the statements that did parse alongside
+    // the first error, for tools that work on broken code
+    pub fn parse_program_partial(&mut self) -> (Program, Option<ParseError>) {
+        let start = self.current.token_span.clone();
+        let mut statements = Vec::new();
+        let mut first_error = None;
+        while !self.check(&EOF) {
+            let depth = self.delimiters.depth();
+            let statement_start = self.current.token_span.start;
+            match self.top_level_statement() {
+                Ok(statement) => statements.push(statement),
+                Err(error) => {
+                    self.diagnostics.push(error.clone().into());
+                    first_error.get_or_insert(error);
+                    self.synchronize(depth, statement_start);
+                }
+            }
+        }
+        self.delimiters.finish(&self.current.token_span);
+        self.diagnostics.extend(self.delimiters.take_diagnostics());
+        self.drop_delimiter_duplicates();
+        let program = Program {
+            statements,
+            span: start.to(&self.current.token_span),
+        };
+        (program, first_error)
+    }
+
+    // Skips the rest of a statement that failed to parse: through a `;` or
+    // `}` that brings the nesting back to `depth`, or up to the next keyword
+    // starting a statement at that depth
+    fn synchronize(&mut self, depth: usize, statement_start: usize) {
+        while !self.check(&EOF) {
+            let starts_statement = matches!(
+                self.current.token_type,
+                TokenType::Declaration(_)
+                    | TokenType::ControlFlow(
+                        ControlFlowToken::If | ControlFlowToken::For | ControlFlowToken::Return
+                    )
+            );
+            if starts_statement
+                && self.delimiters.depth() <= depth
+                && self.current.token_span.start > statement_start
+            {
+                return;
+            }
+            let token = self.advance();
+            if self.delimiters.depth() <= depth {
+                if token.token_type == SEMICOLON {
+                    return;
+                }
+                if token.token_type == CLOSE_CURLY {
+                    self.eat(&SEMICOLON);
+                    return;
+                }
+            }
+        }
+    }
+
+    // A parse error at a delimiter the checker already reported, such as
+    // "expected `]`" at a mismatched `}`, would only repeat it
+    fn drop_delimiter_duplicates(&mut self) {
+        let delimiter_spans: Vec<usize> = self
+            .diagnostics
+            .iter()
+            .filter(|diagnostic| matches!(diagnostic.code, "E0008" | "E0009" | "E0010"))
+            .flat_map(|diagnostic| {
+                std::iter::once(diagnostic.span.start)
+                    .chain(diagnostic.labels.iter().map(|label| label.span.start))
+            })
+            .collect();
+        self.diagnostics.retain(|diagnostic| {
+            diagnostic.code != "E0100" || !delimiter_spans.contains(&diagnostic.span.start)
+        });
+    }
+
+    // Lexical, delimiter and syntax problems found in the tokens read so far
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    // Whitespace and comments only separate tokens, the grammar never looks at
+    // them. Doc comments are returned alongside the token they precede.
+    fn next_significant(scanner: &mut Scanner) -> (Token, Vec<String>) {
+        let mut docs = Vec::new();
+        loop {
+            let token = scanner.next_token();
+            match token.token_type {
+                TokenType::WhiteSpace(_) => {}
+                TokenType::Comment(CommentToken::Doc(text)) => docs.push(text),
+                // Any other comment separates the docs from what follows
+                TokenType::Comment(_) => docs.clear(),
+                _ => return (token, docs),
+            }
+        }
+    }
+
+    // Documentation for the declaration starting at the current token, with
+    // the conventional single space after `///` removed
+    fn take_docs(&mut self) -> Option<String> {
+        if self.current_docs.is_empty() {
+            return None;
+        }
+        let lines: Vec<String> = self
+            .current_docs
+            .drain(..)
+            .map(|line| line.strip_prefix(' ').unwrap_or(&line).to_string())
+            .collect();
+        Some(lines.join("\n"))
+    }
+
+    fn advance(&mut self) -> Token {
+        let (next, docs) = Self::next_significant(&mut self.scanner);
+        self.current_docs = docs;
+        let previous = std::mem::replace(&mut self.current, next);
+        self.previous_span = previous.token_span.clone();
+        self.delimiters.feed(&previous);
+        self.diagnostics.extend(self.delimiters.take_diagnostics());
+        self.diagnostics.extend(self.scanner.take_diagnostics());
+        previous
+    }
+
+    fn check(&self, token_type: &TokenType) -> bool {
+        &self.current.token_type == token_type
+    }
+
+    fn eat(&mut self, token_type: &TokenType) -> Option<TokenSpan> {
+        if self.check(token_type) {
+            Some(self.advance().token_span)
+        } else {
+            None
+        }
+    }
+
+    fn expect(&mut self, token_type: &TokenType, expected: &str) -> Result<TokenSpan, ParseError> {
+        self.eat(token_type)
+            .ok_or_else(|| self.error(&format!("expected {expected}")))
+    }
+
+    fn error(&self, message: &str) -> ParseError {
+        ParseError {
+            message: format!("{message}, found {}", describe(&self.current.token_type)),
+            span: self.current.token_span.clone(),
+        }
+    }
+
+    fn identifier(&mut self, expected: &str) -> Result<Identifier, ParseError> {
+        match &self.current.token_type {
+            TokenType::Identifier(IdentifierToken { value }) => {
+                let name = value.clone();
+                let span = self.advance().token_span;
+                Ok(Identifier { name, span })
+            }
+            _ => Err(self.error(&format!("expected {expected}"))),
+        }
+    }
+
+    // Statements may be terminated by an optional `;`, which becomes part of
+    // the statement's span
+    fn finish_statement(&mut self, kind: StmtKind, start: &TokenSpan) -> Stmt {
+        self.eat(&SEMICOLON);
+        Stmt {
+            kind,
+            span: start.to(&self.previous_span),
+            export: None,
+        }
+    }
+
+    // A statement of the program itself, where a declaration of a name may
+    // be marked `export` so that other sections of a document can read it
+    fn top_level_statement(&mut self) -> Result<Stmt, ParseError> {
+        if !self.check(&EXPORT) {
+            return self.statement();
+        }
+        // Doc comments above `export` document the declaration
+        let docs = std::mem::take(&mut self.current_docs);
+        let export = self.advance().token_span;
+        if self.current_docs.is_empty() {
+            self.current_docs = docs;
+        }
+        if !matches!(
+            self.current.token_type,
+            TokenType::Declaration(
+                DeclarationToken::Let | DeclarationToken::Function | DeclarationToken::Object
+            )
+        ) {
+            return Err(self.error("expected `let`, `fn` or `obj` after `export`"));
+        }
+        let mut statement = self.statement()?;
+        if !matches!(
+            &statement.kind,
+            StmtKind::Let { .. }
+                | StmtKind::Object { .. }
+                | StmtKind::Function(Function { name: Some(_), .. })
+        ) {
+            return Err(ParseError {
+                message: "only a named declaration can be exported".to_string(),
+                span: export.to(&statement.span),
+            });
+        }
+        statement.export = Some(export);
+        Ok(statement)
+    }
+
+    fn statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.current.token_span.clone();
+        match self.current.token_type {
+            TokenType::Declaration(DeclarationToken::Export) => Err(ParseError {
+                message: "`export` is only allowed at the top level".to_string(),
+                span: start,
+            }),
+            TokenType::Declaration(DeclarationToken::Let) => self.let_statement(),
+            TokenType::Declaration(DeclarationToken::Function) => {
+                let function = self.function()?;
+                if function.name.is_some() {
+                    let span = function.span.clone();
+                    Ok(Stmt {
+                        kind: StmtKind::Function(function),
+                        span,
+                        export: None,
+                    })
+                } else {
+                    let span = function.span.clone();
+                    let expr = Expr::new(ExprKind::Function(function), span);
+                    Ok(self.finish_statement(StmtKind::Expression(expr), &start))
+                }
+            }
+            TokenType::Declaration(DeclarationToken::Object) => {
+                let doc = self.take_docs();
+                self.advance();
+                if matches!(self.current.token_type, TokenType::Identifier(_)) {
+                    let name = self.identifier("object name")?;
+                    let (fields, _) = self.object_fields()?;
+                    Ok(Stmt {
+                        kind: StmtKind::Object { doc, name, fields },
+                        span: start.to(&self.previous_span),
+                        export: None,
+                    })
+                } else {
+                    let (fields, end) = self.object_fields()?;
+                    let expr = Expr::new(ExprKind::Object(fields), start.to(&end));
+                    Ok(self.finish_statement(StmtKind::Expression(expr), &start))
+                }
+            }
+            TokenType::ControlFlow(ControlFlowToken::If) => self.if_statement(),
+            TokenType::ControlFlow(ControlFlowToken::For) => self.for_statement(),
+            TokenType::ControlFlow(ControlFlowToken::Return) => {
+                self.advance();
+                let value =
+                    if self.check(&SEMICOLON) || self.check(&CLOSE_CURLY) || self.check(&EOF) {
+                        None
+                    } else {
+                        Some(self.expression()?)
+                    };
+                Ok(self.finish_statement(StmtKind::Return(value), &start))
+            }
+            _ if self.check(&OPEN_CURLY) => {
+                let block = self.block()?;
+                let span = block.span.clone();
+                Ok(Stmt {
+                    kind: StmtKind::Block(block),
+                    span,
+                    export: None,
+                })
+            }
+            _ => {
+                let expr = self.expression()?;
+                Ok(self.finish_statement(StmtKind::Expression(expr), &start))
+            }
+        }
+    }
+
+    fn let_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.advance().token_span;
+        let name = self.identifier("variable name after `let`")?;
+        let annotation = self.annotation()?;
+        let value = if self
+            .eat(&TokenType::Assignment(AssignmentToken::Assign))
+            .is_some()
+        {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        Ok(self.finish_statement(
+            StmtKind::Let {
+                name,
+                annotation,
+                value,
+            },
+            &start,
+        ))
+    }
+
+    fn if_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.advance().token_span;
+        let condition = self.expression()?;
+        let then_branch = self.block()?;
+        let else_branch = if self
+            .eat(&TokenType::ControlFlow(ControlFlowToken::Else))
+            .is_some()
+        {
+            let branch = if self.check(&TokenType::ControlFlow(ControlFlowToken::If)) {
+                self.if_statement()?
+            } else {
+                let block = self.block()?;
+                let span = block.span.clone();
+                Stmt {
+                    kind: StmtKind::Block(block),
+                    span,
+                    export: None,
+                }
+            };
+            Some(Box::new(branch))
+        } else {
+            None
+        };
+        Ok(Stmt {
+            kind: StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            },
+            span: start.to(&self.previous_span),
+            export: None,
+        })
+    }
+
+    fn for_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.advance().token_span;
+        let binding = self.identifier("loop variable after `for`")?;
+        self.expect(&TokenType::ControlFlow(ControlFlowToken::In), "`in`")?;
+        let iterable = self.expression()?;
+        let body = self.block()?;
+        Ok(Stmt {
+            kind: StmtKind::For {
+                binding,
+                iterable,
+                body,
+            },
+            span: start.to(&self.previous_span),
+            export: None,
+        })
+    }
+
+    fn block(&mut self) -> Result<Block, ParseError> {
+        let start = self.expect(&OPEN_CURLY, "`{`")?;
+        let mut statements = Vec::new();
+        while !self.check(&CLOSE_CURLY) {
+            if self.check(&EOF) {
+                return Err(self.error("expected `}` to close block"));
+            }
+            statements.push(self.statement()?);
+        }
+        let end = self.advance().token_span;
+        Ok(Block {
+            statements,
+            span: start.to(&end),
+        })
+    }
+
+    fn function(&mut self) -> Result<Function, ParseError> {
+        let doc = self.take_docs();
+        let start = self.advance().token_span;
+        let name = if matches!(self.current.token_type, TokenType::Identifier(_)) {
+            Some(self.identifier("function name")?)
+        } else {
+            None
+        };
+        self.expect(&OPEN_PAREN, "`(` to start parameter list")?;
+        let mut parameters = Vec::new();
+        while !self.check(&CLOSE_PAREN) {
+            let name = self.identifier("parameter name")?;
+            let annotation = self.annotation()?;
+            parameters.push(Parameter { name, annotation });
+            if self.eat(&COMMA).is_none() {
+                break;
+            }
+        }
+        self.expect(&CLOSE_PAREN, "`)` to close parameter list")?;
+        let return_type = self.annotation()?.map(Box::new);
+        let body = self.block()?;
+        Ok(Function {
+            doc,
+            name,
+            parameters,
+            return_type,
+            span: start.to(&body.span),
+            body,
+        })
+    }
+
+    // An optional `: type`
+    fn annotation(&mut self) -> Result<Option<TypeExpr>, ParseError> {
+        if self.eat(&COLON).is_none() {
+            return Ok(None);
+        }
+        self.type_expr().map(Some)
+    }
+
+    // Members of a union are separated by `|`
+    fn type_expr(&mut self) -> Result<TypeExpr, ParseError> {
+        let first = self.type_primary()?;
+        if !self.check(&PIPE) {
+            return Ok(first);
+        }
+        let start = first.span.clone();
+        let mut members = vec![first];
+        while self.eat(&PIPE).is_some() {
+            members.push(self.type_primary()?);
+        }
+        Ok(TypeExpr {
+            kind: TypeExprKind::Union(members),
+            span: start.to(&self.previous_span),
+        })
+    }
+
+    fn type_primary(&mut self) -> Result<TypeExpr, ParseError> {
+        let start = self.current.token_span.clone();
+        let kind = match &self.current.token_type {
+            TokenType::Identifier(IdentifierToken { value }) => {
+                let name = value.clone();
+                self.advance();
+                TypeExprKind::Named(name)
+            }
+            TokenType::Literal(LiteralToken::Null) => {
+                self.advance();
+                TypeExprKind::Null
+            }
+            TokenType::Literal(LiteralToken::Undefined) => {
+                self.advance();
+                TypeExprKind::Undefined
+            }
+            TokenType::Declaration(DeclarationToken::Function) => {
+                self.advance();
+                self.expect(&OPEN_PAREN, "`(` to start parameter types")?;
+                let mut parameters = Vec::new();
+                while !self.check(&CLOSE_PAREN) {
+                    parameters.push(self.type_expr()?);
+                    if self.eat(&COMMA).is_none() {
+                        break;
+                    }
+                }
+                self.expect(&CLOSE_PAREN, "`)` to close parameter types")?;
+                let result = self.annotation()?.map(Box::new);
+                TypeExprKind::Function { parameters, result }
+            }
+            _ if self.check(&OPEN_CURLY) => {
+                self.advance();
+                let mut fields = Vec::new();
+                while !self.check(&CLOSE_CURLY) {
+                    let key = self.identifier("field name")?;
+                    self.expect(&COLON, "`:` after field name")?;
+                    let annotation = self.type_expr()?;
+                    fields.push(FieldType { key, annotation });
+                    if self.eat(&COMMA).is_none() {
+                        break;
+                    }
+                }
+                self.expect(&CLOSE_CURLY, "`}` to close object type")?;
+                TypeExprKind::Object(fields)
+            }
+            _ => return Err(self.error("expected a type")),
+        };
+        Ok(TypeExpr {
+            kind,
+            span: start.to(&self.previous_span),
+        })
+    }
+
+    // `{ key: value, .. }` following the `obj` keyword; returns the span of
+    // the closing brace
+    fn object_fields(&mut self) -> Result<(Vec<ObjectField>, TokenSpan), ParseError> {
+        self.expect(&OPEN_CURLY, "`{` to start object")?;
+        let mut fields = Vec::new();
+        while !self.check(&CLOSE_CURLY) {
+            let key = self.identifier("field name")?;
+            self.expect(&COLON, "`:` after field name")?;
+            let value = self.expression()?;
+            fields.push(ObjectField { key, value });
+            if self.eat(&COMMA).is_none() {
+                break;
+            }
+        }
+        let end = self.expect(&CLOSE_CURLY, "`}` to close object")?;
+        Ok((fields, end))
+    }
+
+    fn arguments(&mut self) -> Result<Vec<Expr>, ParseError> {
+        let mut arguments = Vec::new();
+        while !self.check(&CLOSE_PAREN) {
+            arguments.push(self.expression()?);
+            if self.eat(&COMMA).is_none() {
+                break;
+            }
+        }
+        self.expect(&CLOSE_PAREN, "`)` to close argument list")?;
+        Ok(arguments)
+    }
+
+    pub fn expression(&mut self) -> Result<Expr, ParseError> {
+        self.assignment()
+    }
+
+    fn assignment(&mut self) -> Result<Expr, ParseError> {
+        let target = self.binary(0)?;
+        let operator = match &self.current.token_type {
+            TokenType::Assignment(operator) => operator.clone(),
+            _ => return Ok(target),
+        };
+        if !matches!(
+            target.kind,
+            ExprKind::Identifier(_) | ExprKind::Member { .. } | ExprKind::Index { .. }
+        ) {
+            return Err(ParseError {
+                message: "invalid assignment target".to_string(),
+                span: target.span,
+            });
+        }
+        let operator_span = self.advance().token_span;
+        // Right associative: `a = b = c` assigns `c` to `b` first
+        let value = self.assignment()?;
+        let span = target.span.to(&value.span);
+        Ok(Expr::new(
+            ExprKind::Assign {
+                operator,
+                operator_span,
+                target: Box::new(target),
+                value: Box::new(value),
+            },
+            span,
+        ))
+    }
+
+    // Pratt loop: keeps folding operators into `left` while they bind at
+    // least as tightly as `min_power`. See `infix_operator` for the table.
+    fn binary(&mut self, min_power: u8) -> Result<Expr, ParseError> {
+        let mut left = self.unary()?;
+        while let Some((operator, level)) = infix_operator(&self.current.token_type) {
+            // Left associative: the right operand must bind strictly tighter
+            let (left_power, right_power) = (level * 2, level * 2 + 1);
+            if left_power < min_power {
+                break;
+            }
+            let operator_span = self.advance().token_span;
+            let right = self.binary(right_power)?;
+            let span = left.span.to(&right.span);
+            left = Expr::new(
+                ExprKind::Binary {
+                    operator,
+                    operator_span,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                },
+                span,
+            );
+        }
+        Ok(left)
+    }
+
+    // Prefix operators bind tighter than every infix operator, so `-a * b`
+    // is `(-a) * b` and `!a == b` is `(!a) == b`
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        let operator = match self.current.token_type {
+            TokenType::Arithmetic(ArithmeticToken::Subtract) => UnaryOperator::Negate,
+            TokenType::Comparison(ComparisonToken::Not) => UnaryOperator::Not,
+            TokenType::Logical(LogicalToken::Not) => UnaryOperator::LogicalNot,
+            _ => return self.postfix(),
+        };
+        let operator_span = self.advance().token_span;
+        let operand = self.unary()?;
+        let span = operator_span.to(&operand.span);
+        Ok(Expr::new(
+            ExprKind::Unary {
+                operator,
+                operator_span,
+                operand: Box::new(operand),
+            },
+            span,
+        ))
+    }
+
+    // Calls, member access and indexing, all binding tighter than any operator
+    fn postfix(&mut self) -> Result<Expr, ParseError> {
+        let expr = self.primary()?;
+        self.postfix_chain(expr, true)
+    }
+
+    // `new` parses its callee without calls so that the first argument list
+    // belongs to the `new` expression itself
+    fn postfix_chain(&mut self, mut expr: Expr, allow_calls: bool) -> Result<Expr, ParseError> {
+        loop {
+            let start = expr.span.clone();
+            let kind = if allow_calls && self.eat(&OPEN_PAREN).is_some() {
+                ExprKind::Call {
+                    callee: Box::new(expr),
+                    arguments: self.arguments()?,
+                }
+            } else if self.eat(&DOT).is_some() {
+                ExprKind::Member {
+                    object: Box::new(expr),
+                    property: self.identifier("property name after `.`")?,
+                }
+            } else if self.eat(&OPEN_SQUARE).is_some() {
+                let index = self.expression()?;
+                self.expect(&CLOSE_SQUARE, "`]` to close index")?;
+                ExprKind::Index {
+                    object: Box::new(expr),
+                    index: Box::new(index),
+                }
+            } else {
+                return Ok(expr);
+            };
+            expr = Expr::new(kind, start.to(&self.previous_span));
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        let start = self.current.token_span.clone();
+        match &self.current.token_type {
+            TokenType::Literal(literal) => {
+                let literal = literal.clone();
+                self.advance();
+                Ok(Expr::new(ExprKind::Literal(literal), start))
+            }
+            TokenType::Identifier(IdentifierToken { value }) => {
+                let name = value.clone();
+                self.advance();
+                Ok(Expr::new(ExprKind::Identifier(name), start))
+            }
+            TokenType::ObjectReference(ObjectReferenceToken::New) => {
+                self.advance();
+                let callee = self.primary()?;
+                let callee = self.postfix_chain(callee, false)?;
+                let arguments = if self.eat(&OPEN_PAREN).is_some() {
+                    self.arguments()?
+                } else {
+                    Vec::new()
+                };
+                Ok(Expr::new(
+                    ExprKind::New {
+                        callee: Box::new(callee),
+                        arguments,
+                    },
+                    start.to(&self.previous_span),
+                ))
+            }
+            TokenType::ObjectReference(reference) => {
+                let reference = reference.clone();
+                self.advance();
+                Ok(Expr::new(ExprKind::ObjectReference(reference), start))
+            }
+            TokenType::Declaration(DeclarationToken::Function) => {
+                let function = self.function()?;
+                let span = function.span.clone();
+                Ok(Expr::new(ExprKind::Function(function), span))
+            }
+            TokenType::Declaration(DeclarationToken::Object) => {
+                self.advance();
+                let (fields, end) = self.object_fields()?;
+                Ok(Expr::new(ExprKind::Object(fields), start.to(&end)))
+            }
+            TokenType::Template(TemplateToken::Start(text)) => {
+                let mut strings = vec![text.clone()];
+                let mut expressions = Vec::new();
+                self.advance();
+                loop {
+                    expressions.push(self.expression()?);
+                    match &self.current.token_type {
+                        TokenType::Template(TemplateToken::Middle(text)) => {
+                            strings.push(text.clone());
+                            self.advance();
+                        }
+                        TokenType::Template(TemplateToken::End(text)) => {
+                            strings.push(text.clone());
+                            self.advance();
+                            break;
+                        }
+                        _ => return Err(self.error("expected `}` to close template interpolation")),
+                    }
+                }
+                Ok(Expr::new(
+                    ExprKind::Template {
+                        strings,
+                        expressions,
+                    },
+                    start.to(&self.previous_span),
+                ))
+            }
+            _ if self.check(&OPEN_PAREN) => {
+                self.advance();
+                let mut expr = self.expression()?;
+                let end = self.expect(&CLOSE_PAREN, "`)` to close group")?;
+                // A name keeps its own span, which resolution keys its uses on
+                if !matches!(expr.kind, ExprKind::Identifier(_)) {
+                    expr.span = start.to(&end);
+                }
+                Ok(expr)
+            }
+            _ => Err(self.error("expected expression")),
+        }
+    }
+}
+
+// Precedence levels for infix operators, loosest first. Every level is left
+// associative, so `a - b - c` is `(a - b) - c`. Assignment sits below level 1
+// and is right associative; prefix `-`, `!` and `not` sit above level 9.
+//
+//   1  ||  or         short-circuit logical or
+//   2  xor  xand      exclusive or, and its negation (true when both agree)
+//   3  &&  and        short-circuit logical and
+//   4  ==  !=
+//   5  <  <=  >  >=
+//   6  |              bitwise or
+//   7  &              bitwise and
+//   8  +  -
+//   9  *  /  %
+//
+// The words `or` and `and` lex as the same tokens as `||` and `&&`, so they
+// behave exactly alike. Unlike C, bitwise operators bind tighter than
+// comparisons so that `flags & mask == 0` means `(flags & mask) == 0`.
+pub fn infix_operator(token_type: &TokenType) -> Option<(BinaryOperator, u8)> {
+    let level = match token_type {
+        TokenType::Logical(LogicalToken::Or) => 1,
+        TokenType::Logical(LogicalToken::XOr | LogicalToken::XAnd) => 2,
+        TokenType::Logical(LogicalToken::And) => 3,
+        TokenType::Comparison(ComparisonToken::Equal | ComparisonToken::NotEqual) => 4,
+        TokenType::Comparison(
+            ComparisonToken::GreaterThan
+            | ComparisonToken::GreaterThanOrEqual
+            | ComparisonToken::LessThan
+            | ComparisonToken::LessThanOrEqual,
+        ) => 5,
+        TokenType::Arithmetic(ArithmeticToken::BitwiseOr) => 6,
+        TokenType::Arithmetic(ArithmeticToken::BitwiseAnd) => 7,
+        TokenType::Arithmetic(ArithmeticToken::Add | ArithmeticToken::Subtract) => 8,
+        TokenType::Arithmetic(
+            ArithmeticToken::Multiply | ArithmeticToken::Divide | ArithmeticToken::Modulo,
+        ) => 9,
+        _ => return None,
+    };
+    let operator = match token_type {
+        TokenType::Arithmetic(operator) => BinaryOperator::Arithmetic(operator.clone()),
+        TokenType::Comparison(operator) => BinaryOperator::Comparison(operator.clone()),
+        TokenType::Logical(operator) => BinaryOperator::Logical(operator.clone()),
+        _ => unreachable!("only operator tokens have a precedence level"),
+    };
+    Some((operator, level))
+}
+
+fn describe(token_type: &TokenType) -> String {
+    match token_type {
+        TokenType::Delimiter(DelimiterToken::EOF) => "end of input".to_string(),
+        TokenType::Identifier(IdentifierToken { value }) => format!("identifier `{value}`"),
+        TokenType::Unknown(c) => format!("unexpected character `{c}`"),
+        TokenType::Template(TemplateToken::Start(_)) => "template string".to_string(),
+        TokenType::Template(_) => "end of template interpolation".to_string(),
+        other => format!("{other:?}"),
+    }
+}
@@ -0,0 +1,216 @@
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::source_map::SourceFile;
+use crate::token::TokenSpan;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RenderStyle {
+    Plain,
+    // Plain text with ANSI color escapes, for terminals
+    Ansi,
+    // `<pre>` markup with a CSS class per colored part, for the visualizer
+    Html,
+}
+
+#[derive(Clone, Copy)]
+enum Paint {
+    Severity(Severity),
+    // Gutter, line numbers and secondary labels
+    Secondary,
+    Bold,
+}
+
+fn paint(style: RenderStyle, paint: Paint, text: &str) -> String {
+    match style {
+        RenderStyle::Plain => text.to_string(),
+        RenderStyle::Ansi => {
+            let code = match paint {
+                Paint::Severity(Severity::Error) => "1;31",
+                Paint::Severity(Severity::Warning) => "1;33",
+                Paint::Severity(Severity::Note) => "1;36",
+                Paint::Secondary => "1;34",
+                Paint::Bold => "1",
+            };
+            format!("\x1b[{code}m{text}\x1b[0m")
+        }
+        RenderStyle::Html => {
+            let class = match paint {
+                Paint::Severity(severity) => format!("severity-{severity}"),
+                Paint::Secondary => "secondary".to_string(),
+                Paint::Bold => "bold".to_string(),
+            };
+            format!("<span class=\"{class}\">{}</span>", escape_html(text))
+        }
+    }
+}
+
+// Text that is not painted still needs escaping in HTML output
+fn text(style: RenderStyle, text: &str) -> String {
+    match style {
+        RenderStyle::Html => escape_html(text),
+        _ => text.to_string(),
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+struct Annotation<'a> {
+    span: &'a TokenSpan,
+    marker: char,
+    message: Option<&'a str>,
+    paint: Paint,
+}
+
+// Renders one diagnostic rustc-style: a header, the file location, each
+// source line involved with the primary span underlined in `^` and labels in
+// `-`, then any notes and suggested fixes.
+//
+//   error[E0001]: unterminated string literal
+//    --> main.toy:1:5
+//     |
+//   1 | x = "abc
+//     |     ^^^^
+//     |     - string starts here
+//     = note: add a closing " to end the string
+pub fn render(diagnostic: &Diagnostic, file: &SourceFile, style: RenderStyle) -> String {
+    let severity = Paint::Severity(diagnostic.severity);
+    let mut annotations = vec![Annotation {
+        span: &diagnostic.span,
+        marker: '^',
+        message: None,
+        paint: severity,
+    }];
+    annotations.extend(diagnostic.labels.iter().map(|label| Annotation {
+        span: &label.span,
+        marker: '-',
+        message: Some(label.message.as_str()),
+        paint: Paint::Secondary,
+    }));
+
+    let mut lines: Vec<usize> = annotations
+        .iter()
+        .map(|annotation| file.line_column(annotation.span.start).line)
+        .collect();
+    lines.sort_unstable();
+    lines.dedup();
+    let gutter_width = lines.last().map_or(1, |line| line.to_string().len());
+    let gutter = |label: &str| {
+        paint(
+            style,
+            Paint::Secondary,
+            &format!("{label:>gutter_width$} |"),
+        )
+    };
+
+    let mut out = String::new();
+    out.push_str(&paint(
+        style,
+        severity,
+        &format!("{}[{}]", diagnostic.severity, diagnostic.code),
+    ));
+    out.push_str(&paint(
+        style,
+        Paint::Bold,
+        &format!(": {}", diagnostic.message),
+    ));
+    out.push('\n');
+
+    let start = file.line_column(diagnostic.span.start);
+    out.push_str(&paint(
+        style,
+        Paint::Secondary,
+        &format!("{:gutter_width$}--> ", ""),
+    ));
+    out.push_str(&text(
+        style,
+        &format!("{}:{}:{}\n", file.name, start.line, start.column),
+    ));
+    out.push_str(&gutter(""));
+    out.push('\n');
+
+    for line in lines {
+        let source = file.line_text(line);
+        out.push_str(&gutter(&line.to_string()));
+        out.push_str(&text(style, &format!(" {source}\n")));
+        for annotation in &annotations {
+            let start = file.line_column(annotation.span.start);
+            if start.line != line {
+                continue;
+            }
+            let end = file.line_column(annotation.span.end);
+            let end_column = if end.line == line {
+                end.column
+            } else {
+                source.chars().count() + 1
+            };
+            // Keep tabs in the padding so the markers line up with the source
+            let padding: String = source
+                .chars()
+                .take(start.column - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let mut markers = annotation
+                .marker
+                .to_string()
+                .repeat(end_column.saturating_sub(start.column).max(1));
+            if let Some(message) = annotation.message {
+                markers.push(' ');
+                markers.push_str(message);
+            }
+            out.push_str(&gutter(""));
+            out.push(' ');
+            out.push_str(&padding);
+            out.push_str(&paint(style, annotation.paint, &markers));
+            out.push('\n');
+        }
+    }
+
+    for note in &diagnostic.notes {
+        out.push_str(&paint(
+            style,
+            Paint::Secondary,
+            &format!("{:gutter_width$} =", ""),
+        ));
+        out.push_str(&paint(style, Paint::Bold, " note"));
+        out.push_str(&text(style, &format!(": {note}\n")));
+    }
+    for suggestion in &diagnostic.suggestions {
+        out.push_str(&paint(
+            style,
+            Paint::Secondary,
+            &format!("{:gutter_width$} =", ""),
+        ));
+        out.push_str(&paint(style, Paint::Bold, " help"));
+        let replacement = if suggestion.replacement.is_empty() {
+            String::new()
+        } else {
+            format!(": `{}`", suggestion.replacement)
+        };
+        out.push_str(&text(
+            style,
+            &format!(": {}{replacement}\n", suggestion.message),
+        ));
+    }
+    out
+}
+
+// Renders every diagnostic, separated by blank lines; HTML output is wrapped
+// in a single `<pre class="diagnostics">`
+pub fn render_all(diagnostics: &[Diagnostic], file: &SourceFile, style: RenderStyle) -> String {
+    if diagnostics.is_empty() {
+        return String::new();
+    }
+    let body = diagnostics
+        .iter()
+        .map(|diagnostic| render(diagnostic, file, style))
+        .collect::<Vec<_>>()
+        .join("\n");
+    match style {
+        RenderStyle::Html => format!("<pre class=\"diagnostics\">{body}</pre>"),
+        _ => body,
+    }
+}
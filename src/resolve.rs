@@ -0,0 +1,567 @@
+use crate::diagnostic::Diagnostic;
+use crate::link::Export;
+use crate::parser::*;
+use crate::token::*;
+use std::collections::HashMap;
+
+// Names the interpreter defines before running a program
+pub const BUILTINS: [&str; 1] = ["print"];
+
+// A name in the source, identified by the byte offset it starts at. No two
+// names start at the same offset, so this stays the same for a given parse
+// and can be worked out again from any `Identifier` or identifier `Expr`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, PartialOrd, Ord)]
+pub struct NodeId(pub usize);
+
+impl NodeId {
+    pub fn of(span: &TokenSpan) -> Self {
+        NodeId(span.start)
+    }
+}
+
+// An index into `Resolution::declarations`
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct DeclId(pub usize);
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DeclarationKind {
+    Let,
+    Function,
+    // `obj Name { .. }`
+    Object,
+    Parameter,
+    // The variable of a `for` loop
+    Loop,
+    // A field of an `obj` or object literal
+    Field,
+    Builtin,
+    // Another section of the same document, whose exports are its fields
+    Section,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Declaration {
+    pub name: String,
+    pub kind: DeclarationKind,
+    // None for builtins
+    pub span: Option<TokenSpan>,
+    // The fields of the object this names, if it is an `obj` or a `let`
+    // whose value is an object literal
+    pub fields: Vec<DeclId>,
+}
+
+// What every name in a program refers to
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Resolution {
+    pub declarations: Vec<Declaration>,
+    // The declaration each use of a name refers to. Member accesses such as
+    // `this.x` or `Point.x` are included when the object is known.
+    pub uses: HashMap<NodeId, DeclId>,
+    // The declaration each declared name introduces
+    pub definitions: HashMap<NodeId, DeclId>,
+    // Every `section.member` read of another section's export, in order
+    pub imports: Vec<Import>,
+}
+
+// A read of `member` from the section declared as `section`, spanning the
+// whole `section.member`
+#[derive(Debug, PartialEq, Clone)]
+pub struct Import {
+    pub section: DeclId,
+    pub member: Identifier,
+    pub span: TokenSpan,
+}
+
+impl Resolution {
+    pub fn declaration(&self, id: DeclId) -> &Declaration {
+        &self.declarations[id.0]
+    }
+
+    pub fn lookup(&self, node: NodeId) -> Option<DeclId> {
+        self.uses.get(&node).copied()
+    }
+
+    pub fn declared(&self, node: NodeId) -> Option<DeclId> {
+        self.definitions.get(&node).copied()
+    }
+
+    // The declaration named at byte `offset`, either by a use or by the
+    // declaration itself, as for go-to-definition
+    pub fn definition_at(&self, offset: usize) -> Option<DeclId> {
+        let covers = |start: usize, name: &str| start <= offset && offset < start + name.len();
+        self.uses
+            .iter()
+            .find(|(node, decl)| covers(node.0, &self.declaration(**decl).name))
+            .map(|(_, decl)| *decl)
+            .or_else(|| {
+                self.definitions
+                    .iter()
+                    .find(|(node, decl)| covers(node.0, &self.declaration(**decl).name))
+                    .map(|(_, decl)| *decl)
+            })
+    }
+
+    // Every use of `decl`, in source order, as for renaming
+    pub fn references(&self, decl: DeclId) -> Vec<NodeId> {
+        let mut references: Vec<NodeId> = self
+            .uses
+            .iter()
+            .filter(|(_, used)| **used == decl)
+            .map(|(node, _)| *node)
+            .collect();
+        references.sort();
+        references
+    }
+}
+
+// Binds every name in `program` to its declaration. Reports names that are
+// never declared, declared twice in one scope, or read before their
+// declaration has run.
+pub fn resolve(program: &Program) -> (Resolution, Vec<Diagnostic>) {
+    resolve_section(program, &[])
+}
+
+// Resolves one section of a document, which can also read the exports of
+// the other `sections` as `name.member`. Each is given by name along with
+// its exports, or None if they are not known, as for a section with syntax
+// errors, in which case reads from it are not checked.
+pub fn resolve_section(
+    program: &Program,
+    sections: &[(&str, Option<&[Export]>)],
+) -> (Resolution, Vec<Diagnostic>) {
+    let mut resolver = Resolver::default();
+    resolver.scopes.push(Scope::default());
+    for builtin in BUILTINS {
+        let id = resolver.add(builtin, DeclarationKind::Builtin, None, Vec::new());
+        resolver.scopes[0].bindings.push(id);
+    }
+    for (name, exports) in sections {
+        let fields = exports
+            .unwrap_or_default()
+            .iter()
+            .map(|export| {
+                let span = Some(export.span.clone());
+                resolver.add(&export.name, DeclarationKind::Field, span, Vec::new())
+            })
+            .collect();
+        let id = resolver.add(name, DeclarationKind::Section, None, fields);
+        resolver.scopes[0].bindings.push(id);
+        if exports.is_none() {
+            resolver.unchecked.push(id);
+        }
+    }
+    let globals = resolver.push_scope(Some(0));
+    for statement in &program.statements {
+        resolver.statement(statement, globals);
+    }
+    // Function bodies run once everything around them has been defined, so
+    // they are resolved last and may use names declared after them
+    while !resolver.functions.is_empty() {
+        for deferred in std::mem::take(&mut resolver.functions) {
+            resolver.function_body(deferred);
+        }
+    }
+    resolver.unresolved();
+    resolver
+        .diagnostics
+        .sort_by_key(|diagnostic| diagnostic.span.start);
+    (resolver.resolution, resolver.diagnostics)
+}
+
+#[derive(Default)]
+struct Scope {
+    parent: Option<usize>,
+    // In the order they were declared
+    bindings: Vec<DeclId>,
+}
+
+// A function body waiting to be resolved in the scope it was defined in
+struct Deferred<'a> {
+    function: &'a Function,
+    scope: usize,
+    // How many declarations there were when the function was defined
+    defined: usize,
+    // The fields `this` refers to, for a function that is a field value
+    this: Option<Vec<DeclId>>,
+}
+
+// A use no declaration had been seen for when it was reached
+struct Unresolved {
+    name: String,
+    span: TokenSpan,
+    scope: usize,
+}
+
+#[derive(Default)]
+struct Resolver<'a> {
+    resolution: Resolution,
+    // Never popped, so deferred function bodies can still see the scopes
+    // they were defined in
+    scopes: Vec<Scope>,
+    functions: Vec<Deferred<'a>>,
+    unresolved: Vec<Unresolved>,
+    diagnostics: Vec<Diagnostic>,
+    // The fields `this` refers to in the function being resolved
+    this: Option<Vec<DeclId>>,
+    // The first scope of the function body being resolved, and how many
+    // declarations there were when the function was defined
+    body: Option<(usize, usize)>,
+    // Sections whose exports are not known
+    unchecked: Vec<DeclId>,
+}
+
+impl<'a> Resolver<'a> {
+    fn push_scope(&mut self, parent: Option<usize>) -> usize {
+        self.scopes.push(Scope {
+            parent,
+            bindings: Vec::new(),
+        });
+        self.scopes.len() - 1
+    }
+
+    fn add(
+        &mut self,
+        name: &str,
+        kind: DeclarationKind,
+        span: Option<TokenSpan>,
+        fields: Vec<DeclId>,
+    ) -> DeclId {
+        let id = DeclId(self.resolution.declarations.len());
+        if let Some(span) = &span {
+            self.resolution.definitions.insert(NodeId::of(span), id);
+        }
+        self.resolution.declarations.push(Declaration {
+            name: name.to_string(),
+            kind,
+            span,
+            fields,
+        });
+        id
+    }
+
+    fn declare(
+        &mut self,
+        name: &Identifier,
+        kind: DeclarationKind,
+        scope: usize,
+        fields: Vec<DeclId>,
+    ) {
+        self.duplicate(name, &self.scopes[scope].bindings.clone());
+        let id = self.add(&name.name, kind, Some(name.span.clone()), fields);
+        self.scopes[scope].bindings.push(id);
+    }
+
+    // Reports `name` if one of `earlier` already uses it
+    fn duplicate(&mut self, name: &Identifier, earlier: &[DeclId]) {
+        let previous = earlier
+            .iter()
+            .map(|id| self.resolution.declaration(*id))
+            .find(|declaration| declaration.name == name.name)
+            .and_then(|declaration| declaration.span.clone());
+        if let Some(previous) = previous {
+            self.diagnostics.push(
+                Diagnostic::error(
+                    "E0301",
+                    format!("`{}` is already declared in this scope", name.name),
+                    name.span.clone(),
+                )
+                .with_label(previous, "first declared here"),
+            );
+        }
+    }
+
+    // The latest declaration of `name` seen so far, from the innermost scope
+    // outwards
+    fn find(&self, name: &str, scope: usize) -> Option<DeclId> {
+        self.find_before(name, scope, usize::MAX).map(|(_, id)| id)
+    }
+
+    // The latest of the first `before` declarations named `name`, from the
+    // innermost scope outwards, along with the scope it is in
+    fn find_before(&self, name: &str, scope: usize, before: usize) -> Option<(usize, DeclId)> {
+        let mut scope = Some(scope);
+        while let Some(index) = scope {
+            let found = self.scopes[index]
+                .bindings
+                .iter()
+                .rev()
+                .find(|id| id.0 < before && self.resolution.declaration(**id).name == name);
+            if let Some(found) = found {
+                return Some((index, *found));
+            }
+            scope = self.scopes[index].parent;
+        }
+        None
+    }
+
+    fn use_name(&mut self, name: &str, span: &TokenSpan, scope: usize) {
+        match self.find_before(name, scope, usize::MAX) {
+            Some((found, id)) => {
+                self.shadowed_later(name, span, found, id);
+                self.resolution.uses.insert(NodeId::of(span), id);
+            }
+            None => self.unresolved.push(Unresolved {
+                name: name.to_string(),
+                span: span.clone(),
+                scope,
+            }),
+        }
+    }
+
+    // Reports a use in a function body of `id`, declared around the function
+    // but after it, when an earlier declaration of the name is also around
+    // it. Which of the two the use means would depend on whether the
+    // function is called before or after `id` is declared.
+    fn shadowed_later(&mut self, name: &str, span: &TokenSpan, found: usize, id: DeclId) {
+        let Some((body, defined)) = self.body else {
+            return;
+        };
+        if found >= body || id.0 < defined || self.find_before(name, found, defined).is_none() {
+            return;
+        }
+        let declaration = self.resolution.declaration(id);
+        self.diagnostics.push(
+            Diagnostic::error(
+                "E0302",
+                format!("`{name}` is used before its declaration"),
+                span.clone(),
+            )
+            .with_label(
+                declaration
+                    .span
+                    .clone()
+                    .expect("only builtins have no span"),
+                "declared here, after the function",
+            )
+            .with_note(format!(
+                "called before this declaration, the function would read an earlier `{name}`"
+            )),
+        );
+    }
+
+    // Uses that still have no declaration now that every scope is complete
+    // are either before a later one or of a name never declared at all
+    fn unresolved(&mut self) {
+        for unresolved in std::mem::take(&mut self.unresolved) {
+            let diagnostic = match self.find(&unresolved.name, unresolved.scope) {
+                Some(id) => Diagnostic::error(
+                    "E0302",
+                    format!("`{}` is used before its declaration", unresolved.name),
+                    unresolved.span,
+                )
+                .with_label(
+                    self.resolution
+                        .declaration(id)
+                        .span
+                        .clone()
+                        .expect("builtins are declared from the start"),
+                    "declared here",
+                ),
+                None => Diagnostic::error(
+                    "E0300",
+                    format!("cannot find `{}` in this scope", unresolved.name),
+                    unresolved.span,
+                ),
+            };
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    fn statements(&mut self, statements: &'a [Stmt], scope: usize) {
+        for statement in statements {
+            self.statement(statement, scope);
+        }
+    }
+
+    fn block(&mut self, block: &'a Block, scope: usize) {
+        let scope = self.push_scope(Some(scope));
+        self.statements(&block.statements, scope);
+    }
+
+    fn defer(&mut self, function: &'a Function, scope: usize, this: Option<Vec<DeclId>>) {
+        self.functions.push(Deferred {
+            function,
+            scope,
+            defined: self.resolution.declarations.len(),
+            this,
+        });
+    }
+
+    // Parameters and body statements share one scope, as when called
+    fn function_body(&mut self, deferred: Deferred<'a>) {
+        let scope = self.push_scope(Some(deferred.scope));
+        self.this = deferred.this;
+        self.body = Some((scope, deferred.defined));
+        for parameter in &deferred.function.parameters {
+            self.declare(
+                &parameter.name,
+                DeclarationKind::Parameter,
+                scope,
+                Vec::new(),
+            );
+        }
+        self.statements(&deferred.function.body.statements, scope);
+        self.this = None;
+        self.body = None;
+    }
+
+    fn statement(&mut self, statement: &'a Stmt, scope: usize) {
+        match &statement.kind {
+            StmtKind::Let { name, value, .. } => {
+                // The value is evaluated before the name exists
+                let fields = match value {
+                    Some(value) => self.expression(value, scope),
+                    None => Vec::new(),
+                };
+                self.declare(name, DeclarationKind::Let, scope, fields);
+            }
+            StmtKind::Function(function) => {
+                if let Some(name) = &function.name {
+                    self.declare(name, DeclarationKind::Function, scope, Vec::new());
+                }
+                self.defer(function, scope, None);
+            }
+            StmtKind::Object { name, fields, .. } => {
+                let fields = self.fields(fields, scope);
+                self.declare(name, DeclarationKind::Object, scope, fields);
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition, scope);
+                self.block(then_branch, scope);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch, scope);
+                }
+            }
+            StmtKind::For {
+                binding,
+                iterable,
+                body,
+            } => {
+                self.expression(iterable, scope);
+                let scope = self.push_scope(Some(scope));
+                self.declare(binding, DeclarationKind::Loop, scope, Vec::new());
+                self.statements(&body.statements, scope);
+            }
+            StmtKind::Return(value) => {
+                if let Some(value) = value {
+                    self.expression(value, scope);
+                }
+            }
+            StmtKind::Block(block) => self.block(block, scope),
+            StmtKind::Expression(expr) => {
+                self.expression(expr, scope);
+            }
+        }
+    }
+
+    // Declares the fields of an object, each as its own name, and resolves
+    // their values. Functions among them see the object as `this`.
+    fn fields(&mut self, fields: &'a [ObjectField], scope: usize) -> Vec<DeclId> {
+        let mut ids = Vec::new();
+        let mut methods = Vec::new();
+        for field in fields {
+            match &field.value.kind {
+                ExprKind::Function(function) => methods.push(function),
+                _ => {
+                    self.expression(&field.value, scope);
+                }
+            }
+            self.duplicate(&field.key, &ids);
+            ids.push(self.add(
+                &field.key.name,
+                DeclarationKind::Field,
+                Some(field.key.span.clone()),
+                Vec::new(),
+            ));
+        }
+        for method in methods {
+            self.defer(method, scope, Some(ids.clone()));
+        }
+        ids
+    }
+
+    // The fields of the object `expr` evaluates to, when known
+    fn expression(&mut self, expr: &'a Expr, scope: usize) -> Vec<DeclId> {
+        match &expr.kind {
+            ExprKind::Literal(_) => {}
+            ExprKind::Identifier(name) => {
+                self.use_name(name, &expr.span, scope);
+                if let Some(id) = self.resolution.lookup(NodeId::of(&expr.span)) {
+                    return self.resolution.declaration(id).fields.clone();
+                }
+            }
+            ExprKind::ObjectReference(ObjectReferenceToken::This) => {
+                return self.this.clone().unwrap_or_default();
+            }
+            ExprKind::ObjectReference(_) => {}
+            ExprKind::New { callee, arguments } | ExprKind::Call { callee, arguments } => {
+                self.expression(callee, scope);
+                for argument in arguments {
+                    self.expression(argument, scope);
+                }
+            }
+            ExprKind::Object(fields) => return self.fields(fields, scope),
+            ExprKind::Function(function) => self.defer(function, scope, None),
+            ExprKind::Template { expressions, .. } => {
+                for expression in expressions {
+                    self.expression(expression, scope);
+                }
+            }
+            ExprKind::Unary { operand, .. } => {
+                self.expression(operand, scope);
+            }
+            ExprKind::Binary { left, right, .. } => {
+                self.expression(left, scope);
+                self.expression(right, scope);
+            }
+            ExprKind::Assign { target, value, .. } => {
+                self.expression(target, scope);
+                self.expression(value, scope);
+            }
+            ExprKind::Member { object, property } => {
+                let fields = self.expression(object, scope);
+                let field = fields
+                    .into_iter()
+                    .find(|id| self.resolution.declaration(*id).name == property.name);
+                if let Some(field) = field {
+                    self.resolution
+                        .uses
+                        .insert(NodeId::of(&property.span), field);
+                }
+                let section = match &object.kind {
+                    ExprKind::Identifier(_) => self
+                        .resolution
+                        .lookup(NodeId::of(&object.span))
+                        .filter(|id| {
+                            self.resolution.declaration(*id).kind == DeclarationKind::Section
+                        }),
+                    _ => None,
+                };
+                if let Some(section) = section {
+                    if field.is_none() && !self.unchecked.contains(&section) {
+                        let name = &self.resolution.declaration(section).name;
+                        self.diagnostics.push(Diagnostic::error(
+                            "E0303",
+                            format!("section `{name}` does not export `{}`", property.name),
+                            property.span.clone(),
+                        ));
+                    }
+                    self.resolution.imports.push(Import {
+                        section,
+                        member: property.clone(),
+                        span: expr.span.clone(),
+                    });
+                }
+            }
+            ExprKind::Index { object, index } => {
+                self.expression(object, scope);
+                self.expression(index, scope);
+            }
+        }
+        Vec::new()
+    }
+}
@@ -0,0 +1,426 @@
+use crate::diagnostic::Diagnostic;
+use crate::parser::*;
+use crate::token::*;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fmt;
+use std::io::Write;
+use std::rc::Rc;
+
+// Calls nested deeper than this fail instead of exhausting memory. The top
+// level counts as one.
+pub(crate) const MAX_FRAMES: usize = 10_000;
+
+// The values and operations shared by the two ways of running a program, the
+// tree-walking `Interpreter` and the bytecode `Vm`, so that both print and
+// fail in exactly the same way for any program that resolves. Each represents the functions a program
+// defines in its own way, as `F`, which keeps a function made by one from
+// ever reaching the other.
+
+#[derive(Debug)]
+pub enum Value<F> {
+    Number(NumberToken),
+    String(String),
+    Boolean(bool),
+    Null,
+    Undefined,
+    Object(Rc<RefCell<Object<F>>>),
+    Function(Rc<FunctionValue<F>>),
+}
+
+// Objects and functions are shared, not copied
+impl<F> Clone for Value<F> {
+    fn clone(&self) -> Self {
+        match self {
+            Value::Number(number) => Value::Number(number.clone()),
+            Value::String(value) => Value::String(value.clone()),
+            Value::Boolean(value) => Value::Boolean(*value),
+            Value::Null => Value::Null,
+            Value::Undefined => Value::Undefined,
+            Value::Object(object) => Value::Object(object.clone()),
+            Value::Function(function) => Value::Function(function.clone()),
+        }
+    }
+}
+
+// Fields keep their insertion order so `for .. in` and printing are stable
+#[derive(Debug)]
+pub struct Object<F> {
+    pub fields: Vec<(String, Value<F>)>,
+}
+
+impl<F> Default for Object<F> {
+    fn default() -> Self {
+        Self { fields: Vec::new() }
+    }
+}
+
+impl<F> Object<F> {
+    pub fn get(&self, key: &str) -> Value<F> {
+        self.fields
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.clone())
+            .unwrap_or(Value::Undefined)
+    }
+
+    pub fn set(&mut self, key: &str, value: Value<F>) {
+        match self.fields.iter_mut().find(|(name, _)| name == key) {
+            Some((_, slot)) => *slot = value,
+            None => self.fields.push((key.to_string(), value)),
+        }
+    }
+}
+
+// Natives only see the output stream, so the interpreter and the VM share them
+pub type NativeFunction<F> = fn(&mut dyn Write, Vec<Value<F>>) -> Result<Value<F>, RuntimeError>;
+
+pub enum FunctionValue<F> {
+    Native {
+        name: &'static str,
+        call: NativeFunction<F>,
+    },
+    // A function the program defines, as the engine running it represents it
+    Defined(F),
+}
+
+impl<F: fmt::Debug> fmt::Debug for FunctionValue<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FunctionValue::Native { name, .. } => write!(f, "<native fn {name}>"),
+            FunctionValue::Defined(function) => function.fmt(f),
+        }
+    }
+}
+
+impl<F: fmt::Display> fmt::Display for FunctionValue<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FunctionValue::Native { name, .. } => write!(f, "<native fn {name}>"),
+            FunctionValue::Defined(function) => function.fmt(f),
+        }
+    }
+}
+
+impl<F> Value<F> {
+    // `false`, `null`, `undefined`, zero, NaN and the empty string are falsy
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Boolean(value) => *value,
+            Value::Null | Value::Undefined => false,
+            Value::Number(NumberToken::Float(value)) if value.is_nan() => false,
+            Value::Number(number) => !number.is_zero(),
+            Value::String(value) => !value.is_empty(),
+            Value::Object(_) | Value::Function(_) => true,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Boolean(_) => "boolean",
+            Value::Null => "null",
+            Value::Undefined => "undefined",
+            Value::Object(_) => "object",
+            Value::Function(_) => "function",
+        }
+    }
+}
+
+impl<F> From<LiteralToken> for Value<F> {
+    fn from(literal: LiteralToken) -> Self {
+        match literal {
+            LiteralToken::Number(number) => Value::Number(number),
+            LiteralToken::String(value) => Value::String(value),
+            LiteralToken::Boolean(value) => Value::Boolean(value),
+            LiteralToken::Null => Value::Null,
+            LiteralToken::Undefined => Value::Undefined,
+        }
+    }
+}
+
+// Primitives compare by value, with integers and floats compared numerically;
+// objects and functions compare by identity
+impl<F> PartialEq for Value<F> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Number(left), Value::Number(right)) => {
+                left.numeric_cmp(right) == Some(Ordering::Equal)
+            }
+            (Value::String(left), Value::String(right)) => left == right,
+            (Value::Boolean(left), Value::Boolean(right)) => left == right,
+            (Value::Null, Value::Null) | (Value::Undefined, Value::Undefined) => true,
+            (Value::Object(left), Value::Object(right)) => Rc::ptr_eq(left, right),
+            (Value::Function(left), Value::Function(right)) => Rc::ptr_eq(left, right),
+            _ => false,
+        }
+    }
+}
+
+impl<F: fmt::Display> fmt::Display for Value<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(number) => write!(f, "{number}"),
+            Value::String(value) => write!(f, "{value}"),
+            Value::Boolean(value) => write!(f, "{value}"),
+            Value::Null => write!(f, "null"),
+            Value::Undefined => write!(f, "undefined"),
+            Value::Object(object) => {
+                let object = object.borrow();
+                if object.fields.is_empty() {
+                    return write!(f, "{{}}");
+                }
+                write!(f, "{{ ")?;
+                for (index, (key, value)) in object.fields.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    match value {
+                        Value::String(value) => write!(f, "{key}: {value:?}")?,
+                        value => write!(f, "{key}: {value}")?,
+                    }
+                }
+                write!(f, " }}")
+            }
+            Value::Function(function) => write!(f, "{function}"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct RuntimeError {
+    pub message: String,
+    pub span: TokenSpan,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {}, offset {}",
+            self.message,
+            self.span.line + 1,
+            self.span.start
+        )
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+impl From<RuntimeError> for Diagnostic {
+    fn from(error: RuntimeError) -> Self {
+        Diagnostic::error("E0200", error.message, error.span)
+    }
+}
+
+pub(crate) fn error<T>(message: impl Into<String>, span: &TokenSpan) -> Result<T, RuntimeError> {
+    Err(RuntimeError {
+        message: message.into(),
+        span: span.clone(),
+    })
+}
+
+// The binary operator a compound assignment such as `+=` applies
+pub(crate) fn compound_operator(operator: &AssignmentToken) -> BinaryOperator {
+    BinaryOperator::Arithmetic(match operator {
+        AssignmentToken::PlusAssign => ArithmeticToken::Add,
+        AssignmentToken::MinusAssign => ArithmeticToken::Subtract,
+        AssignmentToken::MultiplyAssign => ArithmeticToken::Multiply,
+        AssignmentToken::DivideAssign => ArithmeticToken::Divide,
+        AssignmentToken::BitwiseAndAssign => ArithmeticToken::BitwiseAnd,
+        _ => ArithmeticToken::BitwiseOr,
+    })
+}
+
+// `for .. in` visits the keys of an object or the characters of a string
+pub(crate) fn iterate<F>(value: Value<F>, span: &TokenSpan) -> Result<Vec<Value<F>>, RuntimeError> {
+    match value {
+        Value::Object(object) => Ok(object
+            .borrow()
+            .fields
+            .iter()
+            .map(|(key, _)| Value::String(key.clone()))
+            .collect()),
+        Value::String(value) => Ok(value
+            .chars()
+            .map(|c| Value::String(c.to_string()))
+            .collect()),
+        other => error(format!("cannot iterate over a {}", other.type_name()), span),
+    }
+}
+
+pub(crate) fn get_index<F>(
+    object: &Value<F>,
+    index: &Value<F>,
+    span: &TokenSpan,
+) -> Result<Value<F>, RuntimeError> {
+    match (object, index) {
+        (Value::Object(object), Value::String(key)) => Ok(object.borrow().get(key)),
+        (Value::String(value), Value::Number(NumberToken::SignedInteger(index))) => {
+            Ok(usize::try_from(*index)
+                .ok()
+                .and_then(|index| value.chars().nth(index))
+                .map(|c| Value::String(c.to_string()))
+                .unwrap_or(Value::Undefined))
+        }
+        _ => error(
+            format!(
+                "cannot index a {} with a {}",
+                object.type_name(),
+                index.type_name()
+            ),
+            span,
+        ),
+    }
+}
+
+pub(crate) fn set_index<F>(
+    object: &Value<F>,
+    key: &Value<F>,
+    value: Value<F>,
+    span: &TokenSpan,
+) -> Result<(), RuntimeError> {
+    match (object, key) {
+        (Value::Object(object), Value::String(key)) => {
+            object.borrow_mut().set(key, value);
+            Ok(())
+        }
+        _ => error(
+            format!(
+                "cannot assign to a {} key of a {}",
+                key.type_name(),
+                object.type_name()
+            ),
+            span,
+        ),
+    }
+}
+
+pub(crate) fn get_member<F>(
+    object: &Value<F>,
+    name: &str,
+    span: &TokenSpan,
+) -> Result<Value<F>, RuntimeError> {
+    match object {
+        Value::Object(object) => Ok(object.borrow().get(name)),
+        Value::String(value) if name == "length" => Ok(Value::Number(NumberToken::SignedInteger(
+            value.chars().count() as i64,
+        ))),
+        other => error(
+            format!("cannot read `{name}` of a {}", other.type_name()),
+            span,
+        ),
+    }
+}
+
+pub(crate) fn binary<F: fmt::Display>(
+    operator: &BinaryOperator,
+    left: Value<F>,
+    right: Value<F>,
+    span: &TokenSpan,
+) -> Result<Value<F>, RuntimeError> {
+    use ArithmeticToken as A;
+    let type_error = |left: &Value<F>, right: &Value<F>| {
+        error(
+            format!(
+                "unsupported operand types for {operator:?}: {} and {}",
+                left.type_name(),
+                right.type_name()
+            ),
+            span,
+        )
+    };
+    match operator {
+        BinaryOperator::Arithmetic(arithmetic) => match (arithmetic, left, right) {
+            (A::Add, Value::Number(left), Value::Number(right)) => Ok(Value::Number(left + right)),
+            // `+` with a string on either side concatenates
+            (A::Add, left @ Value::String(_), right) | (A::Add, left, right @ Value::String(_)) => {
+                Ok(Value::String(format!("{left}{right}")))
+            }
+            (A::Subtract, Value::Number(left), Value::Number(right)) => {
+                Ok(Value::Number(left - right))
+            }
+            (A::Multiply, Value::Number(left), Value::Number(right)) => {
+                Ok(Value::Number(left * right))
+            }
+            (A::Divide, Value::Number(left), Value::Number(right)) => {
+                Ok(Value::Number(left / right))
+            }
+            (A::Modulo, Value::Number(left), Value::Number(right)) => {
+                Ok(Value::Number(left % right))
+            }
+            (
+                A::BitwiseAnd,
+                Value::Number(NumberToken::SignedInteger(left)),
+                Value::Number(NumberToken::SignedInteger(right)),
+            ) => Ok(Value::Number(NumberToken::SignedInteger(left & right))),
+            (
+                A::BitwiseOr,
+                Value::Number(NumberToken::SignedInteger(left)),
+                Value::Number(NumberToken::SignedInteger(right)),
+            ) => Ok(Value::Number(NumberToken::SignedInteger(left | right))),
+            (_, left, right) => type_error(&left, &right),
+        },
+        BinaryOperator::Comparison(comparison) => {
+            let ordering = match (&left, &right) {
+                (Value::Number(l), Value::Number(r)) => l.numeric_cmp(r),
+                (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+                _ => None,
+            };
+            let result = match comparison {
+                ComparisonToken::Equal => left == right,
+                ComparisonToken::NotEqual => left != right,
+                ComparisonToken::Not => unreachable!("`!` is never parsed as a binary operator"),
+                ordered => {
+                    if !matches!(
+                        (&left, &right),
+                        (Value::Number(_), Value::Number(_)) | (Value::String(_), Value::String(_))
+                    ) {
+                        return type_error(&left, &right);
+                    }
+                    // NaN compares false against everything
+                    ordering.is_some_and(|ordering| match ordered {
+                        ComparisonToken::GreaterThan => ordering.is_gt(),
+                        ComparisonToken::GreaterThanOrEqual => ordering.is_ge(),
+                        ComparisonToken::LessThan => ordering.is_lt(),
+                        _ => ordering.is_le(),
+                    })
+                }
+            };
+            Ok(Value::Boolean(result))
+        }
+        BinaryOperator::Logical(logical) => match logical {
+            // Reached only when short-circuiting did not apply
+            LogicalToken::And | LogicalToken::Or => Ok(right),
+            LogicalToken::XOr => Ok(Value::Boolean(left.is_truthy() != right.is_truthy())),
+            LogicalToken::XAnd => Ok(Value::Boolean(left.is_truthy() == right.is_truthy())),
+            LogicalToken::Not => unreachable!("`not` is never parsed as a binary operator"),
+        },
+    }
+}
+
+// The globals every program starts with
+pub(crate) fn builtins<F: fmt::Display>() -> [(&'static str, Value<F>); 1] {
+    [(
+        "print",
+        Value::Function(Rc::new(FunctionValue::Native {
+            name: "print",
+            call: native_print,
+        })),
+    )]
+}
+
+fn native_print<F: fmt::Display>(
+    output: &mut dyn Write,
+    arguments: Vec<Value<F>>,
+) -> Result<Value<F>, RuntimeError> {
+    let line = arguments
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+    // Output is best effort; a closed pipe should not abort the program
+    let _ = writeln!(output, "{line}");
+    Ok(Value::Undefined)
+}
@@ -0,0 +1,118 @@
+// 1-based position of a byte offset, with the column counted in characters
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct LineColumn {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub name: String,
+    pub text: String,
+    // Byte offset at which each line starts; always begins with 0
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    pub fn new(name: &str, text: &str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
+        SourceFile {
+            name: name.to_string(),
+            text: text.to_string(),
+            line_starts,
+        }
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    // Zero-based index of the line containing `offset`; offsets past the end
+    // belong to the last line
+    fn line_index(&self, offset: usize) -> usize {
+        match self.line_starts.binary_search(&offset) {
+            Ok(index) => index,
+            Err(index) => index - 1,
+        }
+    }
+
+    pub fn line_column(&self, offset: usize) -> LineColumn {
+        let offset = self.floor_char_boundary(offset);
+        let index = self.line_index(offset);
+        let column = self.text[self.line_starts[index]..offset].chars().count() + 1;
+        LineColumn {
+            line: index + 1,
+            column,
+        }
+    }
+
+    // Zero-based line and column of `offset` with the column counted in
+    // UTF-16 code units, the way JavaScript source maps count them
+    pub fn utf16_position(&self, offset: usize) -> (usize, usize) {
+        let offset = self.floor_char_boundary(offset);
+        let index = self.line_index(offset);
+        let column = self.text[self.line_starts[index]..offset]
+            .encode_utf16()
+            .count();
+        (index, column)
+    }
+
+    // Text of the 1-based `line`, without its line terminator
+    pub fn line_text(&self, line: usize) -> &str {
+        let start = self.line_starts[line - 1];
+        let end = self
+            .line_starts
+            .get(line)
+            .map_or(self.text.len(), |next| next - 1);
+        self.text[start..end].trim_end_matches('\r')
+    }
+
+    fn floor_char_boundary(&self, offset: usize) -> usize {
+        let mut offset = offset.min(self.text.len());
+        while !self.text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        offset
+    }
+}
+
+// Every file the compiler has loaded. Files are laid out one after another in
+// a single offset space, so a global offset identifies both the file and the
+// position within it.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    // Each file with the global offset at which it starts
+    files: Vec<(usize, SourceFile)>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adds a file and returns the global offset of its first byte
+    pub fn add_file(&mut self, name: &str, text: &str) -> usize {
+        // Leave a one byte gap so an offset at the end of one file is not the
+        // start of the next
+        let base = self
+            .files
+            .last()
+            .map_or(0, |(base, file)| base + file.text.len() + 1);
+        self.files.push((base, SourceFile::new(name, text)));
+        base
+    }
+
+    // The file containing the global `offset` and the offset local to it
+    pub fn lookup(&self, offset: usize) -> Option<(&SourceFile, usize)> {
+        let index = self.files.partition_point(|(base, _)| *base <= offset);
+        let (base, file) = self.files.get(index.checked_sub(1)?)?;
+        (offset - base <= file.text.len()).then_some((file, offset - base))
+    }
+
+    pub fn line_column(&self, offset: usize) -> Option<(&str, LineColumn)> {
+        let (file, local) = self.lookup(offset)?;
+        Some((file.name.as_str(), file.line_column(local)))
+    }
+}
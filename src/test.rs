@@ -1,11 +1,81 @@
+// Helpers shared by the test modules below
+#[cfg(test)]
+mod fixtures {
+    use crate::interpreter::{with_stack, Interpreter};
+    use crate::lexer::Scanner;
+    use crate::parser::parse;
+    use crate::token::{TokenSpan, TokenType};
+    use std::cell::RefCell;
+    use std::io::Write;
+    use std::rc::Rc;
+
+    // An output that can be handed to a program and read back afterwards
+    #[derive(Clone, Default)]
+    pub struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedOutput {
+        pub fn text(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    // A span on the first line, where UTF-16 offsets match byte offsets
+    pub fn span(start: usize, end: usize) -> TokenSpan {
+        TokenSpan {
+            start,
+            end,
+            line: 0,
+            utf16_start: start,
+            utf16_end: end,
+        }
+    }
+
+    // The types of the tokens of `input`, leaving out whitespace
+    pub fn token_types(input: &str) -> Vec<TokenType> {
+        Scanner::new(input)
+            .map(|token| token.token_type)
+            .filter(|token_type| !matches!(token_type, TokenType::WhiteSpace(_)))
+            .collect()
+    }
+
+    // Runs `source` with the tree-walking interpreter, returning what it
+    // printed and the message of the error it stopped with, if any
+    pub fn run_interpreter(source: &str) -> (String, Option<String>) {
+        let program = parse(source).unwrap();
+        with_stack(|| {
+            let output = SharedOutput::default();
+            let result = Interpreter::new(Box::new(output.clone())).run(&program);
+            (output.text(), result.err().map(|error| error.message))
+        })
+    }
+
+    // Checks that `run` gives what the interpreter does for `source`, and
+    // returns what was printed
+    pub fn same(source: &str, run: impl Fn(&str) -> (String, Option<String>)) -> String {
+        let output = run(source);
+        assert_eq!(output, run_interpreter(source), "for {source}");
+        output.0
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::lexer::Scanner;
+    use crate::test::fixtures::token_types;
     use crate::token::LogicalToken::Or;
     use crate::token::{
         ArithmeticToken, AssignmentToken, ComparisonToken, ControlFlowToken, DeclarationToken,
         DelimiterToken, IdentifierToken, LiteralToken, LogicalToken, NumberToken,
-        ObjectReferenceToken, PunctuatorToken, Token, TokenSpan, TokenType,
+        ObjectReferenceToken, PunctuatorToken, Token, TokenSpan, TokenType, WhiteSpaceToken,
     };
 
     #[test]
@@ -20,6 +90,8 @@ mod tests {
                     start: 0,
                     end: 4,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 4,
                 }
             )
         );
@@ -37,6 +109,8 @@ mod tests {
                     start: 0,
                     end: 0,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 0,
                 }
             )
         );
@@ -53,6 +127,8 @@ mod tests {
                     start: 0,
                     end: 3,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 3,
                 }
             )
         );
@@ -69,6 +145,8 @@ mod tests {
                     start: 0,
                     end: 2,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 2,
                 }
             )
         );
@@ -85,6 +163,8 @@ mod tests {
                     start: 0,
                     end: 3,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 3,
                 }
             )
         );
@@ -103,6 +183,8 @@ mod tests {
                     start: 0,
                     end: 4,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 4,
                 }
             )
         );
@@ -120,6 +202,8 @@ mod tests {
                     start: 0,
                     end: 5,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 5,
                 }
             )
         );
@@ -137,6 +221,8 @@ mod tests {
                     start: 0,
                     end: 4,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 4,
                 }
             )
         );
@@ -154,6 +240,8 @@ mod tests {
                     start: 0,
                     end: 9,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 9,
                 }
             )
         );
@@ -171,6 +259,8 @@ mod tests {
                     start: 0,
                     end: 4,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 4,
                 }
             )
         );
@@ -188,6 +278,8 @@ mod tests {
                     start: 0,
                     end: 5,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 5,
                 }
             )
         );
@@ -205,6 +297,8 @@ mod tests {
                     start: 0,
                     end: 3,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 3,
                 }
             )
         );
@@ -221,8 +315,10 @@ mod tests {
                 TokenType::Delimiter(DelimiterToken::OpenParenthesis),
                 TokenSpan {
                     start: 0,
-                    end: 0,
+                    end: 1,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 1,
                 }
             )
         );
@@ -238,8 +334,10 @@ mod tests {
                 TokenType::Delimiter(DelimiterToken::CloseParenthesis),
                 TokenSpan {
                     start: 0,
-                    end: 0,
+                    end: 1,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 1,
                 }
             )
         );
@@ -252,16 +350,30 @@ mod tests {
         assert_eq!(
             token,
             Token::new(
-                TokenType::Delimiter(DelimiterToken::OpenBracket),
+                TokenType::Delimiter(DelimiterToken::OpenBrace),
                 TokenSpan {
                     start: 0,
-                    end: 0,
+                    end: 1,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 1,
                 }
             )
         );
     }
 
+    #[test]
+    fn bracket_delimiters() {
+        let tokens: Vec<TokenType> = Scanner::new("[]").map(|token| token.token_type).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                TokenType::Delimiter(DelimiterToken::OpenBracket),
+                TokenType::Delimiter(DelimiterToken::CloseBracket),
+            ]
+        );
+    }
+
     #[test]
     fn right_brace_delimiter() {
         let mut lexer = Scanner::new("}");
@@ -269,11 +381,13 @@ mod tests {
         assert_eq!(
             token,
             Token::new(
-                TokenType::Delimiter(DelimiterToken::CloseBracket),
+                TokenType::Delimiter(DelimiterToken::CloseBrace),
                 TokenSpan {
                     start: 0,
-                    end: 0,
+                    end: 1,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 1,
                 }
             )
         );
@@ -289,8 +403,10 @@ mod tests {
                 TokenType::Punctuation(PunctuatorToken::Semicolon),
                 TokenSpan {
                     start: 0,
-                    end: 0,
+                    end: 1,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 1,
                 }
             )
         );
@@ -306,8 +422,10 @@ mod tests {
                 TokenType::Punctuation(PunctuatorToken::Comma),
                 TokenSpan {
                     start: 0,
-                    end: 0,
+                    end: 1,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 1,
                 }
             )
         );
@@ -324,8 +442,10 @@ mod tests {
                 TokenType::Arithmetic(ArithmeticToken::Add),
                 TokenSpan {
                     start: 0,
-                    end: 0,
+                    end: 1,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 1,
                 }
             )
         );
@@ -341,8 +461,10 @@ mod tests {
                 TokenType::Arithmetic(ArithmeticToken::Subtract),
                 TokenSpan {
                     start: 0,
-                    end: 0,
+                    end: 1,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 1,
                 }
             )
         );
@@ -358,8 +480,10 @@ mod tests {
                 TokenType::Arithmetic(ArithmeticToken::Multiply),
                 TokenSpan {
                     start: 0,
-                    end: 0,
+                    end: 1,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 1,
                 }
             )
         );
@@ -375,8 +499,10 @@ mod tests {
                 TokenType::Arithmetic(ArithmeticToken::Divide),
                 TokenSpan {
                     start: 0,
-                    end: 0,
+                    end: 1,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 1,
                 }
             )
         );
@@ -393,8 +519,10 @@ mod tests {
                 TokenType::Comparison(ComparisonToken::Equal),
                 TokenSpan {
                     start: 0,
-                    end: 1,
+                    end: 2,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 2,
                 }
             )
         );
@@ -410,8 +538,10 @@ mod tests {
                 TokenType::Comparison(ComparisonToken::NotEqual),
                 TokenSpan {
                     start: 0,
-                    end: 1,
+                    end: 2,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 2,
                 }
             )
         );
@@ -427,8 +557,10 @@ mod tests {
                 TokenType::Comparison(ComparisonToken::GreaterThan),
                 TokenSpan {
                     start: 0,
-                    end: 0,
+                    end: 1,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 1,
                 }
             )
         );
@@ -444,8 +576,10 @@ mod tests {
                 TokenType::Comparison(ComparisonToken::LessThan),
                 TokenSpan {
                     start: 0,
-                    end: 0,
+                    end: 1,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 1,
                 }
             )
         );
@@ -463,6 +597,8 @@ mod tests {
                     start: 0,
                     end: 2,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 2,
                 }
             )
         );
@@ -478,8 +614,10 @@ mod tests {
                 TokenType::Comparison(ComparisonToken::LessThanOrEqual),
                 TokenSpan {
                     start: 0,
-                    end: 1,
+                    end: 2,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 2,
                 }
             )
         );
@@ -496,8 +634,10 @@ mod tests {
                 TokenType::Assignment(AssignmentToken::Assign),
                 TokenSpan {
                     start: 0,
-                    end: 0,
+                    end: 1,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 1,
                 }
             )
         );
@@ -516,6 +656,8 @@ mod tests {
                     start: 0,
                     end: 2,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 2,
                 }
             )
         );
@@ -533,6 +675,8 @@ mod tests {
                     start: 0,
                     end: 2,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 2,
                 }
             )
         );
@@ -548,11 +692,141 @@ mod tests {
                 TokenType::Comparison(ComparisonToken::Not),
                 TokenSpan {
                     start: 0,
-                    end: 0,
+                    end: 1,
+                    line: 0,
+                    utf16_start: 0,
+                    utf16_end: 1,
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn modulo_operator() {
+        let mut lexer = Scanner::new("%");
+        let token = lexer.next().unwrap();
+        assert_eq!(
+            token,
+            Token::new(
+                TokenType::Arithmetic(ArithmeticToken::Modulo),
+                TokenSpan {
+                    start: 0,
+                    end: 1,
+                    line: 0,
+                    utf16_start: 0,
+                    utf16_end: 1,
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn bitwise_operators() {
+        let tokens: Vec<TokenType> = Scanner::new("& |")
+            .map(|token| token.token_type)
+            .filter(|token_type| !matches!(token_type, TokenType::WhiteSpace(_)))
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                TokenType::Arithmetic(ArithmeticToken::BitwiseAnd),
+                TokenType::Arithmetic(ArithmeticToken::BitwiseOr),
+            ]
+        );
+    }
+
+    #[test]
+    fn compound_assignment_operators() {
+        let tokens: Vec<TokenType> = Scanner::new("+= -= *= /= &= |= &&= ||=")
+            .map(|token| token.token_type)
+            .filter(|token_type| !matches!(token_type, TokenType::WhiteSpace(_)))
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                TokenType::Assignment(AssignmentToken::PlusAssign),
+                TokenType::Assignment(AssignmentToken::MinusAssign),
+                TokenType::Assignment(AssignmentToken::MultiplyAssign),
+                TokenType::Assignment(AssignmentToken::DivideAssign),
+                TokenType::Assignment(AssignmentToken::BitwiseAndAssign),
+                TokenType::Assignment(AssignmentToken::BitwiseOrAssign),
+                TokenType::Assignment(AssignmentToken::AndAssign),
+                TokenType::Assignment(AssignmentToken::OrAssign),
+            ]
+        );
+    }
+
+    #[test]
+    fn and_assign_is_maximal_munch() {
+        let mut lexer = Scanner::new("&&=");
+        let token = lexer.next().unwrap();
+        assert_eq!(
+            token,
+            Token::new(
+                TokenType::Assignment(AssignmentToken::AndAssign),
+                TokenSpan {
+                    start: 0,
+                    end: 3,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 3,
                 }
             )
         );
+        assert_eq!(lexer.next(), None);
+    }
+
+    #[test]
+    fn adjacent_operators() {
+        let tokens: Vec<Token> = Scanner::new("==!").collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(
+                    TokenType::Comparison(ComparisonToken::Equal),
+                    TokenSpan {
+                        start: 0,
+                        end: 2,
+                        line: 0,
+                        utf16_start: 0,
+                        utf16_end: 2,
+                    }
+                ),
+                Token::new(
+                    TokenType::Comparison(ComparisonToken::Not),
+                    TokenSpan {
+                        start: 2,
+                        end: 3,
+                        line: 0,
+                        utf16_start: 2,
+                        utf16_end: 3,
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn word_operators() {
+        let tokens: Vec<TokenType> = Scanner::new("and or not xor xand")
+            .map(|token| token.token_type)
+            .filter(|token_type| !matches!(token_type, TokenType::WhiteSpace(_)))
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                TokenType::Logical(LogicalToken::And),
+                TokenType::Logical(LogicalToken::Or),
+                TokenType::Logical(LogicalToken::Not),
+                TokenType::Logical(LogicalToken::XOr),
+                TokenType::Logical(LogicalToken::XAnd),
+            ]
+        );
+    }
+
+    #[test]
+    fn word_and_symbol_operators_lex_alike() {
+        assert_eq!(token_types("a and b or c"), token_types("a && b || c"));
     }
 
     // Statement token tests
@@ -568,28 +842,46 @@ mod tests {
                     start: 0,
                     end: 2,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 2,
                 }
             )
         );
     }
     #[test]
     fn signed_number() {
+        // The sign is its own operator token; negation happens in the parser
         let mut lexer = Scanner::new("-100");
-        let token = lexer.next().unwrap();
         assert_eq!(
-            token,
+            lexer.next().unwrap(),
             Token::new(
-                TokenType::Literal(LiteralToken::Number(NumberToken::SignedInteger(-100))),
+                TokenType::Arithmetic(ArithmeticToken::Subtract),
                 TokenSpan {
                     start: 0,
+                    end: 1,
+                    line: 0,
+                    utf16_start: 0,
+                    utf16_end: 1,
+                }
+            )
+        );
+        assert_eq!(
+            lexer.next().unwrap(),
+            Token::new(
+                TokenType::Literal(LiteralToken::Number(NumberToken::SignedInteger(100))),
+                TokenSpan {
+                    start: 1,
                     end: 4,
                     line: 0,
+                    utf16_start: 1,
+                    utf16_end: 4,
                 }
             )
         );
     }
+    #[test]
     fn float_number() {
-        let mut lexer = Scanner::new("-100");
+        let mut lexer = Scanner::new("20.5");
         let token = lexer.next().unwrap();
         assert_eq!(
             token,
@@ -599,8 +891,3788 @@ mod tests {
                     start: 0,
                     end: 4,
                     line: 0,
+                    utf16_start: 0,
+                    utf16_end: 4,
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn non_ascii_identifier() {
+        let mut lexer = Scanner::new("café");
+        let token = lexer.next().unwrap();
+        assert_eq!(
+            token,
+            Token::new(
+                TokenType::Identifier(IdentifierToken::new("café".to_string())),
+                TokenSpan {
+                    start: 0,
+                    end: 5,
+                    line: 0,
+                    utf16_start: 0,
+                    utf16_end: 4,
                 }
             )
         );
+        assert_eq!(lexer.next(), None);
+    }
+
+    #[test]
+    fn astral_string_literal() {
+        let tokens: Vec<Token> = Scanner::new("\"😀\" x").collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(
+                    TokenType::Literal(LiteralToken::String("😀".to_string())),
+                    TokenSpan {
+                        start: 0,
+                        end: 6,
+                        line: 0,
+                        utf16_start: 0,
+                        utf16_end: 4,
+                    }
+                ),
+                Token::new(
+                    TokenType::WhiteSpace(WhiteSpaceToken::Space),
+                    TokenSpan {
+                        start: 6,
+                        end: 7,
+                        line: 0,
+                        utf16_start: 4,
+                        utf16_end: 5,
+                    }
+                ),
+                Token::new(
+                    TokenType::Identifier(IdentifierToken::new("x".to_string())),
+                    TokenSpan {
+                        start: 7,
+                        end: 8,
+                        line: 0,
+                        utf16_start: 5,
+                        utf16_end: 6,
+                    }
+                ),
+            ]
+        );
+    }
+}
+
+#[cfg(test)]
+mod parser_tests {
+    use crate::parser::{
+        parse, BinaryOperator, Expr, ExprKind, Identifier, Program, Stmt, StmtKind, UnaryOperator,
+    };
+    use crate::test::fixtures::span;
+    use crate::token::{
+        ArithmeticToken, AssignmentToken, ComparisonToken, LiteralToken, LogicalToken, NumberToken,
+        ObjectReferenceToken,
+    };
+
+    fn single_statement(input: &str) -> Stmt {
+        let Program { mut statements, .. } = parse(input).unwrap();
+        assert_eq!(statements.len(), 1, "{statements:?}");
+        statements.remove(0)
+    }
+
+    fn expression(input: &str) -> ExprKind {
+        match single_statement(input).kind {
+            StmtKind::Expression(expr) => expr.kind,
+            other => panic!("expected expression statement, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn let_binding() {
+        let stmt = single_statement("let x = 1 ;");
+        assert_eq!(stmt.span, span(0, 11));
+        match stmt.kind {
+            StmtKind::Let {
+                name,
+                annotation: None,
+                value,
+            } => {
+                assert_eq!(
+                    name,
+                    Identifier {
+                        name: "x".to_string(),
+                        span: span(4, 5),
+                    }
+                );
+                let value = value.unwrap();
+                assert_eq!(
+                    value.kind,
+                    ExprKind::Literal(LiteralToken::Number(NumberToken::SignedInteger(1)))
+                );
+                assert_eq!(value.span, span(8, 9));
+            }
+            other => panic!("expected let, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn let_without_initializer() {
+        let stmt = single_statement("let x");
+        assert!(matches!(stmt.kind, StmtKind::Let { value: None, .. }));
+    }
+
+    #[test]
+    fn function_declaration() {
+        let stmt = single_statement("fn add ( a , b ) { return a + b ; }");
+        assert_eq!(stmt.span, span(0, 35));
+        let StmtKind::Function(function) = stmt.kind else {
+            panic!("expected fn declaration");
+        };
+        assert_eq!(function.name.unwrap().name, "add");
+        let parameters: Vec<_> = function
+            .parameters
+            .iter()
+            .map(|p| p.name.name.as_str())
+            .collect();
+        assert_eq!(parameters, ["a", "b"]);
+        assert_eq!(function.body.statements.len(), 1);
+        assert!(matches!(
+            function.body.statements[0].kind,
+            StmtKind::Return(Some(_))
+        ));
+    }
+
+    #[test]
+    fn object_declaration_and_literal() {
+        let stmt = single_statement("obj point { x : 1 , y : 2 }");
+        let StmtKind::Object { name, fields, .. } = stmt.kind else {
+            panic!("expected obj declaration");
+        };
+        assert_eq!(name.name, "point");
+        let keys: Vec<_> = fields.iter().map(|f| f.key.name.as_str()).collect();
+        assert_eq!(keys, ["x", "y"]);
+
+        let stmt = single_statement("let p = obj { x : 1 , } ;");
+        let StmtKind::Let {
+            value: Some(value), ..
+        } = stmt.kind
+        else {
+            panic!("expected let");
+        };
+        assert!(matches!(value.kind, ExprKind::Object(ref fields) if fields.len() == 1));
+        assert_eq!(value.span, span(8, 23));
+    }
+
+    #[test]
+    fn if_else_chain() {
+        let stmt = single_statement("if a { b } else if c { d } else { e }");
+        assert_eq!(stmt.span, span(0, 37));
+        let StmtKind::If { else_branch, .. } = stmt.kind else {
+            panic!("expected if");
+        };
+        let else_if = else_branch.unwrap();
+        let StmtKind::If { else_branch, .. } = else_if.kind else {
+            panic!("expected else if");
+        };
+        assert!(matches!(else_branch.unwrap().kind, StmtKind::Block(_)));
+    }
+
+    #[test]
+    fn for_in_loop() {
+        let stmt = single_statement("for key in items { print ( key ) }");
+        let StmtKind::For {
+            binding,
+            iterable,
+            body,
+        } = stmt.kind
+        else {
+            panic!("expected for");
+        };
+        assert_eq!(binding.name, "key");
+        assert_eq!(iterable.kind, ExprKind::Identifier("items".to_string()));
+        assert_eq!(body.statements.len(), 1);
+    }
+
+    #[test]
+    fn call_member_and_index_chain() {
+        let ExprKind::Call { callee, arguments } = expression("a . b [ 0 ] ( c , d )") else {
+            panic!("expected call");
+        };
+        assert_eq!(arguments.len(), 2);
+        let ExprKind::Index { object, index } = callee.kind else {
+            panic!("expected index");
+        };
+        assert_eq!(
+            index.kind,
+            ExprKind::Literal(LiteralToken::Number(NumberToken::SignedInteger(0)))
+        );
+        assert_eq!(object.span, span(0, 5));
+        let ExprKind::Member { object, property } = object.kind else {
+            panic!("expected member access");
+        };
+        assert_eq!(object.kind, ExprKind::Identifier("a".to_string()));
+        assert_eq!(property.name, "b");
+    }
+
+    #[test]
+    fn new_and_this() {
+        let ExprKind::New { callee, arguments } = expression("new this . Point ( 1 )") else {
+            panic!("expected new");
+        };
+        assert_eq!(arguments.len(), 1);
+        let ExprKind::Member { object, .. } = callee.kind else {
+            panic!("expected member access");
+        };
+        assert_eq!(
+            object.kind,
+            ExprKind::ObjectReference(ObjectReferenceToken::This)
+        );
+    }
+
+    #[test]
+    fn multiplication_binds_tighter_than_addition() {
+        let ExprKind::Binary {
+            operator, right, ..
+        } = expression("1 + 2 * 3")
+        else {
+            panic!("expected binary expression");
+        };
+        assert_eq!(operator, BinaryOperator::Arithmetic(ArithmeticToken::Add));
+        assert!(matches!(
+            right.kind,
+            ExprKind::Binary {
+                operator: BinaryOperator::Arithmetic(ArithmeticToken::Multiply),
+                ..
+            }
+        ));
+    }
+
+    // Renders an expression fully parenthesized, prefix style
+    fn grouping(expr: &Expr) -> String {
+        match &expr.kind {
+            ExprKind::Identifier(name) => name.clone(),
+            ExprKind::Unary {
+                operator, operand, ..
+            } => {
+                let operator = match operator {
+                    UnaryOperator::Negate => "-",
+                    UnaryOperator::Not => "!",
+                    UnaryOperator::LogicalNot => "not",
+                };
+                format!("({operator} {})", grouping(operand))
+            }
+            ExprKind::Binary {
+                operator,
+                left,
+                right,
+                ..
+            } => {
+                let operator = match operator {
+                    BinaryOperator::Arithmetic(ArithmeticToken::Add) => "+",
+                    BinaryOperator::Arithmetic(ArithmeticToken::Subtract) => "-",
+                    BinaryOperator::Arithmetic(ArithmeticToken::Multiply) => "*",
+                    BinaryOperator::Arithmetic(ArithmeticToken::Divide) => "/",
+                    BinaryOperator::Arithmetic(ArithmeticToken::Modulo) => "%",
+                    BinaryOperator::Arithmetic(ArithmeticToken::BitwiseAnd) => "&",
+                    BinaryOperator::Arithmetic(ArithmeticToken::BitwiseOr) => "|",
+                    BinaryOperator::Comparison(ComparisonToken::Equal) => "==",
+                    BinaryOperator::Comparison(ComparisonToken::NotEqual) => "!=",
+                    BinaryOperator::Comparison(ComparisonToken::GreaterThan) => ">",
+                    BinaryOperator::Comparison(ComparisonToken::GreaterThanOrEqual) => ">=",
+                    BinaryOperator::Comparison(ComparisonToken::LessThan) => "<",
+                    BinaryOperator::Comparison(ComparisonToken::LessThanOrEqual) => "<=",
+                    BinaryOperator::Comparison(ComparisonToken::Not) => "!",
+                    BinaryOperator::Logical(LogicalToken::And) => "&&",
+                    BinaryOperator::Logical(LogicalToken::Or) => "||",
+                    BinaryOperator::Logical(LogicalToken::Not) => "not",
+                    BinaryOperator::Logical(LogicalToken::XOr) => "xor",
+                    BinaryOperator::Logical(LogicalToken::XAnd) => "xand",
+                };
+                format!("({operator} {} {})", grouping(left), grouping(right))
+            }
+            other => panic!("unexpected expression {other:?}"),
+        }
+    }
+
+    fn assert_grouping(input: &str, expected: &str) {
+        let StmtKind::Expression(expr) = single_statement(input).kind else {
+            panic!("expected expression statement");
+        };
+        assert_eq!(grouping(&expr), expected, "parsing `{input}`");
+    }
+
+    #[test]
+    fn precedence_table() {
+        // One case per adjacent pair of levels, loosest operator first
+        assert_grouping("a || b xor c", "(|| a (xor b c))");
+        assert_grouping("a or b xand c", "(|| a (xand b c))");
+        assert_grouping("a xor b && c", "(xor a (&& b c))");
+        assert_grouping("a xand b and c", "(xand a (&& b c))");
+        assert_grouping("a && b == c", "(&& a (== b c))");
+        assert_grouping("a != b < c", "(!= a (< b c))");
+        assert_grouping("a >= b | c", "(>= a (| b c))");
+        assert_grouping("a | b & c", "(| a (& b c))");
+        assert_grouping("a & b + c", "(& a (+ b c))");
+        assert_grouping("a - b * c", "(- a (* b c))");
+        assert_grouping("a + b / c", "(+ a (/ b c))");
+        // And the same pairs written the other way round
+        assert_grouping("a * b - c", "(- (* a b) c)");
+        assert_grouping("a & b == c", "(== (& a b) c)");
+        assert_grouping("a == b && c", "(&& (== a b) c)");
+        assert_grouping("a && b xor c", "(xor (&& a b) c)");
+        assert_grouping("a xor b || c", "(|| (xor a b) c)");
+    }
+
+    #[test]
+    fn mixed_arithmetic_and_xor() {
+        assert_grouping("a + b * c % d xor e", "(xor (+ a (% (* b c) d)) e)");
+    }
+
+    #[test]
+    fn binary_operators_are_left_associative() {
+        assert_grouping("a - b - c", "(- (- a b) c)");
+        assert_grouping("a / b % c", "(% (/ a b) c)");
+        assert_grouping("a <= b > c", "(> (<= a b) c)");
+        assert_grouping("a xand b xor c", "(xor (xand a b) c)");
+        assert_grouping("a || b or c", "(|| (|| a b) c)");
+    }
+
+    #[test]
+    fn prefix_operators_bind_tightest() {
+        assert_grouping("- a * b", "(* (- a) b)");
+        assert_grouping("! a == b", "(== (! a) b)");
+        assert_grouping("not not a && b", "(&& (not (not a)) b)");
+        assert_grouping("a - - b", "(- a (- b))");
+    }
+
+    #[test]
+    fn parentheses_override_precedence() {
+        assert_grouping("( a + b ) * c", "(* (+ a b) c)");
+    }
+
+    #[test]
+    fn operator_spans() {
+        let ExprKind::Binary {
+            operator_span,
+            right,
+            ..
+        } = expression("a xor - b")
+        else {
+            panic!("expected binary expression");
+        };
+        assert_eq!(operator_span, span(2, 5));
+        let ExprKind::Unary { operator_span, .. } = right.kind else {
+            panic!("expected unary expression");
+        };
+        assert_eq!(operator_span, span(6, 7));
+    }
+
+    #[test]
+    fn assignment_is_right_associative() {
+        let ExprKind::Assign {
+            operator, value, ..
+        } = expression("a = b += 1")
+        else {
+            panic!("expected assignment");
+        };
+        assert_eq!(operator, AssignmentToken::Assign);
+        assert!(matches!(
+            value.kind,
+            ExprKind::Assign {
+                operator: AssignmentToken::PlusAssign,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn invalid_assignment_target() {
+        let error = parse("1 = 2").unwrap_err();
+        assert_eq!(error.message, "invalid assignment target");
+        assert_eq!(error.span, span(0, 1));
+    }
+
+    #[test]
+    fn unclosed_block() {
+        let error = parse("fn f ( ) { return 1").unwrap_err();
+        assert_eq!(
+            error.message,
+            "expected `}` to close block, found end of input"
+        );
+    }
+
+    #[test]
+    fn export_marks_top_level_declarations() {
+        let stmt = single_statement("export let x = 1");
+        assert_eq!(stmt.export, Some(span(0, 6)));
+        assert_eq!(stmt.span, span(7, 16));
+        let stmt = single_statement("/// Doubles\nexport fn double(n) { return n * 2 }");
+        let StmtKind::Function(function) = stmt.kind else {
+            panic!("expected function declaration");
+        };
+        assert_eq!(function.doc.as_deref(), Some("Doubles"));
+        assert!(single_statement("obj O {}").export.is_none());
+
+        let error = parse("{ export let x = 1 }").unwrap_err();
+        assert_eq!(error.message, "`export` is only allowed at the top level");
+        assert_eq!(error.span, span(2, 8));
+        let error = parse("export x = 1").unwrap_err();
+        assert_eq!(
+            error.message,
+            "expected `let`, `fn` or `obj` after `export`, found identifier `x`"
+        );
+        let error = parse("export fn () {}").unwrap_err();
+        assert_eq!(error.message, "only a named declaration can be exported");
+        assert_eq!(error.span, span(0, 15));
+    }
+}
+
+#[cfg(test)]
+mod interpreter_tests {
+    use crate::test::fixtures::run_interpreter;
+    use crate::token::NumberToken;
+
+    // Runs `source` and returns everything it printed
+    fn run(source: &str) -> String {
+        match run_interpreter(source) {
+            (output, None) => output,
+            (_, Some(error)) => panic!("{source} failed: {error}"),
+        }
+    }
+
+    fn run_error(source: &str) -> String {
+        run_interpreter(source).1.expect("the program to fail")
+    }
+
+    #[test]
+    fn template_strings_interpolate() {
+        assert_eq!(
+            run("
+                let name = 'world';
+                let point = obj { x : 1 , y : 2.5 };
+                print(`hello ${name}!`);
+                print(`${point.x + point.y} = ${`${1}${2}`}`);
+                print(`${null}, ${undefined}, ${1 < 2}`);
+                print(`plain`);
+            "),
+            "hello world!\n3.5 = 12\nnull, undefined, true\nplain\n"
+        );
+    }
+
+    #[test]
+    fn number_arithmetic_promotes_on_overflow() {
+        use NumberToken::{Float, SignedInteger};
+        assert_eq!(SignedInteger(7) - SignedInteger(2), SignedInteger(5));
+        assert_eq!(SignedInteger(6) * SignedInteger(7), SignedInteger(42));
+        assert_eq!(SignedInteger(8) / SignedInteger(2), SignedInteger(4));
+        assert_eq!(SignedInteger(7) / SignedInteger(2), Float(3.5));
+        assert_eq!(SignedInteger(7) % SignedInteger(3), SignedInteger(1));
+        assert_eq!(Float(7.5) % SignedInteger(2), Float(1.5));
+        assert_eq!(
+            SignedInteger(i64::MIN) - SignedInteger(1),
+            Float(i64::MIN as f64 - 1.0)
+        );
+        assert_eq!(
+            SignedInteger(i64::MAX) * SignedInteger(2),
+            Float(i64::MAX as f64 * 2.0)
+        );
+        assert_eq!(
+            SignedInteger(i64::MIN) / SignedInteger(-1),
+            Float(-(i64::MIN as f64))
+        );
+        assert_eq!(SignedInteger(1) / SignedInteger(0), Float(f64::INFINITY));
+        assert_eq!(-SignedInteger(i64::MIN), Float(-(i64::MIN as f64)));
+    }
+
+    #[test]
+    fn arithmetic_and_strings() {
+        assert_eq!(run("print ( 1 + 2 * 3 , 7 / 2 , - 5 % 3 )"), "7 3.5 -2\n");
+        assert_eq!(run("print ( \"a\" + 1 + true )"), "a1true\n");
+    }
+
+    #[test]
+    fn let_scopes_shadow_and_restore() {
+        let source = "
+            let x = 1
+            { let x = 2 print ( x ) }
+            print ( x )
+            x = 3
+            print ( x )
+        ";
+        assert_eq!(run(source), "2\n1\n3\n");
+    }
+
+    #[test]
+    fn closures_capture_their_environment() {
+        let source = "
+            fn counter ( ) {
+                let count = 0
+                return fn ( ) { count += 1 return count }
+            }
+            let next = counter ( )
+            next ( )
+            next ( )
+            print ( next ( ) )
+        ";
+        assert_eq!(run(source), "3\n");
+    }
+
+    #[test]
+    fn recursion() {
+        let source = "
+            fn fib ( n ) { if n < 2 { return n } return fib ( n - 1 ) + fib ( n - 2 ) }
+            print ( fib ( 20 ) )
+        ";
+        assert_eq!(run(source), "6765\n");
+    }
+
+    #[test]
+    fn objects_methods_and_for_in() {
+        let source = "
+            obj point {
+                x : 1 ,
+                y : 2 ,
+                sum : fn ( ) { return this . x + this . y }
+            }
+            point . x = 10
+            point [ \"y\" ] += 5
+            for key in point { print ( key ) }
+            print ( point . sum ( ) )
+        ";
+        assert_eq!(run(source), "x\ny\nsum\n17\n");
+    }
+
+    #[test]
+    fn new_binds_this() {
+        let source = "
+            fn Point ( x ) { this . x = x }
+            let p = new Point ( 4 )
+            print ( p )
+        ";
+        assert_eq!(run(source), "{ x: 4 }\n");
+    }
+
+    #[test]
+    fn logical_operators() {
+        let source = "
+            print ( null || \"default\" , 0 && crash ( ) )
+            print ( true xor false , true xand false , not 0 )
+        ";
+        assert_eq!(run(source), "default 0\ntrue false true\n");
+    }
+
+    #[test]
+    fn runtime_errors() {
+        assert_eq!(run_error("missing"), "undefined variable `missing`");
+        assert_eq!(run_error("y = 1"), "assignment to undeclared variable `y`");
+        assert_eq!(run_error("let n = 1 n ( )"), "cannot call a number");
+    }
+}
+
+#[cfg(test)]
+mod diagnostic_tests {
+    use crate::diagnostic::{Label, Severity};
+    use crate::lexer::Scanner;
+    use crate::parser::Parser;
+    use crate::test::fixtures::span;
+    use crate::token::{IdentifierToken, LiteralToken, NumberToken, TokenType};
+
+    #[test]
+    fn unterminated_string() {
+        let mut scanner = Scanner::new("x = \"abc");
+        let tokens: Vec<TokenType> = scanner.by_ref().map(|token| token.token_type).collect();
+        assert_eq!(
+            tokens.last(),
+            Some(&TokenType::Literal(LiteralToken::String("abc".to_string())))
+        );
+        let diagnostics = scanner.take_diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        let diagnostic = &diagnostics[0];
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert_eq!(diagnostic.code, "E0001");
+        assert_eq!(diagnostic.message, "unterminated string literal");
+        assert_eq!(diagnostic.span, span(4, 8));
+        assert_eq!(
+            diagnostic.labels,
+            vec![Label {
+                span: span(4, 5),
+                message: "string starts here".to_string(),
+            }]
+        );
+        assert_eq!(diagnostic.notes, vec!["add a closing \" to end the string"]);
+    }
+
+    #[test]
+    fn unexpected_character_does_not_stop_scanning() {
+        let mut scanner = Scanner::new("a @ b # c");
+        let tokens: Vec<TokenType> = scanner
+            .by_ref()
+            .map(|token| token.token_type)
+            .filter(|token_type| !matches!(token_type, TokenType::WhiteSpace(_)))
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                TokenType::Identifier(IdentifierToken::new("a".to_string())),
+                TokenType::Unknown('@'),
+                TokenType::Identifier(IdentifierToken::new("b".to_string())),
+                TokenType::Unknown('#'),
+                TokenType::Identifier(IdentifierToken::new("c".to_string())),
+            ]
+        );
+        let diagnostics = scanner.diagnostics();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].code, "E0002");
+        assert_eq!(diagnostics[0].message, "unexpected character `@`");
+        assert_eq!(diagnostics[0].span, span(2, 3));
+        assert_eq!(diagnostics[1].span, span(6, 7));
+    }
+
+    #[test]
+    fn number_conversion_does_not_panic() {
+        assert_eq!(
+            NumberToken::try_from("42"),
+            Ok(NumberToken::SignedInteger(42))
+        );
+        assert_eq!(NumberToken::try_from("1.5"), Ok(NumberToken::Float(1.5)));
+        assert_eq!(
+            NumberToken::try_from("99999999999999999999"),
+            Ok(NumberToken::Float(1e20))
+        );
+        assert!(NumberToken::try_from("1,5").is_err());
+        assert!(NumberToken::try_from("inf").is_err());
+        assert!(NumberToken::try_from("NaN").is_err());
+    }
+
+    #[test]
+    fn parser_exposes_scanner_diagnostics() {
+        let mut parser = Parser::new(Scanner::new("let s = 'open"));
+        assert!(parser.parse_program().is_ok());
+        assert_eq!(parser.diagnostics().len(), 1);
+        assert_eq!(parser.diagnostics()[0].code, "E0001");
+    }
+}
+
+#[cfg(test)]
+mod render_tests {
+    use crate::diagnostic::Diagnostic;
+    use crate::lexer::Scanner;
+    use crate::render::{render, render_all, RenderStyle};
+    use crate::source_map::{LineColumn, SourceFile, SourceMap};
+    use crate::test::fixtures::span;
+
+    #[test]
+    fn line_and_column_lookup() {
+        let file = SourceFile::new("main.toy", "let a\r\nlet é = 1\n\nx");
+        assert_eq!(file.line_count(), 4);
+        assert_eq!(file.line_column(0), LineColumn { line: 1, column: 1 });
+        assert_eq!(file.line_column(7), LineColumn { line: 2, column: 1 });
+        // Columns count characters, so `=` after the two-byte `é` is column 7
+        assert_eq!(file.line_column(14), LineColumn { line: 2, column: 7 });
+        assert_eq!(file.line_column(18), LineColumn { line: 3, column: 1 });
+        assert_eq!(file.line_column(19), LineColumn { line: 4, column: 1 });
+        assert_eq!(file.line_column(100), LineColumn { line: 4, column: 2 });
+        assert_eq!(file.line_text(1), "let a");
+        assert_eq!(file.line_text(2), "let é = 1");
+        assert_eq!(file.line_text(3), "");
+    }
+
+    #[test]
+    fn source_map_finds_file() {
+        let mut map = SourceMap::new();
+        let first = map.add_file("a.toy", "one\ntwo");
+        let second = map.add_file("b.toy", "three");
+        assert_eq!(first, 0);
+        assert_eq!(second, 8);
+        assert_eq!(
+            map.line_column(5),
+            Some(("a.toy", LineColumn { line: 2, column: 2 }))
+        );
+        assert_eq!(
+            map.line_column(second + 2),
+            Some(("b.toy", LineColumn { line: 1, column: 3 }))
+        );
+        assert_eq!(map.line_column(100), None);
+    }
+
+    #[test]
+    fn renders_snippet_with_labels_and_notes() {
+        let source = "let x = 1\nlet s = \"abc";
+        let mut scanner = Scanner::new(source);
+        scanner.by_ref().for_each(drop);
+        let file = SourceFile::new("main.toy", source);
+        let rendered = render(&scanner.diagnostics()[0], &file, RenderStyle::Plain);
+        assert_eq!(
+            rendered,
+            "error[E0001]: unterminated string literal
+ --> main.toy:2:9
+  |
+2 | let s = \"abc
+  |         ^^^^
+  |         - string starts here
+  = note: add a closing \" to end the string
+"
+        );
+    }
+
+    #[test]
+    fn renders_suggestions_as_help() {
+        let file = SourceFile::new("main.toy", "if x = 1 {}");
+        let diagnostic = Diagnostic::error("L0005", "assignment in `if` condition", span(5, 6))
+            .with_suggestion("to compare the values instead", span(5, 6), "==")
+            .with_suggestion("or remove it", span(3, 8), "");
+        assert_eq!(
+            render(&diagnostic, &file, RenderStyle::Plain),
+            "error[L0005]: assignment in `if` condition
+ --> main.toy:1:6
+  |
+1 | if x = 1 {}
+  |      ^
+  = help: to compare the values instead: `==`
+  = help: or remove it
+"
+        );
+    }
+
+    #[test]
+    fn renders_labels_on_other_lines() {
+        let file = SourceFile::new("main.toy", "fn f ( ) {\n\n  return\n");
+        let diagnostic = Diagnostic::warning("W0001", "something odd", span(14, 20))
+            .with_label(span(9, 10), "block opened here");
+        assert_eq!(
+            render(&diagnostic, &file, RenderStyle::Plain),
+            "warning[W0001]: something odd
+ --> main.toy:3:3
+  |
+1 | fn f ( ) {
+  |          - block opened here
+3 |   return
+  |   ^^^^^^
+"
+        );
+    }
+
+    #[test]
+    fn html_output_is_escaped() {
+        let file = SourceFile::new("main.toy", "a < @");
+        let diagnostic = Diagnostic::error("E0002", "unexpected character `@`", span(4, 5));
+        let html = render_all(&[diagnostic], &file, RenderStyle::Html);
+        assert!(html.starts_with("<pre class=\"diagnostics\"><span class=\"severity-error\">"));
+        assert!(html.contains(" a &lt; @\n"));
+        assert!(!html.contains(" a < @"));
+        assert_eq!(render_all(&[], &file, RenderStyle::Html), "");
+    }
+
+    #[test]
+    fn ansi_output_is_colored() {
+        let file = SourceFile::new("main.toy", "@");
+        let diagnostic = Diagnostic::error("E0002", "unexpected character `@`", span(0, 1));
+        let rendered = render(&diagnostic, &file, RenderStyle::Ansi);
+        assert!(rendered.starts_with("\x1b[1;31merror[E0002]\x1b[0m"));
+        assert!(rendered.contains("\x1b[1;31m^\x1b[0m"));
+    }
+}
+
+#[cfg(test)]
+mod comment_tests {
+    use crate::lexer::Scanner;
+    use crate::parser::{parse, StmtKind};
+    use crate::test::fixtures::token_types;
+    use crate::token::{
+        ArithmeticToken, CommentToken, IdentifierToken, Token, TokenSpan, TokenType,
+        WhiteSpaceToken,
+    };
+
+    #[test]
+    fn line_comment_stops_at_newline() {
+        let tokens: Vec<Token> = Scanner::new("// note\nx").collect();
+        assert_eq!(
+            tokens[0],
+            Token::new(
+                TokenType::Comment(CommentToken::Line(" note".to_string())),
+                TokenSpan {
+                    start: 0,
+                    end: 7,
+                    line: 0,
+                    utf16_start: 0,
+                    utf16_end: 7,
+                }
+            )
+        );
+        assert_eq!(
+            tokens[1].token_type,
+            TokenType::WhiteSpace(WhiteSpaceToken::NewLine)
+        );
+        assert_eq!(
+            tokens[2].token_type,
+            TokenType::Identifier(IdentifierToken::new("x".to_string()))
+        );
+    }
+
+    #[test]
+    fn doc_comments() {
+        assert_eq!(
+            token_types("/// docs\n//// rule"),
+            vec![
+                TokenType::Comment(CommentToken::Doc(" docs".to_string())),
+                TokenType::Comment(CommentToken::Line("// rule".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_block_comment() {
+        let tokens: Vec<Token> = Scanner::new("/* a /* b */ c */ /").collect();
+        assert_eq!(
+            tokens[0].token_type,
+            TokenType::Comment(CommentToken::Block(" a /* b */ c ".to_string()))
+        );
+        assert_eq!(tokens[0].token_span.end, 17);
+        assert_eq!(
+            tokens[2].token_type,
+            TokenType::Arithmetic(ArithmeticToken::Divide)
+        );
+    }
+
+    #[test]
+    fn block_comment_counts_lines() {
+        let tokens: Vec<Token> = Scanner::new("/*\n\n*/ x").collect();
+        assert_eq!(tokens[2].token_span.line, 2);
+    }
+
+    #[test]
+    fn unterminated_block_comment() {
+        let mut scanner = Scanner::new("x /* a /* b */");
+        let tokens: Vec<TokenType> = scanner.by_ref().map(|token| token.token_type).collect();
+        assert_eq!(
+            tokens.last(),
+            Some(&TokenType::Comment(CommentToken::Block(
+                " a /* b */".to_string()
+            )))
+        );
+        let diagnostics = scanner.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "E0003");
+        assert_eq!(diagnostics[0].span.start, 2);
+        assert_eq!(diagnostics[0].span.end, 14);
+    }
+
+    #[test]
+    fn parser_skips_comments_and_attaches_docs() {
+        let program = parse(
+            "
+            /// Adds two numbers.
+            ///
+            /// Works on floats too.
+            fn add ( a , /* second */ b ) { return a + b } // trailing
+
+            /// Not attached: a plain comment follows
+            // plain
+            obj origin { x : 0 }
+            ",
+        )
+        .unwrap();
+        let StmtKind::Function(function) = &program.statements[0].kind else {
+            panic!("expected fn declaration");
+        };
+        assert_eq!(
+            function.doc.as_deref(),
+            Some("Adds two numbers.\n\nWorks on floats too.")
+        );
+        assert_eq!(function.parameters.len(), 2);
+        let StmtKind::Object { doc, .. } = &program.statements[1].kind else {
+            panic!("expected obj declaration");
+        };
+        assert_eq!(doc, &None);
+    }
+
+    #[test]
+    fn obj_declaration_docs() {
+        let program = parse("/// The origin\nobj origin { x : 0 }").unwrap();
+        let StmtKind::Object { doc, .. } = &program.statements[0].kind else {
+            panic!("expected obj declaration");
+        };
+        assert_eq!(doc.as_deref(), Some("The origin"));
+    }
+}
+
+#[cfg(test)]
+mod number_tests {
+    use crate::interpreter::Value;
+    use crate::lexer::Scanner;
+    use crate::parser::{ExprKind, Parser, StmtKind};
+    use crate::token::{LiteralToken, NumberToken, PunctuatorToken, TokenType};
+    use num_bigint::BigInt;
+
+    fn number(input: &str) -> NumberToken {
+        let mut scanner = Scanner::new(input);
+        let token = scanner.next().unwrap();
+        assert!(
+            scanner.end_of_input(),
+            "`{input}` scanned as several tokens"
+        );
+        assert_eq!(scanner.diagnostics(), &[], "`{input}`");
+        match token.token_type {
+            TokenType::Literal(LiteralToken::Number(number)) => number,
+            other => panic!("`{input}` scanned as {other:?}"),
+        }
+    }
+
+    fn codes(input: &str) -> Vec<(&'static str, String, usize, usize)> {
+        let mut scanner = Scanner::new(input);
+        scanner.by_ref().for_each(drop);
+        scanner
+            .diagnostics()
+            .iter()
+            .map(|d| (d.code, d.message.clone(), d.span.start, d.span.end))
+            .collect()
+    }
+
+    #[test]
+    fn decimal_literals() {
+        use NumberToken::{Float, SignedInteger};
+        assert_eq!(number("0"), SignedInteger(0));
+        assert_eq!(number("1_000_000"), SignedInteger(1_000_000));
+        assert_eq!(number("1.5"), Float(1.5));
+        assert_eq!(number("1e10"), Float(1e10));
+        assert_eq!(number("2.5E-3"), Float(2.5e-3));
+        assert_eq!(number("6.02e+23"), Float(6.02e23));
+        assert_eq!(number("1_0.2_5"), Float(10.25));
+    }
+
+    #[test]
+    fn radix_literals() {
+        use NumberToken::SignedInteger;
+        assert_eq!(number("0xFF"), SignedInteger(255));
+        assert_eq!(number("0Xdead_BEEF"), SignedInteger(0xdead_beef));
+        assert_eq!(number("0o17"), SignedInteger(15));
+        assert_eq!(number("0b1010_1010"), SignedInteger(170));
+    }
+
+    #[test]
+    fn bigint_literals() {
+        assert_eq!(number("123n"), NumberToken::BigInt(BigInt::from(123)));
+        assert_eq!(number("0xFFn"), NumberToken::BigInt(BigInt::from(255)));
+        assert_eq!(
+            number("123456789012345678901234567890n").to_string(),
+            "123456789012345678901234567890"
+        );
+    }
+
+    #[test]
+    fn identifiers_are_not_numbers() {
+        for word in ["inf", "NaN", "infinity", "e10"] {
+            let token = Scanner::new(word).next().unwrap();
+            assert!(
+                matches!(token.token_type, TokenType::Identifier(_)),
+                "{word}"
+            );
+        }
+    }
+
+    #[test]
+    fn dot_without_digits_is_member_access() {
+        let types: Vec<TokenType> = Scanner::new("1.max")
+            .map(|token| token.token_type)
+            .collect();
+        assert_eq!(types[1], TokenType::Punctuation(PunctuatorToken::Dot));
+    }
+
+    #[test]
+    fn integer_overflow_is_reported() {
+        let mut scanner = Scanner::new("x = 99999999999999999999");
+        let tokens: Vec<_> = scanner.by_ref().collect();
+        assert_eq!(
+            tokens.last().unwrap().token_type,
+            TokenType::Literal(LiteralToken::Number(NumberToken::Float(1e20)))
+        );
+        let diagnostics = scanner.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "E0004");
+        assert_eq!(diagnostics[0].message, "integer literal is too large");
+        assert_eq!(
+            (diagnostics[0].span.start, diagnostics[0].span.end),
+            (4, 24)
+        );
+        assert_eq!(codes("9223372036854775807"), vec![]);
+        assert_eq!(codes("9223372036854775808n"), vec![]);
+        assert_eq!(codes("0x8000000000000000").len(), 1);
+    }
+
+    #[test]
+    fn malformed_literals() {
+        let error = |message: &str, start, end| ("E0005", message.to_string(), start, end);
+        assert_eq!(
+            codes("0b102"),
+            vec![error("invalid digit `2` in binary literal", 4, 5)]
+        );
+        assert_eq!(
+            codes("0o8"),
+            vec![error("invalid digit `8` in octal literal", 2, 3)]
+        );
+        assert_eq!(
+            codes("12px"),
+            vec![error("invalid suffix `px` on number literal", 2, 4)]
+        );
+        assert_eq!(codes("0x"), vec![error("missing digits after `0x`", 0, 2)]);
+        let separator = "numeric separator must sit between two digits";
+        assert_eq!(
+            codes("1__0"),
+            vec![error(separator, 1, 2), error(separator, 2, 3)]
+        );
+        assert_eq!(codes("1_"), vec![error(separator, 1, 2)]);
+        assert_eq!(codes("0x_1"), vec![error(separator, 2, 3)]);
+        assert_eq!(codes("1_.5"), vec![error(separator, 1, 2)]);
+        assert_eq!(
+            codes("1.5n"),
+            vec![error(
+                "a BigInt literal cannot have a fraction or exponent",
+                0,
+                4
+            )]
+        );
+    }
+
+    #[test]
+    fn parser_reads_fractional_literals() {
+        let program = Parser::new(Scanner::new("let x = 1.5 + 2e3;"))
+            .parse_program()
+            .unwrap();
+        let StmtKind::Let {
+            value: Some(value), ..
+        } = &program.statements[0].kind
+        else {
+            panic!("expected let");
+        };
+        let ExprKind::Binary { left, right, .. } = &value.kind else {
+            panic!("expected binary expression");
+        };
+        assert_eq!(
+            left.kind,
+            ExprKind::Literal(LiteralToken::Number(NumberToken::Float(1.5)))
+        );
+        assert_eq!(
+            right.kind,
+            ExprKind::Literal(LiteralToken::Number(NumberToken::Float(2000.0)))
+        );
+    }
+
+    #[test]
+    fn bigint_arithmetic() {
+        let big = |value: &str| NumberToken::BigInt(value.parse().unwrap());
+        assert_eq!(
+            big("9223372036854775807") + NumberToken::SignedInteger(1),
+            big("9223372036854775808")
+        );
+        assert_eq!(big("7") / big("2"), NumberToken::Float(3.5));
+        assert_eq!(big("8") / big("2"), big("4"));
+        assert_eq!(big("7") % big("2"), big("1"));
+        assert_eq!(-big("5"), big("-5"));
+        assert_eq!(big("1") + NumberToken::Float(0.5), NumberToken::Float(1.5));
+        assert_eq!(
+            Value::Number(big("3")),
+            Value::Number(NumberToken::SignedInteger(3))
+        );
+        assert!(!Value::Number(big("0")).is_truthy());
+    }
+}
+
+#[cfg(test)]
+mod template_tests {
+    use crate::lexer::Scanner;
+    use crate::parser::{parse, ExprKind, StmtKind};
+    use crate::test::fixtures::token_types;
+    use crate::token::{
+        ArithmeticToken, DelimiterToken, IdentifierToken, LiteralToken, TemplateToken, TokenType,
+    };
+
+    fn identifier(name: &str) -> TokenType {
+        TokenType::Identifier(IdentifierToken::new(name.to_string()))
+    }
+
+    fn template(part: fn(String) -> TemplateToken, text: &str) -> TokenType {
+        TokenType::Template(part(text.to_string()))
+    }
+
+    #[test]
+    fn template_without_interpolation_is_a_string() {
+        assert_eq!(
+            token_types("`a $ b {}`"),
+            vec![TokenType::Literal(LiteralToken::String(
+                "a $ b {}".to_string()
+            ))]
+        );
+    }
+
+    #[test]
+    fn template_parts() {
+        assert_eq!(
+            token_types("`a${x}b${y + 1}c`"),
+            vec![
+                template(TemplateToken::Start, "a"),
+                identifier("x"),
+                template(TemplateToken::Middle, "b"),
+                identifier("y"),
+                TokenType::Arithmetic(ArithmeticToken::Add),
+                TokenType::Literal(LiteralToken::Number(
+                    crate::token::NumberToken::SignedInteger(1)
+                )),
+                template(TemplateToken::End, "c"),
+            ]
+        );
+        let spans: Vec<(usize, usize)> = Scanner::new("`a${x}b`")
+            .map(|token| (token.token_span.start, token.token_span.end))
+            .collect();
+        assert_eq!(spans, vec![(0, 4), (4, 5), (5, 8)]);
+    }
+
+    #[test]
+    fn braces_inside_interpolation() {
+        assert_eq!(
+            token_types("`${ {} }`"),
+            vec![
+                template(TemplateToken::Start, ""),
+                TokenType::Delimiter(DelimiterToken::OpenBrace),
+                TokenType::Delimiter(DelimiterToken::CloseBrace),
+                template(TemplateToken::End, ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_templates() {
+        assert_eq!(
+            token_types("`a${`b${c}d`}e`"),
+            vec![
+                template(TemplateToken::Start, "a"),
+                template(TemplateToken::Start, "b"),
+                identifier("c"),
+                template(TemplateToken::End, "d"),
+                template(TemplateToken::End, "e"),
+            ]
+        );
+    }
+
+    #[test]
+    fn escaped_interpolation_is_text() {
+        assert_eq!(
+            token_types("`\\${x}`"),
+            vec![TokenType::Literal(LiteralToken::String("${x}".to_string()))]
+        );
+    }
+
+    #[test]
+    fn templates_span_lines() {
+        let tokens: Vec<_> = Scanner::new("`a\n${x}\nb` y").collect();
+        assert_eq!(tokens[1].token_span.line, 1);
+        assert_eq!(tokens.last().unwrap().token_span.line, 2);
+    }
+
+    #[test]
+    fn unterminated_template() {
+        let mut scanner = Scanner::new("`a${x}b");
+        scanner.by_ref().for_each(drop);
+        let diagnostics = scanner.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "E0001");
+        assert_eq!(diagnostics[0].span.start, 5);
+    }
+
+    #[test]
+    fn parse_template_expression() {
+        let program = parse("`a${x}b${y}c`").unwrap();
+        let StmtKind::Expression(expr) = &program.statements[0].kind else {
+            panic!("expected expression statement");
+        };
+        let ExprKind::Template {
+            strings,
+            expressions,
+        } = &expr.kind
+        else {
+            panic!("expected template");
+        };
+        assert_eq!(strings, &["a", "b", "c"]);
+        assert_eq!(expressions.len(), 2);
+        assert_eq!((expr.span.start, expr.span.end), (0, 13));
+    }
+
+    #[test]
+    fn empty_interpolation_is_an_error() {
+        let error = parse("`a${}b`").unwrap_err();
+        assert_eq!(
+            error.message,
+            "expected expression, found end of template interpolation"
+        );
+    }
+}
+
+#[cfg(test)]
+mod escape_tests {
+    use crate::lexer::Scanner;
+    use crate::token::{LiteralToken, TokenType};
+
+    // The string value of the single literal in `input` and the diagnostics
+    // as (code, message, start, end)
+    fn scan(input: &str) -> (String, Vec<(&'static str, String, usize, usize)>) {
+        let mut scanner = Scanner::new(input);
+        let tokens: Vec<_> = scanner.by_ref().collect();
+        let TokenType::Literal(LiteralToken::String(value)) = &tokens[0].token_type else {
+            panic!("expected a string, got {:?}", tokens[0].token_type);
+        };
+        let diagnostics = scanner
+            .diagnostics()
+            .iter()
+            .map(|d| (d.code, d.message.clone(), d.span.start, d.span.end))
+            .collect();
+        (value.clone(), diagnostics)
+    }
+
+    #[test]
+    fn simple_escapes() {
+        assert_eq!(
+            scan(r#""a\n\t\r\0\\\"\'\`\$""#),
+            ("a\n\t\r\0\\\"'`$".to_string(), vec![])
+        );
+    }
+
+    #[test]
+    fn hex_and_unicode_escapes() {
+        assert_eq!(scan(r#""\x41\x7e""#), ("A~".to_string(), vec![]));
+        assert_eq!(
+            scan(r#""\u{1F600} \u{e9}""#),
+            ("\u{1F600} é".to_string(), vec![])
+        );
+        assert_eq!(scan(r"`\u{41}`"), ("A".to_string(), vec![]));
+    }
+
+    #[test]
+    fn unknown_escape() {
+        assert_eq!(
+            scan(r#""a\qb""#),
+            (
+                "aqb".to_string(),
+                vec![("E0006", "unknown escape sequence `\\q`".to_string(), 2, 4)]
+            )
+        );
+    }
+
+    #[test]
+    fn invalid_hex_escape() {
+        assert_eq!(
+            scan(r#""\x4g""#),
+            (
+                "g".to_string(),
+                vec![("E0007", "invalid `\\x` escape".to_string(), 1, 4)]
+            )
+        );
+    }
+
+    #[test]
+    fn invalid_unicode_escapes() {
+        let invalid = |start, end| ("E0007", "invalid unicode escape".to_string(), start, end);
+        assert_eq!(scan(r#""\u0041""#).1, vec![invalid(1, 3)]);
+        assert_eq!(scan(r#""\u{}""#).1, vec![invalid(1, 5)]);
+        assert_eq!(scan(r#""\u{1234567}""#).1, vec![invalid(1, 12)]);
+        assert_eq!(scan(r#""\u{12""#).1[0], invalid(1, 6));
+        assert_eq!(
+            scan(r#""x\u{D800}""#).1,
+            vec![(
+                "E0007",
+                "unicode escape `\\u{D800}` is a surrogate".to_string(),
+                2,
+                10
+            )]
+        );
+        assert_eq!(
+            scan(r#""\u{110000}""#).1,
+            vec![(
+                "E0007",
+                "unicode escape `\\u{110000}` is out of range".to_string(),
+                1,
+                11
+            )]
+        );
+    }
+
+    #[test]
+    fn escape_spans_count_utf16() {
+        let mut scanner = Scanner::new("\"😀\\q\"");
+        scanner.by_ref().for_each(drop);
+        let span = &scanner.diagnostics()[0].span;
+        assert_eq!((span.start, span.end), (5, 7));
+        assert_eq!((span.utf16_start, span.utf16_end), (3, 5));
+    }
+
+    #[test]
+    fn raw_strings() {
+        assert_eq!(
+            scan(r#"r"C:\dir\new""#),
+            (r"C:\dir\new".to_string(), vec![])
+        );
+        assert_eq!(scan(r"r'\d+'"), (r"\d+".to_string(), vec![]));
+        assert_eq!(
+            scan(r##"r#"say "hi""#"##),
+            (r#"say "hi""#.to_string(), vec![])
+        );
+        let tokens: Vec<_> = Scanner::new(r##"r#"a"# r"##).collect();
+        assert_eq!(tokens[0].token_span.end, 6);
+        assert!(matches!(tokens[2].token_type, TokenType::Identifier(_)));
+    }
+
+    #[test]
+    fn unterminated_raw_string() {
+        let mut scanner = Scanner::new(r##"r#"a" b"##);
+        scanner.by_ref().for_each(drop);
+        let diagnostics = scanner.diagnostics();
+        assert_eq!(diagnostics[0].code, "E0001");
+        assert_eq!(
+            diagnostics[0].notes,
+            vec!["add a closing \"# to end the string"]
+        );
+    }
+}
+
+#[cfg(test)]
+mod identifier_tests {
+    use crate::lexer::Scanner;
+    use crate::token::{IdentifierToken, TokenType};
+
+    fn identifiers(input: &str) -> Vec<String> {
+        Scanner::new(input)
+            .filter_map(|token| match token.token_type {
+                TokenType::Identifier(IdentifierToken { value }) => Some(value),
+                TokenType::WhiteSpace(_) => None,
+                other => panic!("unexpected token {other:?}"),
+            })
+            .collect()
+    }
+
+    fn warnings(input: &str) -> Vec<(&'static str, String)> {
+        let mut scanner = Scanner::new(input);
+        scanner.by_ref().for_each(drop);
+        scanner
+            .diagnostics()
+            .iter()
+            .map(|d| (d.code, d.message.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn underscore_and_dollar() {
+        assert_eq!(
+            identifiers("_private $el snake_case a$b __proto__ x1"),
+            vec!["_private", "$el", "snake_case", "a$b", "__proto__", "x1"]
+        );
+    }
+
+    #[test]
+    fn unicode_identifiers() {
+        assert_eq!(
+            identifiers("café π 变量 ñandú"),
+            vec!["café", "π", "变量", "ñandú"]
+        );
+    }
+
+    #[test]
+    fn non_identifier_characters_are_rejected() {
+        let types: Vec<TokenType> = Scanner::new("a²").map(|t| t.token_type).collect();
+        assert_eq!(types[1], TokenType::Unknown('²'));
+    }
+
+    #[test]
+    fn identifiers_are_nfc_normalized() {
+        // `e` followed by a combining acute accent
+        let decomposed = identifiers("cafe\u{301}");
+        assert_eq!(decomposed, identifiers("caf\u{e9}"));
+        assert_eq!(decomposed, vec!["café"]);
+        assert_eq!(
+            Scanner::new("cafe\u{301}").next().unwrap().token_span.end,
+            6
+        );
+    }
+
+    #[test]
+    fn mixed_script_identifier_warns() {
+        // Latin `p` with a Cyrillic `а`
+        assert_eq!(
+            warnings("p\u{430}ypal"),
+            vec![(
+                "W0001",
+                "identifier `p\u{430}ypal` mixes characters from different scripts".to_string()
+            )]
+        );
+        assert_eq!(warnings("変数 переменная"), vec![]);
+    }
+
+    #[test]
+    fn confusable_identifiers_warn() {
+        let mut scanner = Scanner::new("paypal = p\u{430}ypal");
+        scanner.by_ref().for_each(drop);
+        let confusable = scanner
+            .diagnostics()
+            .iter()
+            .find(|d| d.code == "W0002")
+            .unwrap();
+        assert_eq!(
+            confusable.message,
+            "identifier `p\u{430}ypal` is confusable with `paypal`"
+        );
+        assert_eq!(confusable.span.start, 9);
+        assert_eq!(confusable.labels[0].span.start, 0);
+        assert!(!confusable.is_error());
+        // Look-alike ASCII names are not reported
+        assert_eq!(warnings("l I rn m"), vec![]);
+    }
+}
+
+#[cfg(test)]
+mod lexer_properties {
+    use crate::lexer::Scanner;
+    use crate::token::{Token, TokenType};
+    use proptest::prelude::*;
+
+    // Every token's span must follow on from the previous one, so the token
+    // texts concatenated give back the input exactly
+    fn assert_lossless(input: &str) -> Result<(), TestCaseError> {
+        let tokens: Vec<Token> = Scanner::new(input).collect();
+        let mut offset = 0;
+        let mut utf16_offset = 0;
+        let mut reconstructed = String::new();
+        for token in &tokens {
+            let span = &token.token_span;
+            prop_assert_eq!(span.start, offset, "gap or overlap before {:?}", token);
+            prop_assert_eq!(span.utf16_start, utf16_offset);
+            prop_assert!(span.end > span.start, "empty token {:?}", token);
+            let text = &input[span.start..span.end];
+            prop_assert_eq!(
+                span.utf16_end - span.utf16_start,
+                text.encode_utf16().count()
+            );
+            // A token's line is the one it ends on
+            prop_assert_eq!(span.line, input[..span.end].matches('\n').count());
+            reconstructed.push_str(text);
+            offset = span.end;
+            utf16_offset = span.utf16_end;
+        }
+        prop_assert_eq!(reconstructed, input);
+        Ok(())
+    }
+
+    fn code_token() -> impl Strategy<Value = String> {
+        prop_oneof![
+            "[a-zA-Z_$][a-zA-Z0-9_$]{0,6}",
+            "[0-9]{1,4}(\\.[0-9]{1,3})?",
+            "0x[0-9a-f]{1,4}",
+            "\"[a-z ]{0,5}\"",
+            prop::sample::select(vec![
+                "(", ")", "[", "]", "{", "}", ",", ".", ":", ";", "+", "-", "*", "/", "%", "&",
+                "|", "=", "!", "<", ">", "==", "!=", "<=", ">=", "&&", "||", "+=", "-=", "*=",
+                "/=", "&=", "|=", "&&=", "||=",
+            ])
+            .prop_map(String::from),
+        ]
+    }
+
+    // Whether `left` and `right` would scan as something else when written
+    // with nothing between them, e.g. `a` `b` as `ab` or `+` `=` as `+=`
+    fn needs_space(left: &str, right: &str) -> bool {
+        let (Some(last), Some(first)) = (left.chars().last(), right.chars().next()) else {
+            return false;
+        };
+        let word = |c: char| c.is_alphanumeric() || c == '_' || c == '$';
+        let operator = |c: char| "+-*/%&|=!<>".contains(c);
+        (word(last) && (word(first) || first == '"'))
+            || (operator(last) && operator(first))
+            || (last.is_ascii_digit() && first == '.')
+            || (last == '.' && first.is_ascii_digit())
+    }
+
+    fn significant(input: &str) -> Vec<TokenType> {
+        Scanner::new(input)
+            .map(|token| token.token_type)
+            .filter(|token_type| !matches!(token_type, TokenType::WhiteSpace(_)))
+            .collect()
+    }
+
+    proptest! {
+        #[test]
+        fn any_input_is_reconstructed(input in any::<String>()) {
+            assert_lossless(&input)?;
+        }
+
+        #[test]
+        fn code_like_input_is_reconstructed(
+            input in "([a-z0-9_$ \t\n\r(){}\\[\\];,.:+*/%&|=!<>\"'`\\\\#-]|\\$\\{|0x|1e|r#?\"|/\\*|\\*/|//|\\\\u\\{)*"
+        ) {
+            assert_lossless(&input)?;
+        }
+
+        #[test]
+        fn whitespace_between_tokens_is_optional(
+            tokens in prop::collection::vec(code_token(), 0..24)
+        ) {
+            let spaced = tokens.join(" ");
+            let mut tight = String::new();
+            for (index, token) in tokens.iter().enumerate() {
+                if index > 0 && needs_space(&tokens[index - 1], token) {
+                    tight.push(' ');
+                }
+                tight.push_str(token);
+            }
+            prop_assert_eq!(significant(&tight), significant(&spaced), "`{}`", tight);
+        }
+    }
+
+    #[test]
+    fn unspaced_statements() {
+        assert_eq!(
+            significant("let x=1;foo(bar)"),
+            significant("let x = 1 ; foo ( bar )")
+        );
+    }
+
+    #[test]
+    fn crlf_is_one_newline() {
+        let tokens: Vec<Token> = Scanner::new("a\r\nb").collect();
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(
+            (tokens[1].token_span.start, tokens[1].token_span.end),
+            (1, 3)
+        );
+        assert_eq!(tokens[2].token_span.line, 1);
+    }
+}
+
+#[cfg(test)]
+mod delimiter_tests {
+    use crate::delimiter::DelimiterChecker;
+    use crate::lexer::Scanner;
+    use crate::parser::Parser;
+    use crate::token::Token;
+
+    // (code, message, primary start, label starts) for each problem
+    fn check(input: &str) -> Vec<(&'static str, String, usize, Vec<usize>)> {
+        let mut checker = DelimiterChecker::new();
+        let mut scanner = Scanner::new(input);
+        for token in scanner.by_ref() {
+            checker.feed(&token);
+        }
+        let end: Token = scanner.next_token();
+        checker.finish(&end.token_span);
+        checker
+            .diagnostics()
+            .iter()
+            .map(|d| {
+                (
+                    d.code,
+                    d.message.clone(),
+                    d.span.start,
+                    d.labels.iter().map(|label| label.span.start).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn balanced_input() {
+        assert_eq!(check("f(a[0], { b: [1, (2)] })"), vec![]);
+        assert_eq!(check("`${ { } }` ( )"), vec![]);
+    }
+
+    #[test]
+    fn depth_follows_nesting() {
+        let mut checker = DelimiterChecker::new();
+        let depths: Vec<usize> = Scanner::new("({[]})")
+            .map(|token| {
+                checker.feed(&token);
+                checker.depth()
+            })
+            .collect();
+        assert_eq!(depths, vec![1, 2, 3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn mismatched_closer_reports_both_locations() {
+        assert_eq!(
+            check("a = [1, 2}"),
+            vec![(
+                "E0008",
+                "mismatched closing delimiter `}`".to_string(),
+                9,
+                vec![4]
+            )]
+        );
+    }
+
+    #[test]
+    fn mismatched_closer_pops_to_its_opener() {
+        // The `)` closes the `(`, abandoning the `[` inside it, so the final
+        // `}` is balanced
+        assert_eq!(
+            check("{ f([1) }"),
+            vec![(
+                "E0008",
+                "mismatched closing delimiter `)`".to_string(),
+                6,
+                vec![4]
+            )]
+        );
+    }
+
+    #[test]
+    fn closer_matching_nothing_open() {
+        // Taken as a typo for the `)`, which leaves the last `)` unmatched
+        assert_eq!(
+            check("( ] )"),
+            vec![
+                (
+                    "E0008",
+                    "mismatched closing delimiter `]`".to_string(),
+                    2,
+                    vec![0]
+                ),
+                (
+                    "E0009",
+                    "unexpected closing delimiter `)`".to_string(),
+                    4,
+                    vec![]
+                ),
+            ]
+        );
+        assert_eq!(
+            check("a ) b"),
+            vec![(
+                "E0009",
+                "unexpected closing delimiter `)`".to_string(),
+                2,
+                vec![]
+            )]
+        );
+    }
+
+    #[test]
+    fn unclosed_delimiters_at_end_of_input() {
+        assert_eq!(
+            check("{ ( x"),
+            vec![
+                ("E0010", "unclosed delimiter `(`".to_string(), 2, vec![5]),
+                ("E0010", "unclosed delimiter `{`".to_string(), 0, vec![5]),
+            ]
+        );
+    }
+
+    fn parse_codes(input: &str) -> Vec<(&'static str, String)> {
+        let mut parser = Parser::new(Scanner::new(input));
+        let _ = parser.parse_program();
+        parser
+            .diagnostics()
+            .iter()
+            .map(|d| (d.code, d.message.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn parser_reports_delimiters_once() {
+        assert_eq!(
+            parse_codes("let a = [1, 2};\nlet b = 3;"),
+            vec![("E0008", "mismatched closing delimiter `}`".to_string())]
+        );
+        assert_eq!(
+            parse_codes("print(1"),
+            vec![("E0010", "unclosed delimiter `(`".to_string())]
+        );
+    }
+
+    #[test]
+    fn parser_recovers_after_errors() {
+        let mut parser = Parser::new(Scanner::new(
+            "let = 1;\nfn f() { return 1 + }\nlet ok = 2;\nif (x) { y = ; }\nlet z = 3",
+        ));
+        let error = parser.parse_program().unwrap_err();
+        assert_eq!(
+            error.message,
+            "expected variable name after `let`, found Assignment(Assign)"
+        );
+        let lines: Vec<usize> = parser.diagnostics().iter().map(|d| d.span.line).collect();
+        assert_eq!(lines, vec![0, 1, 3]);
+        assert!(parser.diagnostics().iter().all(|d| d.code == "E0100"));
+    }
+}
+
+#[cfg(test)]
+mod incremental_tests {
+    use crate::diagnostic::Diagnostic;
+    use crate::incremental::{IncrementalLexer, TokenEdit};
+    use crate::lexer::Scanner;
+    use crate::token::Token;
+    use proptest::prelude::*;
+
+    fn full_lex(source: &str) -> (Vec<Token>, Vec<Diagnostic>) {
+        let mut scanner = Scanner::new(source);
+        let tokens = (&mut scanner).collect();
+        (tokens, scanner.take_diagnostics())
+    }
+
+    fn assert_matches_full_lex(lexer: &IncrementalLexer) {
+        let (tokens, diagnostics) = full_lex(lexer.source());
+        let incremental: Vec<Token> = lexer.tokens().cloned().collect();
+        assert_eq!(incremental, tokens, "tokens of {:?}", lexer.source());
+        assert_eq!(
+            lexer.diagnostics(),
+            diagnostics,
+            "diagnostics of {:?}",
+            lexer.source()
+        );
+    }
+
+    fn edit(source: &str, start: usize, end: usize, text: &str) -> (IncrementalLexer, TokenEdit) {
+        let mut lexer = IncrementalLexer::new(source);
+        let edit = lexer.apply_edit(start, end, text).unwrap();
+        assert_matches_full_lex(&lexer);
+        (lexer, edit)
+    }
+
+    #[test]
+    fn edit_rescans_only_nearby_tokens() {
+        let (_, edit) = edit("let a = 1;\nlet b = 2;\nlet c = 3;", 15, 16, "bee");
+        assert_eq!(
+            edit,
+            TokenEdit {
+                start: 11,
+                removed: 1,
+                inserted: 1
+            }
+        );
+    }
+
+    #[test]
+    fn edit_reaches_back_through_lookahead() {
+        // `1.` followed by `x` scanned as `1` `.` `x`; the `.` now joins the number
+        let (lexer, edit) = edit("1.x;", 2, 3, "5");
+        assert_eq!(edit.start, 0);
+        assert_eq!(lexer.tokens().count(), 2);
+    }
+
+    #[test]
+    fn edit_can_merge_and_split_tokens() {
+        edit("ab cd", 2, 3, "");
+        edit("abcd", 2, 2, " ");
+        edit("a + = b", 3, 4, "");
+        edit("x = 1 // comment\ny = 2", 6, 6, "/*");
+    }
+
+    #[test]
+    fn edit_inside_template_interpolation() {
+        edit("let s = `a${b}c${ {d: 1}.d }e`;\nprint(s)", 12, 13, "x");
+        edit("let s = `a${b}c`;", 10, 12, "");
+        edit("let s = `a${b}c`;", 9, 9, "${");
+        edit("`${`${a}`}` + 1", 5, 5, "}");
+    }
+
+    #[test]
+    fn edit_changes_raw_string_hashes() {
+        edit("r#\"a\"# + 1; r\"b\"", 1, 2, "");
+        edit("r#\"a\"# + 1; r\"b\"", 5, 6, "\"##");
+    }
+
+    #[test]
+    fn edit_opens_and_closes_strings() {
+        edit("let a = 1;\nlet b = \"x\";\nlet c = 3;", 8, 8, "\"");
+        edit("let a = \"1;\nlet b = 2;", 10, 10, "\"");
+    }
+
+    #[test]
+    fn edit_updates_utf16_spans_and_lines() {
+        let (lexer, _) = edit("a = \"é\";\nb = 2;\nc = 3", 5, 7, "𝒳\n\n");
+        let last = lexer.tokens().last().unwrap();
+        assert_eq!(last.token_span.line, 4);
+    }
+
+    #[test]
+    fn confusable_warnings_follow_edits() {
+        // The warning moves to whichever of the pair now comes second
+        let (mut lexer, _) = edit("let paypal = 1;\nlet pаypal = 2;", 4, 10, "x");
+        assert!(lexer.diagnostics().iter().all(|d| d.code != "W0002"));
+        lexer.apply_edit(4, 5, "paypal").unwrap();
+        assert_matches_full_lex(&lexer);
+        assert!(lexer.diagnostics().iter().any(|d| d.code == "W0002"));
+    }
+
+    #[test]
+    fn invalid_edit_ranges_are_rejected() {
+        let mut lexer = IncrementalLexer::new("é");
+        assert!(lexer.apply_edit(1, 1, "x").is_err());
+        assert!(lexer.apply_edit(0, 3, "x").is_err());
+        assert!(lexer.apply_edit(2, 1, "x").is_err());
+        assert_eq!(lexer.source(), "é");
+    }
+
+    fn fragment() -> impl Strategy<Value = String> {
+        prop_oneof![
+            "[a-z_]{1,4}",
+            "[0-9]{1,3}",
+            prop::sample::select(vec![
+                " ",
+                "\n",
+                "\r\n",
+                "\t",
+                "(",
+                ")",
+                "[",
+                "]",
+                "{",
+                "}",
+                ",",
+                ".",
+                ";",
+                ":",
+                "=",
+                "+",
+                "-",
+                "/",
+                "*",
+                "&&",
+                "|",
+                "!",
+                "<",
+                "\"",
+                "'",
+                "`",
+                "${",
+                "\\",
+                "r#",
+                "#",
+                "//",
+                "/*",
+                "*/",
+                "0x",
+                "1.5e",
+                "e+",
+                "_",
+                "let",
+                "fn",
+                "true",
+                "é",
+                "𝒳",
+                "а",
+                "x\u{301}",
+                "\u{10FFFF}",
+                "@",
+            ])
+            .prop_map(String::from),
+        ]
+    }
+
+    fn document() -> impl Strategy<Value = String> {
+        prop::collection::vec(fragment(), 0..30).prop_map(|parts| parts.concat())
+    }
+
+    // Start and end as fractions of the document, plus the inserted text
+    fn edits() -> impl Strategy<Value = Vec<(f64, f64, String)>> {
+        prop::collection::vec(
+            (
+                0.0..=1.0f64,
+                0.0..=1.0f64,
+                prop::collection::vec(fragment(), 0..4).prop_map(|parts| parts.concat()),
+            ),
+            1..8,
+        )
+    }
+
+    // The char boundary nearest below `fraction` of the way through `source`
+    fn boundary(source: &str, fraction: f64) -> usize {
+        let mut offset = (source.len() as f64 * fraction) as usize;
+        while !source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        offset
+    }
+
+    proptest! {
+        #[test]
+        fn edits_match_full_relex(source in document(), edits in edits()) {
+            let mut lexer = IncrementalLexer::new(&source);
+            for (a, b, text) in edits {
+                let (start, end) = {
+                    let (a, b) = (boundary(lexer.source(), a), boundary(lexer.source(), b));
+                    (a.min(b), a.max(b))
+                };
+                let before = lexer.token_count();
+                let edit = lexer.apply_edit(start, end, &text).unwrap();
+                prop_assert_eq!(
+                    lexer.token_count(),
+                    before - edit.removed + edit.inserted
+                );
+                let (tokens, diagnostics) = full_lex(lexer.source());
+                let incremental: Vec<Token> = lexer.tokens().cloned().collect();
+                prop_assert_eq!(&incremental, &tokens, "tokens of {:?}", lexer.source());
+                prop_assert_eq!(lexer.diagnostics(), diagnostics, "diagnostics of {:?}", lexer.source());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod cst_tests {
+    use crate::cst::{parse, SyntaxElement, SyntaxKind, SyntaxNode};
+    use proptest::prelude::*;
+
+    // Every child must sit inside its parent, follow on from its previous
+    // sibling and point back at the parent
+    fn check_structure(node: &SyntaxNode) {
+        let mut offset = node.range().start;
+        for child in node.children() {
+            let range = match &child {
+                SyntaxElement::Node(child) => {
+                    assert_eq!(child.parent().as_ref(), Some(node));
+                    check_structure(child);
+                    child.range()
+                }
+                SyntaxElement::Token(token) => {
+                    assert_eq!(&token.parent(), node);
+                    assert!(!token.text().is_empty());
+                    token.range()
+                }
+            };
+            assert_eq!(range.start, offset);
+            offset = range.end;
+        }
+        assert_eq!(offset, node.range().end);
+    }
+
+    fn assert_lossless(input: &str) {
+        let (root, _) = parse(input);
+        assert_eq!(root.text(), input);
+        assert_eq!(root.range(), 0..input.len());
+        let tokens: String = root.tokens().iter().map(|token| token.text()).collect();
+        assert_eq!(tokens, input);
+        check_structure(&root);
+    }
+
+    #[test]
+    fn tree_keeps_whitespace_and_comments() {
+        let (root, diagnostics) = parse("let x = 1 + y; // sum\n");
+        assert!(diagnostics.is_empty());
+        assert_eq!(
+            root.debug_tree(),
+            "\
+Program@0..22
+  Let@0..14
+    Declaration(Let)@0..3 \"let\"
+    WhiteSpace(Space)@3..4 \" \"
+    Name@4..5
+      Identifier(IdentifierToken { value: \"x\" })@4..5 \"x\"
+    WhiteSpace(Space)@5..6 \" \"
+    Assignment(Assign)@6..7 \"=\"
+    WhiteSpace(Space)@7..8 \" \"
+    Binary@8..13
+      Literal@8..9
+        Literal(Number(SignedInteger(1)))@8..9 \"1\"
+      WhiteSpace(Space)@9..10 \" \"
+      Arithmetic(Add)@10..11 \"+\"
+      WhiteSpace(Space)@11..12 \" \"
+      NameRef@12..13
+        Identifier(IdentifierToken { value: \"y\" })@12..13 \"y\"
+    Punctuation(Semicolon)@13..14 \";\"
+  WhiteSpace(Space)@14..15 \" \"
+  Comment(Line(\" sum\"))@15..21 \"// sum\"
+  WhiteSpace(NewLine)@21..22 \"\\n\"
+"
+        );
+    }
+
+    #[test]
+    fn tree_nests_statements_and_expressions() {
+        let input = "/// Adds\nfn add(a, b) {\n  return a + b;\n}\nif add(1, 2) > 2 { print(`${a}!`) } else { x.y[0] = new P(1) }";
+        assert_lossless(input);
+        let (root, diagnostics) = parse(input);
+        assert!(diagnostics.is_empty());
+        let kinds: Vec<SyntaxKind> = root.child_nodes().map(|node| node.kind()).collect();
+        assert_eq!(kinds, vec![SyntaxKind::Function, SyntaxKind::If]);
+
+        let token = root.token_at_offset(input.find("b;").unwrap()).unwrap();
+        let ancestors: Vec<SyntaxKind> =
+            token.parent().ancestors().map(|node| node.kind()).collect();
+        assert_eq!(
+            ancestors,
+            vec![
+                SyntaxKind::NameRef,
+                SyntaxKind::Binary,
+                SyntaxKind::Return,
+                SyntaxKind::Block,
+                SyntaxKind::Function,
+                SyntaxKind::Program
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_statements_become_error_nodes() {
+        let input = "let a = 1;\nlet = 2;\nlet b = 3 +;\nlet c = 4;";
+        assert_lossless(input);
+        let (root, diagnostics) = parse(input);
+        assert!(!diagnostics.is_empty());
+        let nodes: Vec<(SyntaxKind, String)> = root
+            .child_nodes()
+            .map(|node| (node.kind(), node.text()))
+            .collect();
+        assert_eq!(nodes[0], (SyntaxKind::Let, "let a = 1;".to_string()));
+        // Neighbouring failures share one node
+        assert_eq!(
+            nodes[1],
+            (SyntaxKind::Error, "let = 2;\nlet b = 3 +;".to_string())
+        );
+        assert_eq!(nodes[2], (SyntaxKind::Let, "let c = 4;".to_string()));
+    }
+
+    #[test]
+    fn empty_input_is_an_empty_program() {
+        let (root, _) = parse("");
+        assert_eq!(root.kind(), SyntaxKind::Program);
+        assert!(root.children().is_empty());
+    }
+
+    fn program() -> impl Strategy<Value = String> {
+        prop::collection::vec(
+            prop::sample::select(vec![
+                "let x = 1;",
+                "let y = x * (2 + 3);",
+                "fn f(a) { return a; }",
+                "obj O { k: 1 }",
+                "if x { y = 1 } else { y = 2 }",
+                "for i in xs { print(i) }",
+                "// note\n",
+                "/* block */",
+                "`t${x}t`;",
+                "a.b[c](d);",
+                " ",
+                "\n",
+                "\t",
+                "let",
+                "=",
+                "(",
+                "}",
+                ";",
+                "é",
+            ]),
+            0..12,
+        )
+        .prop_map(|parts| parts.concat())
+    }
+
+    proptest! {
+        #[test]
+        fn tree_round_trips_code(input in program()) {
+            assert_lossless(&input);
+        }
+
+        #[test]
+        fn tree_round_trips_any_text(input in any::<String>()) {
+            assert_lossless(&input);
+        }
+    }
+}
+
+#[cfg(test)]
+mod format_tests {
+    use crate::format::format;
+    use crate::lexer::Scanner;
+    use crate::token::{PunctuatorToken, TokenType};
+    use proptest::prelude::*;
+
+    fn assert_idempotent(input: &str) {
+        let once = format(input).unwrap();
+        let twice = format(&once).unwrap();
+        assert_eq!(twice, once, "formatting {input:?} twice");
+    }
+
+    // Everything but whitespace, comments and the `;` and `,` the formatter
+    // may add or drop
+    fn significant_tokens(input: &str) -> Vec<TokenType> {
+        Scanner::new(input)
+            .map(|token| token.token_type)
+            .filter(|token_type| {
+                !matches!(
+                    token_type,
+                    TokenType::WhiteSpace(_)
+                        | TokenType::Comment(_)
+                        | TokenType::Punctuation(
+                            PunctuatorToken::Semicolon | PunctuatorToken::Comma
+                        )
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn formats_spacing_indentation_and_braces() {
+        let input = "fn add(a,b){return a+b}\nlet x=add( 1 , 2 )\nif x>2&&!done{print(-x*2)}else if x{y=1;}else{ }\nfor i in xs { print(i) ; }\na.b[0] = new P(1) .c";
+        assert_eq!(
+            format(input).unwrap(),
+            "\
+fn add(a, b) {
+    return a + b;
+}
+let x = add(1, 2);
+if x > 2 && !done {
+    print(-x * 2);
+} else if x {
+    y = 1;
+} else {}
+for i in xs {
+    print(i);
+}
+a.b[0] = new P(1).c;
+"
+        );
+    }
+
+    #[test]
+    fn formats_exported_declarations() {
+        assert_eq!(
+            format("export   let x=1\nexport fn f(){return x}\nexport obj O {a:1}").unwrap(),
+            "export let x = 1;\nexport fn f() {\n    return x;\n}\nexport obj O {\n    a: 1,\n}\n"
+        );
+    }
+
+    #[test]
+    fn formats_objects() {
+        assert_eq!(
+            format("obj Point { x : 1, y:2 }\nlet o = obj {a:1,b:obj{},}\nlet p = obj {\na: 1 }")
+                .unwrap(),
+            "\
+obj Point {
+    x: 1,
+    y: 2,
+}
+let o = obj { a: 1, b: obj {} };
+let p = obj {
+    a: 1,
+};
+"
+        );
+    }
+
+    #[test]
+    fn keeps_comments_and_blank_lines() {
+        let input = "/// Adds\nfn add(a, b) { // body\n\n\n  return a + b /* sum */\n  // done\n}\n\n\nlet x = 1; // one\n/* two */\nlet y = 2;\n";
+        assert_eq!(
+            format(input).unwrap(),
+            "\
+/// Adds
+fn add(a, b) { // body
+    return a + b; /* sum */
+    // done
+}
+
+let x = 1; // one
+/* two */
+let y = 2;
+"
+        );
+    }
+
+    #[test]
+    fn keeps_templates_and_literals_verbatim() {
+        assert_eq!(
+            format("let s = `a ${ x+1 } b ${y}`;let n = 0x1F+1.5e3\nlet r = r#\"raw\"#").unwrap(),
+            "let s = `a ${x + 1} b ${y}`;\nlet n = 0x1F + 1.5e3;\nlet r = r#\"raw\"#;\n"
+        );
+        // The space stops `.e5` joining the number
+        assert_eq!(format("1 .e5").unwrap(), "1 .e5;\n");
+    }
+
+    #[test]
+    fn refuses_code_that_does_not_parse() {
+        let diagnostics = format("let = 1;").unwrap_err();
+        assert_eq!(diagnostics[0].code, "E0100");
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        for input in [
+            "",
+            "// only a comment",
+            "let a = obj { f: fn() { return 1 }, // trailing\n g: 2 }",
+            "f(fn(x) { return x }, /* arg */ 2)",
+            "if (a) { } else { // nothing\n }",
+            "let x = - - y; let z = not not w; let q = a xor b xand c",
+            "{ { let deep = 1 } }\n\n\n// end",
+            "print(1 + // one\n 2)",
+        ] {
+            assert_idempotent(input);
+        }
+    }
+
+    fn program() -> impl Strategy<Value = String> {
+        prop::collection::vec(
+            prop::sample::select(vec![
+                "let x = 1;",
+                "let y=x*(2+3)",
+                "fn f(a,b) { return a; }",
+                "obj O { k: 1, }",
+                "if x { y = 1 } else if z { y = 2 } else { }",
+                "for i in xs { print(i) }",
+                "// note\n",
+                "/* block */",
+                "`t${x}t`;",
+                "a.b[c](d);",
+                "let o = obj {a: -1};",
+                "{ let inner = fn() {}; }",
+                "return",
+                " ",
+                "\n",
+                "\n\n",
+                "\t",
+            ]),
+            0..12,
+        )
+        .prop_map(|parts| parts.concat())
+    }
+
+    proptest! {
+        #[test]
+        fn formatting_is_stable(input in program()) {
+            // Not every concatenation parses
+            if let Ok(once) = format(&input) {
+                prop_assert_eq!(format(&once), Ok(once.clone()));
+                prop_assert_eq!(significant_tokens(&once), significant_tokens(&input));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod lint_tests {
+    use crate::diagnostic::{Diagnostic, Severity};
+    use crate::lint::{apply_suggestions, lint, Level, LintConfig};
+    use crate::parser::parse;
+
+    fn lint_with(input: &str, config: &LintConfig) -> Vec<Diagnostic> {
+        lint(&parse(input).unwrap(), input, config)
+    }
+
+    // Codes and the text they point at
+    fn findings(input: &str) -> Vec<(&'static str, String)> {
+        lint_with(input, &LintConfig::default())
+            .into_iter()
+            .map(|diagnostic| {
+                let span = diagnostic.span;
+                (diagnostic.code, input[span.start..span.end].to_string())
+            })
+            .collect()
+    }
+
+    fn fixed(input: &str) -> String {
+        apply_suggestions(input, &lint_with(input, &LintConfig::default()))
+    }
+
+    #[test]
+    fn unused_let() {
+        assert_eq!(
+            findings("let a = 1;\nlet b = 2;\nlet _c = 3;\nprint(b);\nlet d = 1;\nd = 2;"),
+            vec![("L0001", "a".to_string()), ("L0001", "d".to_string())]
+        );
+        assert_eq!(fixed("let a = 1;"), "let _a = 1;");
+        // Other sections may read an exported variable
+        assert_eq!(findings("export let a = 1;"), vec![]);
+        // Read by a function declared before it, and by compound assignment
+        assert!(findings(
+            "fn show() { return later }\nlet later = 1;\nshow();\nlet n = 0;\nn += 1;"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn shadowing() {
+        let diagnostics = lint_with(
+            "let x = 1;\nfn f(x) { return x }\nfor i in x { let i = 2; print(i) }\n{ let f = 3; print(f) }",
+            &LintConfig::default(),
+        );
+        let lines: Vec<(&str, usize)> = diagnostics.iter().map(|d| (d.code, d.span.line)).collect();
+        assert_eq!(lines, vec![("L0002", 1), ("L0002", 2), ("L0002", 3)]);
+        assert_eq!(diagnostics[0].labels[0].message, "previously declared here");
+        assert_eq!(diagnostics[0].labels[0].span.line, 0);
+    }
+
+    #[test]
+    fn unreachable_code() {
+        let input = "fn f(a) {\n    if a { return 1 } else { return 2 }\n    print(a);\n    print(a);\n}\nfn g() { return 1 }";
+        assert_eq!(
+            findings(input),
+            vec![("L0003", "print(a);\n    print(a);".to_string())]
+        );
+        assert_eq!(
+            fixed(input),
+            "fn f(a) {\n    if a { return 1 } else { return 2 }\n}\nfn g() { return 1 }"
+        );
+    }
+
+    #[test]
+    fn null_comparison() {
+        let input =
+            "let v = obj {};\nif v.x == null { print(1) }\nif undefined != v.y { print(2) }";
+        assert_eq!(
+            findings(input),
+            vec![
+                ("L0004", "v.x == null".to_string()),
+                ("L0004", "undefined != v.y".to_string())
+            ]
+        );
+        let fixed = fixed(input);
+        assert_eq!(
+            fixed,
+            "let v = obj {};\nif (v.x == null || v.x == undefined) { print(1) }\nif (v.y != null && v.y != undefined) { print(2) }"
+        );
+        assert!(findings(&fixed).is_empty());
+    }
+
+    #[test]
+    fn null_comparison_fix_only_for_paths() {
+        let input = "fn next() { return undefined }\nlet a = obj {};\n\
+                     if next() == null { print(1) }\nif a[\"x\"] != null { print(2) }";
+        let diagnostics = lint_with(input, &LintConfig::default());
+        let codes: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.code)
+            .collect();
+        assert_eq!(codes, ["L0004", "L0004"]);
+        // Writing the call or index out twice would evaluate it twice
+        assert!(diagnostics
+            .iter()
+            .all(|diagnostic| diagnostic.suggestions.is_empty()));
+        assert_eq!(fixed(input), input);
+    }
+
+    #[test]
+    fn assignment_in_condition() {
+        let diagnostics = lint_with("let x = 1;\nif x = 2 { print(x) }", &LintConfig::default());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "L0005");
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(
+            fixed("let x = 1;\nif x = 2 { print(x) }"),
+            "let x = 1;\nif x == 2 { print(x) }"
+        );
+    }
+
+    #[test]
+    fn xand() {
+        let input = "let a = true;\nprint(a xand false)";
+        assert_eq!(findings(input), vec![("L0006", "a xand false".to_string())]);
+        assert_eq!(fixed(input), "let a = true;\nprint(!(a xor false))");
+    }
+
+    #[test]
+    fn config_sets_levels() {
+        let config = LintConfig::from_toml(
+            "[package]\nname = \"demo\"\n\n[lints]\nunused-let = \"allow\"\nL0006 = \"deny\"\n",
+        )
+        .unwrap();
+        let diagnostics = lint_with("let a = true;\nlet b = a xand a;", &config);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "L0006");
+        assert!(diagnostics[0].is_error());
+
+        assert_eq!(LintConfig::from_toml("").unwrap(), LintConfig::default());
+        assert_eq!(
+            LintConfig::from_toml("[lints]\nunused = \"warn\"").unwrap_err(),
+            "unknown lint `unused`"
+        );
+        assert!(LintConfig::from_toml("[lints]\nxand = \"loud\"").is_err());
+        assert!(LintConfig::from_toml("[lints\n").is_err());
+
+        let mut config = LintConfig::default();
+        config.set("shadowing", Level::Allow).unwrap();
+        assert!(lint_with("let x = 1;\n{ let x = 2; print(x) }\nprint(x)", &config).is_empty());
+    }
+}
+
+#[cfg(test)]
+mod resolve_tests {
+    use crate::parser::parse;
+    use crate::resolve::{resolve, DeclarationKind, NodeId, Resolution};
+
+    fn resolved(input: &str) -> Resolution {
+        let (resolution, diagnostics) = resolve(&parse(input).unwrap());
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+        resolution
+    }
+
+    // Codes and the text they point at
+    fn errors(input: &str) -> Vec<(&'static str, String)> {
+        resolve(&parse(input).unwrap())
+            .1
+            .into_iter()
+            .map(|diagnostic| {
+                let span = diagnostic.span;
+                (diagnostic.code, input[span.start..span.end].to_string())
+            })
+            .collect()
+    }
+
+    // The kind and offset of the declaration the name at `offset` refers to
+    fn definition(resolution: &Resolution, offset: usize) -> Option<(DeclarationKind, usize)> {
+        let declaration = resolution.declaration(resolution.definition_at(offset)?);
+        Some((declaration.kind, declaration.span.as_ref()?.start))
+    }
+
+    #[test]
+    fn binds_uses_to_declarations() {
+        let input = "let a = 1;\nfn f(b) { return a + b; }\nfor c in a { print(c); }";
+        let resolution = resolved(input);
+        let at = |text: &str| input.rfind(text).unwrap();
+        assert_eq!(
+            definition(&resolution, at("a +")),
+            Some((DeclarationKind::Let, 4))
+        );
+        assert_eq!(
+            definition(&resolution, at("b;")),
+            Some((DeclarationKind::Parameter, at("b)")))
+        );
+        assert_eq!(
+            definition(&resolution, at("c)")),
+            Some((DeclarationKind::Loop, at("c in")))
+        );
+        let print = resolution.lookup(NodeId(at("print"))).unwrap();
+        assert_eq!(resolution.declaration(print).kind, DeclarationKind::Builtin);
+        // A declaration is its own definition
+        assert_eq!(definition(&resolution, 4), Some((DeclarationKind::Let, 4)));
+        assert_eq!(definition(&resolution, at("return")), None);
+    }
+
+    #[test]
+    fn inner_scopes_shadow_outer_ones() {
+        let input = "let x = 1;\n{ let x = 2; print(x); }\nprint(x);";
+        let resolution = resolved(input);
+        assert_eq!(
+            definition(&resolution, input.find("x);").unwrap()),
+            Some((DeclarationKind::Let, input.rfind("x = 2").unwrap()))
+        );
+        assert_eq!(
+            definition(&resolution, input.rfind("x);").unwrap()),
+            Some((DeclarationKind::Let, 4))
+        );
+    }
+
+    #[test]
+    fn references_for_rename() {
+        let input = "let n = 0;\nn = n + 1;\nfn f() { return n; }";
+        let resolution = resolved(input);
+        let n = resolution.definition_at(4).unwrap();
+        assert_eq!(
+            resolution.references(n),
+            vec![NodeId(11), NodeId(15), NodeId(input.rfind('n').unwrap())]
+        );
+    }
+
+    #[test]
+    fn parenthesized_references_point_at_the_name() {
+        let input = "let value = 1;\nprint((value));\nprint(((value)) + 1);";
+        let resolution = resolved(input);
+        let value = resolution.definition_at(4).unwrap();
+        let references = resolution.references(value);
+        assert_eq!(
+            references,
+            vec![
+                NodeId(input.find("value))").unwrap()),
+                NodeId(input.rfind("value").unwrap())
+            ]
+        );
+        for NodeId(offset) in references {
+            assert_eq!(&input[offset..offset + 5], "value");
+            assert_eq!(resolution.definition_at(offset), Some(value));
+        }
+    }
+
+    #[test]
+    fn fields() {
+        let input = "obj Point { x: 1, get: fn() { return this.x; } }\nprint(Point.x);\nlet p = obj { y: 2 };\nprint(p.y, p.z);";
+        let resolution = resolved(input);
+        let x = Some((DeclarationKind::Field, input.find("x:").unwrap()));
+        assert_eq!(definition(&resolution, input.find("x;").unwrap()), x);
+        assert_eq!(definition(&resolution, input.find("x)").unwrap()), x);
+        assert_eq!(
+            definition(&resolution, input.find("y,").unwrap()),
+            Some((DeclarationKind::Field, input.find("y:").unwrap()))
+        );
+        // Fields added at run time are not known
+        assert_eq!(definition(&resolution, input.find('z').unwrap()), None);
+    }
+
+    #[test]
+    fn functions_see_later_declarations() {
+        resolved("fn f() { return g() + later; }\nfn g() { return 1; }\nlet later = 2;\nf();");
+        resolved("{ fn f() { return later; } }\nlet later = 1;");
+    }
+
+    #[test]
+    fn undeclared_names() {
+        assert_eq!(
+            errors("print(missing);\nfn f() { return other; }\nundeclared = 1;"),
+            vec![
+                ("E0300", "missing".to_string()),
+                ("E0300", "other".to_string()),
+                ("E0300", "undeclared".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_declarations() {
+        assert_eq!(
+            errors("let a = 1;\nlet a = 2;\nfn f(b, b) { let b = 1; }\nobj O { k: 1, k: 2 }\n{ let a = 3; }"),
+            vec![
+                ("E0301", "a".to_string()),
+                ("E0301", "b".to_string()),
+                ("E0301", "b".to_string()),
+                ("E0301", "k".to_string()),
+            ]
+        );
+        let (_, diagnostics) = resolve(&parse("let a = 1;\nlet a = 2;").unwrap());
+        assert_eq!(diagnostics[0].labels[0].span.start, 4);
+    }
+
+    #[test]
+    fn use_before_definition() {
+        let input = "print(x);\nlet x = 1;\nfn f() { y; let y = 2; }\nlet z = z;";
+        assert_eq!(
+            errors(input),
+            vec![
+                ("E0302", "x".to_string()),
+                ("E0302", "y".to_string()),
+                ("E0302", "z".to_string()),
+            ]
+        );
+        let (_, diagnostics) = resolve(&parse(input).unwrap());
+        assert_eq!(
+            diagnostics[0].labels[0].span.start,
+            input.find("x =").unwrap()
+        );
+        // An outer declaration is used until the inner one runs, as when
+        // the program is interpreted
+        resolved("let x = 1;\n{ print(x); let x = 2; }");
+        // A function body could run on either side of a later declaration
+        // of a name it already sees
+        assert_eq!(
+            errors("let x = 1;\n{ fn f() { return x; } print(f()); let x = 2; }"),
+            vec![("E0302", "x".to_string())]
+        );
+        resolved("let x = 1;\n{ let x = 2; fn f() { return x; } }");
+        resolved("{ fn f() { return g(); } fn g() { return 1; } print(f()); }");
+    }
+}
+
+#[cfg(test)]
+mod type_tests {
+    use crate::parser::parse;
+    use crate::resolve::{resolve, NodeId};
+    use crate::test::fixtures::run_interpreter;
+    use crate::types::check;
+
+    // Codes and the text they point at
+    fn errors(input: &str) -> Vec<(&'static str, String)> {
+        let program = parse(input).unwrap();
+        check(&program, &resolve(&program).0)
+            .1
+            .into_iter()
+            .map(|diagnostic| {
+                let span = diagnostic.span;
+                (diagnostic.code, input[span.start..span.end].to_string())
+            })
+            .collect()
+    }
+
+    fn messages(input: &str) -> Vec<String> {
+        let program = parse(input).unwrap();
+        check(&program, &resolve(&program).0)
+            .1
+            .into_iter()
+            .map(|diagnostic| diagnostic.message)
+            .collect()
+    }
+
+    // The inferred type of the declaration of the last `name` in `input`
+    fn type_of(input: &str, name: &str) -> String {
+        let program = parse(input).unwrap();
+        let (resolution, diagnostics) = resolve(&program);
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+        let (types, diagnostics) = check(&program, &resolution);
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+        let offset = input
+            .match_indices(name)
+            .map(|(offset, _)| offset)
+            .find(|offset| resolution.declared(NodeId(*offset)).is_some())
+            .unwrap();
+        let decl = resolution.declared(NodeId(offset)).unwrap();
+        types.of(decl).unwrap().to_string()
+    }
+
+    #[test]
+    fn parses_annotations() {
+        let program =
+            parse("let x: int | null = 1;\nfn f(a: string, b): { n: fn(int): bool } {}").unwrap();
+        assert_eq!(program.statements.len(), 2);
+        assert_eq!(
+            crate::format::format("let x:int|null=1\nfn f(a:string,b):{n:fn(int):bool}{}").unwrap(),
+            "let x: int | null = 1;\nfn f(a: string, b): { n: fn(int): bool } {}\n"
+        );
+    }
+
+    #[test]
+    fn infers_declarations() {
+        assert_eq!(type_of("let x = 1 + 2.5;", "x"), "float");
+        assert_eq!(type_of("let s = `n: ${1}`;", "s"), "string");
+        assert_eq!(
+            type_of("fn add(a, b) { return a + b; }\nadd(1, 2);", "add"),
+            "fn(_, _): _"
+        );
+        assert_eq!(
+            type_of("fn f(a: int, b: string): bool { return a > 0; }", "f"),
+            "fn(int, string): bool"
+        );
+        assert_eq!(
+            type_of("obj Point { x: 1, y: 2.5, name: \"p\" }", "Point"),
+            "{ x: int, y: float, name: string }"
+        );
+        assert_eq!(
+            type_of("fn getx(p) { return p.x; }", "getx"),
+            "fn({ x: _, .. }): _"
+        );
+        assert_eq!(type_of("fn nothing() {}", "nothing"), "fn(): undefined");
+    }
+
+    #[test]
+    fn functions_are_generic() {
+        assert!(
+            errors("fn id(x) { return x; }\nlet a: int = id(1);\nlet b: string = id(\"b\");")
+                .is_empty()
+        );
+        assert_eq!(
+            errors("fn id(x) { return x; }\nlet a: string = id(1);"),
+            vec![("E0400", "id(1)".to_string())]
+        );
+        // Used before their declaration, functions have a single type
+        assert_eq!(
+            errors("fn f() { return g(1) + g(\"a\"); }\nfn g(x) { return x; }"),
+            vec![("E0400", "\"a\"".to_string())]
+        );
+    }
+
+    #[test]
+    fn annotations_are_checked() {
+        assert_eq!(
+            errors("let x: int = \"one\";\nlet y: float = 1;\nlet z: bool;"),
+            vec![("E0400", "\"one\"".to_string()), ("E0400", "z".to_string())]
+        );
+        assert_eq!(
+            messages("let x: int = \"one\";"),
+            ["expected `int`, found `string`"]
+        );
+        assert_eq!(
+            errors("fn f(a: string): bool { return a; }\nf(1);"),
+            vec![("E0400", "a".to_string()), ("E0400", "1".to_string())]
+        );
+        assert_eq!(
+            errors("let p: Point = 1;\nlet q: int | string = 1;"),
+            vec![
+                ("E0401", "Point".to_string()),
+                ("E0407", "int | string".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn null_and_undefined_are_distinct() {
+        assert!(errors("let a: int | null = null;\na = 1;\nlet b: string | undefined;").is_empty());
+        assert_eq!(
+            errors("let a: int | null = undefined;\nlet b: int | undefined = null;"),
+            vec![
+                ("E0400", "undefined".to_string()),
+                ("E0400", "null".to_string())
+            ]
+        );
+        assert_eq!(
+            messages("let p: { x: int } | null = null;\nprint(p.x);"),
+            ["cannot read `x` of `{ x: int } | null`, which may be `null`"]
+        );
+        // `||` replaces both
+        assert_eq!(
+            type_of(
+                "let a: int | null | undefined = null;\nlet b = a || 0;",
+                "b"
+            ),
+            "int"
+        );
+        assert_eq!(
+            errors("fn f(x: int): int { if x > 0 { return x; } }"),
+            vec![("E0400", "int".to_string())]
+        );
+    }
+
+    #[test]
+    fn objects_are_structural() {
+        let input = "obj Point { x: 1, y: 2 }\nlet p: { x: int } = Point;\nlet q: Point = obj { x: 3, y: 4, z: 5 };\nfn getx(o) { return o.x; }\nlet x: int = getx(Point);";
+        assert!(errors(input).is_empty());
+        assert_eq!(
+            errors("obj Point { x: 1 }\nlet q: Point = obj { y: 1 };\nprint(Point.z);\nfn getx(o) { return o.x; }\ngetx(obj { y: 1 });"),
+            vec![
+                ("E0400", "obj { y: 1 }".to_string()),
+                ("E0404", "z".to_string()),
+                ("E0400", "obj { y: 1 }".to_string()),
+            ]
+        );
+        // Methods see their object as `this`
+        assert_eq!(
+            errors("obj Counter { count: 0, up: fn() { this.count = this.count + \"s\"; } }"),
+            vec![("E0400", "this.count + \"s\"".to_string())]
+        );
+    }
+
+    #[test]
+    fn unannotated_variables_take_any_value() {
+        let programs = [
+            ("let x = null;\nx = 5;\nprint(x + 1);", "6\n"),
+            ("let n = 1;\nn = n + 0.5;\nprint(n);", "1.5\n"),
+            ("let o = obj { a: 1 };\no.b = 2;\nprint(o.b);", "2\n"),
+            ("fn f(a) { a = \"s\"; return a; }\nprint(f(1));", "s\n"),
+        ];
+        for (program, output) in programs {
+            assert!(errors(program).is_empty(), "{program}");
+            assert_eq!(run_interpreter(program), (output.to_string(), None));
+        }
+        assert_eq!(type_of("let x = null;\nx = 5;", "x"), "any");
+        assert_eq!(type_of("let n = 1;\nn = n + 0.5;", "n"), "float");
+        assert_eq!(type_of("let n = 0;\nn += 1;\nn = n * 2;", "n"), "int");
+        // Reads before the assignment see the wider type too
+        assert_eq!(
+            errors("let n = 1;\nlet m: int = n;\nn = 0.5;"),
+            vec![("E0400", "n".to_string())]
+        );
+        // Annotated variables and `obj` declarations keep their type
+        assert_eq!(
+            errors("let x: int | null = null;\nx = \"s\";\nobj Point { a: 1 }\nPoint.b = 2;"),
+            vec![("E0400", "\"s\"".to_string()), ("E0404", "b".to_string())]
+        );
+    }
+
+    #[test]
+    fn int_arithmetic_stays_int() {
+        // A result that overflows is left to the runtime
+        let programs = [
+            "let x: int = 1 + 2 * 3 - 4 % 3;\nlet y: int = -x;\nlet z: int = 9223372036854775807 + 1;",
+            "fn inc(n: int): int { return n + 1; }\nlet two: int = inc(1);",
+            "let i = 0;\ni += 1;\ni *= 2;\nlet k: int = i;",
+            "let total: int = 0;\nfor c in \"abc\" { total += 1; }",
+        ];
+        for program in programs {
+            assert!(errors(program).is_empty(), "{program}");
+        }
+        assert_eq!(type_of("let a = 2 * 3;", "a"), "int");
+        assert_eq!(type_of("let a = 2 * 3.5;", "a"), "float");
+        assert_eq!(type_of("let a = 6 / 3;", "a"), "float");
+        assert_eq!(
+            errors("let i = 0;\ni += 0.5;\nlet k: int = i;"),
+            vec![("E0400", "i".to_string())]
+        );
+    }
+
+    #[test]
+    fn let_bound_functions_are_generic() {
+        let input =
+            "let id = fn(v) { return v; };\nlet a: int = id(1);\nlet b: string = id(\"b\");";
+        assert!(errors(input).is_empty());
+        assert_eq!(type_of(input, "id"), "fn(_): _");
+        // Assigning one another function makes it `any`
+        let input =
+            "let f = fn(v) { return v; };\nf = fn(v) { return 1; };\nlet s: string = f(\"s\");";
+        assert!(errors(input).is_empty());
+        assert_eq!(type_of(input, "f"), "any");
+    }
+
+    #[test]
+    fn operators_and_calls() {
+        assert_eq!(
+            errors("let a = true - 1;\nlet b = 1 < \"s\";\nlet c = -\"s\";\nlet d = \"s\" + 1;"),
+            vec![
+                ("E0403", "true - 1".to_string()),
+                ("E0403", "1 < \"s\"".to_string()),
+                ("E0403", "-\"s\"".to_string()),
+            ]
+        );
+        assert_eq!(
+            errors("fn f(a, b: int | undefined) { return a; }\nf(1);\nf();\nf(1, 2, 3);\nlet n = 1;\nn();\nfor c in 5 {}"),
+            vec![
+                ("E0402", "f()".to_string()),
+                ("E0402", "f(1, 2, 3)".to_string()),
+                ("E0406", "n()".to_string()),
+                ("E0408", "5".to_string()),
+            ]
+        );
+        assert_eq!(
+            messages("fn f(a) {}\nf();"),
+            ["expected 1 argument, found 0"]
+        );
+        // Builtins and names that were not found check nothing
+        assert!(errors("print(1, \"a\", print);\nmissing + 1;").is_empty());
+    }
+}
+
+#[cfg(test)]
+mod vm_tests {
+    use crate::compiler::{compile, disassemble, Instruction};
+    use crate::parser::parse;
+    use crate::resolve::resolve;
+    use crate::test::fixtures::{self, SharedOutput};
+    use crate::vm::Vm;
+    use std::rc::Rc;
+
+    // Runs `source` on the VM, returning what it printed and the message of
+    // the error it stopped with, if any
+    fn run_vm(source: &str) -> (String, Option<String>) {
+        let program = parse(source).unwrap();
+        let (resolution, _) = resolve(&program);
+        let script = Rc::new(compile(&program, &resolution));
+        let output = SharedOutput::default();
+        let result = Vm::new(Box::new(output.clone())).run(script);
+        (output.text(), result.err().map(|error| error.message))
+    }
+
+    // The VM has to behave exactly like the tree-walking interpreter
+    fn same(source: &str) -> String {
+        fixtures::same(source, run_vm)
+    }
+
+    #[test]
+    fn matches_the_interpreter() {
+        let programs = [
+            "print ( 1 + 2 * 3 , 7 / 2 , - 5 % 3 , \"a\" + 1 + true )",
+            "let x = 1 { let x = 2 print ( x ) } print ( x ) x = 3 print ( x )",
+            "fn counter ( ) { let count = 0 return fn ( ) { count += 1 return count } }
+             let next = counter ( ) next ( ) next ( ) print ( next ( ) , counter ( ) ( ) )",
+            "obj point { x : 1 , y : 2 , sum : fn ( ) { return this . x + this . y } }
+             point . x = 10 point [ \"y\" ] += 5
+             for key in point { print ( key ) }
+             print ( point . sum ( ) , point )",
+            "fn Point ( x ) { this . x = x } let p = new Point ( 4 ) print ( p , p . x )
+             fn Other ( ) { return obj { y : 1 } } print ( new Other ( ) )",
+            "print ( null || \"default\" , 0 && crash ( ) , 1 and 2 , 0 or 3 )
+             print ( true xor false , true xand false , not 0 , ! 1 )",
+            "let name = 'world' print ( `hello ${ name } ${ 1 + 1 }!` , `plain` )",
+            "let a = 0 a ||= 5 a &&= a + 1 print ( a )
+             let o = obj { n : 0 , m : 1 } o . n ||= 2 o . m &&= 7 o [ 'n' ] += 1
+             o . n &&= 0 o [ 'm' ] ||= 9 print ( o )",
+            "fn f ( a , b ) { return `${ a } ${ b }` } print ( f ( 1 ) , f ( 1 , 2 , 3 ) )",
+            "let fns = obj { } for c in \"abc\" { fns [ c ] = fn ( ) { return c } }
+             print ( fns . a ( ) + fns . b ( ) + fns . c ( ) , \"abc\" [ 1 ] , \"abc\" . length )",
+            "fn first ( s ) { for c in s { if c != \" \" { return c } } return null }
+             print ( first ( \"  x y\" ) , first ( \"\" ) )",
+            "let n = 0 if n > 0 { print ( 1 ) } else if n < 0 { print ( 2 ) } else { print ( 3 ) }",
+            "fn f ( ) { return g ( ) } fn g ( ) { return 2 } print ( f ( ) )",
+            "print = fn ( x ) { } print ( 1 )",
+        ];
+        for program in programs {
+            same(program);
+        }
+        assert_eq!(
+            same("fn fib ( n ) { if n < 2 { return n } return fib ( n - 1 ) + fib ( n - 2 ) } print ( fib ( 20 ) )"),
+            "6765\n"
+        );
+    }
+
+    #[test]
+    fn errors_match_the_interpreter() {
+        let programs = [
+            "missing",
+            "y = 1",
+            "let n = 1 n ( )",
+            "print ( 1 ) - \"a\"",
+            "for x in 1 { }",
+            "return 1",
+            "fn f ( ) { return later } f ( ) let later = 1",
+            "let o = null o . x",
+            "let s = \"s\" s [ \"k\" ] = 1",
+            "print ( 1 ) new 1 ( )",
+        ];
+        for program in programs {
+            same(program);
+            assert!(run_vm(program).1.is_some(), "{program} should fail");
+        }
+    }
+
+    #[test]
+    fn functions_see_the_same_names_in_both() {
+        // Which `x` the body reads would depend on when it runs, so the
+        // program is stopped before either engine runs it
+        let program = "let x = 1\n{\n fn f() { return x }\n print(f())\n let x = 2\n}";
+        let (_, diagnostics) = resolve(&parse(program).unwrap());
+        let codes: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.code)
+            .collect();
+        assert_eq!(codes, ["E0302"]);
+        assert_eq!(
+            same("let x = 1\n{\n let x = 2\n fn f() { return x }\n print(f())\n}"),
+            "2\n"
+        );
+        assert_eq!(
+            same("let x = 1\n{\n fn f() { return x }\n print(f())\n}\nx = 3\nprint(x)"),
+            "1\n3\n"
+        );
+    }
+
+    #[test]
+    fn deep_recursion_fails_cleanly() {
+        let (_, error) = run_vm("fn f ( n ) { return f ( n + 1 ) } f ( 0 )");
+        assert_eq!(error.as_deref(), Some("stack overflow"));
+        // The interpreter stops at the same depth
+        let output = same(
+            "fn f ( n ) { if n % 1000 == 0 or n > 9990 { print ( n ) } return f ( n + 1 ) } f ( 1 )",
+        );
+        assert!(output.ends_with("9000\n9991\n9992\n9993\n9994\n9995\n9996\n9997\n9998\n9999\n"));
+    }
+
+    #[test]
+    fn variables_compile_to_slots() {
+        let program = parse("let a = 1\nfn f ( b ) { return a + b }\nprint ( f ( 2 ) )").unwrap();
+        let (resolution, _) = resolve(&program);
+        let script = compile(&program, &resolution);
+        assert_eq!(&*script.locals, ["a".to_string(), "f".to_string()]);
+        let f = &script.chunk.functions[0];
+        assert_eq!(f.parameters, 1);
+        assert_eq!(
+            f.chunk.code[..3],
+            [
+                Instruction::Get { depth: 1, slot: 0 },
+                Instruction::Get { depth: 0, slot: 0 },
+                Instruction::Binary(crate::parser::BinaryOperator::Arithmetic(
+                    crate::token::ArithmeticToken::Add
+                )),
+            ]
+        );
+        // Equal literals share one constant
+        let program = parse("print ( 1 , 1 , \"1\" )").unwrap();
+        let script = compile(&program, &resolve(&program).0);
+        assert_eq!(script.chunk.constants.len(), 4);
+    }
+
+    #[test]
+    fn disassembles() {
+        let program = parse("let x = 1\nif x { print ( `${x}` ) }\nfn f ( ) { }").unwrap();
+        let (resolution, _) = resolve(&program);
+        assert_eq!(
+            disassemble(&compile(&program, &resolution)),
+            "\
+== script ==
+locals: x, f
+0000    1  Constant 0 (1)
+0001    1  Set 0 0
+0002    1  Pop
+0003    2  Get 0 0
+0004    2  JumpIfFalse 12
+0005    2  GetGlobal 1 (\"print\")
+0006    2  Constant 2 (\"\")
+0007    2  Get 0 0
+0008    2  Constant 2 (\"\")
+0009    2  Template 3
+0010    2  Call 1
+0011    2  Pop
+0012    3  Closure 0 (fn f)
+0013    3  Set 0 1
+0014    3  Pop
+0015    3  Constant 3 (undefined)
+0016    3  Return
+
+== fn f ==
+0000    3  Constant 0 (undefined)
+0001    3  Return
+"
+        );
+    }
+}
+
+#[cfg(test)]
+mod js_tests {
+    use crate::js::{generate, JsModule};
+    use crate::parser::parse;
+    use crate::resolve::resolve;
+    use crate::source_map::SourceFile;
+
+    fn module(source: &str) -> JsModule {
+        let program = parse(source).unwrap();
+        let (resolution, _) = resolve(&program);
+        generate(
+            &program,
+            &resolution,
+            &SourceFile::new("src/main.toy", source),
+        )
+    }
+
+    // The generated statements, without the header, helpers or footer
+    fn js(source: &str) -> String {
+        let code = module(source).code;
+        code.lines()
+            .skip(1)
+            .take_while(|line| !line.is_empty() && !line.starts_with("//# "))
+            .map(|line| format!("{line}\n"))
+            .collect()
+    }
+
+    // Decodes `mappings` into (generated line, generated column, source line,
+    // source column) for each segment that has a source
+    fn decode(mappings: &str) -> Vec<[i64; 4]> {
+        const BASE64: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut decoded = Vec::new();
+        let mut previous = [0i64; 4];
+        for (line, segments) in mappings.split(';').enumerate() {
+            previous[0] = 0;
+            for segment in segments.split(',').filter(|segment| !segment.is_empty()) {
+                let mut values = Vec::new();
+                let (mut value, mut shift) = (0i64, 0);
+                for c in segment.chars() {
+                    let digit = BASE64.find(c).unwrap() as i64;
+                    value |= (digit & 31) << shift;
+                    shift += 5;
+                    if digit & 32 == 0 {
+                        let magnitude = value >> 1;
+                        values.push(if value & 1 == 1 {
+                            -magnitude
+                        } else {
+                            magnitude
+                        });
+                        (value, shift) = (0, 0);
+                    }
+                }
+                previous[0] += values[0];
+                if values.len() == 4 {
+                    for field in 2..4 {
+                        previous[field] += values[field];
+                    }
+                    decoded.push([line as i64, previous[0], previous[2], previous[3]]);
+                }
+            }
+        }
+        decoded
+    }
+
+    #[test]
+    fn statements_become_javascript() {
+        assert_eq!(
+            js("
+/// Sums the fields
+obj point { x: 1, y: 2, sum: fn() { return this.x + this.y } }
+fn Box(v) { this.v = v }
+let b = new Box(point.sum())
+if b.v > 2 { print(`big ${b.v}`) } else if b.v { print(\"small\") } else {}
+for key in point { print(key) }
+"),
+            "\
+/**
+ * Sums the fields
+ */
+let point = {
+  x: 1,
+  y: 2,
+  sum() {
+    return this.x + this.y;
+  },
+};
+function Box(v) {
+  this.v = v;
+}
+let b = new Box(point.sum());
+if (b.v > 2) {
+  console.log(`big ${b.v}`);
+} else if (b.v) {
+  console.log(\"small\");
+} else {}
+for (let key of $iterate(point)) {
+  console.log(key);
+}
+"
+        );
+    }
+
+    #[test]
+    fn operators_keep_their_meaning() {
+        assert_eq!(
+            js("let a = (1 + 2) * 3 - -(-4) / 5 % 6\nlet b = 1 == 1.0 and 2 != 3 or not true xor false"),
+            "let a = (1 + 2) * 3 - -(-4) / 5 % 6;\nlet b = 1 === 1.0 && 2 !== 3 || (!!true !== !false);\n"
+        );
+        assert_eq!(
+            js("let o = obj { n: 0 }\no.n ||= 1\no[\"n\"] &&= 2\nfn f() { return o }\nf().n ||= 3"),
+            "\
+let o = { n: 0 };
+(o.n || (o.n = 1));
+(o[\"n\"] && (o[\"n\"] = 2));
+function f() {
+  return o;
+}
+(($object) => $object.n || ($object.n = 3))(f());
+"
+        );
+        assert_eq!(
+            js("(fn() { print(1) })()\nobj { a: 1 }\nlet n = 123n"),
+            "(function() {\n  console.log(1);\n})();\n({ a: 1 });\nlet n = 123n;\n"
+        );
+    }
+
+    #[test]
+    fn names_javascript_reserves_are_escaped() {
+        let module = module("export let class = 1\nexport let $x = class\nexport fn print2(console) { return console }\nexport let print = 2\nprint\nlet private = 3");
+        assert!(module.code.contains("let $class = 1;\nlet $$x = $class;\n"));
+        assert!(module
+            .code
+            .contains("function print2($console) {\n  return $console;\n}\n"));
+        // A shadowed `print` is not the builtin
+        assert!(module.code.contains("let print = 2;\nprint;\n"));
+        assert!(module
+            .code
+            .contains("export { $class as class, $$x as $x, print2, print };\n"));
+    }
+
+    #[test]
+    fn methods_can_use_super() {
+        let source = "obj point {
+  x: 1,
+  own: fn(key) { return super.hasOwnProperty.call(this, key) },
+  named: fn describe() { return `x is ${this.x}` },
+}
+fn Box(v) { this.v = v }
+let b = new Box(point.x + 1)
+print(point.own(\"x\"), point.own(\"y\"), point.named(), b.v)";
+        assert!(js(source).contains("  own(key) {\n    return super.hasOwnProperty"));
+        // Run it when node is installed
+        let directory = std::env::temp_dir().join(format!("toy-js-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("main.mjs");
+        std::fs::write(&path, module(source).code).unwrap();
+        let output = std::process::Command::new("node").arg(&path).output();
+        std::fs::remove_dir_all(&directory).unwrap();
+        let Ok(output) = output else {
+            return;
+        };
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "",
+            "node rejected the module"
+        );
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "true false x is 1 2\n"
+        );
+    }
+
+    #[test]
+    fn helpers_are_written_only_when_used() {
+        let code = module("for c in \"ab\" { print(c) }").code;
+        assert!(code.contains("function $iterate(value) {"));
+        assert_eq!(code.matches("function $").count(), 1);
+        assert!(!module("print(1)").code.contains("function $"));
+    }
+
+    #[test]
+    fn source_map_points_back_to_the_source() {
+        let generated = module("let s = \"é😀\"\nfn f(a) {\n  return s + a\n}");
+        assert_eq!(generated.file, "main.js");
+        assert!(generated
+            .code
+            .ends_with("//# sourceMappingURL=main.js.map\n"));
+        assert!(generated.source_map.starts_with(
+            "{\"version\":3,\"file\":\"main.js\",\"sources\":[\"main.toy\"],\
+             \"sourcesContent\":[\"let s = \\\"é😀\\\"\\nfn f(a) {\\n  return s + a\\n}\"],\
+             \"names\":[],\"mappings\":\""
+        ));
+        let decoded = segments(&generated);
+        // `let s = "é😀";` on generated line 1
+        assert!(decoded.contains(&[1, 0, 0, 0]));
+        assert!(decoded.contains(&[1, 4, 0, 4]));
+        assert!(decoded.contains(&[1, 8, 0, 8]));
+        // `return s + a;` on generated line 3, from source line 2
+        assert!(decoded.contains(&[3, 2, 2, 2]));
+        assert!(decoded.contains(&[3, 9, 2, 9]));
+        assert!(decoded.contains(&[3, 13, 2, 13]));
+        // Columns count UTF-16 code units, so `x` is at 16 rather than its
+        // byte offset of 19
+        let decoded = segments(&module("let s = \"é😀\" + x"));
+        assert!(decoded.contains(&[1, 16, 0, 16]));
+    }
+
+    fn segments(module: &JsModule) -> Vec<[i64; 4]> {
+        let (_, mappings) = module.source_map.rsplit_once("\"mappings\":\"").unwrap();
+        decode(mappings.trim_end_matches("\"}"))
+    }
+}
+
+#[cfg(test)]
+mod wasm_tests {
+    use crate::parser::parse;
+    use crate::resolve::resolve;
+    use crate::test::fixtures::{self, run_interpreter};
+    use crate::token::NumberToken;
+    use crate::types::check;
+    use crate::wasm::compile;
+    use wasmi::core::F64;
+    use wasmi::{Caller, Engine, Extern, Linker, Module, Store};
+
+    fn module(source: &str) -> Result<Vec<u8>, Vec<String>> {
+        let program = parse(source).unwrap();
+        let (resolution, _) = resolve(&program);
+        let (types, _) = check(&program, &resolution);
+        compile(&program, &resolution, &types).map_err(|diagnostics| {
+            diagnostics
+                .iter()
+                .map(|diagnostic| format!("{}: {}", diagnostic.code, diagnostic.message))
+                .collect()
+        })
+    }
+
+    // Runs the module for `source` under wasmi, with host functions that
+    // write the way the interpreter's `print` does. Returns what it printed
+    // and the trap it stopped with, if any.
+    fn run_wasm(source: &str) -> (String, Option<String>) {
+        let bytes = module(source).unwrap();
+        let engine = Engine::default();
+        let module = Module::new(&engine, &bytes[..]).unwrap();
+        let mut store = Store::new(&engine, String::new());
+        let mut linker = <Linker<String>>::new(&engine);
+        linker
+            .func_wrap(
+                "toy",
+                "write_int",
+                |mut caller: Caller<'_, String>, value: i64| {
+                    caller.data_mut().push_str(&value.to_string());
+                },
+            )
+            .unwrap();
+        linker
+            .func_wrap(
+                "toy",
+                "write_float",
+                |mut caller: Caller<'_, String>, value: F64| {
+                    let text = NumberToken::Float(value.into()).to_string();
+                    caller.data_mut().push_str(&text);
+                },
+            )
+            .unwrap();
+        linker
+            .func_wrap(
+                "toy",
+                "write_string",
+                |mut caller: Caller<'_, String>, pointer: i32, length: i32| {
+                    let memory = caller
+                        .get_export("memory")
+                        .and_then(Extern::into_memory)
+                        .unwrap();
+                    let mut bytes = vec![0; length as usize];
+                    memory.read(&caller, pointer as usize, &mut bytes).unwrap();
+                    let text = String::from_utf8(bytes).unwrap();
+                    caller.data_mut().push_str(&text);
+                },
+            )
+            .unwrap();
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .unwrap()
+            .start(&mut store)
+            .unwrap();
+        let main = instance.get_typed_func::<(), ()>(&store, "main").unwrap();
+        let trap = main.call(&mut store, ()).err();
+        (store.into_data(), trap.map(|trap| trap.to_string()))
+    }
+
+    // The module has to behave exactly like the tree-walking interpreter
+    fn same(source: &str) -> String {
+        fixtures::same(source, run_wasm)
+    }
+
+    #[test]
+    fn matches_the_interpreter() {
+        let programs = [
+            "print ( 1 + 2 * 3 , 7 / 2 , 8 / 2 , - 5 % 3 , 7.5 % 2 , 1 | 6 , 3 & 6 )",
+            "print ( 1 < 2 , 2.5 >= 3 , 1 == 1.0 , \"a\" == \"a\" , \"a\" != \"b\" , 1 == \"1\" )",
+            "fn fib ( n : int ) : int { if n < 2 { return n } return fib ( n - 1 ) + fib ( n - 2 ) }
+             print ( fib ( 20 ) )",
+            "fn half ( x : float ) : float { return x / 2 } print ( half ( 3 ) , half ( 0.5 ) )",
+            "let count = 0 fn bump ( by : int ) { count += by } bump ( 2 ) bump ( 3 ) print ( count )",
+            "let x = 1 { let x = \"inner\" print ( x ) } x *= 10 print ( x )",
+            "let name = \"héllo\" print ( `${ name } has ${ name . length } characters` )",
+            "for c in \"añb\" { print ( c ) }",
+            "print ( \"n=\" + 42 , - 9223372036854775807 - 1 , 0 , \"\" + true + false )",
+            "let s = \"\" if s || \"x\" { print ( \"yes\" , s && \"no\" ) } else { print ( \"no\" ) }",
+            "print ( 0.5 and 2.5 , 0 or 3 , true xor false , ! 0 , not \"\" )",
+            "let a = 0 a ||= 5 let b = 2 b &&= 7 print ( a , b )",
+            "if 0.0 / 0.0 { print ( \"nan\" ) } else if 1 { print ( \"one\" ) }",
+            "fn greet ( who : string ) : string { return \"hi \" + who } print ( greet ( \"you\" ) )",
+        ];
+        for program in programs {
+            same(program);
+        }
+    }
+
+    #[test]
+    fn grows_memory_as_strings_are_built() {
+        let source = "fn grow ( s : string , n : int ) : string {
+                if n == 0 { return s }
+                return grow ( s + s , n - 1 )
+            }
+            print ( grow ( \"ab\" , 17 ) . length )";
+        assert_eq!(same(source), "262144\n");
+    }
+
+    #[test]
+    fn int_arithmetic_wraps() {
+        let source = "print ( 9223372036854775807 + 1 , 3037000500 * 3037000500 )";
+        assert_eq!(
+            run_wasm(source),
+            (
+                "-9223372036854775808 -9223372036709301616\n".to_string(),
+                None
+            )
+        );
+        assert_eq!(
+            run_interpreter(source),
+            (
+                "9223372036854776000 9223372037000250000\n".to_string(),
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn remainder_by_zero_traps() {
+        let source = "print ( 1 ) print ( 5 % 0 )";
+        let (output, trap) = run_wasm(source);
+        assert_eq!(output, "1\n");
+        assert_eq!(trap.as_deref(), Some("integer divide by zero"));
+        assert_eq!(run_interpreter(source), ("1\nNaN\n".to_string(), None));
+    }
+
+    #[test]
+    fn imports_output_and_exports_main() {
+        let bytes = module("print ( 1 , 2.5 , \"three\" )").unwrap();
+        let module = Module::new(&Engine::default(), &bytes[..]).unwrap();
+        let imports: Vec<_> = module
+            .imports()
+            .map(|import| format!("{}.{}", import.module(), import.name()))
+            .collect();
+        assert_eq!(
+            imports,
+            ["toy.write_int", "toy.write_float", "toy.write_string"]
+        );
+        let mut exports: Vec<_> = module.exports().map(|export| export.name()).collect();
+        exports.sort();
+        assert_eq!(exports, ["main", "memory"]);
+    }
+
+    #[test]
+    fn reports_what_it_cannot_compile() {
+        let cases = [
+            (
+                "obj point { x : 1 }",
+                "E0500: an object is not supported by the wasm target",
+            ),
+            (
+                "let nothing = null",
+                "E0500: a value of type `null` is not supported by the wasm target",
+            ),
+            (
+                "fn id ( x ) { return x }",
+                "E0500: the wasm target needs to know this type",
+            ),
+            (
+                "fn outer ( ) { fn inner ( ) { } }",
+                "E0500: a nested function is not supported by the wasm target",
+            ),
+            (
+                "print ( 1.5 + \"\" )",
+                "E0500: converting a `float` to a string is not supported by the wasm target",
+            ),
+            (
+                "print ( \"a\" < \"b\" )",
+                "E0500: ordering strings is not supported by the wasm target",
+            ),
+        ];
+        for (source, message) in cases {
+            assert_eq!(
+                module(source),
+                Err(vec![message.to_string()]),
+                "for {source}"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod document_tests {
+    use crate::document::parse_document;
+    use crate::parser::{ExprKind, StmtKind};
+
+    const README: &str = r#"<script type="text/javascript" tech="nodejs" name="backend">
+  export const someData = mongoService.getData();
+</script>
+
+<!-- the page -->
+<SCRIPT type='application/typescript' tech=angular name="frontend" >
+export class AppComponent {}
+</script >
+
+<script name="shared">
+let greeting = "héllo"
+print(greeting)
+</script>
+"#;
+
+    #[test]
+    fn splits_sections_with_their_attributes() {
+        let (document, diagnostics) = parse_document(README);
+        assert_eq!(diagnostics, []);
+        let summary: Vec<_> = document
+            .sections
+            .iter()
+            .map(|section| {
+                (
+                    section.media_type(),
+                    section.tech(),
+                    section.name(),
+                    section.is_toy(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    Some("text/javascript"),
+                    Some("nodejs"),
+                    Some("backend"),
+                    false
+                ),
+                (
+                    Some("application/typescript"),
+                    Some("angular"),
+                    Some("frontend"),
+                    false
+                ),
+                (None, None, Some("shared"), true),
+            ]
+        );
+        let backend = document.section("backend").unwrap();
+        assert_eq!(
+            backend.source,
+            "\n  export const someData = mongoService.getData();\n"
+        );
+        assert_eq!(
+            &README[backend.offset..][..backend.source.len()],
+            backend.source
+        );
+        assert_eq!(backend.program, None);
+        let frontend = document.section("frontend").unwrap();
+        assert!(README[frontend.span.start..frontend.span.end].starts_with("<SCRIPT"));
+        assert!(README[frontend.span.start..frontend.span.end].ends_with("</script >"));
+        assert_eq!(
+            document
+                .toy_sections()
+                .map(|section| section.name())
+                .collect::<Vec<_>>(),
+            [Some("shared")]
+        );
+    }
+
+    #[test]
+    fn toy_sections_have_file_relative_spans() {
+        let (document, _) = parse_document(README);
+        let shared = document.section("shared").unwrap();
+        let program = shared.program.as_ref().unwrap();
+        let StmtKind::Expression(call) = &program.statements[1].kind else {
+            panic!("expected a call");
+        };
+        let ExprKind::Call { arguments, .. } = &call.kind else {
+            panic!("expected a call");
+        };
+        let argument = &arguments[0].span;
+        let expected = README.rfind("greeting)").unwrap();
+        assert_eq!((argument.start, argument.line), (expected, 11));
+        // `é` is two bytes but one UTF-16 code unit
+        assert_eq!(argument.utf16_start, argument.start - 1);
+    }
+
+    #[test]
+    fn plain_files_are_one_section() {
+        let source = "let x = 1\nprint(x)\n";
+        let (document, diagnostics) = parse_document(source);
+        assert_eq!(diagnostics, []);
+        assert_eq!(document.sections.len(), 1);
+        let section = &document.sections[0];
+        assert_eq!((section.name(), section.is_toy()), (None, true));
+        assert_eq!(section.source, source);
+        assert_eq!(section.program.as_ref().unwrap().statements.len(), 2);
+    }
+
+    #[test]
+    fn syntax_errors_point_into_the_document() {
+        let source = "<script name=\"a\">\nlet = 1\n</script>";
+        let (document, diagnostics) = parse_document(source);
+        assert_eq!(document.sections[0].program, None);
+        let errors: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.code, diagnostic.span.start, diagnostic.span.line))
+            .collect();
+        assert_eq!(errors, [("E0100", source.find("= 1").unwrap(), 1)]);
+    }
+
+    #[test]
+    fn reports_malformed_documents() {
+        let cases = [
+            (
+                "<script name=a></script>\nstray text\n<script name=b></script>",
+                vec![("E0600", "expected a `<script>` section", "stray text")],
+            ),
+            (
+                "<script name=a></script><script name=a></script>",
+                vec![(
+                    "E0603",
+                    "there is already a section named `a`",
+                    "<script name=a>",
+                )],
+            ),
+            (
+                "<script name=a name=b></script>",
+                vec![("E0602", "duplicate attribute `name`", "name")],
+            ),
+            (
+                "<script name=\"a></script>",
+                vec![("E0602", "unterminated attribute value", "\"")],
+            ),
+            (
+                "<script name=a>\nlet x = 1\n",
+                vec![(
+                    "E0601",
+                    "`<script>` section is never closed",
+                    "<script name=a>",
+                )],
+            ),
+            (
+                "<script name=a",
+                vec![("E0601", "unterminated `<script>` tag", "<script")],
+            ),
+        ];
+        for (source, expected) in cases {
+            let (_, diagnostics) = parse_document(source);
+            let found: Vec<_> = diagnostics
+                .iter()
+                .map(|diagnostic| {
+                    (
+                        diagnostic.code,
+                        diagnostic.message.as_str(),
+                        &source[diagnostic.span.start..diagnostic.span.end],
+                    )
+                })
+                .collect();
+            assert_eq!(found, expected, "for {source}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod link_tests {
+    use crate::document::parse_document;
+    use crate::link::link;
+    use crate::resolve::{DeclarationKind, NodeId};
+    use crate::types::check;
+
+    // The code, message and spanned text of each diagnostic from linking
+    fn link_errors(source: &str) -> Vec<(&'static str, String, &str)> {
+        let (document, _) = parse_document(source);
+        let (_, diagnostics) = link(&document);
+        diagnostics
+            .into_iter()
+            .map(|diagnostic| {
+                let text = &source[diagnostic.span.start..diagnostic.span.end];
+                (diagnostic.code, diagnostic.message, text)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_frontend_reads_the_backend() {
+        let source = r#"<script type="text/javascript" tech="nodejs" name="backend">
+  export const someData = mongoService.getData();
+</script>
+
+<script type="application/typescript" tech="angular" name="frontend">
+export class AppComponent {
+  someData = backend.someData;
+}
+</script>"#;
+        let (document, _) = parse_document(source);
+        let (links, diagnostics) = link(&document);
+        assert_eq!(diagnostics, []);
+        let exports: Vec<Vec<&str>> = links
+            .exports
+            .iter()
+            .map(|exports| {
+                let exports = exports.as_ref().unwrap();
+                exports.iter().map(|export| export.name.as_str()).collect()
+            })
+            .collect();
+        assert_eq!(exports, [vec!["someData"], vec!["AppComponent"]]);
+        let [reference] = links.references.as_slice() else {
+            panic!("expected one reference, got {:?}", links.references);
+        };
+        assert_eq!((reference.from, reference.to), (1, 0));
+        assert_eq!(reference.member, "someData");
+        assert_eq!(
+            &source[reference.span.start..reference.span.end],
+            "backend.someData"
+        );
+        assert_eq!(links.order, Some(vec![0, 1]));
+    }
+
+    #[test]
+    fn toy_sections_export_the_names_marked_export() {
+        let source = "<script name=\"shared\">
+let separator = \" \"
+export let greeting = \"hello\"
+export fn greet(name) {
+  let punctuation = \"!\"
+  return `${greeting}${separator}${name}${punctuation}`
+}
+export obj Point { x: 1 }
+fn helper() {}
+{ let step = 1 }
+</script>
+<script name=\"app\">
+print(shared.greeting, shared.greet(\"you\"), shared.Point.x)
+print(shared.separator, shared.helper, shared.punctuation, shared.step)
+</script>";
+        let (document, _) = parse_document(source);
+        let (links, _) = link(&document);
+        let exports: Vec<&str> = links.exports[0]
+            .iter()
+            .flatten()
+            .map(|export| export.name.as_str())
+            .collect();
+        assert_eq!(exports, ["greeting", "greet", "Point"]);
+        assert_eq!(
+            link_errors(source),
+            [
+                (
+                    "E0303",
+                    "section `shared` does not export `separator`".to_string(),
+                    "separator"
+                ),
+                (
+                    "E0303",
+                    "section `shared` does not export `helper`".to_string(),
+                    "helper"
+                ),
+                (
+                    "E0303",
+                    "section `shared` does not export `punctuation`".to_string(),
+                    "punctuation"
+                ),
+                (
+                    "E0303",
+                    "section `shared` does not export `step`".to_string(),
+                    "step"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn toy_sections_read_each_others_declarations() {
+        let source = "<script name=\"app\">
+print(lib.limit, lib.double(2))
+</script>
+<script name=\"lib\">
+export let limit = 10
+export fn double(n) { return n * 2 }
+</script>";
+        let (document, diagnostics) = parse_document(source);
+        assert_eq!(diagnostics, []);
+        let (links, diagnostics) = link(&document);
+        assert_eq!(diagnostics, []);
+        assert_eq!(links.order, Some(vec![1, 0]));
+        let app = document.sections[0].program.as_ref().unwrap();
+        let resolution = links.resolutions[0].as_ref().unwrap();
+        let limit = resolution
+            .lookup(NodeId(source.find("limit,").unwrap()))
+            .unwrap();
+        let declaration = resolution.declaration(limit);
+        assert_eq!(declaration.kind, DeclarationKind::Field);
+        assert_eq!(
+            declaration.span.as_ref().map(|span| span.start),
+            source.find("limit =")
+        );
+        let members: Vec<_> = links
+            .references
+            .iter()
+            .map(|reference| reference.member.as_str())
+            .collect();
+        assert_eq!(members, ["limit", "double"]);
+        assert_eq!(check(app, resolution).1, []);
+    }
+
+    #[test]
+    fn reports_members_that_are_not_exported() {
+        let source = "<script name=\"a\">export let x = 1</script>
+<script name=\"b\">print(a.x, a.y)</script>
+<script type=\"text/javascript\" name=\"c\">console.log(a.z, window.a.w)</script>";
+        assert_eq!(
+            link_errors(source),
+            [
+                ("E0303", "section `a` does not export `y`".to_string(), "y"),
+                ("E0303", "section `a` does not export `z`".to_string(), "z"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_cyclic_sections() {
+        let source = "<script name=\"a\">export let x = b.y</script>
+<script name=\"b\">export let y = c.z</script>
+<script type=\"text/javascript\" name=\"c\">export const z = a.x</script>";
+        let (document, _) = parse_document(source);
+        let (links, diagnostics) = link(&document);
+        assert_eq!(links.order, None);
+        let [diagnostic] = diagnostics.as_slice() else {
+            panic!("expected one diagnostic, got {diagnostics:?}");
+        };
+        assert_eq!(diagnostic.code, "E0604");
+        assert_eq!(
+            diagnostic.message,
+            "sections read from each other in a cycle: `a` -> `b` -> `c` -> `a`"
+        );
+        assert_eq!(&source[diagnostic.span.start..diagnostic.span.end], "b.y");
+        let labels: Vec<_> = diagnostic
+            .labels
+            .iter()
+            .map(|label| &source[label.span.start..label.span.end])
+            .collect();
+        assert_eq!(labels, ["c.z", "a.x"]);
+    }
+
+    #[test]
+    fn finds_javascript_exports() {
+        let source = "<script type=\"text/javascript\" name=\"js\">
+export { d, e as f };
+export async function g() {}
+export default h;
+export let i = 1;
+export function* j() {}
+</script>";
+        let (document, _) = parse_document(source);
+        let (links, _) = link(&document);
+        let names: Vec<_> = links.exports[0]
+            .iter()
+            .flatten()
+            .map(|export| export.name.as_str())
+            .collect();
+        assert_eq!(names, ["d", "f", "g", "i", "j"]);
+    }
+
+    #[test]
+    fn sections_with_syntax_errors_are_not_checked() {
+        let source = "<script name=\"a\">let = </script>
+<script name=\"b\">print(a.anything)</script>";
+        assert_eq!(link_errors(source), []);
     }
 }
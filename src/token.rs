@@ -1,37 +1,204 @@
-use std::ops::Add;
+use num_bigint::BigInt;
+use num_traits::{ToPrimitive, Zero};
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
 
+// `start` and `end` are byte offsets into the source, `end` exclusive. The
+// UTF-16 offsets cover the same range for hosts such as JS that index strings
+// by code unit.
 #[derive(Debug, PartialEq, Clone)]
 pub struct TokenSpan {
     pub start: usize,
     pub end: usize,
     pub line: usize,
+    pub utf16_start: usize,
+    pub utf16_end: usize,
+}
+
+impl TokenSpan {
+    // Span covering everything from the start of `self` to the end of `other`
+    pub fn to(&self, other: &TokenSpan) -> TokenSpan {
+        TokenSpan {
+            start: self.start,
+            end: other.end,
+            line: self.line,
+            utf16_start: self.utf16_start,
+            utf16_end: other.utf16_end,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum NumberToken {
     SignedInteger(i64),
     Float(f64),
+    // Arbitrary-precision integer from a literal with an `n` suffix, e.g. `123n`
+    BigInt(BigInt),
 }
 
-impl Add for NumberToken {
-    type Output = NumberToken;
-    fn add(self, rhs: Self) -> Self::Output {
+impl NumberToken {
+    // Applies an operator, keeping integer results while they fit in an i64 and
+    // promoting to a float when `integer` reports overflow (or any other case
+    // it cannot represent, such as a remainder by zero). Once a BigInt is
+    // involved integer arithmetic stays exact through `big` instead.
+    fn combine(
+        self,
+        rhs: Self,
+        integer: fn(i64, i64) -> Option<i64>,
+        big: fn(&BigInt, &BigInt) -> Option<BigInt>,
+        float: fn(f64, f64) -> f64,
+    ) -> Self {
         match (self, rhs) {
             // If both are signed integers, keep as signed integer if possible
             (Self::SignedInteger(left), Self::SignedInteger(right)) => {
                 // Check for overflow
-                match left.checked_add(right) {
+                match integer(left, right) {
                     Some(result) => Self::SignedInteger(result),
-                    _ => Self::Float(left as f64 + right as f64),
+                    _ => Self::Float(float(left as f64, right as f64)),
                 }
             }
-            // If one is float, result is float
-            (Self::Float(left), Self::Float(right)) => Self::Float(left + right),
-            (Self::Float(left), Self::SignedInteger(right)) => Self::Float(left + right as f64),
-            (Self::SignedInteger(left), Self::Float(right)) => Self::Float(left as f64 + right),
+            (left, right) => {
+                let result = match (left.as_bigint(), right.as_bigint()) {
+                    (Some(l), Some(r)) => big(&l, &r),
+                    // If one is float, result is float
+                    _ => None,
+                };
+                result.map_or_else(
+                    || Self::Float(float(left.as_f64(), right.as_f64())),
+                    Self::BigInt,
+                )
+            }
+        }
+    }
+
+    pub fn as_f64(&self) -> f64 {
+        match self {
+            Self::SignedInteger(value) => *value as f64,
+            Self::Float(value) => *value,
+            Self::BigInt(value) => value.to_f64().unwrap_or(f64::NAN),
+        }
+    }
+
+    // The exact value of an integer of either width; `None` for floats
+    pub fn as_bigint(&self) -> Option<BigInt> {
+        match self {
+            Self::SignedInteger(value) => Some(BigInt::from(*value)),
+            Self::Float(_) => None,
+            Self::BigInt(value) => Some(value.clone()),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Self::SignedInteger(value) => *value == 0,
+            Self::Float(value) => *value == 0.0,
+            Self::BigInt(value) => value.is_zero(),
+        }
+    }
+
+    // Compares by numeric value: integers exactly, anything involving a float
+    // as f64, and `None` when either side is NaN
+    pub fn numeric_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Self::SignedInteger(left), Self::SignedInteger(right)) => Some(left.cmp(right)),
+            _ => match (self.as_bigint(), other.as_bigint()) {
+                (Some(left), Some(right)) => Some(left.cmp(&right)),
+                _ => self.as_f64().partial_cmp(&other.as_f64()),
+            },
+        }
+    }
+}
+
+impl Add for NumberToken {
+    type Output = NumberToken;
+    fn add(self, rhs: Self) -> Self::Output {
+        self.combine(
+            rhs,
+            i64::checked_add,
+            |left, right| Some(left + right),
+            |left, right| left + right,
+        )
+    }
+}
+
+impl Sub for NumberToken {
+    type Output = NumberToken;
+    fn sub(self, rhs: Self) -> Self::Output {
+        self.combine(
+            rhs,
+            i64::checked_sub,
+            |left, right| Some(left - right),
+            |left, right| left - right,
+        )
+    }
+}
+
+impl Mul for NumberToken {
+    type Output = NumberToken;
+    fn mul(self, rhs: Self) -> Self::Output {
+        self.combine(
+            rhs,
+            i64::checked_mul,
+            |left, right| Some(left * right),
+            |left, right| left * right,
+        )
+    }
+}
+
+impl Div for NumberToken {
+    type Output = NumberToken;
+    fn div(self, rhs: Self) -> Self::Output {
+        // Integer division only stays an integer when it is exact, so `7 / 2`
+        // is 3.5 and `1 / 0` is infinity rather than a panic
+        self.combine(
+            rhs,
+            |left, right| match left.checked_rem(right) {
+                Some(0) => left.checked_div(right),
+                _ => None,
+            },
+            |left, right| (!right.is_zero() && (left % right).is_zero()).then(|| left / right),
+            |left, right| left / right,
+        )
+    }
+}
+
+impl Rem for NumberToken {
+    type Output = NumberToken;
+    fn rem(self, rhs: Self) -> Self::Output {
+        self.combine(
+            rhs,
+            i64::checked_rem,
+            |left, right| (!right.is_zero()).then(|| left % right),
+            |left, right| left % right,
+        )
+    }
+}
+
+impl Neg for NumberToken {
+    type Output = NumberToken;
+    fn neg(self) -> Self::Output {
+        match self {
+            Self::SignedInteger(value) => match value.checked_neg() {
+                Some(result) => Self::SignedInteger(result),
+                None => Self::Float(-(value as f64)),
+            },
+            Self::Float(value) => Self::Float(-value),
+            Self::BigInt(value) => Self::BigInt(-value),
+        }
+    }
+}
+
+impl fmt::Display for NumberToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SignedInteger(value) => write!(f, "{value}"),
+            Self::Float(value) => write!(f, "{value}"),
+            Self::BigInt(value) => write!(f, "{value}"),
         }
     }
 }
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum WhiteSpaceToken {
     Space,
@@ -39,6 +206,29 @@ pub enum WhiteSpaceToken {
     NewLine,
 }
 
+// The text between the comment delimiters, e.g. ` note` for `// note`
+#[derive(Debug, PartialEq, Clone)]
+pub enum CommentToken {
+    Line(String),
+    Block(String),
+    // `///`, documenting the `fn` or `obj` declaration that follows
+    Doc(String),
+}
+
+// The text pieces of a backtick string with `${}` interpolations:
+// `` `a${x}b${y}c` `` scans as Start("a"), the tokens of `x`, Middle("b"), the
+// tokens of `y`, then End("c"). A backtick string without interpolations is an
+// ordinary string literal.
+#[derive(Debug, PartialEq, Clone)]
+pub enum TemplateToken {
+    // From the opening backtick through the first `${`
+    Start(String),
+    // From the `}` closing one interpolation through the next `${`
+    Middle(String),
+    // From the `}` closing the last interpolation through the closing backtick
+    End(String),
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum PunctuatorToken {
     Semicolon,
@@ -78,8 +268,6 @@ pub enum ArithmeticToken {
     Divide,
     BitwiseAnd,
     BitwiseOr,
-    Or,
-    And,
     Modulo,
 }
 
@@ -141,6 +329,8 @@ pub enum DeclarationToken {
     Let,
     Function,
     Object,
+    // `export`, in front of a top-level `let`, `fn` or `obj`
+    Export,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -162,8 +352,10 @@ pub enum TokenType {
     Delimiter(DelimiterToken),
     ControlFlow(ControlFlowToken),
     WhiteSpace(WhiteSpaceToken),
+    Comment(CommentToken),
     Identifier(IdentifierToken),
     Literal(LiteralToken),
+    Template(TemplateToken),
     Declaration(DeclarationToken),
     ObjectReference(ObjectReferenceToken),
     Unknown(char),
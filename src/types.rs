@@ -0,0 +1,1339 @@
+use crate::diagnostic::Diagnostic;
+use crate::parser::*;
+use crate::resolve::{DeclId, DeclarationKind, NodeId, Resolution};
+use crate::token::*;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct TypeVar(pub usize);
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Type {
+    Int,
+    Float,
+    String,
+    Bool,
+    Null,
+    Undefined,
+    // Anything at all, checked only at run time, such as the arguments of
+    // `print`
+    Any,
+    // Fields in the order written. An open object, as inferred for `p` from
+    // `p.x`, may have more fields than those listed, which `rest` stands for.
+    Object {
+        fields: Vec<(String, Type)>,
+        rest: Option<TypeVar>,
+    },
+    Function {
+        parameters: Vec<Type>,
+        result: Box<Type>,
+    },
+    // A type along with `null`, `undefined` or both
+    Union(Vec<Type>),
+    // Not known yet
+    Var(TypeVar),
+}
+
+impl Type {
+    fn is_nullish(&self) -> bool {
+        matches!(self, Type::Null | Type::Undefined)
+    }
+
+    // Whether the type has no variables left in it
+    fn is_settled(&self) -> bool {
+        match self {
+            Type::Var(_) => false,
+            Type::Object { fields, rest } => {
+                rest.is_none() && fields.iter().all(|(_, field)| field.is_settled())
+            }
+            Type::Function { parameters, result } => {
+                parameters.iter().all(Type::is_settled) && result.is_settled()
+            }
+            Type::Union(members) => members.iter().all(Type::is_settled),
+            _ => true,
+        }
+    }
+
+    fn is_numeric(&self) -> bool {
+        matches!(self, Type::Int | Type::Float)
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::String => write!(f, "string"),
+            Type::Bool => write!(f, "bool"),
+            Type::Null => write!(f, "null"),
+            Type::Undefined => write!(f, "undefined"),
+            Type::Any => write!(f, "any"),
+            Type::Object { fields, rest } => {
+                if fields.is_empty() && rest.is_none() {
+                    return write!(f, "{{}}");
+                }
+                let mut parts: Vec<String> = fields
+                    .iter()
+                    .map(|(name, field)| format!("{name}: {field}"))
+                    .collect();
+                if rest.is_some() {
+                    parts.push("..".to_string());
+                }
+                write!(f, "{{ {} }}", parts.join(", "))
+            }
+            Type::Function { parameters, result } => {
+                let parameters: Vec<String> = parameters.iter().map(Type::to_string).collect();
+                write!(f, "fn({}): {result}", parameters.join(", "))
+            }
+            Type::Union(members) => {
+                let members: Vec<String> = members.iter().map(Type::to_string).collect();
+                write!(f, "{}", members.join(" | "))
+            }
+            Type::Var(_) => write!(f, "_"),
+        }
+    }
+}
+
+// Flattens nested unions and drops repeated members
+fn union(members: Vec<Type>) -> Type {
+    let mut flat: Vec<Type> = Vec::new();
+    for member in members {
+        let nested = match member {
+            Type::Union(nested) => nested,
+            other => vec![other],
+        };
+        for member in nested {
+            if !flat.contains(&member) {
+                flat.push(member);
+            }
+        }
+    }
+    if flat.len() == 1 {
+        flat.pop().expect("one member")
+    } else {
+        Type::Union(flat)
+    }
+}
+
+fn literal_type(literal: &LiteralToken) -> Type {
+    match literal {
+        LiteralToken::Number(NumberToken::Float(_)) => Type::Float,
+        LiteralToken::Number(_) => Type::Int,
+        LiteralToken::String(_) => Type::String,
+        LiteralToken::Boolean(_) => Type::Bool,
+        LiteralToken::Null => Type::Null,
+        LiteralToken::Undefined => Type::Undefined,
+    }
+}
+
+pub(crate) fn symbol(operator: &BinaryOperator) -> &'static str {
+    match operator {
+        BinaryOperator::Arithmetic(ArithmeticToken::Add) => "+",
+        BinaryOperator::Arithmetic(ArithmeticToken::Subtract) => "-",
+        BinaryOperator::Arithmetic(ArithmeticToken::Multiply) => "*",
+        BinaryOperator::Arithmetic(ArithmeticToken::Divide) => "/",
+        BinaryOperator::Arithmetic(ArithmeticToken::Modulo) => "%",
+        BinaryOperator::Comparison(ComparisonToken::GreaterThan) => ">",
+        BinaryOperator::Comparison(ComparisonToken::GreaterThanOrEqual) => ">=",
+        BinaryOperator::Comparison(ComparisonToken::LessThan) => "<",
+        BinaryOperator::Comparison(ComparisonToken::LessThanOrEqual) => "<=",
+        BinaryOperator::Comparison(ComparisonToken::Equal) => "==",
+        BinaryOperator::Comparison(ComparisonToken::NotEqual) => "!=",
+        BinaryOperator::Arithmetic(ArithmeticToken::BitwiseAnd) => "&",
+        BinaryOperator::Arithmetic(ArithmeticToken::BitwiseOr) => "|",
+        BinaryOperator::Logical(LogicalToken::And) => "&&",
+        BinaryOperator::Logical(LogicalToken::Or) => "||",
+        BinaryOperator::Logical(LogicalToken::XOr) => "xor",
+        BinaryOperator::Logical(LogicalToken::XAnd) => "xand",
+        _ => "operator",
+    }
+}
+
+fn count(number: usize, noun: &str) -> String {
+    match number {
+        1 => format!("1 {noun}"),
+        _ => format!("{number} {noun}s"),
+    }
+}
+
+// A declaration's type, with the variables listed in `generic` replaced by
+// fresh ones at each use so that `fn id(x) { return x; }` works for any `x`
+#[derive(Debug, Clone)]
+struct Scheme {
+    generic: Vec<TypeVar>,
+    body: Type,
+}
+
+impl Scheme {
+    fn monomorphic(body: Type) -> Self {
+        Scheme {
+            generic: Vec::new(),
+            body,
+        }
+    }
+}
+
+// The type of every declaration in a program, keyed like the resolver's
+// side table
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Types {
+    pub declarations: HashMap<DeclId, Type>,
+}
+
+impl Types {
+    pub fn of(&self, decl: DeclId) -> Option<&Type> {
+        self.declarations.get(&decl)
+    }
+}
+
+// Infers a type for every declaration in `program` and checks it against
+// any annotations and every use. Names are looked up through `resolution`;
+// those it could not bind are left unchecked.
+//
+// Annotations are optional, and a variable without one may be assigned
+// anything. An assignment that does not fit the type inferred so far widens
+// the variable instead, to the type assigned if the old one fits it and to
+// `any` otherwise, and the program is checked again with the variable
+// having that type from its declaration on.
+pub fn check(program: &Program, resolution: &Resolution) -> (Types, Vec<Diagnostic>) {
+    let mut annotated = HashSet::new();
+    let mut widened = HashMap::new();
+    let mut checker = loop {
+        let mut checker = Checker {
+            resolution,
+            bindings: Vec::new(),
+            levels: Vec::new(),
+            level: 0,
+            declarations: HashMap::new(),
+            named: HashMap::new(),
+            result: None,
+            this: Type::Any,
+            annotated: annotated.clone(),
+            widened: widened.clone(),
+            diagnostics: Vec::new(),
+        };
+        checker.statements(&program.statements);
+        if checker.widened == widened {
+            break checker;
+        }
+        annotated = checker.annotated;
+        widened = checker.widened;
+    };
+    let declarations = checker
+        .declarations
+        .iter()
+        .map(|(decl, scheme)| (*decl, checker.resolved(&scheme.body)))
+        .collect();
+    checker
+        .diagnostics
+        .sort_by_key(|diagnostic| diagnostic.span.start);
+    (Types { declarations }, checker.diagnostics)
+}
+
+struct Checker<'a> {
+    resolution: &'a Resolution,
+    // What each type variable has been found to be
+    bindings: Vec<Option<Type>>,
+    // How many functions deep each variable was created. Those deeper than
+    // a declaration when its body is done are generalized.
+    levels: Vec<usize>,
+    level: usize,
+    declarations: HashMap<DeclId, Scheme>,
+    // Types named by `obj` declarations
+    named: HashMap<String, Type>,
+    // The result type of the function being checked
+    result: Option<Type>,
+    this: Type,
+    // Variables and parameters declared with a type, which never widen
+    annotated: HashSet<DeclId>,
+    // The types unannotated variables widened to, on this pass or earlier
+    // ones
+    widened: HashMap<DeclId, Type>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+    fn fresh_var(&mut self, level: usize) -> TypeVar {
+        self.bindings.push(None);
+        self.levels.push(level);
+        TypeVar(self.bindings.len() - 1)
+    }
+
+    fn fresh(&mut self) -> Type {
+        Type::Var(self.fresh_var(self.level))
+    }
+
+    // Follows bound variables, and the rest of open objects, to the type as
+    // far as it is known
+    fn shallow(&self, ty: &Type) -> Type {
+        match ty {
+            Type::Var(var) => match &self.bindings[var.0] {
+                Some(bound) => self.shallow(bound),
+                None => ty.clone(),
+            },
+            Type::Object {
+                fields,
+                rest: Some(rest),
+            } if self.bindings[rest.0].is_some() => match self.shallow(&Type::Var(*rest)) {
+                Type::Object { fields: more, rest } => Type::Object {
+                    fields: fields.iter().cloned().chain(more).collect(),
+                    rest,
+                },
+                _ => unreachable!("the rest of an object is only bound to an object"),
+            },
+            _ => ty.clone(),
+        }
+    }
+
+    // The type with everything known about it filled in
+    fn resolved(&self, ty: &Type) -> Type {
+        match self.shallow(ty) {
+            Type::Object { fields, rest } => Type::Object {
+                fields: fields
+                    .iter()
+                    .map(|(name, field)| (name.clone(), self.resolved(field)))
+                    .collect(),
+                rest,
+            },
+            Type::Function { parameters, result } => Type::Function {
+                parameters: parameters.iter().map(|ty| self.resolved(ty)).collect(),
+                result: Box::new(self.resolved(&result)),
+            },
+            Type::Union(members) => union(members.iter().map(|ty| self.resolved(ty)).collect()),
+            other => other,
+        }
+    }
+
+    // Whether `var` appears in `ty`. Variables in `ty` are moved out to the
+    // level of `var` on the way, since binding `var` makes them just as
+    // widely visible.
+    fn occurs(&mut self, var: TypeVar, ty: &Type) -> bool {
+        match self.shallow(ty) {
+            Type::Var(other) => {
+                self.levels[other.0] = self.levels[other.0].min(self.levels[var.0]);
+                other == var
+            }
+            Type::Object { fields, rest } => {
+                if let Some(rest) = rest {
+                    if rest == var {
+                        return true;
+                    }
+                    self.levels[rest.0] = self.levels[rest.0].min(self.levels[var.0]);
+                }
+                fields.iter().any(|(_, field)| self.occurs(var, field))
+            }
+            Type::Function { parameters, result } => {
+                parameters.iter().any(|ty| self.occurs(var, ty)) || self.occurs(var, &result)
+            }
+            Type::Union(members) => members.iter().any(|ty| self.occurs(var, ty)),
+            _ => false,
+        }
+    }
+
+    fn bind(&mut self, var: TypeVar, ty: &Type) -> bool {
+        if self.occurs(var, ty) {
+            return false;
+        }
+        self.bindings[var.0] = Some(ty.clone());
+        true
+    }
+
+    // Makes a value of type `actual` fit where `expected` is wanted. Besides
+    // equal types, an `int` fits a `float`, a type fits a union with it, and
+    // an object fits an object type with fewer fields.
+    fn unify(&mut self, actual: &Type, expected: &Type) -> bool {
+        let (actual, expected) = (self.shallow(actual), self.shallow(expected));
+        match (&actual, &expected) {
+            (Type::Any, _) | (_, Type::Any) => true,
+            (Type::Var(left), Type::Var(right)) if left == right => true,
+            (Type::Var(var), other) | (other, Type::Var(var)) => self.bind(*var, other),
+            (Type::Union(members), _) => members.iter().all(|member| self.unify(member, &expected)),
+            (_, Type::Union(members)) => {
+                if actual.is_nullish() {
+                    return members.contains(&actual);
+                }
+                members
+                    .iter()
+                    .filter(|member| !member.is_nullish())
+                    .any(|member| self.try_unify(&actual, member))
+            }
+            (Type::Int, Type::Float) => true,
+            (
+                Type::Object {
+                    fields: actual_fields,
+                    rest: actual_rest,
+                },
+                Type::Object {
+                    fields: expected_fields,
+                    rest: expected_rest,
+                },
+            ) => self.unify_objects(actual_fields, *actual_rest, expected_fields, *expected_rest),
+            (
+                Type::Function {
+                    parameters: actual_parameters,
+                    result: actual_result,
+                },
+                Type::Function {
+                    parameters: expected_parameters,
+                    result: expected_result,
+                },
+            ) => {
+                // Parameters go the other way: a function expecting a
+                // `float` can stand in for one expecting an `int`
+                actual_parameters.len() == expected_parameters.len()
+                    && actual_parameters
+                        .iter()
+                        .zip(expected_parameters)
+                        .all(|(actual, expected)| self.unify(expected, actual))
+                    && self.unify(actual_result, expected_result)
+            }
+            (actual, expected) => actual == expected,
+        }
+    }
+
+    fn unify_objects(
+        &mut self,
+        actual_fields: &[(String, Type)],
+        actual_rest: Option<TypeVar>,
+        expected_fields: &[(String, Type)],
+        expected_rest: Option<TypeVar>,
+    ) -> bool {
+        let mut missing = Vec::new();
+        for (name, expected) in expected_fields {
+            match actual_fields.iter().find(|(field, _)| field == name) {
+                Some((_, actual)) => {
+                    if !self.unify(actual, expected) {
+                        return false;
+                    }
+                }
+                None if actual_rest.is_some() => missing.push((name.clone(), expected.clone())),
+                None => return false,
+            }
+        }
+        let extra: Vec<(String, Type)> = actual_fields
+            .iter()
+            .filter(|(name, _)| !expected_fields.iter().any(|(field, _)| field == name))
+            .cloned()
+            .collect();
+        match (actual_rest, expected_rest) {
+            (Some(actual), Some(expected)) if actual == expected => {
+                missing.is_empty() && extra.is_empty()
+            }
+            (Some(actual), Some(expected)) => {
+                let rest = Some(self.fresh_var(self.level));
+                self.bind(
+                    actual,
+                    &Type::Object {
+                        fields: missing,
+                        rest,
+                    },
+                ) && self.bind(
+                    expected,
+                    &Type::Object {
+                        fields: extra,
+                        rest,
+                    },
+                )
+            }
+            (Some(actual), None) => {
+                missing.is_empty() || {
+                    let rest = Some(self.fresh_var(self.level));
+                    self.bind(
+                        actual,
+                        &Type::Object {
+                            fields: missing,
+                            rest,
+                        },
+                    )
+                }
+            }
+            (None, Some(expected)) => self.bind(
+                expected,
+                &Type::Object {
+                    fields: extra,
+                    rest: None,
+                },
+            ),
+            (None, None) => true,
+        }
+    }
+
+    // `unify`, leaving everything as it was if the types do not fit
+    fn try_unify(&mut self, actual: &Type, expected: &Type) -> bool {
+        let bindings = self.bindings.clone();
+        let levels = self.levels.clone();
+        if self.unify(actual, expected) {
+            return true;
+        }
+        self.bindings = bindings;
+        self.levels = levels;
+        false
+    }
+
+    // Reports a mismatch at `span` if `actual` does not fit `expected`
+    fn expect(&mut self, actual: &Type, expected: &Type, span: &TokenSpan) -> bool {
+        if self.try_unify(actual, expected) {
+            return true;
+        }
+        let (actual, expected) = (self.resolved(actual), self.resolved(expected));
+        self.error(
+            "E0400",
+            format!("expected `{expected}`, found `{actual}`"),
+            span,
+        );
+        false
+    }
+
+    fn error(&mut self, code: &'static str, message: String, span: &TokenSpan) {
+        self.diagnostics
+            .push(Diagnostic::error(code, message, span.clone()));
+    }
+
+    // Variables created inside the declaration just finished, and not tied
+    // to anything outside it, can be anything at each use
+    fn generalize(&mut self, ty: &Type) -> Scheme {
+        let body = self.resolved(ty);
+        let mut generic = Vec::new();
+        self.generic_variables(&body, &mut generic);
+        Scheme { generic, body }
+    }
+
+    fn generic_variables(&self, ty: &Type, generic: &mut Vec<TypeVar>) {
+        let mut add = |var: TypeVar| {
+            if self.levels[var.0] > self.level && !generic.contains(&var) {
+                generic.push(var);
+            }
+        };
+        match ty {
+            Type::Var(var) => add(*var),
+            Type::Object { fields, rest } => {
+                if let Some(rest) = rest {
+                    add(*rest);
+                }
+                for (_, field) in fields {
+                    self.generic_variables(field, generic);
+                }
+            }
+            Type::Function { parameters, result } => {
+                for parameter in parameters {
+                    self.generic_variables(parameter, generic);
+                }
+                self.generic_variables(result, generic);
+            }
+            Type::Union(members) => {
+                for member in members {
+                    self.generic_variables(member, generic);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        if scheme.generic.is_empty() {
+            return scheme.body.clone();
+        }
+        let fresh: HashMap<TypeVar, TypeVar> = scheme
+            .generic
+            .iter()
+            .map(|var| (*var, self.fresh_var(self.level)))
+            .collect();
+        self.substitute(&scheme.body, &fresh)
+    }
+
+    fn substitute(&self, ty: &Type, fresh: &HashMap<TypeVar, TypeVar>) -> Type {
+        let replace = |var: TypeVar| fresh.get(&var).copied().unwrap_or(var);
+        match self.shallow(ty) {
+            Type::Var(var) => Type::Var(replace(var)),
+            Type::Object { fields, rest } => Type::Object {
+                fields: fields
+                    .iter()
+                    .map(|(name, field)| (name.clone(), self.substitute(field, fresh)))
+                    .collect(),
+                rest: rest.map(replace),
+            },
+            Type::Function { parameters, result } => Type::Function {
+                parameters: parameters
+                    .iter()
+                    .map(|ty| self.substitute(ty, fresh))
+                    .collect(),
+                result: Box::new(self.substitute(&result, fresh)),
+            },
+            Type::Union(members) => Type::Union(
+                members
+                    .iter()
+                    .map(|ty| self.substitute(ty, fresh))
+                    .collect(),
+            ),
+            other => other,
+        }
+    }
+
+    // The type of a use of `decl`. A name used before its declaration is
+    // checked gets a variable that the declaration must then fit.
+    fn declaration_type(&mut self, decl: DeclId) -> Type {
+        // Neither builtins nor other sections are checked
+        if matches!(
+            self.resolution.declaration(decl).kind,
+            DeclarationKind::Builtin | DeclarationKind::Section
+        ) {
+            return Type::Any;
+        }
+        match self.declarations.get(&decl).cloned() {
+            Some(scheme) => self.instantiate(&scheme),
+            None => {
+                let var = Type::Var(self.fresh_var(0));
+                self.declarations
+                    .insert(decl, Scheme::monomorphic(var.clone()));
+                var
+            }
+        }
+    }
+
+    fn define(&mut self, name: &Identifier, mut ty: Type) {
+        let Some(decl) = self.resolution.declared(NodeId::of(&name.span)) else {
+            return;
+        };
+        if let Some(wider) = self.widened.get(&decl).cloned() {
+            if !self.annotated.contains(&decl) {
+                self.try_unify(&ty, &wider);
+                ty = wider;
+            }
+        }
+        match self
+            .declarations
+            .get(&decl)
+            .map(|scheme| scheme.body.clone())
+        {
+            Some(used) => {
+                self.expect(&ty, &used, &name.span);
+            }
+            None => {
+                self.declarations.insert(decl, Scheme::monomorphic(ty));
+            }
+        }
+    }
+
+    // Marks the name as declared with a type, so that it never widens
+    fn annotate(&mut self, name: &Identifier) {
+        if let Some(decl) = self.resolution.declared(NodeId::of(&name.span)) {
+            self.annotated.insert(decl);
+        }
+    }
+
+    fn annotation(&mut self, annotation: &TypeExpr) -> Type {
+        match &annotation.kind {
+            TypeExprKind::Named(name) => match name.as_str() {
+                "int" => Type::Int,
+                "float" => Type::Float,
+                "string" => Type::String,
+                "bool" => Type::Bool,
+                "any" => Type::Any,
+                _ => match self.named.get(name) {
+                    Some(named) => named.clone(),
+                    None => {
+                        self.error(
+                            "E0401",
+                            format!("cannot find type `{name}`"),
+                            &annotation.span,
+                        );
+                        Type::Any
+                    }
+                },
+            },
+            TypeExprKind::Null => Type::Null,
+            TypeExprKind::Undefined => Type::Undefined,
+            TypeExprKind::Object(fields) => Type::Object {
+                fields: fields
+                    .iter()
+                    .map(|field| (field.key.name.clone(), self.annotation(&field.annotation)))
+                    .collect(),
+                rest: None,
+            },
+            TypeExprKind::Function { parameters, result } => Type::Function {
+                parameters: parameters.iter().map(|ty| self.annotation(ty)).collect(),
+                result: Box::new(match result {
+                    Some(result) => self.annotation(result),
+                    None => Type::Undefined,
+                }),
+            },
+            TypeExprKind::Union(members) => {
+                let members: Vec<Type> = members.iter().map(|ty| self.annotation(ty)).collect();
+                if members.iter().filter(|ty| !ty.is_nullish()).count() > 1 {
+                    self.error(
+                        "E0407",
+                        "a union can only add `null` and `undefined` to one other type".to_string(),
+                        &annotation.span,
+                    );
+                    return Type::Any;
+                }
+                union(members)
+            }
+        }
+    }
+
+    fn statements(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Stmt) {
+        match &statement.kind {
+            StmtKind::Let {
+                name,
+                annotation: None,
+                value: Some(value),
+            } if matches!(value.kind, ExprKind::Function(_)) => self.let_function(name, value),
+            StmtKind::Let {
+                name,
+                annotation,
+                value,
+            } => {
+                if annotation.is_some() {
+                    self.annotate(name);
+                }
+                let annotated = annotation
+                    .as_ref()
+                    .map(|annotation| self.annotation(annotation));
+                let ty = match (value, annotated) {
+                    (Some(value), Some(annotated)) => {
+                        let actual = self.expression(value);
+                        self.expect(&actual, &annotated, &value.span);
+                        annotated
+                    }
+                    (Some(value), None) => self.expression(value),
+                    // Without a value the variable starts out `undefined`
+                    (None, Some(annotated)) => {
+                        self.expect(&Type::Undefined, &annotated, &name.span);
+                        annotated
+                    }
+                    (None, None) => self.fresh(),
+                };
+                self.define(name, ty);
+            }
+            StmtKind::Function(function) => self.function_declaration(function),
+            StmtKind::Object { name, fields, .. } => {
+                let ty = self.object(fields);
+                self.named.insert(name.name.clone(), ty.clone());
+                self.define(name, ty);
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                self.statements(&then_branch.statements);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            StmtKind::For {
+                binding,
+                iterable,
+                body,
+            } => {
+                let ty = self.expression(iterable);
+                match self.shallow(&ty) {
+                    Type::Object { .. } | Type::String | Type::Any | Type::Var(_) => {}
+                    other => {
+                        let other = self.resolved(&other);
+                        self.error(
+                            "E0408",
+                            format!("cannot iterate over `{other}`"),
+                            &iterable.span,
+                        );
+                    }
+                }
+                // Both the keys of an object and the characters of a string
+                self.define(binding, Type::String);
+                self.statements(&body.statements);
+            }
+            StmtKind::Return(value) => {
+                let (ty, span) = match value {
+                    Some(value) => (self.expression(value), &value.span),
+                    None => (Type::Undefined, &statement.span),
+                };
+                if let Some(result) = self.result.clone() {
+                    self.expect(&ty, &result, span);
+                }
+            }
+            StmtKind::Block(block) => self.statements(&block.statements),
+            StmtKind::Expression(expr) => {
+                self.expression(expr);
+            }
+        }
+    }
+
+    // Declared functions are generalized, unless they were used before
+    // their declaration was reached. Recursive calls inside the body see a
+    // single type.
+    fn function_declaration(&mut self, function: &Function) {
+        let Some((name, decl)) = function.name.as_ref().and_then(|name| {
+            let decl = self.resolution.declared(NodeId::of(&name.span))?;
+            Some((name, decl))
+        }) else {
+            self.function(function, Type::Any);
+            return;
+        };
+        let used_earlier = self.declarations.contains_key(&decl);
+        self.level += 1;
+        if !used_earlier {
+            let own = self.fresh();
+            self.declarations.insert(decl, Scheme::monomorphic(own));
+        }
+        let ty = self.function(function, Type::Any);
+        self.level -= 1;
+        let own = self.declarations[&decl].body.clone();
+        self.expect(&ty, &own, &name.span);
+        if !used_earlier {
+            let scheme = self.generalize(&ty);
+            self.declarations.insert(decl, scheme);
+        }
+    }
+
+    // A function expression bound with `let` is generalized as a declared
+    // function is, unless it was used before its declaration was reached or
+    // has been widened by an assignment
+    fn let_function(&mut self, name: &Identifier, value: &Expr) {
+        let decl = self.resolution.declared(NodeId::of(&name.span));
+        let generic = decl.filter(|decl| {
+            !self.declarations.contains_key(decl) && !self.widened.contains_key(decl)
+        });
+        self.level += 1;
+        let ty = self.expression(value);
+        self.level -= 1;
+        self.define(name, ty.clone());
+        if let Some(decl) = generic {
+            let scheme = self.generalize(&ty);
+            self.declarations.insert(decl, scheme);
+        }
+    }
+
+    fn function(&mut self, function: &Function, this: Type) -> Type {
+        let mut parameters = Vec::new();
+        for parameter in &function.parameters {
+            let ty = match &parameter.annotation {
+                Some(annotation) => {
+                    self.annotate(&parameter.name);
+                    self.annotation(annotation)
+                }
+                None => self.fresh(),
+            };
+            self.define(&parameter.name, ty.clone());
+            parameters.push(ty);
+        }
+        let result = match &function.return_type {
+            Some(annotation) => self.annotation(annotation),
+            None => self.fresh(),
+        };
+        let outer_result = self.result.replace(result.clone());
+        let outer_this = std::mem::replace(&mut self.this, this);
+        self.statements(&function.body.statements);
+        if !function.body.always_returns() && !self.try_unify(&Type::Undefined, &result) {
+            let span = match (&function.return_type, &function.name) {
+                (Some(annotation), _) => &annotation.span,
+                (None, Some(name)) => &name.span,
+                (None, None) => &function.span,
+            };
+            let expected = self.resolved(&result);
+            self.diagnostics.push(
+                Diagnostic::error(
+                    "E0400",
+                    format!("expected `{expected}`, found `undefined`"),
+                    span.clone(),
+                )
+                .with_note("a function that reaches the end of its body returns `undefined`"),
+            );
+        }
+        self.result = outer_result;
+        self.this = outer_this;
+        Type::Function {
+            parameters,
+            result: Box::new(result),
+        }
+    }
+
+    // Functions among the fields see the object as `this`
+    fn object(&mut self, fields: &[ObjectField]) -> Type {
+        let mut types: Vec<(String, Type)> = Vec::new();
+        let mut methods = Vec::new();
+        for field in fields {
+            let ty = match &field.value.kind {
+                ExprKind::Function(function) => {
+                    let ty = self.fresh();
+                    methods.push((function, ty.clone(), &field.value.span));
+                    ty
+                }
+                _ => self.expression(&field.value),
+            };
+            self.define(&field.key, ty.clone());
+            // A repeated key replaces the earlier value, as when run
+            match types.iter_mut().find(|(name, _)| *name == field.key.name) {
+                Some((_, slot)) => *slot = ty,
+                None => types.push((field.key.name.clone(), ty)),
+            }
+        }
+        let object = Type::Object {
+            fields: types,
+            rest: None,
+        };
+        for (function, ty, span) in methods {
+            let method = self.function(function, object.clone());
+            self.expect(&method, &ty, span);
+        }
+        object
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Type {
+        match &expr.kind {
+            ExprKind::Literal(literal) => literal_type(literal),
+            ExprKind::Identifier(_) => match self.resolution.lookup(NodeId::of(&expr.span)) {
+                Some(decl) => self.declaration_type(decl),
+                None => Type::Any,
+            },
+            ExprKind::ObjectReference(ObjectReferenceToken::This) => self.this.clone(),
+            ExprKind::ObjectReference(_) => Type::Any,
+            // A constructor may return any object in place of `this`
+            ExprKind::New { callee, arguments } => {
+                let callee = self.expression(callee);
+                self.call(&callee, arguments, &expr.span);
+                Type::Any
+            }
+            ExprKind::Object(fields) => self.object(fields),
+            ExprKind::Function(function) => self.function(function, Type::Any),
+            ExprKind::Template { expressions, .. } => {
+                for expression in expressions {
+                    self.expression(expression);
+                }
+                Type::String
+            }
+            ExprKind::Unary {
+                operator: UnaryOperator::Negate,
+                operand,
+                ..
+            } => {
+                let ty = self.expression(operand);
+                match self.shallow(&ty) {
+                    Type::Int | Type::Float | Type::Any | Type::Var(_) => ty,
+                    other => {
+                        let other = self.resolved(&other);
+                        self.error(
+                            "E0403",
+                            format!("`-` cannot be applied to `{other}`"),
+                            &expr.span,
+                        );
+                        Type::Any
+                    }
+                }
+            }
+            ExprKind::Unary { operand, .. } => {
+                self.expression(operand);
+                Type::Bool
+            }
+            ExprKind::Binary {
+                operator,
+                left,
+                right,
+                ..
+            } => self.binary(operator, left, right, &expr.span),
+            ExprKind::Assign {
+                operator,
+                target,
+                value,
+                ..
+            } => self.assign(operator, target, value),
+            ExprKind::Call { callee, arguments } => {
+                let callee = self.expression(callee);
+                self.call(&callee, arguments, &expr.span)
+            }
+            ExprKind::Member { object, property } => {
+                let object = self.expression(object);
+                self.member(&object, property)
+            }
+            ExprKind::Index { object, index } => {
+                let object = self.expression(object);
+                let key = self.expression(index);
+                match self.shallow(&object) {
+                    Type::String => {
+                        self.expect(&key, &Type::Int, &index.span);
+                        union(vec![Type::String, Type::Undefined])
+                    }
+                    Type::Object { .. } => {
+                        self.expect(&key, &Type::String, &index.span);
+                        Type::Any
+                    }
+                    _ => Type::Any,
+                }
+            }
+        }
+    }
+
+    fn binary(
+        &mut self,
+        operator: &BinaryOperator,
+        left: &Expr,
+        right: &Expr,
+        span: &TokenSpan,
+    ) -> Type {
+        use ArithmeticToken as A;
+        let left_type = self.expression(left);
+        let right_type = self.expression(right);
+        match operator {
+            BinaryOperator::Arithmetic(A::BitwiseAnd | A::BitwiseOr) => {
+                self.expect(&left_type, &Type::Int, &left.span);
+                self.expect(&right_type, &Type::Int, &right.span);
+                Type::Int
+            }
+            BinaryOperator::Logical(LogicalToken::And) => self.either(left_type, right_type),
+            // The left side is only the result when it is truthy, so never
+            // `null` or `undefined`
+            BinaryOperator::Logical(LogicalToken::Or) => match self.shallow(&left_type) {
+                Type::Null | Type::Undefined => right_type,
+                Type::Union(members) => {
+                    let left_type =
+                        union(members.into_iter().filter(|ty| !ty.is_nullish()).collect());
+                    self.either(left_type, right_type)
+                }
+                _ => self.either(left_type, right_type),
+            },
+            BinaryOperator::Arithmetic(_) => self.arithmetic(operator, left_type, right_type, span),
+            BinaryOperator::Comparison(ComparisonToken::Equal | ComparisonToken::NotEqual)
+            | BinaryOperator::Logical(_) => Type::Bool,
+            BinaryOperator::Comparison(_) => {
+                let (left_type, right_type) = (self.shallow(&left_type), self.shallow(&right_type));
+                let ordered = match (&left_type, &right_type) {
+                    (Type::Any, _) | (_, Type::Any) | (Type::String, Type::String) => true,
+                    (left, right) if left.is_numeric() && right.is_numeric() => true,
+                    (Type::Var(_), other) | (other, Type::Var(_))
+                        if other.is_numeric() || matches!(other, Type::String | Type::Var(_)) =>
+                    {
+                        self.unify(&left_type, &right_type)
+                    }
+                    _ => false,
+                };
+                if !ordered {
+                    self.operands(operator, &left_type, &right_type, span);
+                }
+                Type::Bool
+            }
+        }
+    }
+
+    // `+` adds numbers or joins anything to a string; `-`, `*`, `/` and `%`
+    // only take numbers. Division may not come out whole. Other operators on
+    // ints give an `int`: the float the interpreter gives when one overflows,
+    // or for `%` by zero, is left to the runtime.
+    fn arithmetic(
+        &mut self,
+        operator: &BinaryOperator,
+        left: Type,
+        right: Type,
+        span: &TokenSpan,
+    ) -> Type {
+        let (left, right) = (self.shallow(&left), self.shallow(&right));
+        let divide = *operator == BinaryOperator::Arithmetic(ArithmeticToken::Divide);
+        if *operator == BinaryOperator::Arithmetic(ArithmeticToken::Add)
+            && (left == Type::String || right == Type::String)
+        {
+            return Type::String;
+        }
+        let result = match (&left, &right) {
+            (Type::Any, _) | (_, Type::Any) => return Type::Any,
+            (Type::Int, Type::Int) => Type::Int,
+            (left, right) if left.is_numeric() && right.is_numeric() => Type::Float,
+            (Type::Var(_), other) | (other, Type::Var(_))
+                if other.is_numeric() || matches!(other, Type::Var(_)) =>
+            {
+                self.unify(&left, &right);
+                other.clone()
+            }
+            _ => {
+                self.operands(operator, &left, &right, span);
+                return Type::Any;
+            }
+        };
+        if divide {
+            Type::Float
+        } else {
+            result
+        }
+    }
+
+    fn operands(&mut self, operator: &BinaryOperator, left: &Type, right: &Type, span: &TokenSpan) {
+        let (left, right) = (self.resolved(left), self.resolved(right));
+        self.error(
+            "E0403",
+            format!(
+                "`{}` cannot be applied to `{left}` and `{right}`",
+                symbol(operator)
+            ),
+            span,
+        );
+    }
+
+    // The type of a value that is one of two, when one fits the other
+    fn either(&mut self, left: Type, right: Type) -> Type {
+        if self.try_unify(&right, &left) {
+            left
+        } else if self.try_unify(&left, &right) {
+            right
+        } else {
+            Type::Any
+        }
+    }
+
+    fn assign(&mut self, operator: &AssignmentToken, target: &Expr, value: &Expr) -> Type {
+        use ArithmeticToken as A;
+        // A generic variable could be assigned a value that is less generic,
+        // so it becomes `any` instead
+        if self.is_generic(target) {
+            self.widen(target, &Type::Any, &Type::Any);
+        }
+        let target_type = self.target(target);
+        let value_type = self.expression(value);
+        let arithmetic = match operator {
+            AssignmentToken::Assign | AssignmentToken::AndAssign | AssignmentToken::OrAssign => {
+                None
+            }
+            AssignmentToken::PlusAssign => Some(A::Add),
+            AssignmentToken::MinusAssign => Some(A::Subtract),
+            AssignmentToken::MultiplyAssign => Some(A::Multiply),
+            AssignmentToken::DivideAssign => Some(A::Divide),
+            AssignmentToken::BitwiseAndAssign => Some(A::BitwiseAnd),
+            AssignmentToken::BitwiseOrAssign => Some(A::BitwiseOr),
+        };
+        let new_type = match arithmetic {
+            None => value_type,
+            Some(bitwise @ (A::BitwiseAnd | A::BitwiseOr)) => {
+                let operator = BinaryOperator::Arithmetic(bitwise);
+                if !self.try_unify(&value_type, &Type::Int) {
+                    self.operands(&operator, &target_type, &value_type, &value.span);
+                }
+                Type::Int
+            }
+            Some(operator) => self.arithmetic(
+                &BinaryOperator::Arithmetic(operator),
+                target_type.clone(),
+                value_type,
+                &value.span,
+            ),
+        };
+        // An unannotated variable whose type is not known yet, such as a
+        // parameter, is not narrowed to whatever it is first assigned
+        let known = !matches!(self.shallow(&target_type), Type::Var(_));
+        let fits = known && self.try_unify(&new_type, &target_type);
+        if !fits && !self.widen(target, &target_type, &new_type) {
+            self.expect(&new_type, &target_type, &value.span);
+        }
+        target_type
+    }
+
+    // The type of what `target` holds. Assigning an object a field it does
+    // not have adds the field, which is allowed when the object is held by
+    // an unannotated variable, by widening the variable to `any`.
+    fn target(&mut self, target: &Expr) -> Type {
+        let ExprKind::Member { object, property } = &target.kind else {
+            return self.expression(target);
+        };
+        let object_type = self.expression(object);
+        if let Type::Object { fields, rest: None } = self.shallow(&object_type) {
+            let mut root = &**object;
+            while let ExprKind::Member { object, .. } | ExprKind::Index { object, .. } = &root.kind
+            {
+                root = object;
+            }
+            if !fields.iter().any(|(name, _)| *name == property.name)
+                && self.widen(root, &object_type, &Type::Any)
+            {
+                return Type::Any;
+            }
+        }
+        self.member(&object_type, property)
+    }
+
+    // Widens the variable `target` names, if it is one without an
+    // annotation, so that it can hold `value` as well as the `held` type it
+    // was inferred to have. The widened type applies from the next pass on.
+    fn widen(&mut self, target: &Expr, held: &Type, value: &Type) -> bool {
+        let ExprKind::Identifier(_) = target.kind else {
+            return false;
+        };
+        let Some(decl) = self.resolution.lookup(NodeId::of(&target.span)) else {
+            return false;
+        };
+        let gradual = matches!(
+            self.resolution.declaration(decl).kind,
+            DeclarationKind::Let | DeclarationKind::Parameter | DeclarationKind::Loop
+        );
+        if !gradual || self.annotated.contains(&decl) {
+            return false;
+        }
+        let wider = self.wider(held, value);
+        let wider = match self.widened.get(&decl).cloned() {
+            Some(earlier) => self.wider(&earlier, &wider),
+            None => wider,
+        };
+        self.widened.insert(decl, wider);
+        true
+    }
+
+    fn is_generic(&self, expr: &Expr) -> bool {
+        let ExprKind::Identifier(_) = expr.kind else {
+            return false;
+        };
+        self.resolution
+            .lookup(NodeId::of(&expr.span))
+            .and_then(|decl| self.declarations.get(&decl))
+            .is_some_and(|scheme| !scheme.generic.is_empty())
+    }
+
+    // Whichever of the two the other fits, or `any` if neither does. Types
+    // still being inferred are `any` too, since the next pass starts over.
+    fn wider(&mut self, from: &Type, to: &Type) -> Type {
+        let (from, to) = (self.resolved(from), self.resolved(to));
+        if !from.is_settled() || !to.is_settled() {
+            Type::Any
+        } else if self.try_unify(&from, &to) {
+            to
+        } else if self.try_unify(&to, &from) {
+            from
+        } else {
+            Type::Any
+        }
+    }
+
+    fn call(&mut self, callee: &Type, arguments: &[Expr], span: &TokenSpan) -> Type {
+        let arguments: Vec<(Type, &TokenSpan)> = arguments
+            .iter()
+            .map(|argument| (self.expression(argument), &argument.span))
+            .collect();
+        match self.shallow(callee) {
+            Type::Any => Type::Any,
+            Type::Var(var) => {
+                let result = self.fresh();
+                let function = Type::Function {
+                    parameters: arguments.iter().map(|(ty, _)| ty.clone()).collect(),
+                    result: Box::new(result.clone()),
+                };
+                self.bind(var, &function);
+                result
+            }
+            Type::Function { parameters, result } => {
+                // Missing arguments are `undefined`, which the parameter
+                // must allow
+                let missing = parameters[arguments.len().min(parameters.len())..]
+                    .iter()
+                    .any(|parameter| !self.allows_undefined(parameter));
+                if arguments.len() > parameters.len() || missing {
+                    self.error(
+                        "E0402",
+                        format!(
+                            "expected {}, found {}",
+                            count(parameters.len(), "argument"),
+                            arguments.len()
+                        ),
+                        span,
+                    );
+                }
+                for ((argument, span), parameter) in arguments.iter().zip(&parameters) {
+                    self.expect(argument, parameter, span);
+                }
+                *result
+            }
+            Type::Union(members) if members.iter().any(Type::is_nullish) => {
+                let callee = self.resolved(callee);
+                self.error(
+                    "E0405",
+                    format!("cannot call `{callee}`, which may not be a function"),
+                    span,
+                );
+                Type::Any
+            }
+            other => {
+                let other = self.resolved(&other);
+                self.error("E0406", format!("`{other}` is not a function"), span);
+                Type::Any
+            }
+        }
+    }
+
+    fn allows_undefined(&self, ty: &Type) -> bool {
+        match self.shallow(ty) {
+            Type::Any | Type::Undefined => true,
+            Type::Union(members) => members.contains(&Type::Undefined),
+            _ => false,
+        }
+    }
+
+    fn member(&mut self, object: &Type, property: &Identifier) -> Type {
+        let name = &property.name;
+        match self.shallow(object) {
+            Type::Any => Type::Any,
+            Type::String if name == "length" => Type::Int,
+            Type::Object { fields, rest } => {
+                if let Some((_, field)) = fields.iter().find(|(field, _)| field == name) {
+                    return field.clone();
+                }
+                match rest {
+                    Some(rest) => {
+                        let field = self.fresh();
+                        let more = Type::Object {
+                            fields: vec![(name.clone(), field.clone())],
+                            rest: Some(self.fresh_var(self.level)),
+                        };
+                        self.bind(rest, &more);
+                        field
+                    }
+                    None => {
+                        let object = self.resolved(object);
+                        self.error(
+                            "E0404",
+                            format!("no field `{name}` on `{object}`"),
+                            &property.span,
+                        );
+                        Type::Any
+                    }
+                }
+            }
+            Type::Var(var) => {
+                let field = self.fresh();
+                let object = Type::Object {
+                    fields: vec![(name.clone(), field.clone())],
+                    rest: Some(self.fresh_var(self.level)),
+                };
+                self.bind(var, &object);
+                field
+            }
+            Type::Union(members) if members.iter().any(Type::is_nullish) => {
+                let nullish: Vec<String> = members
+                    .iter()
+                    .filter(|member| member.is_nullish())
+                    .map(|member| format!("`{member}`"))
+                    .collect();
+                let object = self.resolved(object);
+                self.error(
+                    "E0405",
+                    format!(
+                        "cannot read `{name}` of `{object}`, which may be {}",
+                        nullish.join(" or ")
+                    ),
+                    &property.span,
+                );
+                Type::Any
+            }
+            other => {
+                let other = self.resolved(&other);
+                self.error(
+                    "E0404",
+                    format!("no field `{name}` on `{other}`"),
+                    &property.span,
+                );
+                Type::Any
+            }
+        }
+    }
+}
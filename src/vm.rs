@@ -0,0 +1,419 @@
+use crate::compiler::{Instruction, Prototype};
+use crate::runtime::{
+    self, binary, builtins, error, get_index, get_member, iterate, set_index, FunctionValue,
+    Object, RuntimeError, MAX_FRAMES,
+};
+use crate::token::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::rc::Rc;
+
+// A compiled function, with the scope it was created in
+pub struct CompiledClosure {
+    prototype: Rc<Prototype>,
+    environment: Rc<RefCell<Scope>>,
+}
+
+// By name only, as for the interpreter's `Closure`
+impl fmt::Debug for CompiledClosure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+impl fmt::Display for CompiledClosure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.prototype.name {
+            Some(name) => write!(f, "<fn {name}>"),
+            None => write!(f, "<fn>"),
+        }
+    }
+}
+
+pub type Value = runtime::Value<CompiledClosure>;
+
+// The variables of one run-time scope, in the slots the compiler gave them.
+// A slot stays empty until its declaration runs.
+#[derive(Debug)]
+pub struct Scope {
+    slots: Vec<Option<Value>>,
+    names: Rc<[String]>,
+    this: Option<Value>,
+    parent: Option<Rc<RefCell<Scope>>>,
+}
+
+impl Scope {
+    fn new(
+        names: Rc<[String]>,
+        this: Option<Value>,
+        parent: Option<Rc<RefCell<Scope>>>,
+    ) -> Rc<RefCell<Self>> {
+        Rc::new(RefCell::new(Scope {
+            slots: vec![None; names.len()],
+            names,
+            this,
+            parent,
+        }))
+    }
+}
+
+// `depth` scopes out from `scope`
+fn ancestor(scope: &Rc<RefCell<Scope>>, depth: usize) -> Rc<RefCell<Scope>> {
+    let mut scope = scope.clone();
+    for _ in 0..depth {
+        let parent = scope
+            .borrow()
+            .parent
+            .clone()
+            .expect("scope depth past the globals");
+        scope = parent;
+    }
+    scope
+}
+
+struct Frame {
+    prototype: Rc<Prototype>,
+    ip: usize,
+    scope: Rc<RefCell<Scope>>,
+    // Where this call's values start on the stack and how many iterations
+    // were already running, so `return` can drop what the call left behind
+    base: usize,
+    iterators: usize,
+    // The object `new` made, returned unless the call returns an object
+    instance: Option<Value>,
+}
+
+// Runs bytecode from `compiler::compile`, printing and failing exactly as the
+// `Interpreter` would for the same program
+pub struct Vm {
+    globals: HashMap<String, Value>,
+    output: Box<dyn Write>,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    iterators: Vec<std::vec::IntoIter<Value>>,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new(Box::new(std::io::stdout()))
+    }
+}
+
+impl Vm {
+    pub fn new(output: Box<dyn Write>) -> Self {
+        Self {
+            globals: builtins()
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+            output,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            iterators: Vec::new(),
+        }
+    }
+
+    pub fn run(&mut self, script: Rc<Prototype>) -> Result<(), RuntimeError> {
+        let scope = Scope::new(script.locals.clone(), None, None);
+        self.frames.push(Frame {
+            prototype: script,
+            ip: 0,
+            scope,
+            base: 0,
+            iterators: 0,
+            instance: None,
+        });
+        let result = self.execute();
+        self.stack.clear();
+        self.frames.clear();
+        self.iterators.clear();
+        result
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("the compiler balances the stack")
+    }
+
+    fn peek(&self) -> &Value {
+        self.stack.last().expect("the compiler balances the stack")
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("a frame is running")
+    }
+
+    fn execute(&mut self) -> Result<(), RuntimeError> {
+        loop {
+            let frame = self.frame();
+            let prototype = frame.prototype.clone();
+            let ip = frame.ip;
+            frame.ip += 1;
+            let chunk = &prototype.chunk;
+            let span = &chunk.spans[ip];
+            match &chunk.code[ip] {
+                Instruction::Constant(index) => {
+                    self.stack.push(chunk.constants[*index].clone().into());
+                }
+                Instruction::Pop => {
+                    self.pop();
+                }
+                Instruction::Dup => self.stack.push(self.peek().clone()),
+                Instruction::Dup2 => {
+                    let len = self.stack.len();
+                    self.stack.extend_from_within(len - 2..);
+                }
+                Instruction::Swap => {
+                    let len = self.stack.len();
+                    self.stack.swap(len - 1, len - 2);
+                }
+                Instruction::Get { depth, slot } => {
+                    let scope = ancestor(&self.frame().scope, *depth);
+                    let scope = scope.borrow();
+                    match &scope.slots[*slot] {
+                        Some(value) => self.stack.push(value.clone()),
+                        None => {
+                            return error(
+                                format!("undefined variable `{}`", scope.names[*slot]),
+                                span,
+                            )
+                        }
+                    }
+                }
+                Instruction::Set { depth, slot } => {
+                    let value = self.peek().clone();
+                    ancestor(&self.frame().scope, *depth).borrow_mut().slots[*slot] = Some(value);
+                }
+                Instruction::GetGlobal(index) => {
+                    let name = name(&chunk.constants[*index]);
+                    match self.globals.get(name) {
+                        Some(value) => self.stack.push(value.clone()),
+                        None => return error(format!("undefined variable `{name}`"), span),
+                    }
+                }
+                Instruction::SetGlobal(index) => {
+                    let value = self.peek().clone();
+                    self.globals
+                        .insert(name(&chunk.constants[*index]).to_string(), value);
+                }
+                Instruction::Error(index) => return error(name(&chunk.constants[*index]), span),
+                Instruction::This => {
+                    let mut scope = Some(self.frame().scope.clone());
+                    let mut this = Value::Undefined;
+                    while let Some(current) = scope {
+                        let current = current.borrow();
+                        if let Some(value) = &current.this {
+                            this = value.clone();
+                            break;
+                        }
+                        scope = current.parent.clone();
+                    }
+                    self.stack.push(this);
+                }
+                Instruction::PushScope(index) => {
+                    let frame = self.frame();
+                    frame.scope = Scope::new(
+                        chunk.scopes[*index].clone(),
+                        None,
+                        Some(frame.scope.clone()),
+                    );
+                }
+                Instruction::PopScope => {
+                    let frame = self.frame();
+                    let parent = frame.scope.borrow().parent.clone();
+                    frame.scope = parent.expect("`PopScope` follows a `PushScope`");
+                }
+                Instruction::Closure(index) => {
+                    let environment = self.frame().scope.clone();
+                    self.stack
+                        .push(Value::Function(Rc::new(FunctionValue::Defined(
+                            CompiledClosure {
+                                prototype: chunk.functions[*index].clone(),
+                                environment,
+                            },
+                        ))));
+                }
+                Instruction::Object(count) => {
+                    let values = self.stack.split_off(self.stack.len() - count * 2);
+                    let mut object = Object::default();
+                    for pair in values.chunks(2) {
+                        if let Value::String(key) = &pair[0] {
+                            object.set(key, pair[1].clone());
+                        }
+                    }
+                    self.stack
+                        .push(Value::Object(Rc::new(RefCell::new(object))));
+                }
+                Instruction::GetMember(index) => {
+                    let object = self.pop();
+                    let value = get_member(&object, name(&chunk.constants[*index]), span)?;
+                    self.stack.push(value);
+                }
+                Instruction::SetMember(index) => {
+                    let value = self.pop();
+                    let object = self.pop();
+                    let key = Value::String(name(&chunk.constants[*index]).to_string());
+                    set_index(&object, &key, value.clone(), span)?;
+                    self.stack.push(value);
+                }
+                Instruction::GetIndex => {
+                    let index = self.pop();
+                    let object = self.pop();
+                    let value = get_index(&object, &index, span)?;
+                    self.stack.push(value);
+                }
+                Instruction::SetIndex => {
+                    let value = self.pop();
+                    let index = self.pop();
+                    let object = self.pop();
+                    set_index(&object, &index, value.clone(), span)?;
+                    self.stack.push(value);
+                }
+                Instruction::Template(count) => {
+                    let parts = self.stack.split_off(self.stack.len() - count);
+                    let text = parts.iter().map(|part| part.to_string()).collect();
+                    self.stack.push(Value::String(text));
+                }
+                Instruction::Negate => match self.pop() {
+                    Value::Number(number) => self.stack.push(Value::Number(-number)),
+                    other => return error(format!("cannot negate a {}", other.type_name()), span),
+                },
+                Instruction::Not => {
+                    let value = self.pop();
+                    self.stack.push(Value::Boolean(!value.is_truthy()));
+                }
+                Instruction::Binary(operator) => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.stack.push(binary(operator, left, right, span)?);
+                }
+                Instruction::Jump(target) => self.frame().ip = *target,
+                Instruction::JumpIfFalse(target) => {
+                    if !self.pop().is_truthy() {
+                        self.frame().ip = *target;
+                    }
+                }
+                Instruction::JumpIfFalsePeek(target) => {
+                    if !self.peek().is_truthy() {
+                        self.frame().ip = *target;
+                    }
+                }
+                Instruction::JumpIfTruePeek(target) => {
+                    if self.peek().is_truthy() {
+                        self.frame().ip = *target;
+                    }
+                }
+                Instruction::Call(count) => {
+                    let arguments = self.stack.split_off(self.stack.len() - count);
+                    let function = self.pop();
+                    self.call(function, arguments, None, None, span)?;
+                }
+                Instruction::CallMethod(count) => {
+                    let arguments = self.stack.split_off(self.stack.len() - count);
+                    let function = self.pop();
+                    let this = self.pop();
+                    self.call(function, arguments, Some(this), None, span)?;
+                }
+                Instruction::New(count) => {
+                    let arguments = self.stack.split_off(self.stack.len() - count);
+                    let constructor = self.pop();
+                    let instance = Value::Object(Rc::new(RefCell::new(Object::default())));
+                    self.call(
+                        constructor,
+                        arguments,
+                        Some(instance.clone()),
+                        Some(instance),
+                        span,
+                    )?;
+                }
+                Instruction::Return => {
+                    let value = self.pop();
+                    let frame = self.frames.pop().expect("a frame is running");
+                    self.stack.truncate(frame.base);
+                    self.iterators.truncate(frame.iterators);
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.stack.push(constructed(value, frame.instance));
+                }
+                Instruction::Iterate => {
+                    let items = iterate(self.pop(), span)?;
+                    self.iterators.push(items.into_iter());
+                }
+                Instruction::Next(target) => {
+                    let iterator = self.iterators.last_mut().expect("`Next` follows `Iterate`");
+                    match iterator.next() {
+                        Some(item) => self.stack.push(item),
+                        None => {
+                            self.iterators.pop();
+                            self.frame().ip = *target;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Natives run to completion here; compiled functions get a frame that the
+    // main loop picks up
+    fn call(
+        &mut self,
+        function: Value,
+        arguments: Vec<Value>,
+        this: Option<Value>,
+        instance: Option<Value>,
+        span: &TokenSpan,
+    ) -> Result<(), RuntimeError> {
+        let Value::Function(function) = function else {
+            return error(format!("cannot call a {}", function.type_name()), span);
+        };
+        match function.as_ref() {
+            FunctionValue::Native { call, .. } => {
+                let value = call(self.output.as_mut(), arguments)?;
+                self.stack.push(constructed(value, instance));
+            }
+            FunctionValue::Defined(CompiledClosure {
+                prototype,
+                environment,
+            }) => {
+                if self.frames.len() >= MAX_FRAMES {
+                    return error("stack overflow", span);
+                }
+                let scope = Scope::new(prototype.locals.clone(), this, Some(environment.clone()));
+                {
+                    // Missing arguments are `undefined`, extra ones are ignored
+                    let mut scope = scope.borrow_mut();
+                    let mut arguments = arguments.into_iter();
+                    for slot in &mut scope.slots[..prototype.parameters] {
+                        *slot = Some(arguments.next().unwrap_or(Value::Undefined));
+                    }
+                }
+                self.frames.push(Frame {
+                    prototype: prototype.clone(),
+                    ip: 0,
+                    scope,
+                    base: self.stack.len(),
+                    iterators: self.iterators.len(),
+                    instance,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+// A constructor may return its own object in place of `this`
+fn constructed(value: Value, instance: Option<Value>) -> Value {
+    match (value, instance) {
+        (value @ Value::Object(_), _) | (value, None) => value,
+        (_, Some(instance)) => instance,
+    }
+}
+
+fn name(constant: &LiteralToken) -> &str {
+    match constant {
+        LiteralToken::String(name) => name,
+        other => unreachable!("{other:?} is not a name"),
+    }
+}
@@ -0,0 +1,1337 @@
+use crate::diagnostic::Diagnostic;
+use crate::parser::*;
+use crate::resolve::{DeclId, DeclarationKind, NodeId, Resolution};
+use crate::runtime::compound_operator;
+use crate::token::*;
+use crate::types::{symbol, Type, Types};
+use std::collections::HashMap;
+use wasm_encoder::{
+    BlockType, CodeSection, ConstExpr, DataSection, EntityType, ExportKind, ExportSection,
+    Function as WasmFunction, FunctionSection, GlobalSection, GlobalType, ImportSection,
+    Instruction, MemArg, MemorySection, MemoryType, Module, TypeSection, ValType,
+};
+
+// Compiles a program straight to a WebAssembly module. Every value must have
+// a type the checker could work out: ints are i64, floats f64, bools i32 and
+// strings an i32 pointer into linear memory, where a string is its byte
+// length as an i32 followed by its UTF-8 bytes. Objects, function values and
+// `null` have no representation and are reported instead.
+//
+// The module imports its output from the host as `toy.write_int(i64)`,
+// `toy.write_float(f64)` and `toy.write_string(pointer, length)`, the latter
+// naming bytes in the exported `memory`, and exports `main`, which runs the
+// top-level statements.
+//
+// Ints stay i64 throughout, as the checker types int arithmetic. Where the
+// interpreter turns a result too big for an int into a float, `+`, `-`, `*`
+// and negation wrap around instead, and `%` by zero traps rather than giving
+// NaN.
+
+// Host functions, imported in this order
+const IMPORTS: [(&str, ValType); 3] = [
+    ("write_int", ValType::I64),
+    ("write_float", ValType::F64),
+    ("write_string", ValType::I32),
+];
+const WRITE_INT: u32 = 0;
+const WRITE_FLOAT: u32 = 1;
+const WRITE_STRING: u32 = 2;
+
+// Functions every module defines, following the imports. See `runtime`.
+const ALLOC: u32 = 3;
+const CONCAT: u32 = 4;
+const STR_EQ: u32 = 5;
+const INT_TO_STRING: u32 = 6;
+const STR_LENGTH: u32 = 7;
+const SLICE: u32 = 8;
+const NEXT_CHAR: u32 = 9;
+const FIRST_FUNCTION: u32 = 10;
+
+// The global holding the next free heap address. The program's own top-level
+// variables follow it.
+const HEAP: u32 = 0;
+
+// String literals are laid out from here, leaving address 0 unused
+const DATA_START: usize = 8;
+
+// A string's length, and its bytes just after
+const LENGTH: MemArg = MemArg {
+    offset: 0,
+    align: 0,
+    memory_index: 0,
+};
+const BYTES: MemArg = MemArg {
+    offset: 4,
+    align: 0,
+    memory_index: 0,
+};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Ty {
+    Int,
+    Float,
+    Bool,
+    String,
+    // `undefined`, which takes no space on the stack
+    Void,
+}
+
+impl Ty {
+    fn val_type(self) -> Option<ValType> {
+        match self {
+            Ty::Int => Some(ValType::I64),
+            Ty::Float => Some(ValType::F64),
+            Ty::Bool | Ty::String => Some(ValType::I32),
+            Ty::Void => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Ty::Int => "int",
+            Ty::Float => "float",
+            Ty::Bool => "bool",
+            Ty::String => "string",
+            Ty::Void => "undefined",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Signature {
+    parameters: Vec<Ty>,
+    result: Ty,
+}
+
+// Code that leaves a value of the given type on the stack
+type Fragment = (Ty, Vec<Instruction<'static>>);
+
+#[derive(Debug, Clone, Copy)]
+enum Variable {
+    Local(u32, Ty),
+    Global(u32, Ty),
+}
+
+impl Variable {
+    fn ty(self) -> Ty {
+        match self {
+            Variable::Local(_, ty) | Variable::Global(_, ty) => ty,
+        }
+    }
+
+    fn get(self) -> Instruction<'static> {
+        match self {
+            Variable::Local(index, _) => Instruction::LocalGet(index),
+            Variable::Global(index, _) => Instruction::GlobalGet(index),
+        }
+    }
+
+    fn set(self) -> Instruction<'static> {
+        match self {
+            Variable::Local(index, _) => Instruction::LocalSet(index),
+            Variable::Global(index, _) => Instruction::GlobalSet(index),
+        }
+    }
+}
+
+// The function being compiled
+struct Body {
+    parameters: u32,
+    locals: Vec<ValType>,
+    variables: HashMap<DeclId, Variable>,
+    // None for `main`, which cannot return
+    result: Option<Ty>,
+    code: Vec<Instruction<'static>>,
+}
+
+impl Body {
+    fn new(parameters: u32, result: Option<Ty>) -> Self {
+        Body {
+            parameters,
+            locals: Vec::new(),
+            variables: HashMap::new(),
+            result,
+            code: Vec::new(),
+        }
+    }
+
+    fn local(&mut self, ty: ValType) -> u32 {
+        self.locals.push(ty);
+        self.parameters + self.locals.len() as u32 - 1
+    }
+
+    fn finish(mut self) -> WasmFunction {
+        self.code.push(Instruction::End);
+        let mut function = WasmFunction::new_with_locals_types(self.locals);
+        for instruction in &self.code {
+            function.instruction(instruction);
+        }
+        function
+    }
+}
+
+// Diagnostics are boxed on their way up, as they are much larger than what
+// compiles
+fn unsupported(what: impl AsRef<str>, span: &TokenSpan) -> Box<Diagnostic> {
+    Box::new(Diagnostic::error(
+        "E0500",
+        format!("{} is not supported by the wasm target", what.as_ref()),
+        span.clone(),
+    ))
+}
+
+fn unknown(span: &TokenSpan) -> Box<Diagnostic> {
+    Box::new(
+        Diagnostic::error(
+            "E0500",
+            "the wasm target needs to know this type",
+            span.clone(),
+        )
+        .with_note("add a type annotation"),
+    )
+}
+
+// Compiles a program that has been resolved and checked into the bytes of a
+// WebAssembly module, or reports everything it could not compile
+pub fn compile(
+    program: &Program,
+    resolution: &Resolution,
+    types: &Types,
+) -> Result<Vec<u8>, Vec<Diagnostic>> {
+    let mut compiler = Compiler {
+        resolution,
+        types,
+        data: Vec::new(),
+        strings: HashMap::new(),
+        functions: HashMap::new(),
+        globals: HashMap::new(),
+        global_types: Vec::new(),
+        top_level: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+    // Top-level functions and variables are visible from every function, so
+    // they are numbered before any code is compiled
+    let mut functions = Vec::new();
+    for statement in &program.statements {
+        match &statement.kind {
+            StmtKind::Function(function) => {
+                let Some(name) = &function.name else { continue };
+                compiler.top_level.push(NodeId::of(&name.span));
+                match compiler.signature(name) {
+                    Ok((decl, signature)) => {
+                        let index = FIRST_FUNCTION + functions.len() as u32;
+                        compiler.functions.insert(decl, (index, signature.clone()));
+                        functions.push((function, signature));
+                    }
+                    Err(diagnostic) => compiler.diagnostics.push(*diagnostic),
+                }
+            }
+            // Those without a usable type are reported where they are declared
+            StmtKind::Let { name, .. } => {
+                if let Ok((decl, ty)) = compiler.declared_type(name) {
+                    let index = HEAP + 1 + compiler.global_types.len() as u32;
+                    compiler.globals.insert(decl, Variable::Global(index, ty));
+                    compiler.global_types.extend(ty.val_type());
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut bodies: Vec<(Signature, WasmFunction)> = functions
+        .into_iter()
+        .map(|(function, signature)| {
+            let body = compiler.function(function, &signature);
+            (signature, body)
+        })
+        .collect();
+    let mut main = Body::new(0, None);
+    compiler.statements(&mut main, &program.statements);
+    let main_signature = Signature {
+        parameters: Vec::new(),
+        result: Ty::Void,
+    };
+    bodies.push((main_signature, main.finish()));
+    if !compiler.diagnostics.is_empty() {
+        compiler
+            .diagnostics
+            .sort_by_key(|diagnostic| diagnostic.span.start);
+        return Err(compiler.diagnostics);
+    }
+    Ok(compiler.module(bodies))
+}
+
+struct Compiler<'a> {
+    resolution: &'a Resolution,
+    types: &'a Types,
+    // String literals, laid out from `DATA_START`, and where each one is
+    data: Vec<u8>,
+    strings: HashMap<String, i32>,
+    functions: HashMap<DeclId, (u32, Signature)>,
+    globals: HashMap<DeclId, Variable>,
+    global_types: Vec<ValType>,
+    // The names of the top-level functions, compiled on their own
+    top_level: Vec<NodeId>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Compiler<'_> {
+    fn module(self, bodies: Vec<(Signature, WasmFunction)>) -> Vec<u8> {
+        // Each function gets its own type, at the same index as the function
+        let mut types = TypeSection::new();
+        let mut imports = ImportSection::new();
+        for (index, (name, parameter)) in IMPORTS.iter().enumerate() {
+            let parameters: &[ValType] = if *parameter == ValType::I32 {
+                &[ValType::I32, ValType::I32]
+            } else {
+                &[*parameter]
+            };
+            types.function(parameters.iter().copied(), []);
+            imports.import("toy", name, EntityType::Function(index as u32));
+        }
+        let mut functions = FunctionSection::new();
+        let mut code = CodeSection::new();
+        let mut index = IMPORTS.len() as u32;
+        for (parameters, results, locals, instructions) in runtime() {
+            types.function(parameters, results);
+            functions.function(index);
+            let mut function = WasmFunction::new_with_locals_types(locals);
+            for instruction in &instructions {
+                function.instruction(instruction);
+            }
+            code.function(&function);
+            index += 1;
+        }
+        let main = index + bodies.len() as u32 - 1;
+        for (signature, body) in &bodies {
+            let parameters: Vec<ValType> = signature
+                .parameters
+                .iter()
+                .filter_map(|ty| ty.val_type())
+                .collect();
+            types.function(parameters, signature.result.val_type());
+            functions.function(index);
+            code.function(body);
+            index += 1;
+        }
+
+        let mut memories = MemorySection::new();
+        let pages = (DATA_START + self.data.len()).div_ceil(0x10000).max(1);
+        memories.memory(MemoryType {
+            minimum: pages as u64,
+            maximum: None,
+            memory64: false,
+            shared: false,
+        });
+
+        let mut globals = GlobalSection::new();
+        let heap = (DATA_START + self.data.len()).next_multiple_of(8);
+        globals.global(
+            GlobalType {
+                val_type: ValType::I32,
+                mutable: true,
+            },
+            &ConstExpr::i32_const(heap as i32),
+        );
+        // Top-level variables start out zero and are set as `main` reaches them
+        for ty in &self.global_types {
+            let zero = match ty {
+                ValType::I64 => ConstExpr::i64_const(0),
+                ValType::F64 => ConstExpr::f64_const(0.0),
+                _ => ConstExpr::i32_const(0),
+            };
+            let global = GlobalType {
+                val_type: *ty,
+                mutable: true,
+            };
+            globals.global(global, &zero);
+        }
+
+        let mut exports = ExportSection::new();
+        exports.export("memory", ExportKind::Memory, 0);
+        exports.export("main", ExportKind::Func, main);
+
+        let mut data = DataSection::new();
+        data.active(0, &ConstExpr::i32_const(DATA_START as i32), self.data);
+
+        let mut module = Module::new();
+        module
+            .section(&types)
+            .section(&imports)
+            .section(&functions)
+            .section(&memories)
+            .section(&globals)
+            .section(&exports)
+            .section(&code)
+            .section(&data);
+        module.finish()
+    }
+
+    // The address of a string literal, adding it to the data segment the
+    // first time it is used
+    fn string(&mut self, text: &str) -> i32 {
+        if let Some(address) = self.strings.get(text) {
+            return *address;
+        }
+        // Lengths stay 4-byte aligned
+        while !self.data.len().is_multiple_of(4) {
+            self.data.push(0);
+        }
+        let address = (DATA_START + self.data.len()) as i32;
+        self.data
+            .extend_from_slice(&(text.len() as u32).to_le_bytes());
+        self.data.extend_from_slice(text.as_bytes());
+        self.strings.insert(text.to_string(), address);
+        address
+    }
+
+    fn declared(&self, name: &Identifier) -> Result<DeclId, Box<Diagnostic>> {
+        self.resolution
+            .declared(NodeId::of(&name.span))
+            .ok_or_else(|| unsupported(format!("the unresolved `{}`", name.name), &name.span))
+    }
+
+    fn ty(&self, ty: &Type, span: &TokenSpan) -> Result<Ty, Box<Diagnostic>> {
+        match ty {
+            Type::Int => Ok(Ty::Int),
+            Type::Float => Ok(Ty::Float),
+            Type::Bool => Ok(Ty::Bool),
+            Type::String => Ok(Ty::String),
+            Type::Undefined => Ok(Ty::Void),
+            Type::Var(_) | Type::Any => Err(unknown(span)),
+            other => Err(unsupported(format!("a value of type `{other}`"), span)),
+        }
+    }
+
+    // The type of a value that is stored somewhere, which cannot be nothing
+    fn value_type(&self, ty: &Type, span: &TokenSpan) -> Result<Ty, Box<Diagnostic>> {
+        match self.ty(ty, span)? {
+            Ty::Void => Err(unsupported("storing `undefined`", span)),
+            ty => Ok(ty),
+        }
+    }
+
+    fn declared_type(&self, name: &Identifier) -> Result<(DeclId, Ty), Box<Diagnostic>> {
+        let decl = self.declared(name)?;
+        let ty = match self.types.of(decl) {
+            Some(ty) => self.value_type(ty, &name.span)?,
+            None => return Err(unknown(&name.span)),
+        };
+        Ok((decl, ty))
+    }
+
+    fn signature(&self, name: &Identifier) -> Result<(DeclId, Signature), Box<Diagnostic>> {
+        let decl = self.declared(name)?;
+        let Some(Type::Function { parameters, result }) = self.types.of(decl) else {
+            return Err(unknown(&name.span));
+        };
+        let parameters = parameters
+            .iter()
+            .map(|parameter| self.value_type(parameter, &name.span))
+            .collect::<Result<_, _>>()?;
+        let result = self.ty(result, &name.span)?;
+        Ok((decl, Signature { parameters, result }))
+    }
+
+    fn variable(&self, body: &Body, expr: &Expr) -> Option<Variable> {
+        let decl = self.resolution.lookup(NodeId::of(&expr.span))?;
+        body.variables
+            .get(&decl)
+            .or_else(|| self.globals.get(&decl))
+            .copied()
+    }
+
+    fn function(&mut self, function: &Function, signature: &Signature) -> WasmFunction {
+        let mut body = Body::new(signature.parameters.len() as u32, Some(signature.result));
+        for (index, (parameter, ty)) in function
+            .parameters
+            .iter()
+            .zip(&signature.parameters)
+            .enumerate()
+        {
+            match self.declared(&parameter.name) {
+                Ok(decl) => {
+                    body.variables
+                        .insert(decl, Variable::Local(index as u32, *ty));
+                }
+                Err(diagnostic) => self.diagnostics.push(*diagnostic),
+            }
+        }
+        self.statements(&mut body, &function.body.statements);
+        // A function that returns a value always does so explicitly; the
+        // checker would have made its result `undefined` otherwise
+        if signature.result != Ty::Void {
+            body.code.push(Instruction::Unreachable);
+        }
+        body.finish()
+    }
+
+    fn statements(&mut self, body: &mut Body, statements: &[Stmt]) {
+        for statement in statements {
+            if let Err(diagnostic) = self.statement(body, statement) {
+                self.diagnostics.push(*diagnostic);
+            }
+        }
+    }
+
+    fn statement(&mut self, body: &mut Body, statement: &Stmt) -> Result<(), Box<Diagnostic>> {
+        match &statement.kind {
+            StmtKind::Let { name, value, .. } => {
+                let Some(value) = value else {
+                    return Err(unsupported("`let` without a value", &statement.span));
+                };
+                let decl = self.declared(name)?;
+                let variable = match self.globals.get(&decl) {
+                    Some(global) => *global,
+                    None => {
+                        let (_, ty) = self.declared_type(name)?;
+                        let index = body.local(ty.val_type().expect("values take space"));
+                        let local = Variable::Local(index, ty);
+                        body.variables.insert(decl, local);
+                        local
+                    }
+                };
+                let value = self.expression(body, value)?;
+                let code = self.coerce(value, variable.ty(), &name.span)?;
+                body.code.extend(code);
+                body.code.push(variable.set());
+            }
+            StmtKind::Function(function) => {
+                let name = function.name.as_ref();
+                if !name.is_some_and(|name| self.top_level.contains(&NodeId::of(&name.span))) {
+                    return Err(unsupported("a nested function", &statement.span));
+                }
+            }
+            StmtKind::Object { .. } => return Err(unsupported("an object", &statement.span)),
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let (ty, code) = self.expression(body, condition)?;
+                body.code.extend(code);
+                let truthy = self.truthy(body, ty);
+                body.code.extend(truthy);
+                body.code.push(Instruction::If(BlockType::Empty));
+                self.statements(body, &then_branch.statements);
+                if let Some(else_branch) = else_branch {
+                    body.code.push(Instruction::Else);
+                    self.statements(body, std::slice::from_ref(else_branch));
+                }
+                body.code.push(Instruction::End);
+            }
+            StmtKind::For {
+                binding,
+                iterable,
+                body: block,
+            } => {
+                let (ty, code) = self.expression(body, iterable)?;
+                if ty != Ty::String {
+                    return Err(unsupported(
+                        format!("`for` over a `{}`", ty.name()),
+                        &iterable.span,
+                    ));
+                }
+                let decl = self.declared(binding)?;
+                let string = body.local(ValType::I32);
+                let offset = body.local(ValType::I32);
+                let character = body.local(ValType::I32);
+                body.variables
+                    .insert(decl, Variable::Local(character, Ty::String));
+                body.code.extend(code);
+                body.code.extend([
+                    Instruction::LocalSet(string),
+                    Instruction::I32Const(0),
+                    Instruction::LocalSet(offset),
+                    Instruction::Block(BlockType::Empty),
+                    Instruction::Loop(BlockType::Empty),
+                    Instruction::LocalGet(offset),
+                    Instruction::LocalGet(string),
+                    Instruction::I32Load(LENGTH),
+                    Instruction::I32GeU,
+                    Instruction::BrIf(1),
+                    // Step over the character by its length in bytes
+                    Instruction::LocalGet(string),
+                    Instruction::LocalGet(offset),
+                    Instruction::Call(NEXT_CHAR),
+                    Instruction::LocalTee(character),
+                    Instruction::I32Load(LENGTH),
+                    Instruction::LocalGet(offset),
+                    Instruction::I32Add,
+                    Instruction::LocalSet(offset),
+                ]);
+                self.statements(body, &block.statements);
+                body.code
+                    .extend([Instruction::Br(0), Instruction::End, Instruction::End]);
+            }
+            StmtKind::Return(value) => {
+                let Some(result) = body.result else {
+                    return Err(unsupported("`return` outside a function", &statement.span));
+                };
+                if let Some(value) = value {
+                    let value = self.expression(body, value)?;
+                    let code = self.coerce(value, result, &statement.span)?;
+                    body.code.extend(code);
+                }
+                body.code.push(Instruction::Return);
+            }
+            StmtKind::Block(block) => self.statements(body, &block.statements),
+            StmtKind::Expression(expr) => {
+                let (ty, code) = self.expression(body, expr)?;
+                body.code.extend(code);
+                if ty != Ty::Void {
+                    body.code.push(Instruction::Drop);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self, body: &mut Body, expr: &Expr) -> Result<Fragment, Box<Diagnostic>> {
+        match &expr.kind {
+            ExprKind::Literal(literal) => self.literal(literal, &expr.span),
+            ExprKind::Identifier(name) => match self.variable(body, expr) {
+                Some(variable) => Ok((variable.ty(), vec![variable.get()])),
+                None => Err(unsupported(
+                    format!("using `{name}` as a value"),
+                    &expr.span,
+                )),
+            },
+            ExprKind::Template {
+                strings,
+                expressions,
+            } => {
+                let mut code = vec![Instruction::I32Const(self.string(&strings[0]))];
+                for (expression, text) in expressions.iter().zip(&strings[1..]) {
+                    let part = self.expression(body, expression)?;
+                    code.extend(self.stringify(body, part, &expression.span)?);
+                    code.push(Instruction::Call(CONCAT));
+                    if !text.is_empty() {
+                        code.push(Instruction::I32Const(self.string(text)));
+                        code.push(Instruction::Call(CONCAT));
+                    }
+                }
+                Ok((Ty::String, code))
+            }
+            ExprKind::Unary {
+                operator, operand, ..
+            } => {
+                let (ty, mut code) = self.expression(body, operand)?;
+                match (operator, ty) {
+                    (UnaryOperator::Negate, Ty::Int) => {
+                        code.insert(0, Instruction::I64Const(0));
+                        code.push(Instruction::I64Sub);
+                        Ok((Ty::Int, code))
+                    }
+                    (UnaryOperator::Negate, Ty::Float) => {
+                        code.push(Instruction::F64Neg);
+                        Ok((Ty::Float, code))
+                    }
+                    (UnaryOperator::Negate, ty) => Err(unsupported(
+                        format!("negating a `{}`", ty.name()),
+                        &expr.span,
+                    )),
+                    (UnaryOperator::Not | UnaryOperator::LogicalNot, ty) => {
+                        code.extend(self.truthy(body, ty));
+                        code.push(Instruction::I32Eqz);
+                        Ok((Ty::Bool, code))
+                    }
+                }
+            }
+            ExprKind::Binary {
+                operator,
+                left,
+                right,
+                ..
+            } => {
+                let left = self.expression(body, left)?;
+                let right = self.expression(body, right)?;
+                self.binary(body, operator, left, right, &expr.span)
+            }
+            ExprKind::Assign {
+                operator,
+                target,
+                value,
+                ..
+            } => {
+                let ExprKind::Identifier(name) = &target.kind else {
+                    return Err(unsupported("assigning to a member", &target.span));
+                };
+                let Some(variable) = self.variable(body, target) else {
+                    return Err(unsupported(format!("assigning to `{name}`"), &target.span));
+                };
+                let value = self.expression(body, value)?;
+                let current = (variable.ty(), vec![variable.get()]);
+                let value = match operator {
+                    AssignmentToken::Assign => value,
+                    AssignmentToken::AndAssign => {
+                        let and = BinaryOperator::Logical(LogicalToken::And);
+                        self.binary(body, &and, current, value, &expr.span)?
+                    }
+                    AssignmentToken::OrAssign => {
+                        let or = BinaryOperator::Logical(LogicalToken::Or);
+                        self.binary(body, &or, current, value, &expr.span)?
+                    }
+                    compound => {
+                        let operator = compound_operator(compound);
+                        self.binary(body, &operator, current, value, &expr.span)?
+                    }
+                };
+                let mut code = self.coerce(value, variable.ty(), &expr.span)?;
+                code.extend([variable.set(), variable.get()]);
+                Ok((variable.ty(), code))
+            }
+            ExprKind::Call { callee, arguments } => {
+                let ExprKind::Identifier(name) = &callee.kind else {
+                    return Err(unsupported("calling a method", &callee.span));
+                };
+                let decl = self.resolution.lookup(NodeId::of(&callee.span));
+                if let Some(decl) = decl {
+                    if self.resolution.declaration(decl).kind == DeclarationKind::Builtin {
+                        return self.print(body, arguments);
+                    }
+                }
+                let Some((index, signature)) =
+                    decl.and_then(|decl| self.functions.get(&decl)).cloned()
+                else {
+                    return Err(unsupported(
+                        format!("calling `{name}`, which is not a top-level function"),
+                        &callee.span,
+                    ));
+                };
+                if arguments.len() != signature.parameters.len() {
+                    return Err(unsupported(
+                        format!(
+                            "calling `{name}` with {} arguments instead of {}",
+                            arguments.len(),
+                            signature.parameters.len()
+                        ),
+                        &expr.span,
+                    ));
+                }
+                let mut code = Vec::new();
+                for (argument, ty) in arguments.iter().zip(&signature.parameters) {
+                    let value = self.expression(body, argument)?;
+                    code.extend(self.coerce(value, *ty, &argument.span)?);
+                }
+                code.push(Instruction::Call(index));
+                Ok((signature.result, code))
+            }
+            ExprKind::Member { object, property } => {
+                let (ty, mut code) = self.expression(body, object)?;
+                if ty != Ty::String || property.name != "length" {
+                    return Err(unsupported(
+                        format!("reading `{}` of a `{}`", property.name, ty.name()),
+                        &expr.span,
+                    ));
+                }
+                code.push(Instruction::Call(STR_LENGTH));
+                Ok((Ty::Int, code))
+            }
+            ExprKind::ObjectReference(_) => Err(unsupported("`this` or `super`", &expr.span)),
+            ExprKind::New { .. } => Err(unsupported("`new`", &expr.span)),
+            ExprKind::Object(_) => Err(unsupported("an object", &expr.span)),
+            ExprKind::Function(_) => Err(unsupported("a function expression", &expr.span)),
+            ExprKind::Index { .. } => Err(unsupported("indexing", &expr.span)),
+        }
+    }
+
+    fn literal(
+        &mut self,
+        literal: &LiteralToken,
+        span: &TokenSpan,
+    ) -> Result<Fragment, Box<Diagnostic>> {
+        match literal {
+            LiteralToken::Number(NumberToken::SignedInteger(value)) => {
+                Ok((Ty::Int, vec![Instruction::I64Const(*value)]))
+            }
+            LiteralToken::Number(NumberToken::Float(value)) => {
+                Ok((Ty::Float, vec![Instruction::F64Const(*value)]))
+            }
+            LiteralToken::Number(NumberToken::BigInt(_)) => {
+                Err(unsupported("an integer wider than 64 bits", span))
+            }
+            LiteralToken::String(text) => {
+                Ok((Ty::String, vec![Instruction::I32Const(self.string(text))]))
+            }
+            LiteralToken::Boolean(value) => {
+                Ok((Ty::Bool, vec![Instruction::I32Const(*value as i32)]))
+            }
+            LiteralToken::Null => Err(unsupported("`null`", span)),
+            LiteralToken::Undefined => Ok((Ty::Void, Vec::new())),
+        }
+    }
+
+    fn binary(
+        &mut self,
+        body: &mut Body,
+        operator: &BinaryOperator,
+        (left, mut code): Fragment,
+        (right, right_code): Fragment,
+        span: &TokenSpan,
+    ) -> Result<Fragment, Box<Diagnostic>> {
+        use ArithmeticToken as A;
+        use ComparisonToken as C;
+        let mismatch = || {
+            unsupported(
+                format!(
+                    "`{}` on a `{}` and a `{}`",
+                    symbol(operator),
+                    left.name(),
+                    right.name()
+                ),
+                span,
+            )
+        };
+        let numeric = |ty| matches!(ty, Ty::Int | Ty::Float);
+        match operator {
+            BinaryOperator::Logical(logical @ (LogicalToken::And | LogicalToken::Or)) => {
+                let Some(val_type) = left.val_type().filter(|_| left == right) else {
+                    return Err(mismatch());
+                };
+                let saved = body.local(val_type);
+                code.push(Instruction::LocalTee(saved));
+                code.extend(self.truthy(body, left));
+                code.push(Instruction::If(BlockType::Result(val_type)));
+                if *logical == LogicalToken::And {
+                    code.extend(right_code);
+                    code.extend([Instruction::Else, Instruction::LocalGet(saved)]);
+                } else {
+                    code.extend([Instruction::LocalGet(saved), Instruction::Else]);
+                    code.extend(right_code);
+                }
+                code.push(Instruction::End);
+                Ok((left, code))
+            }
+            BinaryOperator::Logical(logical) => {
+                code.extend(self.truthy(body, left));
+                code.extend(right_code);
+                code.extend(self.truthy(body, right));
+                code.push(match logical {
+                    LogicalToken::XOr => Instruction::I32Ne,
+                    _ => Instruction::I32Eq,
+                });
+                Ok((Ty::Bool, code))
+            }
+            BinaryOperator::Arithmetic(A::Add) if left == Ty::String || right == Ty::String => {
+                let mut code = self.stringify(body, (left, code), span)?;
+                code.extend(self.stringify(body, (right, right_code), span)?);
+                code.push(Instruction::Call(CONCAT));
+                Ok((Ty::String, code))
+            }
+            BinaryOperator::Arithmetic(arithmetic) if numeric(left) && numeric(right) => {
+                if left == Ty::Int && right == Ty::Int && *arithmetic != A::Divide {
+                    code.extend(right_code);
+                    code.push(match arithmetic {
+                        A::Add => Instruction::I64Add,
+                        A::Subtract => Instruction::I64Sub,
+                        A::Multiply => Instruction::I64Mul,
+                        A::Modulo => Instruction::I64RemS,
+                        A::BitwiseAnd => Instruction::I64And,
+                        _ => Instruction::I64Or,
+                    });
+                    return Ok((Ty::Int, code));
+                }
+                let mut code = self.coerce((left, code), Ty::Float, span)?;
+                code.extend(self.coerce((right, right_code), Ty::Float, span)?);
+                match arithmetic {
+                    A::Add => code.push(Instruction::F64Add),
+                    A::Subtract => code.push(Instruction::F64Sub),
+                    A::Multiply => code.push(Instruction::F64Mul),
+                    A::Divide => code.push(Instruction::F64Div),
+                    // a - trunc(a / b) * b, as wasm has no float remainder
+                    A::Modulo => {
+                        let (dividend, divisor) =
+                            (body.local(ValType::F64), body.local(ValType::F64));
+                        code.extend([
+                            Instruction::LocalSet(divisor),
+                            Instruction::LocalTee(dividend),
+                            Instruction::LocalGet(dividend),
+                            Instruction::LocalGet(divisor),
+                            Instruction::F64Div,
+                            Instruction::F64Trunc,
+                            Instruction::LocalGet(divisor),
+                            Instruction::F64Mul,
+                            Instruction::F64Sub,
+                        ]);
+                    }
+                    _ => return Err(mismatch()),
+                }
+                Ok((Ty::Float, code))
+            }
+            BinaryOperator::Arithmetic(_) => Err(mismatch()),
+            BinaryOperator::Comparison(comparison) => {
+                let mut code = if numeric(left) && numeric(right) && left != right {
+                    let mut code = self.coerce((left, code), Ty::Float, span)?;
+                    code.extend(self.coerce((right, right_code), Ty::Float, span)?);
+                    code
+                } else {
+                    code.extend(right_code);
+                    code
+                };
+                let instruction = match (left, right) {
+                    (Ty::Int, Ty::Int) => match comparison {
+                        C::Equal => Instruction::I64Eq,
+                        C::NotEqual => Instruction::I64Ne,
+                        C::GreaterThan => Instruction::I64GtS,
+                        C::GreaterThanOrEqual => Instruction::I64GeS,
+                        C::LessThan => Instruction::I64LtS,
+                        _ => Instruction::I64LeS,
+                    },
+                    (left, right) if numeric(left) && numeric(right) => match comparison {
+                        C::Equal => Instruction::F64Eq,
+                        C::NotEqual => Instruction::F64Ne,
+                        C::GreaterThan => Instruction::F64Gt,
+                        C::GreaterThanOrEqual => Instruction::F64Ge,
+                        C::LessThan => Instruction::F64Lt,
+                        _ => Instruction::F64Le,
+                    },
+                    (Ty::String, Ty::String) => match comparison {
+                        C::Equal => Instruction::Call(STR_EQ),
+                        C::NotEqual => {
+                            code.push(Instruction::Call(STR_EQ));
+                            Instruction::I32Eqz
+                        }
+                        _ => return Err(unsupported("ordering strings", span)),
+                    },
+                    (Ty::Bool, Ty::Bool) | (Ty::Void, Ty::Void) => {
+                        if left == Ty::Void {
+                            code.extend([Instruction::I32Const(0), Instruction::I32Const(0)]);
+                        }
+                        match comparison {
+                            C::Equal => Instruction::I32Eq,
+                            C::NotEqual => Instruction::I32Ne,
+                            _ => return Err(mismatch()),
+                        }
+                    }
+                    // Values of different types are never equal
+                    _ => {
+                        let equal = match comparison {
+                            C::Equal => 0,
+                            C::NotEqual => 1,
+                            _ => return Err(mismatch()),
+                        };
+                        for ty in [left, right] {
+                            if ty != Ty::Void {
+                                code.push(Instruction::Drop);
+                            }
+                        }
+                        Instruction::I32Const(equal)
+                    }
+                };
+                code.push(instruction);
+                Ok((Ty::Bool, code))
+            }
+        }
+    }
+
+    // Turns the value on the stack into an i32 that is 1 when it is truthy
+    fn truthy(&mut self, body: &mut Body, ty: Ty) -> Vec<Instruction<'static>> {
+        match ty {
+            Ty::Int => vec![Instruction::I64Eqz, Instruction::I32Eqz],
+            // NaN is falsy too
+            Ty::Float => {
+                let value = body.local(ValType::F64);
+                vec![
+                    Instruction::LocalTee(value),
+                    Instruction::F64Const(0.0),
+                    Instruction::F64Ne,
+                    Instruction::LocalGet(value),
+                    Instruction::LocalGet(value),
+                    Instruction::F64Eq,
+                    Instruction::I32And,
+                ]
+            }
+            Ty::Bool => Vec::new(),
+            Ty::String => vec![
+                Instruction::I32Load(LENGTH),
+                Instruction::I32Const(0),
+                Instruction::I32Ne,
+            ],
+            Ty::Void => vec![Instruction::I32Const(0)],
+        }
+    }
+
+    // Converts a value for a place that holds `ty`, which only ints need
+    fn coerce(
+        &mut self,
+        (from, mut code): Fragment,
+        to: Ty,
+        span: &TokenSpan,
+    ) -> Result<Vec<Instruction<'static>>, Box<Diagnostic>> {
+        match (from, to) {
+            (from, to) if from == to => {}
+            (Ty::Int, Ty::Float) => code.push(Instruction::F64ConvertI64S),
+            (from, to) => {
+                return Err(unsupported(
+                    format!("using a `{}` as a `{}`", from.name(), to.name()),
+                    span,
+                ))
+            }
+        }
+        Ok(code)
+    }
+
+    // The value as a string, as `+` and templates join it
+    fn stringify(
+        &mut self,
+        body: &mut Body,
+        (ty, mut code): Fragment,
+        span: &TokenSpan,
+    ) -> Result<Vec<Instruction<'static>>, Box<Diagnostic>> {
+        match ty {
+            Ty::Int => code.push(Instruction::Call(INT_TO_STRING)),
+            Ty::String => {}
+            Ty::Bool => {
+                let value = body.local(ValType::I32);
+                code.extend([
+                    Instruction::LocalSet(value),
+                    Instruction::I32Const(self.string("true")),
+                    Instruction::I32Const(self.string("false")),
+                    Instruction::LocalGet(value),
+                    Instruction::Select,
+                ]);
+            }
+            Ty::Void => code.push(Instruction::I32Const(self.string("undefined"))),
+            Ty::Float => return Err(unsupported("converting a `float` to a string", span)),
+        }
+        Ok(code)
+    }
+
+    // `print` writes its arguments separated by spaces, then a newline
+    fn print(&mut self, body: &mut Body, arguments: &[Expr]) -> Result<Fragment, Box<Diagnostic>> {
+        let mut code = Vec::new();
+        for (index, argument) in arguments.iter().enumerate() {
+            if index > 0 {
+                code.push(Instruction::I32Const(self.string(" ")));
+                code.extend(self.write_string(body));
+            }
+            let (ty, value) = self.expression(body, argument)?;
+            match ty {
+                Ty::Int => code.extend(value.into_iter().chain([Instruction::Call(WRITE_INT)])),
+                Ty::Float => code.extend(value.into_iter().chain([Instruction::Call(WRITE_FLOAT)])),
+                ty => {
+                    code.extend(self.stringify(body, (ty, value), &argument.span)?);
+                    code.extend(self.write_string(body));
+                }
+            }
+        }
+        code.push(Instruction::I32Const(self.string("\n")));
+        code.extend(self.write_string(body));
+        Ok((Ty::Void, code))
+    }
+
+    // Passes the string on the stack to the host
+    fn write_string(&mut self, body: &mut Body) -> Vec<Instruction<'static>> {
+        let string = body.local(ValType::I32);
+        vec![
+            Instruction::LocalTee(string),
+            Instruction::I32Const(4),
+            Instruction::I32Add,
+            Instruction::LocalGet(string),
+            Instruction::I32Load(LENGTH),
+            Instruction::Call(WRITE_STRING),
+        ]
+    }
+}
+
+type RuntimeFunction = (
+    Vec<ValType>,
+    Vec<ValType>,
+    Vec<ValType>,
+    Vec<Instruction<'static>>,
+);
+
+// The functions compiled code calls for memory and strings, in index order
+// from `ALLOC`: parameters, results, locals and code. Memory is never freed;
+// `alloc` bumps `HEAP` and grows memory when it runs out.
+fn runtime() -> [RuntimeFunction; 7] {
+    use Instruction as I;
+    use ValType::{I32, I64};
+    let alloc = vec![
+        I::GlobalGet(HEAP),
+        I::LocalSet(1),
+        I::GlobalGet(HEAP),
+        I::LocalGet(0),
+        I::I32Add,
+        I::I32Const(7),
+        I::I32Add,
+        I::I32Const(-8),
+        I::I32And,
+        I::GlobalSet(HEAP),
+        I::Block(BlockType::Empty),
+        I::GlobalGet(HEAP),
+        I::MemorySize(0),
+        I::I32Const(16),
+        I::I32Shl,
+        I::I32LeU,
+        I::BrIf(0),
+        // Enough pages to cover the rest
+        I::GlobalGet(HEAP),
+        I::MemorySize(0),
+        I::I32Const(16),
+        I::I32Shl,
+        I::I32Sub,
+        I::I32Const(0xFFFF),
+        I::I32Add,
+        I::I32Const(16),
+        I::I32ShrU,
+        I::MemoryGrow(0),
+        I::I32Const(-1),
+        I::I32Eq,
+        I::If(BlockType::Empty),
+        I::Unreachable,
+        I::End,
+        I::End,
+        I::LocalGet(1),
+        I::End,
+    ];
+    let copy = I::MemoryCopy {
+        src_mem: 0,
+        dst_mem: 0,
+    };
+    let concat = vec![
+        I::LocalGet(0),
+        I::I32Load(LENGTH),
+        I::LocalSet(2),
+        I::LocalGet(1),
+        I::I32Load(LENGTH),
+        I::LocalSet(3),
+        I::LocalGet(2),
+        I::LocalGet(3),
+        I::I32Add,
+        I::I32Const(4),
+        I::I32Add,
+        I::Call(ALLOC),
+        I::LocalTee(4),
+        I::LocalGet(2),
+        I::LocalGet(3),
+        I::I32Add,
+        I::I32Store(LENGTH),
+        I::LocalGet(4),
+        I::I32Const(4),
+        I::I32Add,
+        I::LocalGet(0),
+        I::I32Const(4),
+        I::I32Add,
+        I::LocalGet(2),
+        copy.clone(),
+        I::LocalGet(4),
+        I::I32Const(4),
+        I::I32Add,
+        I::LocalGet(2),
+        I::I32Add,
+        I::LocalGet(1),
+        I::I32Const(4),
+        I::I32Add,
+        I::LocalGet(3),
+        copy.clone(),
+        I::LocalGet(4),
+        I::End,
+    ];
+    let str_eq = vec![
+        I::LocalGet(0),
+        I::I32Load(LENGTH),
+        I::LocalTee(3),
+        I::LocalGet(1),
+        I::I32Load(LENGTH),
+        I::I32Ne,
+        I::If(BlockType::Empty),
+        I::I32Const(0),
+        I::Return,
+        I::End,
+        I::Block(BlockType::Empty),
+        I::Loop(BlockType::Empty),
+        I::LocalGet(2),
+        I::LocalGet(3),
+        I::I32GeU,
+        I::BrIf(1),
+        I::LocalGet(0),
+        I::LocalGet(2),
+        I::I32Add,
+        I::I32Load8U(BYTES),
+        I::LocalGet(1),
+        I::LocalGet(2),
+        I::I32Add,
+        I::I32Load8U(BYTES),
+        I::I32Ne,
+        I::If(BlockType::Empty),
+        I::I32Const(0),
+        I::Return,
+        I::End,
+        I::LocalGet(2),
+        I::I32Const(1),
+        I::I32Add,
+        I::LocalSet(2),
+        I::Br(0),
+        I::End,
+        I::End,
+        I::I32Const(1),
+        I::End,
+    ];
+    // Digits are written backwards from the end of a 24 byte buffer, which
+    // fits the 20 characters of `i64::MIN` with its length in front
+    let int_to_string = vec![
+        I::I32Const(24),
+        I::Call(ALLOC),
+        I::LocalSet(1),
+        I::I32Const(24),
+        I::LocalSet(2),
+        I::LocalGet(0),
+        I::I64Const(0),
+        I::I64LtS,
+        I::LocalSet(3),
+        I::Loop(BlockType::Empty),
+        I::LocalGet(2),
+        I::I32Const(1),
+        I::I32Sub,
+        I::LocalSet(2),
+        I::LocalGet(0),
+        I::I64Const(10),
+        I::I64RemS,
+        I::LocalSet(4),
+        I::LocalGet(1),
+        I::LocalGet(2),
+        I::I32Add,
+        // The remainder is negative for negative numbers
+        I::I64Const(0),
+        I::LocalGet(4),
+        I::I64Sub,
+        I::LocalGet(4),
+        I::LocalGet(3),
+        I::Select,
+        I::I32WrapI64,
+        I::I32Const(b'0' as i32),
+        I::I32Add,
+        I::I32Store8(LENGTH),
+        I::LocalGet(0),
+        I::I64Const(10),
+        I::I64DivS,
+        I::LocalTee(0),
+        I::I64Eqz,
+        I::I32Eqz,
+        I::BrIf(0),
+        I::End,
+        I::LocalGet(3),
+        I::If(BlockType::Empty),
+        I::LocalGet(2),
+        I::I32Const(1),
+        I::I32Sub,
+        I::LocalSet(2),
+        I::LocalGet(1),
+        I::LocalGet(2),
+        I::I32Add,
+        I::I32Const(b'-' as i32),
+        I::I32Store8(LENGTH),
+        I::End,
+        I::LocalGet(1),
+        I::LocalGet(2),
+        I::I32Add,
+        I::I32Const(4),
+        I::I32Sub,
+        I::LocalTee(1),
+        I::I32Const(24),
+        I::LocalGet(2),
+        I::I32Sub,
+        I::I32Store(LENGTH),
+        I::LocalGet(1),
+        I::End,
+    ];
+    // Characters, not bytes: every byte that does not continue a UTF-8
+    // sequence starts one
+    let str_length = vec![
+        I::LocalGet(0),
+        I::I32Load(LENGTH),
+        I::LocalSet(3),
+        I::Block(BlockType::Empty),
+        I::Loop(BlockType::Empty),
+        I::LocalGet(1),
+        I::LocalGet(3),
+        I::I32GeU,
+        I::BrIf(1),
+        I::LocalGet(2),
+        I::LocalGet(0),
+        I::LocalGet(1),
+        I::I32Add,
+        I::I32Load8U(BYTES),
+        I::I32Const(0xC0),
+        I::I32And,
+        I::I32Const(0x80),
+        I::I32Ne,
+        I::I32Add,
+        I::LocalSet(2),
+        I::LocalGet(1),
+        I::I32Const(1),
+        I::I32Add,
+        I::LocalSet(1),
+        I::Br(0),
+        I::End,
+        I::End,
+        I::LocalGet(2),
+        I::I64ExtendI32U,
+        I::End,
+    ];
+    // A new string of `length` bytes from `start`
+    let slice = vec![
+        I::LocalGet(2),
+        I::I32Const(4),
+        I::I32Add,
+        I::Call(ALLOC),
+        I::LocalTee(3),
+        I::LocalGet(2),
+        I::I32Store(LENGTH),
+        I::LocalGet(3),
+        I::I32Const(4),
+        I::I32Add,
+        I::LocalGet(0),
+        I::I32Const(4),
+        I::I32Add,
+        I::LocalGet(1),
+        I::I32Add,
+        I::LocalGet(2),
+        copy,
+        I::LocalGet(3),
+        I::End,
+    ];
+    // The character starting at a byte offset, sized by its first byte
+    let next_char = vec![
+        I::LocalGet(0),
+        I::LocalGet(1),
+        I::I32Add,
+        I::I32Load8U(BYTES),
+        I::LocalSet(2),
+        I::LocalGet(0),
+        I::LocalGet(1),
+        I::I32Const(1),
+        I::I32Const(2),
+        I::I32Const(3),
+        I::I32Const(4),
+        I::LocalGet(2),
+        I::I32Const(0xF0),
+        I::I32LtU,
+        I::Select,
+        I::LocalGet(2),
+        I::I32Const(0xE0),
+        I::I32LtU,
+        I::Select,
+        I::LocalGet(2),
+        I::I32Const(0x80),
+        I::I32LtU,
+        I::Select,
+        I::Call(SLICE),
+        I::End,
+    ];
+    [
+        (vec![I32], vec![I32], vec![I32], alloc),
+        (vec![I32, I32], vec![I32], vec![I32, I32, I32], concat),
+        (vec![I32, I32], vec![I32], vec![I32, I32], str_eq),
+        (
+            vec![I64],
+            vec![I32],
+            vec![I32, I32, I32, I64],
+            int_to_string,
+        ),
+        (vec![I32], vec![I64], vec![I32, I32, I32], str_length),
+        (vec![I32, I32, I32], vec![I32], vec![I32], slice),
+        (vec![I32, I32], vec![I32], vec![I32], next_char),
+    ]
+}